@@ -0,0 +1,406 @@
+//! `Copy` handles to nodes, for building formulas without cloning `Rc`s.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::computational_graph::{Comparison, CustomOp, Node, NodeCelled, PowPolicy, RoundMode};
+use crate::pool::GraphPool;
+
+/// Handle to a node of an `Arena`. Only meaningful for the arena that made it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Expr(u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpnError {
+    /// Token number `at` needs more operands than the stack holds.
+    Underflow { at: usize, token: String },
+    /// Token number `at` is neither a number, an op nor a known name.
+    Unknown { at: usize, token: String },
+    /// The tokens leave `values` values on the stack instead of one.
+    Unbalanced { values: usize },
+}
+
+impl fmt::Display for RpnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Underflow { at, token } => {
+                write!(f, "token {at}: not enough operands for `{token}`")
+            }
+            Self::Unknown { at, token } => write!(f, "token {at}: unknown name `{token}`"),
+            Self::Unbalanced { values } => {
+                write!(f, "expected one value at the end, found {values}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RpnError {}
+
+/// Old `Expr`s to new ones, from `Arena::compact`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remap(Vec<Option<Expr>>);
+
+impl Remap {
+    /// `None` if `old` was dropped.
+    pub fn get(&self, old: Expr) -> Option<Expr> {
+        self.0.get(old.0 as usize).copied().flatten()
+    }
+}
+
+/// Owns nodes and hands out `Expr`s to them. Nodes are interned like in a
+/// `GraphPool`, so building the same expression twice yields the same handle.
+#[derive(Debug, Default)]
+pub struct Arena {
+    nodes: Vec<NodeCelled>,
+    exprs: HashMap<*const (), Expr>,
+    pool: GraphPool,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Arena with room for `capacity` nodes, so that building a generated
+    /// model of known size doesn't keep growing and rehashing its tables.
+    /// Each node is still an `Rc` of its own.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+            exprs: HashMap::with_capacity(capacity),
+            pool: GraphPool::with_capacity(capacity),
+        }
+    }
+
+    /// Makes room for `additional` more nodes, see `with_capacity`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+        self.exprs.reserve(additional);
+        self.pool.reserve(additional);
+    }
+
+    pub fn input(&mut self, x: f32) -> Expr {
+        self.push(Node::create_input(x))
+    }
+
+    pub fn time(&mut self, t: f32) -> Expr {
+        self.push(Node::create_time(t))
+    }
+
+    pub fn constant(&mut self, x: f32) -> Expr {
+        self.push(Node::create_const(x))
+    }
+
+    pub fn add(&mut self, a: Expr, b: Expr) -> Expr {
+        self.push(Node::create_add(self.node(a), self.node(b)))
+    }
+
+    pub fn mul(&mut self, a: Expr, b: Expr) -> Expr {
+        self.push(Node::create_mul(self.node(a), self.node(b)))
+    }
+
+    pub fn pow(&mut self, a: Expr, b: Expr) -> Expr {
+        self.push(Node::create_pow(self.node(a), self.node(b)))
+    }
+
+    pub fn strict_pow(&mut self, a: Expr, b: Expr, policy: PowPolicy) -> Expr {
+        self.push(Node::create_strict_pow(self.node(a), self.node(b), policy))
+    }
+
+    pub fn sin(&mut self, x: Expr) -> Expr {
+        self.push(Node::create_sin(self.node(x)))
+    }
+
+    pub fn cos(&mut self, x: Expr) -> Expr {
+        self.push(Node::create_cos(self.node(x)))
+    }
+
+    pub fn mul_add(&mut self, a: Expr, b: Expr, c: Expr) -> Expr {
+        self.push(Node::create_mul_add(
+            self.node(a),
+            self.node(b),
+            self.node(c),
+        ))
+    }
+
+    pub fn and(&mut self, a: Expr, b: Expr) -> Expr {
+        self.push(Node::create_and(self.node(a), self.node(b)))
+    }
+
+    pub fn or(&mut self, a: Expr, b: Expr) -> Expr {
+        self.push(Node::create_or(self.node(a), self.node(b)))
+    }
+
+    pub fn not(&mut self, x: Expr) -> Expr {
+        self.push(Node::create_not(self.node(x)))
+    }
+
+    pub fn compare(&mut self, a: Expr, b: Expr, cmp: Comparison) -> Expr {
+        self.push(Node::create_compare(self.node(a), self.node(b), cmp))
+    }
+
+    pub fn select(&mut self, condition: Expr, then: Expr, otherwise: Expr) -> Expr {
+        let (condition, then, otherwise) =
+            (self.node(condition), self.node(then), self.node(otherwise));
+        self.push(Node::create_select(condition, then, otherwise))
+    }
+
+    pub fn round(&mut self, x: Expr, digits: i32, mode: RoundMode) -> Expr {
+        self.push(Node::create_round(self.node(x), digits, mode))
+    }
+
+    pub fn custom(&mut self, op: Arc<dyn CustomOp>, args: &[Expr]) -> Expr {
+        let args = args.iter().map(|arg| self.node(*arg)).collect();
+        self.push(Node::create_custom(op, args))
+    }
+
+    /// Builds the expression written in reverse Polish notation by `tokens`,
+    /// e.g. `["x", "2", "^", "sin"]` for `sin(x^2)`, with a stack instead of
+    /// recursion, so any depth works. Tokens are numbers, names resolved by
+    /// `variable`, and the ops `+ - * / ^ < <= > >= == != && ||` (two
+    /// operands), `neg ! sin cos sqrt` (one) and `select mul_add` (three).
+    pub fn from_rpn<T: AsRef<str>>(
+        &mut self,
+        tokens: impl IntoIterator<Item = T>,
+        variable: impl Fn(&str) -> Option<Expr>,
+    ) -> Result<Expr, RpnError> {
+        let mut stack: Vec<Expr> = Vec::new();
+        for (at, token) in tokens.into_iter().enumerate() {
+            let token = token.as_ref();
+            let arity = match token {
+                "neg" | "!" | "sin" | "cos" | "sqrt" => 1,
+                "select" | "mul_add" => 3,
+                "+" | "-" | "*" | "/" | "^" | "<" | "<=" | ">" | ">=" | "==" | "!=" | "&&"
+                | "||" => 2,
+                _ => 0,
+            };
+            if stack.len() < arity {
+                return Err(RpnError::Underflow {
+                    at,
+                    token: token.to_string(),
+                });
+            }
+            let args = stack.split_off(stack.len() - arity);
+            let res = match (token, args.as_slice()) {
+                (_, []) => match token.parse::<f32>() {
+                    Ok(x) => self.constant(x),
+                    Err(_) => variable(token).ok_or_else(|| RpnError::Unknown {
+                        at,
+                        token: token.to_string(),
+                    })?,
+                },
+                ("neg", [x]) => {
+                    let minus_one = self.constant(-1.0);
+                    self.mul(minus_one, *x)
+                }
+                ("!", [x]) => self.not(*x),
+                ("sin", [x]) => self.sin(*x),
+                ("cos", [x]) => self.cos(*x),
+                ("sqrt", [x]) => {
+                    let half = self.constant(0.5);
+                    self.pow(*x, half)
+                }
+                ("+", [a, b]) => self.add(*a, *b),
+                ("-", [a, b]) => {
+                    let minus_one = self.constant(-1.0);
+                    let minus_b = self.mul(minus_one, *b);
+                    self.add(*a, minus_b)
+                }
+                ("*", [a, b]) => self.mul(*a, *b),
+                ("/", [a, b]) => {
+                    let minus_one = self.constant(-1.0);
+                    let reciprocal = self.pow(*b, minus_one);
+                    self.mul(*a, reciprocal)
+                }
+                ("^", [a, b]) => self.pow(*a, *b),
+                ("&&", [a, b]) => self.and(*a, *b),
+                ("||", [a, b]) => self.or(*a, *b),
+                ("select", [c, a, b]) => self.select(*c, *a, *b),
+                ("mul_add", [a, b, c]) => self.mul_add(*a, *b, *c),
+                (_, [a, b]) => {
+                    let cmp = match token {
+                        "<" => Comparison::Lt,
+                        "<=" => Comparison::Le,
+                        ">" => Comparison::Gt,
+                        ">=" => Comparison::Ge,
+                        "==" => Comparison::Eq,
+                        _ => Comparison::Ne,
+                    };
+                    self.compare(*a, *b, cmp)
+                }
+                _ => unreachable!(),
+            };
+            stack.push(res);
+        }
+
+        match stack[..] {
+            [res] => Ok(res),
+            _ => Err(RpnError::Unbalanced {
+                values: stack.len(),
+            }),
+        }
+    }
+
+    /// Adopts the graph computing `output`, built outside the arena.
+    pub fn insert(&mut self, output: &NodeCelled) -> Expr {
+        let pooled = self.pool.intern_graph(output);
+        for node in Node::topo_order(&pooled) {
+            self.register(node);
+        }
+        self.exprs[&(Rc::as_ptr(&pooled) as *const ())]
+    }
+
+    /// The node behind `expr`, e.g. to compile or serialize it.
+    pub fn node(&self, expr: Expr) -> NodeCelled {
+        self.nodes[expr.0 as usize].clone()
+    }
+
+    /// Panics on evaluation errors, like `Node::compute`.
+    pub fn compute(&self, expr: Expr) -> f32 {
+        self.nodes[expr.0 as usize].borrow().compute()
+    }
+
+    pub fn set(&self, input: Expr, x: f32) {
+        self.nodes[input.0 as usize].borrow().set(x);
+    }
+
+    /// Number of distinct nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Drops every node that `roots` don't depend on, renumbers the rest
+    /// densely in their old order and shrinks the storage. `Expr`s held
+    /// elsewhere must be translated with the returned `Remap`.
+    pub fn compact(&mut self, roots: &[Expr]) -> Remap {
+        let mut live = HashSet::new();
+        for root in roots {
+            live.extend(Node::topo_order(&self.node(*root)).iter().map(Rc::as_ptr));
+        }
+
+        let mut remap = Vec::with_capacity(self.nodes.len());
+        let mut nodes = Vec::with_capacity(live.len());
+        for node in self.nodes.drain(..) {
+            if live.contains(&Rc::as_ptr(&node)) {
+                remap.push(Some(Expr(nodes.len() as u32)));
+                nodes.push(node);
+            } else {
+                remap.push(None);
+                Node::detach(&node);
+            }
+        }
+        self.nodes = nodes;
+        self.exprs = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (Rc::as_ptr(node) as *const (), Expr(i as u32)))
+            .collect();
+        self.pool.retain(|node| live.contains(&Rc::as_ptr(node)));
+
+        Remap(remap)
+    }
+
+    fn push(&mut self, node: NodeCelled) -> Expr {
+        let node = self.pool.intern(node);
+        self.register(node)
+    }
+
+    fn register(&mut self, node: NodeCelled) -> Expr {
+        let ptr = Rc::as_ptr(&node) as *const ();
+        if let Some(expr) = self.exprs.get(&ptr) {
+            return *expr;
+        }
+
+        let expr = Expr(self.nodes.len() as u32);
+        self.nodes.push(node);
+        self.exprs.insert(ptr, expr);
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_equal_expressions() {
+        let mut arena = Arena::new();
+        let (x, y) = (arena.input(2f32), arena.input(2f32));
+        assert_ne!(x, y);
+        let two = arena.constant(2f32);
+        let a = arena.mul(x, two);
+        let b = arena.mul(x, two);
+        assert_eq!(a, b);
+        assert_ne!(a, arena.mul(y, two));
+        assert_eq!(arena.len(), 5);
+
+        let outside = Node::create_mul(arena.node(x), Node::create_const(2f32));
+        assert_eq!(arena.insert(&outside), a);
+        assert_eq!(arena.len(), 5);
+    }
+
+    #[test]
+    fn builds_rpn() {
+        let mut arena = Arena::new();
+        let x = arena.input(3f32);
+        let variable = |name: &str| (name == "x").then_some(x);
+        let expr = arena
+            .from_rpn(["x", "2", "^", "x", "-", "4", "/"], variable)
+            .unwrap();
+        assert_eq!(arena.compute(expr), 1.5f32);
+        arena.set(x, 5f32);
+        assert_eq!(arena.compute(expr), 5f32);
+
+        let expr = arena
+            .from_rpn(["x", "4", ">", "1", "0", "select"], variable)
+            .unwrap();
+        assert_eq!(arena.compute(expr), 1f32);
+
+        let res = arena.from_rpn(["x", "+"], variable);
+        assert_eq!(
+            res,
+            Err(RpnError::Underflow {
+                at: 1,
+                token: "+".into()
+            })
+        );
+        let res = arena.from_rpn(["x", "y", "*"], variable);
+        assert_eq!(
+            res,
+            Err(RpnError::Unknown {
+                at: 1,
+                token: "y".into()
+            })
+        );
+        let res = arena.from_rpn(["x", "x"], variable);
+        assert_eq!(res, Err(RpnError::Unbalanced { values: 2 }));
+    }
+
+    #[test]
+    fn compacts_to_the_roots() {
+        let mut arena = Arena::new();
+        let x = arena.input(2f32);
+        let dropped = arena.sin(x);
+        let y = arena.input(3f32);
+        let kept = arena.mul(x, y);
+
+        let remap = arena.compact(&[kept]);
+        assert_eq!(remap.get(dropped), None);
+        assert_eq!(arena.len(), 3);
+        let (x, kept) = (remap.get(x).unwrap(), remap.get(kept).unwrap());
+        assert_eq!(arena.compute(kept), 6f32);
+        assert_eq!(arena.node(x).borrow().dependents().len(), 1);
+        // Interning still finds the kept nodes, and only those.
+        let y = remap.get(y).unwrap();
+        assert_eq!(arena.mul(x, y), kept);
+        arena.sin(x);
+        assert_eq!(arena.len(), 4);
+    }
+}
@@ -0,0 +1,158 @@
+//! Arrow record batches as columns: a `RecordBatch` is `Columns`, so
+//! `ColumnExpr`s compute from it directly, and converts to and from a
+//! `ColumnBatch`.
+//!
+//! Numeric columns are read as `f32`, `Float32` ones without copying, and
+//! nulls as NaN. Other columns, e.g. of strings, aren't read.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::Float32Type;
+use arrow_array::{Array, ArrayRef, Float32Array, RecordBatch, RecordBatchOptions};
+use arrow_schema::{DataType, Field, Schema};
+
+use crate::columnar::{ColumnBatch, ColumnError, ColumnExpr, Columns};
+
+/// `array` as `f32`s, `None` if it isn't numeric.
+pub(crate) fn floats(array: &ArrayRef) -> Option<Cow<'_, [f32]>> {
+    if !array.data_type().is_numeric() {
+        return None;
+    }
+    if array.data_type() == &DataType::Float32 && array.null_count() == 0 {
+        return Some(Cow::Borrowed(array.as_primitive::<Float32Type>().values()));
+    }
+    let array = arrow_cast::cast(array, &DataType::Float32).ok()?;
+    let array = array.as_primitive::<Float32Type>();
+    Some(Cow::Owned(
+        array
+            .iter()
+            .map(|value| value.unwrap_or(f32::NAN))
+            .collect(),
+    ))
+}
+
+impl Columns for RecordBatch {
+    fn rows(&self) -> usize {
+        self.num_rows()
+    }
+
+    fn column(&self, name: &str) -> Option<Cow<'_, [f32]>> {
+        floats(self.column_by_name(name)?)
+    }
+}
+
+impl ColumnBatch {
+    /// The numeric columns of `batch`, in its order.
+    pub fn from_record_batch(batch: &RecordBatch) -> Self {
+        let mut res = Self::new();
+        for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+            if let Some(values) = floats(column) {
+                res.push_column(field.name(), values.into_owned())
+                    .expect("record batches have unique, equal-length columns");
+            }
+        }
+        res
+    }
+
+    /// A record batch of non-nullable `Float32` columns, NaN staying NaN.
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let fields: Vec<_> = self
+            .names()
+            .iter()
+            .map(|name| Field::new(name, DataType::Float32, false))
+            .collect();
+        let columns = self
+            .names()
+            .iter()
+            .map(|name| {
+                let values = self.column(name).unwrap().to_vec();
+                Arc::new(Float32Array::from(values)) as ArrayRef
+            })
+            .collect();
+        // Without columns, the row count has to be given.
+        let options = RecordBatchOptions::new().with_row_count(Some(self.len()));
+        RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), columns, &options)
+            .expect("columns of a batch have equal lengths")
+    }
+}
+
+impl ColumnExpr {
+    /// `batch` with this expression's column added, as a non-nullable
+    /// `Float32` column under its name.
+    pub fn extend_batch(&self, batch: &RecordBatch) -> Result<RecordBatch, ColumnError> {
+        if batch.column_by_name(self.name()).is_some() {
+            return Err(ColumnError::Duplicate(self.name().to_string()));
+        }
+        let values = self.apply(batch)?;
+        let schema = batch.schema();
+        let mut fields: Vec<_> = schema.fields().iter().cloned().collect();
+        fields.push(Arc::new(Field::new(self.name(), DataType::Float32, false)));
+        let mut columns = batch.columns().to_vec();
+        columns.push(Arc::new(Float32Array::from(values)));
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        Ok(RecordBatch::try_new(Arc::new(schema), columns).expect("the column has every row"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Int64Array, StringArray};
+
+    use crate::parser::Parser;
+
+    fn batch() -> RecordBatch {
+        RecordBatch::try_from_iter([
+            (
+                "id",
+                Arc::new(StringArray::from(vec!["a", "b", "c"])) as ArrayRef,
+            ),
+            (
+                "price",
+                Arc::new(Float32Array::from(vec![10.0, 20.0, 30.0])),
+            ),
+            (
+                "quantity",
+                Arc::new(Int64Array::from(vec![Some(1), None, Some(3)])),
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_numeric_columns() {
+        let batch = batch();
+        assert!(matches!(
+            Columns::column(&batch, "price"),
+            Some(Cow::Borrowed([10.0, 20.0, 30.0]))
+        ));
+        let quantity = Columns::column(&batch, "quantity").unwrap();
+        assert_eq!((quantity[0], quantity[2]), (1.0, 3.0));
+        assert!(quantity[1].is_nan());
+        assert!(Columns::column(&batch, "id").is_none());
+
+        let columns = ColumnBatch::from_record_batch(&batch);
+        assert_eq!(columns.names(), ["price", "quantity"]);
+        let round_trip = ColumnBatch::from_record_batch(&columns.to_record_batch());
+        assert_eq!(round_trip.column("price"), columns.column("price"));
+        assert_eq!(round_trip.len(), 3);
+        assert_eq!(ColumnBatch::new().to_record_batch().num_rows(), 0);
+    }
+
+    #[test]
+    fn extends_record_batches() {
+        let expr = ColumnExpr::parse(Parser::new(), "price * quantity", &["price", "quantity"])
+            .unwrap()
+            .with_name("total");
+        let batch = expr.extend_batch(&batch()).unwrap();
+        assert_eq!(batch.num_columns(), 4);
+        let total = Columns::column(&batch, "total").unwrap();
+        assert_eq!((total[0], total[2]), (10.0, 90.0));
+        assert!(total[1].is_nan());
+
+        let res = expr.extend_batch(&batch);
+        assert!(matches!(res, Err(ColumnError::Duplicate(name)) if name == "total"));
+    }
+}
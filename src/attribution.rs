@@ -0,0 +1,267 @@
+//! Why an output moved: its change since a snapshot of the inputs, split
+//! into the contributions of the inputs that changed.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::autodiff::{gradient, GradError};
+use crate::computational_graph::{Node, NodeCelled, NodeId};
+
+/// The values of a graph's inputs at one point, to attribute later changes
+/// against.
+#[derive(Debug, Clone)]
+pub struct InputSnapshot {
+    values: Vec<(NodeCelled, f32)>,
+}
+
+impl InputSnapshot {
+    pub fn get(&self, input: &NodeCelled) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|(node, _)| Rc::ptr_eq(node, input))
+            .map(|(_, value)| *value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Attribution {
+    /// Each input's contribution is the output's change when only it takes
+    /// its new value. Exact for a single change; with several, whatever
+    /// they do only together is left in the residual.
+    #[default]
+    OneAtATime,
+    /// Each input's contribution is the derivative at the new values times
+    /// its change: one backward pass, but only a first-order estimate.
+    /// Derivatives don't reach the free inputs of composites' bodies, so
+    /// their changes are left in the residual.
+    Gradient,
+}
+
+/// One changed input's share of the output's change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub input: NodeId,
+    pub name: Option<Rc<str>>,
+    pub old: f32,
+    pub new: f32,
+    pub contribution: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeAttribution {
+    /// The output at the snapshot's input values.
+    pub old: f32,
+    pub new: f32,
+    /// Largest first, by magnitude. Inputs that didn't change are left out.
+    pub contributions: Vec<Contribution>,
+    /// The change the contributions don't account for: interactions, and
+    /// for `Gradient`, curvature.
+    pub residual: f32,
+}
+
+impl ChangeAttribution {
+    pub fn delta(&self) -> f32 {
+        self.new - self.old
+    }
+}
+
+impl fmt::Display for ChangeAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} -> {} ({:+})", self.old, self.new, self.delta())?;
+        for contribution in &self.contributions {
+            match &contribution.name {
+                Some(name) => write!(f, "  {name}")?,
+                None => write!(f, "  {}", contribution.input)?,
+            }
+            writeln!(
+                f,
+                ": {} -> {}: {:+}",
+                contribution.old, contribution.new, contribution.contribution
+            )?;
+        }
+        writeln!(f, "  residual: {:+}", self.residual)
+    }
+}
+
+impl Node {
+    /// The current values of the inputs `this` depends on, composites' free
+    /// inputs included.
+    pub fn snapshot_inputs(this: &NodeCelled) -> InputSnapshot {
+        let mut inputs = Self::inputs(this);
+        for node in Self::topo_order(this) {
+            inputs.extend(node.borrow().free_inputs().iter().cloned());
+        }
+        let mut seen = HashSet::new();
+        inputs.retain(|input| seen.insert(Rc::as_ptr(input)));
+
+        InputSnapshot {
+            values: inputs
+                .into_iter()
+                .map(|input| {
+                    let value = input.borrow().compute();
+                    (input, value)
+                })
+                .collect(),
+        }
+    }
+
+    /// Splits the change of `this` since `baseline` into the contributions
+    /// of the inputs that changed, with `method`. The old value is computed
+    /// as a `Context`, so the graph's inputs and caches are left as they are.
+    pub fn attribute_change(
+        this: &NodeCelled,
+        baseline: &InputSnapshot,
+        method: Attribution,
+    ) -> Result<ChangeAttribution, GradError> {
+        let changed: Vec<_> = baseline
+            .values
+            .iter()
+            .filter_map(|(input, old)| {
+                let new = input.borrow().compute();
+                (new.to_bits() != old.to_bits()).then(|| (input.clone(), *old, new))
+            })
+            .collect();
+        let reverted: Vec<_> = changed
+            .iter()
+            .map(|(input, old, _)| (input.clone(), *old))
+            .collect();
+        let old = this.borrow().compute_with(&reverted)?;
+        let new = this.borrow().try_compute()?;
+
+        let shares = match method {
+            Attribution::OneAtATime => {
+                let mut shares = Vec::with_capacity(changed.len());
+                for i in 0..changed.len() {
+                    let mut overrides = reverted.clone();
+                    overrides.swap_remove(i);
+                    shares.push(this.borrow().compute_with(&overrides)? - old);
+                }
+                shares
+            }
+            Attribution::Gradient => {
+                let inputs: Vec<_> = changed.iter().map(|(input, ..)| input.clone()).collect();
+                gradient(this, &inputs)?
+                    .into_iter()
+                    .zip(&changed)
+                    .map(|(derivative, (_, old, new))| derivative * (new - old))
+                    .collect()
+            }
+        };
+
+        let mut contributions: Vec<_> = changed
+            .iter()
+            .zip(shares)
+            .map(|((input, old, new), contribution)| {
+                let input = input.borrow();
+                Contribution {
+                    input: input.id(),
+                    name: input.name(),
+                    old: *old,
+                    new: *new,
+                    contribution,
+                }
+            })
+            .collect();
+        contributions.sort_by(|a, b| b.contribution.abs().total_cmp(&a.contribution.abs()));
+        let explained: f32 = contributions.iter().map(|c| c.contribution).sum();
+
+        Ok(ChangeAttribution {
+            old,
+            new,
+            contributions,
+            residual: new - old - explained,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+
+    #[test]
+    fn attributes_free_inputs_of_composites() {
+        let k = Node::create_input(2f32);
+        let f = Function::define("f", 1, |p| Node::create_mul(p[0].clone(), k.clone()));
+        let x = Node::create_input(3f32);
+        let out = f.call(vec![x.clone()]);
+        let baseline = Node::snapshot_inputs(&out);
+        assert_eq!(baseline.get(&k), Some(2f32));
+
+        k.borrow().set(10f32);
+        let res = Node::attribute_change(&out, &baseline, Attribution::OneAtATime).unwrap();
+        assert_eq!((res.old, res.new), (6f32, 30f32));
+        assert_eq!(res.contributions.len(), 1);
+        assert_eq!(res.contributions[0].input, k.borrow().id());
+        assert_eq!(res.contributions[0].contribution, 24f32);
+        assert_eq!(res.residual, 0f32);
+    }
+
+    /// `3a - 2b + c`, with `a`, `b` and `c`.
+    fn linear() -> (NodeCelled, [NodeCelled; 3]) {
+        let inputs = [1f32, 5f32, 7f32].map(Node::create_input);
+        let [a, b, c] = inputs.clone();
+        let out = Node::create_add(
+            Node::create_mul_add(a, Node::create_const(3.0), c),
+            Node::create_mul(b, Node::create_const(-2.0)),
+        );
+        (out, inputs)
+    }
+
+    #[test]
+    fn attributes_linear_changes_exactly() {
+        let (out, [a, b, c]) = linear();
+        a.borrow().set_name("a");
+        let baseline = Node::snapshot_inputs(&out);
+        a.borrow().set(2.0);
+        b.borrow().set(1.0);
+
+        for method in [Attribution::OneAtATime, Attribution::Gradient] {
+            let res = Node::attribute_change(&out, &baseline, method).unwrap();
+            assert_eq!((res.old, res.new, res.delta()), (0.0, 11.0, 11.0));
+            let shares: Vec<_> = res
+                .contributions
+                .iter()
+                .map(|c| (c.input, c.old, c.new, c.contribution))
+                .collect();
+            assert_eq!(
+                shares,
+                [
+                    (b.borrow().id(), 5.0, 1.0, 8.0),
+                    (a.borrow().id(), 1.0, 2.0, 3.0),
+                ],
+                "{method:?}"
+            );
+            assert_eq!(res.residual, 0.0);
+        }
+        assert_eq!(c.borrow().compute(), 7.0);
+        assert_eq!(out.borrow().compute(), 11.0);
+
+        let res = Node::attribute_change(&out, &baseline, Attribution::default()).unwrap();
+        let b = b.borrow().id();
+        assert_eq!(
+            res.to_string(),
+            format!("0 -> 11 (+11)\n  {b}: 5 -> 1: +8\n  a: 1 -> 2: +3\n  residual: +0\n")
+        );
+    }
+
+    #[test]
+    fn leaves_interactions_in_the_residual() {
+        let [x, y] = [2f32, 3f32].map(Node::create_input);
+        let out = Node::create_mul(x.clone(), y.clone());
+        let baseline = Node::snapshot_inputs(&out);
+        x.borrow().set(4.0);
+        y.borrow().set(5.0);
+
+        let res = Node::attribute_change(&out, &baseline, Attribution::OneAtATime).unwrap();
+        let shares: Vec<_> = res.contributions.iter().map(|c| c.contribution).collect();
+        assert_eq!(shares, [6.0, 4.0]);
+        assert_eq!(res.residual, 4.0);
+        // Derivatives at the new values: 5 * 2 and 4 * 2.
+        let res = Node::attribute_change(&out, &baseline, Attribution::Gradient).unwrap();
+        let shares: Vec<_> = res.contributions.iter().map(|c| c.contribution).collect();
+        assert_eq!(shares, [10.0, 8.0]);
+        assert_eq!(res.residual, -4.0);
+    }
+}
@@ -0,0 +1,220 @@
+//! Record of input changes for review: every `set`, and so every `refresh`,
+//! `step` and `advance_time`, and every input `reset_inputs` restores, with
+//! who made it, when, and the values before and after.
+//!
+//! Recording is per thread, like graphs: `record_to` starts it, and
+//! `with_context` says on whose behalf the changes within are made. Values
+//! solvers, fits and sweeps try aren't changes: only where they leave an
+//! input is recorded.
+
+use std::cell::RefCell;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::computational_graph::{Node, NodeId};
+use crate::scenario::write_csv_field;
+
+thread_local! {
+    static LOG: RefCell<Option<AuditLog>> = const { RefCell::new(None) };
+    static CONTEXT: RefCell<Option<AuditContext>> = const { RefCell::new(None) };
+}
+
+/// On whose behalf, and why, inputs are changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditContext {
+    actor: String,
+    reason: Option<String>,
+}
+
+impl AuditContext {
+    /// `actor` is a user, service or job name, as the application knows it.
+    pub fn new(actor: impl Into<String>) -> Self {
+        Self {
+            actor: actor.into(),
+            reason: None,
+        }
+    }
+
+    /// E.g. a ticket or request id.
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    /// `set`, directly or through `refresh`, `step` or `advance_time`.
+    Set,
+    /// `reset_inputs` restoring the input's default.
+    Reset,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Set => "set",
+            Self::Reset => "reset",
+        })
+    }
+}
+
+/// One input change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    /// `None` for changes made outside `with_context`.
+    pub context: Option<AuditContext>,
+    pub node: NodeId,
+    /// The input's name at the time.
+    pub name: Option<Rc<str>>,
+    pub action: AuditAction,
+    pub old: f32,
+    pub new: f32,
+}
+
+/// Entries in the order the changes were made, shared by the log's clones.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    entries: Rc<RefCell<Vec<AuditEntry>>>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.borrow().clone()
+    }
+
+    /// The changes of the input `node`, oldest first.
+    pub fn history(&self, node: NodeId) -> Vec<AuditEntry> {
+        self.entries
+            .borrow()
+            .iter()
+            .filter(|entry| entry.node == node)
+            .cloned()
+            .collect()
+    }
+
+    /// Removes and returns every entry, e.g. to persist them.
+    pub fn take(&self) -> Vec<AuditEntry> {
+        self.entries.take()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// One row per entry, the time in seconds since the Unix epoch.
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("time,actor,reason,node,name,action,old,new\n");
+        for entry in self.entries.borrow().iter() {
+            let time = entry
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            write!(res, "{time:.3},").unwrap();
+            let context = entry.context.as_ref();
+            write_csv_field(&mut res, context.map_or("", AuditContext::actor));
+            res.push(',');
+            write_csv_field(
+                &mut res,
+                context.and_then(AuditContext::reason).unwrap_or(""),
+            );
+            write!(res, ",{},", entry.node).unwrap();
+            write_csv_field(&mut res, entry.name.as_deref().unwrap_or(""));
+            writeln!(res, ",{},{},{}", entry.action, entry.old, entry.new).unwrap();
+        }
+        res
+    }
+}
+
+/// Records this thread's input changes in `log` from now on, returning the
+/// log it replaces.
+pub fn record_to(log: &AuditLog) -> Option<AuditLog> {
+    LOG.with(|current| current.borrow_mut().replace(log.clone()))
+}
+
+/// Stops recording this thread's input changes.
+pub fn stop() -> Option<AuditLog> {
+    LOG.with(|current| current.borrow_mut().take())
+}
+
+/// Runs `f`, recording the changes it makes on this thread as made under
+/// `context`.
+pub fn with_context<R>(context: AuditContext, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<AuditContext>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CONTEXT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(CONTEXT.with(|current| current.borrow_mut().replace(context)));
+    f()
+}
+
+/// Records that `node`, an input, changes from `old` to `new`.
+pub(crate) fn record(node: &Node, action: AuditAction, old: f32, new: f32) {
+    let Some(log) = LOG.with(|current| current.borrow().clone()) else {
+        return;
+    };
+    let entry = AuditEntry {
+        time: SystemTime::now(),
+        context: CONTEXT.with(|current| current.borrow().clone()),
+        node: node.id(),
+        name: node.name(),
+        action,
+        old,
+        new,
+    };
+    log.entries.borrow_mut().push(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+
+    #[test]
+    fn pure_compute_records_nothing() {
+        let log = AuditLog::new();
+        record_to(&log);
+        let f = Function::define("f", 1, |p| Node::create_mul(p[0].clone(), p[0].clone()));
+        let x = Node::create_input(3f32);
+        let call = f.call(vec![x.clone()]);
+        assert_eq!(call.borrow().compute(), 9f32);
+        assert_eq!(
+            call.borrow().compute_with(&[(x.clone(), 4f32)]).unwrap(),
+            16f32
+        );
+        assert!(log.is_empty());
+
+        x.borrow().set(5f32);
+        assert_eq!(call.borrow().compute(), 25f32);
+        stop();
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].node, entries[0].old, entries[0].new),
+            (x.borrow().id(), 3f32, 5f32)
+        );
+    }
+}
@@ -0,0 +1,675 @@
+//! Derivatives of a graph's output with respect to its nodes, by reverse
+//! accumulation, and checks of them against finite differences. Higher orders
+//! in a single input propagate truncated Taylor series forward instead.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::computational_graph::{
+    BinaryOp, EvalError, InputKind, Node, NodeCelled, NodeId, TernaryOp, UnaryOp,
+};
+use crate::constraint::ConstraintError;
+use crate::testing::approx_eq;
+
+#[derive(Debug, Clone)]
+pub enum GradError {
+    Eval(EvalError),
+    /// A custom op without `CustomOp::derivatives`, or needed beyond the
+    /// first order.
+    NotDifferentiable {
+        node: NodeId,
+        op: String,
+    },
+    /// An input's constraint rejected a value it had to take, such as a
+    /// sample's in `LeastSquares::fit`.
+    Constraint(ConstraintError),
+}
+
+impl fmt::Display for GradError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Eval(e) => e.fmt(f),
+            Self::NotDifferentiable { node, op } => {
+                write!(f, "node {node}: {op} has no derivatives")
+            }
+            Self::Constraint(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GradError {}
+
+impl From<EvalError> for GradError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+impl From<ConstraintError> for GradError {
+    fn from(e: ConstraintError) -> Self {
+        Self::Constraint(e)
+    }
+}
+
+/// Derivative of `output` with respect to every node it depends on, itself
+/// included, at the current input values. Logical ops, comparisons and
+/// rounding are piecewise constant and pass on nothing; `Select` passes
+/// everything to the branch taken.
+///
+/// Forward values come from the nodes' caches, so `Recompute` nodes are
+/// recomputed as needed, see `checkpointed_gradients`.
+pub fn gradients(output: &NodeCelled) -> Result<HashMap<NodeId, f32>, GradError> {
+    let order = Node::topo_order(output);
+    let mut adjoints = HashMap::new();
+    adjoints.insert(output.borrow().id(), 1f32);
+
+    for node in order.iter().rev() {
+        let node = node.borrow();
+        let adjoint = match adjoints.get(&node.id()) {
+            Some(adjoint) if *adjoint != 0.0 => *adjoint,
+            _ => continue,
+        };
+        let children = node.children();
+        if children.is_empty() {
+            continue;
+        }
+
+        let partials = local_partials(&node)?;
+        for (child, partial) in children.iter().zip(partials) {
+            *adjoints.entry(child.borrow().id()).or_insert(0.0) += adjoint * partial;
+        }
+    }
+
+    Ok(adjoints)
+}
+
+/// Derivatives of `output` with respect to each of `inputs`, `0` for those it
+/// doesn't depend on.
+pub fn gradient(output: &NodeCelled, inputs: &[NodeCelled]) -> Result<Vec<f32>, GradError> {
+    let adjoints = gradients(output)?;
+    Ok(inputs
+        .iter()
+        .map(|input| adjoints.get(&input.borrow().id()).copied().unwrap_or(0.0))
+        .collect())
+}
+
+/// `gradients`, storing forward values only at `output` and `checkpoints`
+/// and recomputing the rest from the nearest checkpoints below as the
+/// backward pass needs them, to bound memory on large graphs. Cache
+/// policies are set as by `Node::checkpoint` for the pass and restored
+/// afterwards. Checkpoints should include `Node::shared`, or shared nodes
+/// are recomputed once per reader.
+pub fn checkpointed_gradients(
+    output: &NodeCelled,
+    checkpoints: &[NodeCelled],
+) -> Result<HashMap<NodeId, f32>, GradError> {
+    let order = Node::topo_order(output);
+    let policies: Vec<_> = order
+        .iter()
+        .map(|node| node.borrow().cache_policy())
+        .collect();
+    Node::checkpoint(output, checkpoints);
+    let res = gradients(output);
+    for (node, policy) in order.iter().zip(policies) {
+        let node = node.borrow();
+        if node.cache_policy() != policy {
+            node.set_cache_policy(policy);
+        }
+    }
+    res
+}
+
+/// Derivatives of `node`'s op with respect to each operand, at their current
+/// values.
+fn local_partials(node: &Node) -> Result<Vec<f32>, GradError> {
+    let args = node
+        .children()
+        .iter()
+        .map(|child| child.borrow().try_compute())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match node {
+        Node::Input { .. } => Vec::new(),
+        Node::Binary { op, .. } => {
+            let (a, b) = (args[0], args[1]);
+            match op {
+                BinaryOp::Add => vec![1.0, 1.0],
+                BinaryOp::Mul => vec![b, a],
+                BinaryOp::Pow(_) => {
+                    let value = node.try_compute()?;
+                    // `ln(a)` is undefined at 0, where `a^b` is flat in `b`.
+                    let by_exponent = if value == 0.0 { 0.0 } else { value * a.ln() };
+                    vec![b * a.powf(b - 1.0), by_exponent]
+                }
+                BinaryOp::And | BinaryOp::Or | BinaryOp::Compare(_) => vec![0.0, 0.0],
+            }
+        }
+        Node::Unary { op, .. } => vec![match op {
+            UnaryOp::Sin => args[0].cos(),
+            UnaryOp::Cos => -args[0].sin(),
+            UnaryOp::Not | UnaryOp::Round { .. } => 0.0,
+        }],
+        Node::Ternary { op, .. } => match op {
+            TernaryOp::MulAdd => vec![args[1], args[0], 1.0],
+            TernaryOp::Select if args[0] != 0.0 => vec![0.0, 1.0, 0.0],
+            TernaryOp::Select => vec![0.0, 0.0, 1.0],
+        },
+        Node::Custom { op, .. } => {
+            op.derivatives(&args)
+                .ok_or_else(|| GradError::NotDifferentiable {
+                    node: node.id(),
+                    op: op.name().to_string(),
+                })?
+        }
+        Node::Composite { graph, port, .. } => {
+            // Evaluating binds the subgraph's inputs to this call's arguments.
+            node.apply(&args)?;
+            gradient(&graph.outputs()[*port], graph.inputs())?
+        }
+    })
+}
+
+impl Node {
+    /// Polynomial of degree `order` in `input` matching this node's value and
+    /// first `order` derivatives at the current point, as a new graph reading
+    /// `input`. Other inputs are baked in at their current values.
+    pub fn taylor(&self, input: &NodeCelled, order: usize) -> Result<NodeCelled, GradError> {
+        let coefficients = self.taylor_coefficients(input, order)?;
+        let center = input.borrow().try_compute()?;
+        let offset = Node::create_add(input.clone(), Node::create_const(-center));
+
+        let mut coefficients = coefficients.into_iter().rev();
+        let highest = Node::create_const(coefficients.next().unwrap());
+        Ok(coefficients.fold(highest, |res, coefficient| {
+            Node::create_mul_add(res, offset.clone(), Node::create_const(coefficient))
+        }))
+    }
+
+    /// `f^(k)(x) / k!` for `k` up to `order`, where `f` is this node as a
+    /// function of `input` at its current value `x`.
+    pub fn taylor_coefficients(
+        &self,
+        input: &NodeCelled,
+        order: usize,
+    ) -> Result<Vec<f32>, GradError> {
+        let mut seed = vec![0.0; order + 1];
+        seed[0] = input.borrow().try_compute()?;
+        if order > 0 {
+            seed[1] = 1.0;
+        }
+        let seeds = HashMap::from([(input.borrow().id(), seed)]);
+
+        let mut series = HashMap::new();
+        for child in self.children() {
+            for node in Node::topo_order(&child) {
+                let node = node.borrow();
+                if !series.contains_key(&node.id()) {
+                    let jet = jet(&node, &series, &seeds, order)?;
+                    series.insert(node.id(), jet);
+                }
+            }
+        }
+        match series.remove(&self.id()) {
+            Some(jet) => Ok(jet),
+            None => jet(self, &series, &seeds, order),
+        }
+    }
+}
+
+/// Taylor coefficients of `node` in the seeded variable, from those of its
+/// operands in `series`. Inputs without a seed are constant.
+fn jet(
+    node: &Node,
+    series: &HashMap<NodeId, Vec<f32>>,
+    seeds: &HashMap<NodeId, Vec<f32>>,
+    order: usize,
+) -> Result<Vec<f32>, GradError> {
+    if let Some(seed) = seeds.get(&node.id()) {
+        return Ok(seed.clone());
+    }
+    let children = node.children();
+    let args: Vec<&[f32]> = children
+        .iter()
+        .map(|child| &series[&child.borrow().id()][..])
+        .collect();
+    let constant = |jet: &[f32]| jet[1..].iter().all(|c| *c == 0.0);
+
+    let value = match node {
+        Node::Composite { graph, port, .. } => {
+            let seeds = graph
+                .inputs()
+                .iter()
+                .zip(&args)
+                .map(|(input, arg)| (input.borrow().id(), arg.to_vec()))
+                .collect();
+            let output = graph.outputs()[*port].borrow();
+            let mut inner = HashMap::new();
+            for child in output.children() {
+                for node in Node::topo_order(&child) {
+                    let node = node.borrow();
+                    if !inner.contains_key(&node.id()) {
+                        let jet = jet(&node, &inner, &seeds, order)?;
+                        inner.insert(node.id(), jet);
+                    }
+                }
+            }
+            return match inner.remove(&output.id()) {
+                Some(jet) => Ok(jet),
+                None => jet(&output, &inner, &seeds, order),
+            };
+        }
+        _ => {
+            let values: Vec<_> = args.iter().map(|arg| arg[0]).collect();
+            node.apply(&values)?.0
+        }
+    };
+    let mut res = vec![0.0; order + 1];
+    res[0] = value;
+    if order == 0 || args.iter().all(|arg| constant(arg)) {
+        return Ok(res);
+    }
+
+    match node {
+        Node::Binary {
+            op: BinaryOp::Add, ..
+        } => {
+            for k in 1..=order {
+                res[k] = args[0][k] + args[1][k];
+            }
+        }
+        Node::Binary {
+            op: BinaryOp::Mul, ..
+        } => product(args[0], args[1], &mut res),
+        Node::Binary {
+            op: BinaryOp::Pow(_),
+            ..
+        } => power(args[0], args[1], &mut res),
+        Node::Unary { op, .. } => match op {
+            UnaryOp::Sin | UnaryOp::Cos => {
+                let (sin, cos) = sin_cos(args[0]);
+                res = if matches!(op, UnaryOp::Sin) { sin } else { cos };
+            }
+            UnaryOp::Not | UnaryOp::Round { .. } => {}
+        },
+        Node::Ternary { op, .. } => match op {
+            TernaryOp::MulAdd => {
+                product(args[0], args[1], &mut res);
+                res[0] = value;
+                for k in 1..=order {
+                    res[k] += args[2][k];
+                }
+            }
+            TernaryOp::Select => {
+                let taken = if args[0][0] != 0.0 { args[1] } else { args[2] };
+                res.copy_from_slice(taken);
+            }
+        },
+        Node::Custom { op, .. } => {
+            let values: Vec<_> = args.iter().map(|arg| arg[0]).collect();
+            let derivatives = op.derivatives(&values).filter(|_| order == 1);
+            let derivatives = derivatives.ok_or_else(|| GradError::NotDifferentiable {
+                node: node.id(),
+                op: op.name().to_string(),
+            })?;
+            res[1] = derivatives
+                .iter()
+                .zip(&args)
+                .map(|(d, arg)| d * arg[1])
+                .sum();
+        }
+        // Piecewise constant.
+        Node::Binary { .. } | Node::Input { .. } => {}
+        Node::Composite { .. } => unreachable!(),
+    }
+    Ok(res)
+}
+
+/// Truncated product of two series into `res`.
+fn product(a: &[f32], b: &[f32], res: &mut [f32]) {
+    for k in 0..res.len() {
+        res[k] = (0..=k).map(|j| a[j] * b[k - j]).sum();
+    }
+}
+
+fn sin_cos(u: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let mut sin = vec![u[0].sin(); u.len()];
+    let mut cos = vec![u[0].cos(); u.len()];
+    for k in 1..u.len() {
+        let k_f = k as f32;
+        sin[k] = (1..=k).map(|j| j as f32 * u[j] * cos[k - j]).sum::<f32>() / k_f;
+        cos[k] = -(1..=k).map(|j| j as f32 * u[j] * sin[k - j]).sum::<f32>() / k_f;
+    }
+    (sin, cos)
+}
+
+/// `a^b` into `res`, whose constant term is already set.
+fn power(a: &[f32], b: &[f32], res: &mut [f32]) {
+    let order = res.len() - 1;
+    let constant_exponent = b[1..].iter().all(|c| *c == 0.0);
+    if constant_exponent && a[0] != 0.0 {
+        let exponent = b[0];
+        for k in 1..=order {
+            let sum: f32 = (1..=k)
+                .map(|j| ((exponent + 1.0) * j as f32 - k as f32) * a[j] * res[k - j])
+                .sum();
+            res[k] = sum / (k as f32 * a[0]);
+        }
+        return;
+    }
+    if constant_exponent && b[0].fract() == 0.0 && b[0] >= 0.0 {
+        // `a` starts at 0, so `a^n` is `O(t^n)`: multiply out up to `order`.
+        let mut acc = vec![0.0; order + 1];
+        acc[0] = 1.0;
+        for _ in 0..(b[0] as usize).min(order + 1) {
+            let mut next = vec![0.0; order + 1];
+            product(&acc, a, &mut next);
+            acc = next;
+        }
+        res[1..].copy_from_slice(&acc[1..]);
+        return;
+    }
+
+    // `exp(b ln a)`.
+    let mut log = vec![a[0].ln(); order + 1];
+    for k in 1..=order {
+        let sum: f32 = (1..k).map(|j| j as f32 * log[j] * a[k - j]).sum();
+        log[k] = (a[k] - sum / k as f32) / a[0];
+    }
+    let mut exponent = vec![0.0; order + 1];
+    product(b, &log, &mut exponent);
+    for k in 1..=order {
+        let sum: f32 = (1..=k).map(|j| j as f32 * exponent[j] * res[k - j]).sum();
+        res[k] = sum / k as f32;
+    }
+}
+
+/// Input whose derivative from `gradients` disagrees with central differences.
+#[derive(Debug, Clone)]
+pub struct Discrepancy {
+    pub input: NodeId,
+    pub analytic: f32,
+    pub numeric: f32,
+    /// Nodes from the output down to the first one, on the way to `input`,
+    /// whose own derivatives disagree with differences of its op. Empty if
+    /// none does, e.g. when the step is too coarse for the graph.
+    pub path: Vec<NodeId>,
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "d/d{}: analytic {}, numeric {}",
+            self.input, self.analytic, self.numeric
+        )?;
+        if !self.path.is_empty() {
+            f.write_str(", path to the wrong derivative:")?;
+            for node in &self.path {
+                write!(f, " {node}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the derivative of `output` with respect to each settable input to
+/// `(f(x + eps) - f(x - eps)) / 2eps`, returning those that differ by more
+/// than `tol`, relative as in `approx_eq`. Inputs whose constraint keeps them
+/// from moving by `eps` aren't checked. Inputs are restored afterwards.
+pub fn check_gradients(
+    output: &NodeCelled,
+    eps: f32,
+    tol: f32,
+) -> Result<Vec<Discrepancy>, GradError> {
+    let adjoints = gradients(output)?;
+    let inputs: Vec<_> = Node::inputs(output)
+        .into_iter()
+        .filter(|input| {
+            !matches!(
+                &*input.borrow(),
+                Node::Input {
+                    kind: InputKind::Const,
+                    ..
+                }
+            )
+        })
+        .collect();
+
+    let mut res = Vec::new();
+    for input in &inputs {
+        let id = input.borrow().id();
+        let x = input.borrow().try_compute()?;
+        if let Some(constraint) = input.borrow().constraint() {
+            if constraint.check(x - eps).is_err() || constraint.check(x + eps).is_err() {
+                continue;
+            }
+        }
+        let analytic = adjoints.get(&id).copied().unwrap_or(0.0);
+        let numeric = central_difference(
+            eps,
+            |x| {
+                input.borrow().probe(x)?;
+                Ok::<_, GradError>(output.borrow().try_compute()?)
+            },
+            x,
+        )?;
+        if !approx_eq(analytic, numeric, tol) {
+            res.push(Discrepancy {
+                input: id,
+                analytic,
+                numeric,
+                path: culprit_path(output, input, eps, tol)?,
+            });
+        }
+    }
+
+    Ok(res)
+}
+
+/// Central difference of `f` at `x`, leaving `f` evaluated at `x`.
+fn central_difference<E>(
+    eps: f32,
+    mut f: impl FnMut(f32) -> Result<f32, E>,
+    x: f32,
+) -> Result<f32, E> {
+    let above = f(x + eps);
+    let below = f(x - eps);
+    f(x)?;
+    Ok((above? - below?) / (2.0 * eps))
+}
+
+/// Shortest path from `output` to a node between it and `input` whose local
+/// partials disagree with central differences of its op.
+fn culprit_path(
+    output: &NodeCelled,
+    input: &NodeCelled,
+    eps: f32,
+    tol: f32,
+) -> Result<Vec<NodeId>, GradError> {
+    // Nodes on some path to `input`.
+    let mut reaches = HashSet::new();
+    reaches.insert(Rc::as_ptr(input));
+    for node in Node::topo_order(output) {
+        let ptr = Rc::as_ptr(&node);
+        let children = node.borrow().children();
+        if children
+            .iter()
+            .any(|child| reaches.contains(&Rc::as_ptr(child)))
+        {
+            reaches.insert(ptr);
+        }
+    }
+
+    let mut parents: HashMap<*const _, NodeCelled> = HashMap::new();
+    let mut queue = VecDeque::from([output.clone()]);
+    let mut visited = HashSet::from([Rc::as_ptr(output)]);
+    while let Some(node) = queue.pop_front() {
+        if Rc::ptr_eq(&node, input) {
+            continue;
+        }
+        if !locally_correct(&node.borrow(), eps, tol)? {
+            let mut path = vec![node.borrow().id()];
+            let mut current = node;
+            while let Some(parent) = parents.get(&Rc::as_ptr(&current)) {
+                path.push(parent.borrow().id());
+                current = parent.clone();
+            }
+            path.reverse();
+            return Ok(path);
+        }
+
+        for child in node.borrow().children() {
+            let ptr = Rc::as_ptr(&child);
+            if reaches.contains(&ptr) && visited.insert(ptr) {
+                parents.insert(ptr, node.clone());
+                queue.push_back(child);
+            }
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// Whether `local_partials` of `node` match central differences of its op.
+fn locally_correct(node: &Node, eps: f32, tol: f32) -> Result<bool, GradError> {
+    let partials = local_partials(node)?;
+    let args = node
+        .children()
+        .iter()
+        .map(|child| child.borrow().try_compute())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (i, partial) in partials.iter().enumerate() {
+        let mut shifted = args.clone();
+        let numeric = central_difference(
+            eps,
+            |x| {
+                shifted[i] = x;
+                node.apply(&shifted).map(|(value, _)| value)
+            },
+            args[i],
+        )?;
+        if !approx_eq(*partial, numeric, tol) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::computational_graph::{Comparison, CustomOp};
+    use crate::function::Function;
+
+    /// `x²` with the derivative `x`, off by a factor of 2.
+    #[derive(Debug)]
+    struct WrongSquare;
+
+    impl CustomOp for WrongSquare {
+        fn name(&self) -> &str {
+            "wrong_square"
+        }
+
+        fn compute(&self, args: &[f32]) -> Result<f32, String> {
+            Ok(args[0] * args[0])
+        }
+
+        fn derivatives(&self, args: &[f32]) -> Option<Vec<f32>> {
+            Some(vec![args[0]])
+        }
+    }
+
+    /// `sin(x * y) + x^y`, with `x` and `y`.
+    fn graph() -> (NodeCelled, NodeCelled, NodeCelled) {
+        let x = Node::create_input(1.3f32);
+        let y = Node::create_input(0.7f32);
+        let output = Node::create_add(
+            Node::create_sin(Node::create_mul(x.clone(), y.clone())),
+            Node::create_pow(x.clone(), y.clone()),
+        );
+        (output, x, y)
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let (output, x, y) = graph();
+        output.borrow().compute();
+        let [dx, dy] = gradient(&output, &[x.clone(), y.clone()]).unwrap()[..] else {
+            unreachable!()
+        };
+        let (a, b) = (1.3f32, 0.7f32);
+        assert!(approx_eq(dx, b * (a * b).cos() + b * a.powf(b - 1.0), 1e-5));
+        assert!(approx_eq(dy, a * (a * b).cos() + a.powf(b) * a.ln(), 1e-5));
+        assert!(check_gradients(&output, 1e-2, 1e-3).unwrap().is_empty());
+        assert_eq!(x.borrow().compute(), 1.3);
+
+        // Through calls, and where checkpoints recompute what isn't cached.
+        let f = Function::define("f", 2, |p| {
+            Node::create_mul_add(p[0].clone(), p[1].clone(), Node::create_cos(p[0].clone()))
+        });
+        let call = f.call(vec![output.clone(), x.clone()]);
+        call.borrow().compute();
+        assert!(check_gradients(&call, 1e-2, 1e-3).unwrap().is_empty());
+        let checkpointed = checkpointed_gradients(&call, &[]).unwrap();
+        assert_eq!(checkpointed, gradients(&call).unwrap());
+    }
+
+    #[test]
+    fn selects_pass_on_the_branch_taken() {
+        let (output, x, y) = graph();
+        let condition = Node::create_compare(x.clone(), y.clone(), Comparison::Gt);
+        let select = Node::create_select(condition, output, Node::create_mul(x.clone(), x.clone()));
+        select.borrow().compute();
+        let taken = gradient(&select, &[x.clone(), y.clone()]).unwrap();
+        assert!(taken[1] != 0.0);
+        assert!(check_gradients(&select, 1e-2, 1e-3).unwrap().is_empty());
+
+        x.borrow().set(0.5);
+        select.borrow().compute();
+        assert_eq!(gradient(&select, &[x, y]).unwrap(), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn finds_wrong_derivatives() {
+        let x = Node::create_input(3f32);
+        let square = Node::create_custom(Arc::new(WrongSquare), vec![x.clone()]);
+        let output = Node::create_sin(square.clone());
+        output.borrow().compute();
+        let res = check_gradients(&output, 1e-3, 1e-3).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].input, x.borrow().id());
+        assert!(approx_eq(res[0].numeric, 2.0 * res[0].analytic, 1e-2));
+        assert_eq!(res[0].path, [output.borrow().id(), square.borrow().id()]);
+    }
+
+    #[test]
+    fn expands_taylor_series() {
+        let x = Node::create_input(0f32);
+        let sin = Node::create_sin(x.clone());
+        let coefficients = sin.borrow().taylor_coefficients(&x, 3).unwrap();
+        assert!(approx_eq(coefficients[1], 1.0, 1e-6));
+        assert!(approx_eq(coefficients[3], -1.0 / 6.0, 1e-6));
+        assert_eq!((coefficients[0], coefficients[2]), (0.0, 0.0));
+
+        // `x³` at 2 is `8 + 12t + 6t² + t³`.
+        x.borrow().set(2.0);
+        let cube = Node::create_pow(x.clone(), Node::create_const(3.0));
+        let coefficients = cube.borrow().taylor_coefficients(&x, 4).unwrap();
+        for (c, expected) in coefficients.iter().zip([8.0, 12.0, 6.0, 1.0, 0.0]) {
+            assert!(approx_eq(*c, expected, 1e-5), "{coefficients:?}");
+        }
+        let polynomial = cube.borrow().taylor(&x, 3).unwrap();
+        x.borrow().set(3.0);
+        assert!(approx_eq(polynomial.borrow().compute(), 27.0, 1e-5));
+
+        let square = Node::create_custom(Arc::new(WrongSquare), vec![x.clone()]);
+        let res = square.borrow().taylor_coefficients(&x, 2);
+        assert!(
+            matches!(res, Err(GradError::NotDifferentiable { op, .. }) if op == "wrong_square")
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Conservative ranges of every node's value given ranges of the inputs, by
+//! interval arithmetic without evaluating anything, so overflow and domain
+//! problems can be reported before a graph first computes.
+
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+use std::fmt;
+
+use crate::computational_graph::{
+    from_bool, BinaryOp, InputKind, Node, NodeCelled, NodeId, PowPolicy, TernaryOp, UnaryOp,
+};
+
+/// Non-NaN values from `lo` to `hi`, infinities included, and whether NaN is
+/// possible too. `lo > hi` if NaN is the only possible value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f32,
+    pub hi: f32,
+    pub nan: bool,
+}
+
+impl Interval {
+    /// Any value but NaN.
+    pub const FULL: Self = Self {
+        lo: f32::NEG_INFINITY,
+        hi: f32::INFINITY,
+        nan: false,
+    };
+
+    const EMPTY: Self = Self {
+        lo: f32::INFINITY,
+        hi: f32::NEG_INFINITY,
+        nan: false,
+    };
+
+    pub fn new(lo: f32, hi: f32) -> Self {
+        assert!(lo <= hi, "Bounds must be ordered and not NaN");
+        Self { lo, hi, nan: false }
+    }
+
+    pub fn point(x: f32) -> Self {
+        if x.is_nan() {
+            Self::EMPTY.with_nan(true)
+        } else {
+            Self::new(x, x)
+        }
+    }
+
+    fn with_nan(mut self, nan: bool) -> Self {
+        self.nan |= nan;
+        self
+    }
+
+    /// Whether NaN is the only possible value.
+    pub fn is_empty(&self) -> bool {
+        self.lo > self.hi
+    }
+
+    pub fn contains(&self, x: f32) -> bool {
+        if x.is_nan() {
+            self.nan
+        } else {
+            self.lo <= x && x <= self.hi
+        }
+    }
+
+    /// Whether no value is infinite.
+    pub fn is_finite(&self) -> bool {
+        self.is_empty() || (self.lo.is_finite() && self.hi.is_finite())
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+            nan: self.nan || other.nan,
+        }
+    }
+
+    /// The single non-NaN value, if there is exactly one.
+    fn as_point(&self) -> Option<f32> {
+        (self.lo == self.hi && !self.nan).then_some(self.lo)
+    }
+
+    /// Smallest interval holding the non-NaN `values`, with `nan` if any is
+    /// NaN.
+    fn hull(values: impl IntoIterator<Item = f32>) -> Self {
+        values
+            .into_iter()
+            .fold(Self::EMPTY, |res, x| res.union(&Self::point(x)))
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.is_empty(), self.nan) {
+            (true, _) => f.write_str("NaN"),
+            (false, false) => write!(f, "[{}, {}]", self.lo, self.hi),
+            (false, true) => write!(f, "[{}, {}] or NaN", self.lo, self.hi),
+        }
+    }
+}
+
+/// Something a node may do for inputs within their bounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoundsWarning {
+    /// Reach an infinity although its operands are finite.
+    Overflow { node: NodeId },
+    /// Yield NaN although its operands can't be NaN.
+    Nan { node: NodeId },
+    /// Fail with `EvalError::PowDomain` under its `PowPolicy`.
+    PowDomain { node: NodeId },
+}
+
+impl fmt::Display for BoundsWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow { node } => write!(f, "node {node}: may overflow"),
+            Self::Nan { node } => write!(f, "node {node}: may be NaN"),
+            Self::PowDomain { node } => write!(f, "node {node}: may raise 0 to a power <= 0"),
+        }
+    }
+}
+
+/// Result of `Node::bounds`.
+#[derive(Debug, Clone)]
+pub struct Bounds {
+    intervals: HashMap<NodeId, Interval>,
+    warnings: Vec<BoundsWarning>,
+}
+
+impl Bounds {
+    /// Range of `node`, if it was analysed. Nodes inside composites aren't
+    /// listed, their range depends on the call.
+    pub fn get(&self, node: &NodeCelled) -> Option<Interval> {
+        self.intervals.get(&node.borrow().id()).copied()
+    }
+
+    /// Problems found, in topological order. Nodes inside composites are
+    /// reported once, however often they are called.
+    pub fn warnings(&self) -> &[BoundsWarning] {
+        &self.warnings
+    }
+}
+
+impl Node {
+    /// Ranges of this node and everything it depends on when each of `inputs`
+    /// stays within its interval. Constants keep their value, other inputs may
+    /// be any non-NaN value, and custom ops anything at all. Ranges hold every
+    /// value the graph can compute, but may be wider, e.g. `x * x` over
+    /// `[-1, 1]` gives `[-1, 1]`.
+    pub fn bounds(&self, inputs: &[(NodeCelled, Interval)]) -> Bounds {
+        let seeds = inputs
+            .iter()
+            .map(|(input, interval)| (input.borrow().id(), *interval))
+            .collect();
+        let mut res = Bounds {
+            intervals: HashMap::new(),
+            warnings: Vec::new(),
+        };
+        propagate(self, &seeds, &mut res);
+        res
+    }
+}
+
+/// Fills in the intervals of `output` and its dependencies, `seeds` giving
+/// those of inputs. Returns the one of `output`.
+fn propagate(output: &Node, seeds: &HashMap<NodeId, Interval>, res: &mut Bounds) -> Interval {
+    for child in output.children() {
+        for node in Node::topo_order(&child) {
+            let node = node.borrow();
+            if !res.intervals.contains_key(&node.id()) {
+                let interval = interval(&node, seeds, res);
+                res.intervals.insert(node.id(), interval);
+            }
+        }
+    }
+    match res.intervals.get(&output.id()) {
+        Some(interval) => *interval,
+        None => {
+            let interval = interval(output, seeds, res);
+            res.intervals.insert(output.id(), interval);
+            interval
+        }
+    }
+}
+
+/// Interval of `node` from its operands' ones in `res`, noting warnings.
+fn interval(node: &Node, seeds: &HashMap<NodeId, Interval>, res: &mut Bounds) -> Interval {
+    let args: Vec<Interval> = node
+        .children()
+        .iter()
+        .map(|child| res.intervals[&child.borrow().id()])
+        .collect();
+    let mut warn = |warning| {
+        if !res.warnings.contains(&warning) {
+            res.warnings.push(warning);
+        }
+    };
+
+    let interval = match node {
+        Node::Input { x, kind, .. } => {
+            return match seeds.get(&node.id()) {
+                Some(interval) => *interval,
+                None if *kind == InputKind::Const => Interval::point(*x.borrow()),
+                None => Interval::FULL,
+            };
+        }
+        Node::Custom { .. } => return Interval::FULL.with_nan(true),
+        Node::Composite { graph, port, .. } => {
+            let seeds = graph
+                .inputs()
+                .iter()
+                .zip(&args)
+                .map(|(input, arg)| (input.borrow().id(), *arg))
+                .collect();
+            // The subgraph's intervals are for this call only.
+            let mut inner = Bounds {
+                intervals: HashMap::new(),
+                warnings: std::mem::take(&mut res.warnings),
+            };
+            let interval = propagate(&graph.outputs()[*port].borrow(), &seeds, &mut inner);
+            res.warnings = inner.warnings;
+            return interval;
+        }
+        Node::Binary { op, .. } => match op {
+            BinaryOp::Add => add(args[0], args[1]),
+            BinaryOp::Mul => mul(args[0], args[1]),
+            BinaryOp::Pow(policy) => {
+                let (interval, fails) = pow(args[0], args[1], *policy);
+                if fails {
+                    warn(BoundsWarning::PowDomain { node: node.id() });
+                }
+                interval
+            }
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Compare(_) => match (
+                args[0]
+                    .as_point()
+                    .or(args[0].is_empty().then_some(f32::NAN)),
+                args[1]
+                    .as_point()
+                    .or(args[1].is_empty().then_some(f32::NAN)),
+            ) {
+                (Some(a), Some(b)) => Interval::point(op.apply(a, b, node.id()).unwrap()),
+                _ => Interval::new(0.0, 1.0),
+            },
+        },
+        Node::Unary { op, .. } => match op {
+            UnaryOp::Sin => periodic(args[0], f32::sin, FRAC_PI_2, -FRAC_PI_2),
+            UnaryOp::Cos => periodic(args[0], f32::cos, 0.0, PI),
+            UnaryOp::Not => match truth(args[0]) {
+                (true, true) => Interval::new(0.0, 1.0),
+                (maybe_true, _) => Interval::point(from_bool(!maybe_true)),
+            },
+            UnaryOp::Round { .. } if args[0].is_empty() => args[0],
+            UnaryOp::Round { .. } => Interval {
+                lo: op.apply(args[0].lo),
+                hi: op.apply(args[0].hi),
+                nan: args[0].nan,
+            },
+        },
+        Node::Ternary { op, .. } => match op {
+            TernaryOp::MulAdd => mul_add(args[0], args[1], args[2]),
+            TernaryOp::Select => match truth(args[0]) {
+                (true, true) => args[1].union(&args[2]),
+                (true, false) => args[1],
+                (false, _) => args[2],
+            },
+        },
+    };
+
+    if interval.nan && !args.iter().any(|arg| arg.nan) {
+        warn(BoundsWarning::Nan { node: node.id() });
+    }
+    if !interval.is_finite() && args.iter().all(Interval::is_finite) {
+        warn(BoundsWarning::Overflow { node: node.id() });
+    }
+    interval
+}
+
+/// Whether a value in `interval` may be true and may be false.
+fn truth(interval: Interval) -> (bool, bool) {
+    let maybe_false = interval.contains(0.0);
+    let maybe_true =
+        interval.nan || (!interval.is_empty() && (interval.lo != 0.0 || interval.hi != 0.0));
+    (maybe_true, maybe_false)
+}
+
+/// Sums and products are monotone in each operand, so their extremes are at
+/// the corners, and rounding to nearest keeps them there. NaN only comes from
+/// `inf - inf` at a corner or `0 * inf` anywhere.
+fn add(a: Interval, b: Interval) -> Interval {
+    if a.is_empty() || b.is_empty() {
+        return Interval::EMPTY.with_nan(true);
+    }
+    Interval::hull([a.lo + b.lo, a.lo + b.hi, a.hi + b.lo, a.hi + b.hi]).with_nan(a.nan || b.nan)
+}
+
+fn mul(a: Interval, b: Interval) -> Interval {
+    if a.is_empty() || b.is_empty() {
+        return Interval::EMPTY.with_nan(true);
+    }
+    let zero_inf = |a: Interval, b: Interval| a.contains(0.0) && !b.is_finite();
+    Interval::hull([a.lo * b.lo, a.lo * b.hi, a.hi * b.lo, a.hi * b.hi])
+        .with_nan(a.nan || b.nan || zero_inf(a, b) || zero_inf(b, a))
+}
+
+/// `a * b + c` rounds once, which the corners capture as long as nothing is
+/// infinite. Otherwise it's bounded like a separate product and sum.
+fn mul_add(a: Interval, b: Interval, c: Interval) -> Interval {
+    if !(a.is_finite() && b.is_finite() && c.is_finite())
+        || a.is_empty()
+        || b.is_empty()
+        || c.is_empty()
+    {
+        return add(mul(a, b), c);
+    }
+    let corners = [a.lo, a.hi]
+        .into_iter()
+        .flat_map(|a| [b.lo, b.hi].map(move |b| (a, b)))
+        .flat_map(|(a, b)| [c.lo, c.hi].map(move |c| a.mul_add(b, c)));
+    Interval::hull(corners).with_nan(a.nan || b.nan || c.nan)
+}
+
+/// `sin` or `cos` over `x`, given where they peak at `1` and `-1` modulo `2π`.
+/// Between those they are monotone, so the extremes are at the ends unless a
+/// peak lies within.
+fn periodic(x: Interval, f: fn(f32) -> f32, max_at: f64, min_at: f64) -> Interval {
+    if x.is_empty() {
+        return x;
+    }
+    let nan = x.nan || !x.is_finite();
+    let (lo, hi) = (x.lo as f64, x.hi as f64);
+    if !x.is_finite() || hi - lo >= TAU {
+        return Interval::new(-1.0, 1.0).with_nan(nan);
+    }
+
+    // Whether `[lo, hi]` holds `at` plus a multiple of `2π`.
+    let hits = |at: f64| at + ((lo - at) / TAU).ceil() * TAU <= hi;
+    let mut res = Interval::hull([f(x.lo), f(x.hi)]);
+    if hits(max_at) {
+        res.hi = 1.0;
+    }
+    if hits(min_at) {
+        res.lo = -1.0;
+    }
+    res.with_nan(nan)
+}
+
+/// `a^b` and whether it may fail under `policy`. Over non-negative bases,
+/// `powf` is monotone in each operand, so the extremes are at the corners.
+/// Negative bases give the same magnitudes with a sign from the exponent's
+/// parity, and NaN for fractional exponents.
+fn pow(a: Interval, b: Interval, policy: PowPolicy) -> (Interval, bool) {
+    if a.is_empty() || b.is_empty() {
+        return (Interval::EMPTY.with_nan(true), false);
+    }
+    let corners = |lo: f32, hi: f32| {
+        Interval::hull([lo.powf(b.lo), lo.powf(b.hi), hi.powf(b.lo), hi.powf(b.hi)])
+    };
+
+    let mut res = Interval::EMPTY.with_nan(a.nan || b.nan);
+    if a.hi >= 0.0 {
+        res = res.union(&corners(a.lo.max(0.0), a.hi));
+    }
+    if a.lo < 0.0 {
+        let magnitude = corners((-a.hi).max(0.0), -a.lo);
+        let negative = match b.as_point() {
+            Some(n) if n.fract() == 0.0 && n % 2.0 == 0.0 => magnitude,
+            Some(n) if n.fract() == 0.0 => Interval::new(-magnitude.hi, -magnitude.lo),
+            _ => {
+                let integers = b.lo.ceil() <= b.hi.floor();
+                let values = if integers {
+                    Interval::new(-magnitude.hi, magnitude.hi)
+                } else {
+                    Interval::EMPTY
+                };
+                values.with_nan(true)
+            }
+        };
+        res = res.union(&negative);
+    }
+
+    // `0^0` and `0^negative`, where `policy` may step in.
+    let zero_nonpositive = a.contains(0.0) && b.lo <= 0.0;
+    let zero_negative = a.contains(0.0) && b.lo < 0.0;
+    let fails = match policy {
+        PowPolicy::Native | PowPolicy::Nan => false,
+        PowPolicy::Error => zero_nonpositive,
+        PowPolicy::ZeroPowZeroIsOne => zero_negative,
+    };
+    if policy == PowPolicy::Nan && zero_nonpositive {
+        res.nan = true;
+    }
+    (res, fails)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::{Comparison, RoundMode};
+    use crate::function::Function;
+
+    #[test]
+    fn propagates_intervals() {
+        let (x, y) = (Node::create_input(1f32), Node::create_input(0f32));
+        let sum = Node::create_add(x.clone(), y.clone());
+        let product = Node::create_mul(x.clone(), y.clone());
+        let sine = Node::create_sin(Node::create_mul(x.clone(), Node::create_const(2f32)));
+        let cosine = Node::create_cos(y.clone());
+        let rounded = Node::create_round(product.clone(), 1, RoundMode::HalfUp);
+        let square = Node::create_pow(y.clone(), Node::create_const(2f32));
+        let out = Node::create_add(
+            Node::create_add(sum.clone(), sine.clone()),
+            Node::create_add(
+                cosine.clone(),
+                Node::create_add(rounded.clone(), square.clone()),
+            ),
+        );
+        let bounds = out.borrow().bounds(&[
+            (x.clone(), Interval::new(1.0, 2.0)),
+            (y.clone(), Interval::new(-1.5, 3.0)),
+        ]);
+
+        let get = |node: &NodeCelled| bounds.get(node).unwrap();
+        assert_eq!(get(&sum), Interval::new(-0.5, 5.0));
+        assert_eq!(get(&product), Interval::new(-3.0, 6.0));
+        // No peak of `sin` lies within [2, 4], while `cos` peaks at 0.
+        assert_eq!(get(&sine), Interval::new(4f32.sin(), 2f32.sin()));
+        assert_eq!(get(&cosine), Interval::new(3f32.cos(), 1.0));
+        assert_eq!(get(&rounded), Interval::new(-3.0, 6.0));
+        assert_eq!(get(&square), Interval::new(0.0, 9.0));
+        assert!(bounds.warnings().is_empty());
+        assert!(get(&out).contains(out.borrow().compute()));
+    }
+
+    #[test]
+    fn keeps_constants_and_frees_other_inputs() {
+        let (x, k) = (Node::create_input(1f32), Node::create_const(3f32));
+        let out = Node::create_mul(x.clone(), k.clone());
+        let bounds = out.borrow().bounds(&[]);
+        assert_eq!(bounds.get(&k), Some(Interval::point(3.0)));
+        assert_eq!(bounds.get(&x), Some(Interval::FULL));
+        assert_eq!(bounds.get(&out), Some(Interval::FULL));
+        assert!(bounds.warnings().is_empty());
+        assert_eq!(Interval::FULL.to_string(), "[-inf, inf]");
+
+        // Finite values can overflow.
+        let bounds = out
+            .borrow()
+            .bounds(&[(x.clone(), Interval::new(0.0, f32::MAX))]);
+        let overflow = BoundsWarning::Overflow {
+            node: out.borrow().id(),
+        };
+        assert_eq!(bounds.warnings(), [overflow]);
+        assert_eq!(bounds.get(&out), Some(Interval::new(0.0, f32::INFINITY)));
+    }
+
+    #[test]
+    fn warns_about_domains() {
+        let x = Node::create_input(0.5f32);
+        let root = Node::create_pow(x.clone(), Node::create_const(0.5f32));
+        let reciprocal =
+            Node::create_strict_pow(x.clone(), Node::create_const(-1f32), PowPolicy::Error);
+        let out = Node::create_add(root.clone(), reciprocal.clone());
+
+        let bounds = out
+            .borrow()
+            .bounds(&[(x.clone(), Interval::new(0.25, 4.0))]);
+        assert_eq!(bounds.get(&root), Some(Interval::new(0.5, 2.0)));
+        assert_eq!(bounds.get(&reciprocal), Some(Interval::new(0.25, 4.0)));
+        assert!(bounds.warnings().is_empty());
+
+        let bounds = out
+            .borrow()
+            .bounds(&[(x.clone(), Interval::new(-1.0, 1.0))]);
+        let root_interval = bounds.get(&root).unwrap();
+        assert!(root_interval.nan && root_interval.contains(1.0));
+        assert_eq!(root_interval.to_string(), "[0, 1] or NaN");
+        let (root, reciprocal) = (root.borrow().id(), reciprocal.borrow().id());
+        assert_eq!(
+            bounds.warnings(),
+            [
+                BoundsWarning::Nan { node: root },
+                BoundsWarning::PowDomain { node: reciprocal },
+                BoundsWarning::Overflow { node: reciprocal },
+            ]
+        );
+        assert_eq!(
+            bounds.warnings()[1].to_string(),
+            format!("node {reciprocal}: may raise 0 to a power <= 0")
+        );
+    }
+
+    #[test]
+    fn follows_known_conditions_and_calls() {
+        let x = Node::create_input(1f32);
+        let positive = Node::create_compare(x.clone(), Node::create_const(0f32), Comparison::Gt);
+        let select = Node::create_select(positive, x.clone(), Node::create_const(-1f32));
+        let bounds = select.borrow().bounds(&[(x.clone(), Interval::point(2.0))]);
+        assert_eq!(bounds.get(&select), Some(Interval::point(2.0)));
+        // Comparisons of ranges may go either way.
+        let bounds = select
+            .borrow()
+            .bounds(&[(x.clone(), Interval::new(-1.0, 2.0))]);
+        assert_eq!(bounds.get(&select), Some(Interval::new(-1.0, 2.0)));
+
+        let double = Function::define("double", 1, |p| {
+            Node::create_mul(p[0].clone(), Node::create_const(2f32))
+        });
+        let call = double.call(vec![x.clone()]);
+        let bounds = call.borrow().bounds(&[(x, Interval::new(-1.0, 3.0))]);
+        assert_eq!(bounds.get(&call), Some(Interval::new(-2.0, 6.0)));
+    }
+}
@@ -0,0 +1,117 @@
+//! Caching only selected nodes of huge graphs and recomputing the rest, to
+//! trade compute for memory.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::computational_graph::{CachePolicy, Node, NodeCelled};
+
+impl Node {
+    /// Non-input nodes below `output` read by more than one node. With these
+    /// as checkpoints, every recomputed node has a single reader, so a
+    /// computation still evaluates each node at most once.
+    pub fn shared(output: &NodeCelled) -> Vec<NodeCelled> {
+        let order = Self::topo_order(output);
+        let mut readers: HashMap<*const _, usize> = HashMap::new();
+        for node in &order {
+            for child in node.borrow().children() {
+                *readers.entry(Rc::as_ptr(&child)).or_default() += 1;
+            }
+        }
+        order
+            .into_iter()
+            .filter(|node| {
+                readers.get(&Rc::as_ptr(node)).copied().unwrap_or(0) > 1
+                    && !matches!(&*node.borrow(), Self::Input { .. })
+            })
+            .collect()
+    }
+
+    /// Makes every `Cache` node below `output` `Recompute`, except `output`
+    /// itself and `checkpoints`, which go back to `Cache`. `NoCache` and
+    /// `Sticky` nodes are left as they are. Without checkpoints below it, a
+    /// node read by several others is recomputed for each of them, see
+    /// `Node::shared`. Under `Tracking::Pull`, checking whether a checkpoint
+    /// is current recomputes the nodes between it and the checkpoints below.
+    pub fn checkpoint(output: &NodeCelled, checkpoints: &[NodeCelled]) {
+        let kept: HashSet<_> = checkpoints.iter().chain([output]).map(Rc::as_ptr).collect();
+        for node in Self::topo_order(output) {
+            let node_ref = node.borrow();
+            let recomputable = matches!(
+                node_ref.cache_policy(),
+                CachePolicy::Cache | CachePolicy::Recompute
+            );
+            if !recomputable || matches!(&*node_ref, Self::Input { .. }) {
+                continue;
+            }
+            node_ref.set_cache_policy(if kept.contains(&Rc::as_ptr(&node)) {
+                CachePolicy::Cache
+            } else {
+                CachePolicy::Recompute
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpointing_again_passes_changes_on() {
+        let x = Node::create_input(1f32);
+        let a = Node::create_sin(x.clone());
+        let out = Node::create_mul(a.clone(), Node::create_cos(a.clone()));
+        let expected = |x: f32| x.sin() * x.sin().cos();
+
+        Node::checkpoint(&out, &[]);
+        assert_eq!(a.borrow().cache_policy(), CachePolicy::Recompute);
+        assert_eq!(out.borrow().compute(), expected(1f32));
+        Node::checkpoint(&out, std::slice::from_ref(&a));
+        assert_eq!(a.borrow().cache_policy(), CachePolicy::Cache);
+        x.borrow().set(2f32);
+        assert_eq!(out.borrow().compute(), expected(2f32));
+        x.borrow().set(3f32);
+        assert_eq!(out.borrow().compute(), expected(3f32));
+    }
+
+    /// `x * 2 + 1` read twice by its square, and `sin x` read once.
+    fn graph() -> (NodeCelled, NodeCelled, NodeCelled) {
+        let x = Node::create_input(0.5f32);
+        let shared = Node::create_add(
+            Node::create_mul(x.clone(), Node::create_input(2f32)),
+            Node::create_input(1f32),
+        );
+        let square = Node::create_mul(shared.clone(), shared.clone());
+        let out = Node::create_add(square, Node::create_sin(x.clone()));
+        (x, shared, out)
+    }
+
+    #[test]
+    fn finds_nodes_read_more_than_once() {
+        let (x, shared, out) = graph();
+        let found = Node::shared(&out);
+        assert_eq!(found.len(), 1);
+        assert!(Rc::ptr_eq(&found[0], &shared));
+        assert!(!found.iter().any(|node| Rc::ptr_eq(node, &x)));
+    }
+
+    #[test]
+    fn recomputes_what_caching_computes() {
+        let (x, _, out) = graph();
+        let (cached_x, _, cached) = graph();
+        Node::checkpoint(&out, &Node::shared(&out));
+        for value in [0.5f32, -1.25, 3.0, 3.0, 0.0] {
+            x.borrow().set(value);
+            cached_x.borrow().set(value);
+            assert_eq!(out.borrow().compute(), cached.borrow().compute());
+        }
+
+        Node::checkpoint(&out, &[]);
+        for value in [2.0f32, -0.75] {
+            x.borrow().set(value);
+            cached_x.borrow().set(value);
+            assert_eq!(out.borrow().compute(), cached.borrow().compute());
+        }
+    }
+}
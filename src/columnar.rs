@@ -0,0 +1,366 @@
+//! Named columns of values, evaluated as a whole by compiled graphs, for
+//! exchanging batches with dataframe tooling.
+//!
+//! A `ColumnBatch` holds what an Arrow record batch of `Float32` columns
+//! does: each column is one contiguous buffer, and nulls are NaN. With the
+//! `arrow` feature, record batches are `Columns` and convert to and from
+//! batches, see `arrow`; with `parquet`, batches are read from and written
+//! to Parquet files, see `parquet`; with `polars`, `ColumnExpr`s are Polars
+//! expressions and data frames are `Columns`, see `polars`. Implementing
+//! `Columns` for another dataframe type lets `ColumnExpr`s compute new
+//! columns from it too.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::compiled::CompiledGraph;
+use crate::computational_graph::{EvalError, Node};
+use crate::parser::{ParseError, Parser};
+
+#[derive(Debug, Clone)]
+pub enum ColumnError {
+    /// A column whose length differs from the batch's.
+    Length {
+        column: String,
+        expected: usize,
+        found: usize,
+    },
+    Duplicate(String),
+    Unknown(String),
+    Eval(EvalError),
+}
+
+impl fmt::Display for ColumnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length {
+                column,
+                expected,
+                found,
+            } => write!(
+                f,
+                "column \"{column}\" has {found} values, expected {expected}"
+            ),
+            Self::Duplicate(column) => write!(f, "column \"{column}\" already exists"),
+            Self::Unknown(column) => write!(f, "no column \"{column}\""),
+            Self::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ColumnError {}
+
+impl From<EvalError> for ColumnError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+/// Named columns of equal length, such as a dataframe's.
+pub trait Columns {
+    /// Number of rows.
+    fn rows(&self) -> usize;
+
+    /// The column `name` as `f32`s, converted if stored otherwise.
+    fn column(&self, name: &str) -> Option<Cow<'_, [f32]>>;
+}
+
+/// A compiled graph over named columns, computing a new one, like a
+/// dataframe library's column expressions.
+#[derive(Debug, Clone)]
+pub struct ColumnExpr {
+    graph: CompiledGraph,
+    inputs: Vec<String>,
+    name: String,
+}
+
+impl ColumnExpr {
+    /// `graph`'s inputs read from the columns named `inputs`, in the order it
+    /// was compiled with. The result is named `output` unless renamed.
+    pub fn new(graph: CompiledGraph, inputs: &[&str]) -> Self {
+        assert_eq!(
+            inputs.len(),
+            graph.input_count(),
+            "Wrong number of input columns"
+        );
+        Self {
+            graph,
+            inputs: inputs.iter().map(|name| name.to_string()).collect(),
+            name: "output".to_string(),
+        }
+    }
+
+    /// `text` with each of `columns` as a variable. Only the columns the
+    /// formula uses are read.
+    pub fn parse(parser: Parser, text: &str, columns: &[&str]) -> Result<Self, ParseError> {
+        let nodes: Vec<_> = columns.iter().map(|_| Node::create_input(0.0)).collect();
+        let parser = columns
+            .iter()
+            .zip(&nodes)
+            .fold(parser, |parser, (name, node)| {
+                parser.with_variable(name, node.clone())
+            });
+        let output = parser.parse(text)?;
+
+        let used: HashSet<_> = Node::inputs(&output).iter().map(Rc::as_ptr).collect();
+        let (inputs, nodes): (Vec<_>, Vec<_>) = columns
+            .iter()
+            .zip(nodes)
+            .filter(|(_, node)| used.contains(&Rc::as_ptr(node)))
+            .unzip();
+        Ok(Self::new(CompiledGraph::compile(&output, &nodes), &inputs).with_name(text))
+    }
+
+    /// Names the computed column.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The columns read, in the graph's input order.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub fn graph(&self) -> &CompiledGraph {
+        &self.graph
+    }
+
+    /// The computed column, one value per row of `frame`.
+    pub fn apply(&self, frame: &impl Columns) -> Result<Vec<f32>, ColumnError> {
+        let columns = self
+            .inputs
+            .iter()
+            .map(|name| {
+                frame
+                    .column(name)
+                    .ok_or_else(|| ColumnError::Unknown(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let rows = frame.rows();
+        for (name, column) in self.inputs.iter().zip(&columns) {
+            if column.len() != rows {
+                return Err(ColumnError::Length {
+                    column: name.clone(),
+                    expected: rows,
+                    found: column.len(),
+                });
+            }
+        }
+        if columns.is_empty() {
+            // No column to take the row count from.
+            return Ok(vec![self.graph.eval(&[])?; rows]);
+        }
+        let columns: Vec<&[f32]> = columns.iter().map(|column| &column[..]).collect();
+        Ok(self.graph.eval_columns(&columns)?)
+    }
+}
+
+/// Columns of equal length, in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnBatch {
+    names: Vec<String>,
+    columns: Vec<Vec<f32>>,
+}
+
+impl ColumnBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_column(
+        mut self,
+        name: impl Into<String>,
+        values: Vec<f32>,
+    ) -> Result<Self, ColumnError> {
+        self.push_column(name, values)?;
+        Ok(self)
+    }
+
+    /// Adds a column. The first one sets the batch's length.
+    pub fn push_column(
+        &mut self,
+        name: impl Into<String>,
+        values: Vec<f32>,
+    ) -> Result<(), ColumnError> {
+        let name = name.into();
+        if self.names.contains(&name) {
+            return Err(ColumnError::Duplicate(name));
+        }
+        if !self.columns.is_empty() && values.len() != self.len() {
+            return Err(ColumnError::Length {
+                column: name,
+                expected: self.len(),
+                found: values.len(),
+            });
+        }
+        self.names.push(name);
+        self.columns.push(values);
+        Ok(())
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn column(&self, name: &str) -> Option<&[f32]> {
+        let i = self.names.iter().position(|column| column == name)?;
+        Some(&self.columns[i])
+    }
+
+    /// Removes and returns a column, e.g. to hand its buffer on.
+    pub fn take_column(&mut self, name: &str) -> Option<Vec<f32>> {
+        let i = self.names.iter().position(|column| column == name)?;
+        self.names.remove(i);
+        Some(self.columns.remove(i))
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.columns.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `graph` over every row, its inputs read from the columns named
+    /// `inputs`, in the order it was compiled with.
+    pub fn evaluate(
+        &self,
+        graph: &CompiledGraph,
+        inputs: &[&str],
+    ) -> Result<Vec<f32>, ColumnError> {
+        ColumnExpr::new(graph.clone(), inputs).apply(self)
+    }
+
+    /// `evaluate`, adding the result as the column `output`.
+    pub fn append(
+        &mut self,
+        graph: &CompiledGraph,
+        inputs: &[&str],
+        output: impl Into<String>,
+    ) -> Result<(), ColumnError> {
+        let values = self.evaluate(graph, inputs)?;
+        self.push_column(output, values)
+    }
+
+    /// Adds `expr`'s column, under its name.
+    pub fn with_expr(mut self, expr: &ColumnExpr) -> Result<Self, ColumnError> {
+        let values = expr.apply(&self)?;
+        self.push_column(expr.name(), values)?;
+        Ok(self)
+    }
+}
+
+impl Columns for ColumnBatch {
+    fn rows(&self) -> usize {
+        self.len()
+    }
+
+    fn column(&self, name: &str) -> Option<Cow<'_, [f32]>> {
+        ColumnBatch::column(self, name).map(Cow::Borrowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch() -> ColumnBatch {
+        ColumnBatch::new()
+            .with_column("price", vec![10.0, 20.0, 30.0])
+            .unwrap()
+            .with_column("quantity", vec![1.0, 2.0, 3.0])
+            .unwrap()
+    }
+
+    #[test]
+    fn holds_columns_of_one_length() {
+        let mut batch = batch();
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.names(), ["price", "quantity"]);
+        let res = batch.push_column("price", vec![0.0; 3]);
+        assert!(matches!(res, Err(ColumnError::Duplicate(name)) if name == "price"));
+        let res = batch.push_column("short", vec![0.0; 2]);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "column \"short\" has 2 values, expected 3"
+        );
+
+        assert_eq!(batch.take_column("price"), Some(vec![10.0, 20.0, 30.0]));
+        assert_eq!(batch.names(), ["quantity"]);
+        assert_eq!(batch.take_column("price"), None);
+        assert!(ColumnBatch::new().is_empty());
+    }
+
+    #[test]
+    fn computes_columns_from_formulas() {
+        let expr = ColumnExpr::parse(
+            Parser::new(),
+            "price * quantity + 1",
+            &["quantity", "price", "unused"],
+        )
+        .unwrap();
+        assert_eq!(expr.name(), "price * quantity + 1");
+        assert_eq!(expr.inputs(), ["quantity", "price"]);
+        let batch = batch().with_expr(&expr.with_name("total")).unwrap();
+        assert_eq!(batch.column("total"), Some(&[11.0, 41.0, 91.0][..]));
+
+        let constant = ColumnExpr::parse(Parser::new(), "2 * 3", &[]).unwrap();
+        assert_eq!(constant.apply(&batch).unwrap(), [6.0; 3]);
+        let missing = ColumnExpr::parse(Parser::new(), "tax * 2", &["tax"]).unwrap();
+        let error = missing.apply(&batch).unwrap_err();
+        assert_eq!(error.to_string(), "no column \"tax\"");
+    }
+
+    #[test]
+    fn evaluates_compiled_graphs_over_rows() {
+        let (a, b) = (Node::create_input(0f32), Node::create_input(0f32));
+        let graph = CompiledGraph::compile(&Node::create_add(a.clone(), b.clone()), &[a, b]);
+        let mut batch = batch();
+        assert_eq!(
+            batch.evaluate(&graph, &["quantity", "price"]).unwrap(),
+            [11.0, 22.0, 33.0]
+        );
+        batch.append(&graph, &["price", "price"], "double").unwrap();
+        assert_eq!(batch.column("double"), Some(&[20.0, 40.0, 60.0][..]));
+        let res = batch.append(&graph, &["price", "price"], "double");
+        assert!(matches!(res, Err(ColumnError::Duplicate(_))));
+    }
+
+    /// Columns of which one is short, as a foreign frame might report.
+    struct Ragged;
+
+    impl Columns for Ragged {
+        fn rows(&self) -> usize {
+            2
+        }
+
+        fn column(&self, name: &str) -> Option<Cow<'_, [f32]>> {
+            match name {
+                "x" => Some(Cow::Owned(vec![1.0])),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn checks_foreign_column_lengths() {
+        let expr = ColumnExpr::parse(Parser::new(), "x + 1", &["x"]).unwrap();
+        assert!(matches!(
+            expr.apply(&Ragged),
+            Err(ColumnError::Length {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,492 @@
+//! Flat instruction tape for evaluating one graph over many input rows.
+//!
+//! Batches are processed `LANES` rows at a time: every instruction runs over a
+//! fixed-size lane array, which the compiler turns into packed SIMD for the
+//! elementwise ops. Rows that don't fill a whole chunk take the scalar path.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::computational_graph::{
+    apply_custom, BinaryOp, CustomOp, EvalError, Node, NodeCelled, NodeId, Subgraph, TernaryOp,
+    UnaryOp,
+};
+
+/// Rows evaluated per instruction on the batch path.
+pub const LANES: usize = 8;
+
+type Lanes = [f32; LANES];
+
+#[derive(Debug, Clone)]
+pub(crate) enum Instr {
+    Input(usize),
+    Const(f32),
+    Unary(UnaryOp, usize),
+    Binary(BinaryOp, usize, usize),
+    Ternary(TernaryOp, usize, usize, usize),
+    Custom(Arc<dyn CustomOp>, Vec<usize>),
+}
+
+impl Instr {
+    pub(crate) fn eval(
+        &self,
+        id: NodeId,
+        inputs: &[f32],
+        slot: impl Fn(usize) -> f32,
+    ) -> Result<f32, EvalError> {
+        Ok(match self {
+            Self::Input(i) => inputs[*i],
+            Self::Const(x) => *x,
+            Self::Unary(op, x) => op.apply(slot(*x)),
+            Self::Binary(op, a, b) => op.apply(slot(*a), slot(*b), id)?,
+            Self::Ternary(op, a, b, c) => op.apply(slot(*a), slot(*b), slot(*c)),
+            Self::Custom(op, args) => {
+                let args: Vec<_> = args.iter().map(|arg| slot(*arg)).collect();
+                apply_custom(op.as_ref(), &args, id)?
+            }
+        })
+    }
+
+    /// Slots read by this instruction.
+    pub(crate) fn operands(&self) -> Vec<usize> {
+        match self {
+            Self::Input(_) | Self::Const(_) => Vec::new(),
+            Self::Unary(_, x) => vec![*x],
+            Self::Binary(_, a, b) => vec![*a, *b],
+            Self::Ternary(_, a, b, c) => vec![*a, *b, *c],
+            Self::Custom(_, args) => args.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tape {
+    instrs: Vec<Instr>,
+    ids: Vec<NodeId>,
+    /// Slots of inlined subgraphs by argument slots, shared by all ports.
+    inlined: HashMap<(*const Subgraph, Vec<usize>), HashMap<*const (), usize>>,
+}
+
+impl Tape {
+    fn emit(
+        &mut self,
+        node: &NodeCelled,
+        declared: &HashMap<*const (), usize>,
+        slots: &mut HashMap<*const (), usize>,
+    ) -> usize {
+        let key = Rc::as_ptr(node) as *const ();
+        if let Some(&slot) = slots.get(&key) {
+            return slot;
+        }
+
+        if let Node::Composite {
+            graph, port, args, ..
+        } = &*node.borrow()
+        {
+            let args = self.emit_all(&args.iter().collect::<Vec<_>>(), declared, slots);
+            let mut inner = self
+                .inlined
+                .remove(&(Rc::as_ptr(graph), args.clone()))
+                .unwrap_or_else(|| {
+                    graph
+                        .inputs()
+                        .iter()
+                        .zip(&args)
+                        .map(|(input, arg)| (Rc::as_ptr(input) as *const (), *arg))
+                        .collect()
+                });
+            let slot = self.emit(&graph.outputs()[*port], declared, &mut inner);
+            self.inlined.insert((Rc::as_ptr(graph), args), inner);
+            slots.insert(key, slot);
+            return slot;
+        }
+
+        let instr = match &*node.borrow() {
+            Node::Input { x, .. } => match declared.get(&key) {
+                Some(&i) => Instr::Input(i),
+                None => Instr::Const(*x.borrow()),
+            },
+            Node::Unary { op, x, .. } => Instr::Unary(op.clone(), self.emit(x, declared, slots)),
+            Node::Binary { op, a, b, .. } => {
+                let operands = self.emit_all(&[a, b], declared, slots);
+                Instr::Binary(op.clone(), operands[0], operands[1])
+            }
+            Node::Ternary { op, a, b, c, .. } => {
+                let operands = self.emit_all(&[a, b, c], declared, slots);
+                Instr::Ternary(op.clone(), operands[0], operands[1], operands[2])
+            }
+            Node::Custom { op, args, .. } => {
+                let args = self.emit_all(&args.iter().collect::<Vec<_>>(), declared, slots);
+                Instr::Custom(op.clone(), args)
+            }
+            Node::Composite { .. } => unreachable!(),
+        };
+        let slot = self.push(instr, node.borrow().id());
+        slots.insert(key, slot);
+
+        slot
+    }
+
+    /// Slots of `nodes`, emitted in `Node::eval_order`.
+    fn emit_all(
+        &mut self,
+        nodes: &[&NodeCelled],
+        declared: &HashMap<*const (), usize>,
+        slots: &mut HashMap<*const (), usize>,
+    ) -> Vec<usize> {
+        let order = Node::eval_order();
+        let mut res = vec![0; nodes.len()];
+        for i in 0..nodes.len() {
+            let i = order.operand(i, nodes.len());
+            res[i] = self.emit(nodes[i], declared, slots);
+        }
+        res
+    }
+
+    fn push(&mut self, instr: Instr, id: NodeId) -> usize {
+        self.instrs.push(instr);
+        self.ids.push(id);
+        self.instrs.len() - 1
+    }
+}
+
+/// Value buffers of evaluations.
+#[derive(Debug, Default)]
+struct Scratch {
+    slots: Vec<f32>,
+    lanes: Vec<Lanes>,
+}
+
+thread_local! {
+    static SCRATCH: Cell<Scratch> = Cell::default();
+}
+
+/// Runs `f` with this thread's buffers. They're taken for the duration, so a
+/// custom op evaluating another graph meanwhile gets fresh ones.
+fn with_scratch<T>(f: impl FnOnce(&mut Scratch) -> T) -> T {
+    let mut scratch = SCRATCH.with(Cell::take);
+    let res = f(&mut scratch);
+    SCRATCH.with(|cell| cell.set(scratch));
+    res
+}
+
+/// Snapshot of a graph's topology. Instruction `i` writes slot `i`, the last
+/// one is the output. Later edits to the source graph are not reflected.
+#[derive(Debug, Clone)]
+pub struct CompiledGraph {
+    pub(crate) instrs: Arc<[Instr]>,
+    pub(crate) ids: Arc<[NodeId]>,
+    input_count: usize,
+}
+
+impl CompiledGraph {
+    /// Row values are matched to `inputs` by position. Any other `Input` met in
+    /// the graph is baked in as a constant holding its current value.
+    pub fn compile(output: &NodeCelled, inputs: &[NodeCelled]) -> Self {
+        let mut tape = Tape::default();
+        let declared: HashMap<_, _> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| (Rc::as_ptr(input) as *const (), i))
+            .collect();
+        tape.emit(output, &declared, &mut HashMap::new());
+
+        Self {
+            instrs: tape.instrs.into(),
+            ids: tape.ids.into(),
+            input_count: inputs.len(),
+        }
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.input_count
+    }
+
+    /// Evaluates a single row. Buffers are reused across calls on the same
+    /// thread, so this doesn't allocate once warmed up.
+    pub fn eval(&self, inputs: &[f32]) -> Result<f32, EvalError> {
+        with_scratch(|scratch| self.eval_scalar(inputs, &mut scratch.slots))
+    }
+
+    /// Evaluates every row, `LANES` rows per instruction.
+    pub fn eval_batch(&self, rows: &[&[f32]]) -> Result<Vec<f32>, EvalError> {
+        with_scratch(|scratch| self.eval_rows(rows, scratch))
+    }
+
+    /// Evaluates the rows made of the `i`th value of each of `columns`, as
+    /// `eval_batch` does, for inputs stored column by column.
+    pub fn eval_columns(&self, columns: &[&[f32]]) -> Result<Vec<f32>, EvalError> {
+        with_scratch(|scratch| self.eval_column_rows(columns, scratch))
+    }
+
+    /// Handle evaluating this graph with buffers of its own.
+    pub fn evaluator(&self) -> Evaluator {
+        Evaluator {
+            graph: self.clone(),
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Fails unless `found` values, or columns, are one per input.
+    fn check_inputs(&self, found: usize) -> Result<(), EvalError> {
+        match found == self.input_count {
+            true => Ok(()),
+            false => Err(EvalError::Inputs {
+                expected: self.input_count,
+                found,
+            }),
+        }
+    }
+
+    fn eval_rows(&self, rows: &[&[f32]], scratch: &mut Scratch) -> Result<Vec<f32>, EvalError> {
+        for row in rows {
+            self.check_inputs(row.len())?;
+        }
+        let mut res = Vec::with_capacity(rows.len());
+        let lanes = &mut scratch.lanes;
+        lanes.resize(self.instrs.len(), [0f32; LANES]);
+
+        let mut chunks = rows.chunks_exact(LANES);
+        for chunk in &mut chunks {
+            self.eval_lanes(chunk, lanes)?;
+            res.extend_from_slice(&lanes[self.instrs.len() - 1]);
+        }
+
+        for row in chunks.remainder() {
+            res.push(self.eval_scalar(row, &mut scratch.slots)?);
+        }
+
+        Ok(res)
+    }
+
+    fn eval_column_rows(
+        &self,
+        columns: &[&[f32]],
+        scratch: &mut Scratch,
+    ) -> Result<Vec<f32>, EvalError> {
+        self.check_inputs(columns.len())?;
+        let len = columns.first().map_or(0, |column| column.len());
+        if let Some(column) = columns.iter().find(|column| column.len() != len) {
+            return Err(EvalError::ColumnLengths {
+                expected: len,
+                found: column.len(),
+            });
+        }
+
+        let mut res = Vec::with_capacity(len);
+        scratch.lanes.resize(self.instrs.len(), [0f32; LANES]);
+        // `LANES` rows at a time, transposed into `values`.
+        let width = self.input_count;
+        let mut values = vec![0f32; LANES * width];
+        for start in (0..len).step_by(LANES) {
+            let count = LANES.min(len - start);
+            for (j, column) in columns.iter().enumerate() {
+                for (l, x) in column[start..start + count].iter().enumerate() {
+                    values[l * width + j] = *x;
+                }
+            }
+            let rows: [&[f32]; LANES] =
+                std::array::from_fn(|l| &values[l * width..(l + 1) * width]);
+            if count == LANES {
+                self.eval_lanes(&rows, &mut scratch.lanes)?;
+                res.extend_from_slice(&scratch.lanes[self.instrs.len() - 1]);
+            } else {
+                for row in &rows[..count] {
+                    res.push(self.eval_scalar(row, &mut scratch.slots)?);
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn eval_scalar(&self, inputs: &[f32], slots: &mut Vec<f32>) -> Result<f32, EvalError> {
+        self.check_inputs(inputs.len())?;
+        slots.clear();
+
+        for (instr, id) in self.instrs.iter().zip(self.ids.iter()) {
+            let value = instr.eval(*id, inputs, |slot| slots[slot])?;
+            slots.push(value);
+        }
+
+        Ok(slots[self.instrs.len() - 1])
+    }
+
+    /// `rows` must hold one value per input, see `check_inputs`.
+    fn eval_lanes(&self, rows: &[&[f32]], lanes: &mut [Lanes]) -> Result<(), EvalError> {
+        for (i, (instr, id)) in self.instrs.iter().zip(self.ids.iter()).enumerate() {
+            let (done, rest) = lanes.split_at_mut(i);
+            let dst = &mut rest[0];
+
+            match instr {
+                Instr::Input(input) => {
+                    for (d, row) in dst.iter_mut().zip(rows) {
+                        *d = row[*input];
+                    }
+                }
+                Instr::Const(x) => *dst = [*x; LANES],
+                Instr::Unary(op, x) => map1(dst, &done[*x], |x| op.apply(x)),
+                Instr::Binary(BinaryOp::Add, a, b) => map2(dst, &done[*a], &done[*b], |a, b| a + b),
+                Instr::Binary(BinaryOp::Mul, a, b) => map2(dst, &done[*a], &done[*b], |a, b| a * b),
+                Instr::Binary(op, a, b) => {
+                    for l in 0..LANES {
+                        dst[l] = op.apply(done[*a][l], done[*b][l], *id)?;
+                    }
+                }
+                Instr::Custom(op, args) => {
+                    for l in 0..LANES {
+                        let args: Vec<_> = args.iter().map(|arg| done[*arg][l]).collect();
+                        dst[l] = apply_custom(op.as_ref(), &args, *id)?;
+                    }
+                }
+                Instr::Ternary(TernaryOp::MulAdd, a, b, c) => {
+                    map3(dst, &done[*a], &done[*b], &done[*c], f32::mul_add)
+                }
+                Instr::Ternary(op, a, b, c) => {
+                    map3(dst, &done[*a], &done[*b], &done[*c], |a, b, c| {
+                        op.apply(a, b, c)
+                    })
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[inline(always)]
+fn map1(dst: &mut Lanes, x: &Lanes, f: impl Fn(f32) -> f32) {
+    for (d, x) in dst.iter_mut().zip(x) {
+        *d = f(*x);
+    }
+}
+
+#[inline(always)]
+fn map2(dst: &mut Lanes, a: &Lanes, b: &Lanes, f: impl Fn(f32, f32) -> f32) {
+    for ((d, a), b) in dst.iter_mut().zip(a).zip(b) {
+        *d = f(*a, *b);
+    }
+}
+
+#[inline(always)]
+fn map3(dst: &mut Lanes, a: &Lanes, b: &Lanes, c: &Lanes, f: impl Fn(f32, f32, f32) -> f32) {
+    for (((d, a), b), c) in dst.iter_mut().zip(a).zip(b).zip(c) {
+        *d = f(*a, *b, *c);
+    }
+}
+
+/// Evaluates one `CompiledGraph` with buffers of its own, reused across
+/// calls, e.g. one per worker thread. Clones share the tape but not the
+/// buffers, so they're cheap to hand out to other threads.
+#[derive(Debug)]
+pub struct Evaluator {
+    graph: CompiledGraph,
+    scratch: Scratch,
+}
+
+impl Clone for Evaluator {
+    fn clone(&self) -> Self {
+        self.graph.evaluator()
+    }
+}
+
+impl Evaluator {
+    pub fn graph(&self) -> &CompiledGraph {
+        &self.graph
+    }
+
+    /// Same as `CompiledGraph::eval`.
+    pub fn eval(&mut self, inputs: &[f32]) -> Result<f32, EvalError> {
+        self.graph.eval_scalar(inputs, &mut self.scratch.slots)
+    }
+
+    /// Same as `CompiledGraph::eval_batch`.
+    pub fn eval_batch(&mut self, rows: &[&[f32]]) -> Result<Vec<f32>, EvalError> {
+        self.graph.eval_rows(rows, &mut self.scratch)
+    }
+
+    /// Same as `CompiledGraph::eval_columns`.
+    pub fn eval_columns(&mut self, columns: &[&[f32]]) -> Result<Vec<f32>, EvalError> {
+        self.graph.eval_column_rows(columns, &mut self.scratch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Comparison;
+    use crate::random::Rng;
+
+    /// `x < y ? sin(x) * y + z : x * z + y^2`, with `inputs` `x`, `y`, `z`.
+    fn graph() -> (NodeCelled, Vec<NodeCelled>) {
+        let inputs: Vec<_> = (0..3).map(|_| Node::create_input(0f32)).collect();
+        let [x, y, z] = [0, 1, 2].map(|i| inputs[i].clone());
+        let less = Node::create_compare(x.clone(), y.clone(), Comparison::Lt);
+        let taken = Node::create_add(
+            Node::create_mul(Node::create_sin(x.clone()), y.clone()),
+            z.clone(),
+        );
+        let square = Node::create_pow(y, Node::create_const(2f32));
+        let other = Node::create_mul_add(x, z, square);
+        (Node::create_select(less, taken, other), inputs)
+    }
+
+    #[test]
+    fn batches_match_single_rows() {
+        let (output, inputs) = graph();
+        let compiled = CompiledGraph::compile(&output, &inputs);
+        let rng = Rng::new(7);
+        for len in [0, 1, 7, LANES, 9, 2 * LANES + 3, 64] {
+            let values: Vec<Vec<f32>> = (0..len)
+                .map(|_| (0..3).map(|_| rng.next_f32() * 20.0 - 10.0).collect())
+                .collect();
+            let rows: Vec<&[f32]> = values.iter().map(|row| &row[..]).collect();
+            let expected: Vec<_> = rows.iter().map(|row| compiled.eval(row).unwrap()).collect();
+
+            let batch = compiled.eval_batch(&rows).unwrap();
+            assert_eq!(batch.len(), len);
+            let columns: Vec<Vec<f32>> = (0..3)
+                .map(|j| values.iter().map(|row| row[j]).collect())
+                .collect();
+            let columns: Vec<&[f32]> = columns.iter().map(|column| &column[..]).collect();
+            let by_column = compiled.eval_columns(&columns).unwrap();
+            for i in 0..len {
+                assert_eq!(
+                    batch[i].to_bits(),
+                    expected[i].to_bits(),
+                    "row {i} of {len}"
+                );
+                assert_eq!(by_column[i].to_bits(), expected[i].to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_input_counts() {
+        let (output, inputs) = graph();
+        let compiled = CompiledGraph::compile(&output, &inputs);
+        let inputs_error = |res: Result<_, EvalError>| match res {
+            Err(EvalError::Inputs { expected, found }) => (expected, found),
+            res => panic!("{res:?}"),
+        };
+        assert_eq!(inputs_error(compiled.eval(&[1.0, 2.0]).map(|_| ())), (3, 2));
+        let mut rows: Vec<&[f32]> = vec![&[1.0, 2.0, 3.0]; LANES - 1];
+        rows.push(&[1.0]);
+        assert_eq!(inputs_error(compiled.eval_batch(&rows).map(|_| ())), (3, 1));
+        let columns: [&[f32]; 2] = [&[1.0], &[2.0]];
+        let res = compiled.evaluator().eval_columns(&columns);
+        assert_eq!(inputs_error(res.map(|_| ())), (3, 2));
+
+        let columns: [&[f32]; 3] = [&[1.0, 2.0], &[1.0, 2.0], &[1.0]];
+        let res = compiled.eval_columns(&columns);
+        assert!(matches!(
+            res,
+            Err(EvalError::ColumnLengths {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
@@ -1,27 +1,365 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::constraint::{Constraint, ConstraintError};
+use crate::watch::Watch;
 
 pub type NodeCelled = Rc<RefCell<Node>>;
 
+/// Process-wide unique node identifier, used in error reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl NodeId {
+    fn next() -> Self {
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The id the next node created will get: nodes created from now on have
+    /// ids at or above it.
+    pub(crate) fn upcoming() -> Self {
+        Self(NEXT_ID.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone)]
+pub enum EvalError {
+    /// `0^0` or `0^negative` hit a strict `Pow` node whose policy rejects it.
+    PowDomain {
+        node: NodeId,
+        base: f32,
+        exponent: f32,
+    },
+    /// A `CustomOp` failed.
+    Custom {
+        node: NodeId,
+        op: String,
+        message: String,
+    },
+    /// A compiled graph got a row, or columns, without one value per input.
+    Inputs { expected: usize, found: usize },
+    /// A compiled graph got input columns of different lengths.
+    ColumnLengths { expected: usize, found: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PowDomain {
+                node,
+                base,
+                exponent,
+            } => write!(f, "node {node}: {base}^{exponent} is undefined"),
+            Self::Custom { node, op, message } => write!(f, "node {node}: {op}: {message}"),
+            Self::Inputs { expected, found } => {
+                write!(f, "{found} input values, expected {expected}")
+            }
+            Self::ColumnLengths { expected, found } => {
+                write!(f, "input columns of {found} and {expected} values")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// User-defined operation over any number of operands. Results are cached in
+/// the node like those of built-in ops.
+pub trait CustomOp: fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String>;
+
+    /// Partial derivatives of `compute` at `args`, one per operand, for
+    /// `autodiff`. `None` if the op has none.
+    fn derivatives(&self, args: &[f32]) -> Option<Vec<f32>> {
+        let _ = args;
+        None
+    }
+
+    /// Policy of new nodes of this op. Ops whose result isn't a function of
+    /// their operands alone return `NoCache`.
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy::Cache
+    }
+
+    /// What tells this op apart from others of the same name that compute
+    /// differently, e.g. a version bumped when `compute` changes, for
+    /// `Node::fingerprint` and `DiskCache`. Ops of one name and identity
+    /// share persisted results; the default is empty.
+    fn identity(&self) -> String {
+        String::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    #[default]
+    Cache,
+    /// Recompute on every `compute()`, e.g. for random or time-dependent ops.
+    /// Dependents don't cache values derived from such a node either.
+    NoCache,
+    /// Keep the cached value when inputs change, until `invalidate()` on it
+    /// or a node below, or a change of policy.
+    Sticky,
+    /// Keep no value, recompute it whenever a dependent needs it. Unlike
+    /// `NoCache`, dependents still cache, so a huge graph can keep values
+    /// only at checkpoints, see `Node::checkpoint`.
+    Recompute,
+}
+
+/// How nodes learn that their cache is out of date, see `Node::set_tracking`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tracking {
+    /// Nodes register with their operands, and changes mark everything
+    /// downstream stale. Checking a cache is O(1).
+    #[default]
+    Push,
+    /// Nodes keep no link from operands to themselves. After a change, a cache
+    /// is checked against the versions of the operands it was computed from,
+    /// so `set` costs the same at any fan-out and graphs hold no `Rc` cycles.
+    Pull,
+}
+
+/// Order in which an op's operands are evaluated, see `Node::set_eval_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvalOrder {
+    #[default]
+    LeftToRight,
+    RightToLeft,
+}
+
+impl EvalOrder {
+    /// Position of the `i`th operand evaluated, out of `len`.
+    pub(crate) fn operand(self, i: usize, len: usize) -> usize {
+        match self {
+            Self::LeftToRight => i,
+            Self::RightToLeft => len - 1 - i,
+        }
+    }
+}
+
+thread_local! {
+    static TRACKING: Cell<Tracking> = const { Cell::new(Tracking::Push) };
+    static EVAL_ORDER: Cell<EvalOrder> = const { Cell::new(EvalOrder::LeftToRight) };
+}
+
+/// Bumped by every invalidation. Caches computed before the generation their
+/// node went stale in are out of date.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Bumped by invalidations other than composites binding their ports, which
+/// only change what's inside a subgraph.
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// The current epoch. Anything computed outside subgraphs in it stays valid
+/// until it moves.
+pub(crate) fn generation() -> u64 {
+    EPOCH.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cached {
+    value: f32,
+    /// When the value was last computed or checked to be current.
+    generation: u64,
+    /// When the value last differed from the one before, i.e. its version.
+    changed_at: u64,
+}
+
+/// Result of `compute_tracked`. `volatile` values came (transitively) from a
+/// `NoCache` node, so nothing on the way up may cache them either.
+pub(crate) struct Tracked {
+    pub(crate) value: f32,
+    changed_at: u64,
+    pub(crate) volatile: bool,
+}
+
+impl From<Cached> for Tracked {
+    fn from(cached: Cached) -> Self {
+        Self {
+            value: cached.value,
+            changed_at: cached.changed_at,
+            volatile: false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct NodeData {
-    cache: RefCell<Option<f32>>,
+    id: NodeId,
+    cache: Cell<Option<Cached>>,
+    stale_since: Cell<u64>,
+    /// Some node at or below this one uses `Tracking::Pull`, so staleness
+    /// marks alone can't be trusted.
+    pulls: Cell<bool>,
+    policy: Cell<CachePolicy>,
     dependents: RefCell<Vec<NodeCelled>>,
+    name: RefCell<Option<Rc<str>>>,
+    doc: RefCell<Option<Rc<str>>>,
+    position: Cell<Option<(f32, f32)>>,
+    watches: RefCell<Vec<Watch>>,
 }
 
 impl NodeData {
-    fn clear_cache(&self) {
-        for dependent in self.dependents.borrow().iter() {
-            dependent.borrow_mut().data_mut().clear_cache();
+    fn new() -> Self {
+        Self {
+            id: NodeId::next(),
+            cache: Cell::new(None),
+            stale_since: Cell::new(0),
+            pulls: Cell::new(false),
+            policy: Cell::new(CachePolicy::Cache),
+            dependents: RefCell::new(Vec::new()),
+            name: RefCell::new(None),
+            doc: RefCell::new(None),
+            position: Cell::new(None),
+            watches: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Calls back the watches `value` meets, see `Node::watch`.
+    fn check_watches(&self, value: f32) {
+        if self.watches.borrow().is_empty() {
+            return;
+        }
+        // Collected first so callbacks may add or remove watches.
+        let met = crate::watch::check(self.id, &self.watches.borrow(), value);
+        for (callback, event) in met {
+            callback(&event);
+        }
+    }
+
+    /// The cache, unless it has been marked stale.
+    fn cached(&self) -> Option<Cached> {
+        self.cache
+            .get()
+            .filter(|cached| cached.generation >= self.stale_since.get())
+    }
+
+    /// Stores `value` computed at `generation`, keeping the version if it
+    /// didn't change.
+    fn store(&self, value: f32, generation: u64) -> Cached {
+        let changed_at = match self.cache.get() {
+            Some(old) if old.value.to_bits() == value.to_bits() => old.changed_at,
+            _ => generation,
+        };
+        let cached = Cached {
+            value,
+            generation,
+            changed_at,
+        };
+        self.cache.set(Some(cached));
+        cached
+    }
+
+    /// Marks this cache and, transitively, the dependents' ones stale.
+    /// Propagation stops at `Sticky` dependents, whose value doesn't change,
+    /// and at stale ones: whatever depends on those hasn't been recomputed
+    /// since, so it's stale already. Setting an input repeatedly between
+    /// computations therefore costs next to nothing. `Recompute` dependents
+    /// never have a cache and are passed through, each once however many
+    /// paths lead to it. `past_sticky` drops the caches of `Sticky` nodes,
+    /// this one included, and passes through them too.
+    fn invalidate(&self, past_sticky: bool) -> u64 {
+        EPOCH.fetch_add(1, Ordering::Relaxed);
+        let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_invalidation();
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Invalidated {
+            node: self.id,
+            generation,
+        });
+        self.mark_stale(generation, past_sticky);
+        generation
+    }
+
+    /// `invalidate` under a given generation, so that several nodes can be
+    /// invalidated as one change.
+    fn mark_stale(&self, generation: u64, past_sticky: bool) {
+        self.stale_since.set(generation);
+        if past_sticky && self.policy.get() == CachePolicy::Sticky {
+            self.cache.set(None);
+        }
+
+        let mut visited = HashSet::new();
+        let mut stack = self.dependents.borrow().clone();
+        while let Some(node) = stack.pop() {
+            if !visited.insert(Rc::as_ptr(&node)) {
+                continue;
+            }
+            let node = node.borrow();
+            let data = node.data();
+            let policy = data.policy.get();
+            if (policy == CachePolicy::Sticky && !past_sticky)
+                || (policy != CachePolicy::Recompute && data.cached().is_none())
+            {
+                continue;
+            }
+            if policy == CachePolicy::Sticky {
+                data.cache.set(None);
+            }
+            data.stale_since.set(generation);
+            stack.extend(data.dependents.borrow().iter().cloned());
         }
-        *self.cache.borrow_mut() = None;
     }
 }
 
-#[derive(Debug, Clone)]
+/// Dependents are listed by id, the graph is cyclic through them.
+impl fmt::Debug for NodeData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dependents: Vec<_> = self
+            .dependents
+            .borrow()
+            .iter()
+            .map(|dependent| dependent.borrow().id())
+            .collect();
+
+        f.debug_struct("NodeData")
+            .field("id", &self.id)
+            .field("cache", &self.cache.get())
+            .field("stale_since", &self.stale_since.get())
+            .field("pulls", &self.pulls.get())
+            .field("policy", &self.policy.get())
+            .field("dependents", &dependents)
+            .field("name", &self.name.borrow())
+            .field("doc", &self.doc.borrow())
+            .field("position", &self.position.get())
+            .finish()
+    }
+}
+
+#[derive(Clone)]
 pub enum Node {
     Input {
         x: RefCell<f32>,
+        kind: InputKind,
+        /// Provider read by `refresh`, for inputs bound to a data source.
+        source: RefCell<Option<Rc<dyn Fn() -> f32>>>,
+        /// Node a `Delay` samples on `step`. Not an operand, so recurrences
+        /// don't make the graph cyclic.
+        feed: RefCell<Option<NodeCelled>>,
+        /// Values `set` accepts.
+        constraint: RefCell<Option<Rc<Constraint>>>,
+        /// Value `reset_inputs` restores.
+        default: Cell<f32>,
         data: NodeData,
     },
     Binary {
@@ -35,28 +373,486 @@ pub enum Node {
         x: NodeCelled,
         data: NodeData,
     },
+    Ternary {
+        op: TernaryOp,
+        a: NodeCelled,
+        b: NodeCelled,
+        c: NodeCelled,
+        data: NodeData,
+    },
+    Custom {
+        op: Arc<dyn CustomOp>,
+        args: Vec<NodeCelled>,
+        data: NodeData,
+    },
+    /// Output `port` of `graph` evaluated at `args`.
+    Composite {
+        graph: Rc<Subgraph>,
+        port: usize,
+        args: Vec<NodeCelled>,
+        data: NodeData,
+    },
+}
+
+/// Self-contained graph with declared input and output ports, used through
+/// `Node::create_composite`. One subgraph can back any number of composite
+/// nodes.
+#[derive(Debug)]
+pub struct Subgraph {
+    name: Option<String>,
+    inputs: Vec<NodeCelled>,
+    outputs: Vec<NodeCelled>,
+    /// Non-`Const` inputs the body reads other than `inputs`, directly or
+    /// through nested composites. Composite nodes depend on them too.
+    free: Vec<NodeCelled>,
+}
+
+impl Subgraph {
+    /// `inputs` must be non-`Const` `Input` nodes of the graph(s) producing
+    /// `outputs`.
+    pub fn new(inputs: Vec<NodeCelled>, outputs: Vec<NodeCelled>) -> Rc<Self> {
+        Self::create(None, inputs, outputs)
+    }
+
+    pub fn named(
+        name: impl Into<String>,
+        inputs: Vec<NodeCelled>,
+        outputs: Vec<NodeCelled>,
+    ) -> Rc<Self> {
+        Self::create(Some(name.into()), inputs, outputs)
+    }
+
+    fn create(name: Option<String>, inputs: Vec<NodeCelled>, outputs: Vec<NodeCelled>) -> Rc<Self> {
+        for input in &inputs {
+            assert!(
+                matches!(&*input.borrow(), Node::Input { kind, .. } if *kind != InputKind::Const),
+                "Subgraph inputs must be settable \"Input\" nodes"
+            );
+        }
+
+        let ports: HashSet<_> = inputs.iter().map(Rc::as_ptr).collect();
+        let mut seen = HashSet::new();
+        let mut free = Vec::new();
+        for output in &outputs {
+            for node in Node::topo_order(output) {
+                let read = match &*node.borrow() {
+                    Node::Input { kind, .. } if *kind != InputKind::Const => vec![node.clone()],
+                    Node::Composite { graph, .. } => graph.free.clone(),
+                    _ => Vec::new(),
+                };
+                for input in read {
+                    let key = Rc::as_ptr(&input);
+                    if !ports.contains(&key) && seen.insert(key) {
+                        free.push(input);
+                    }
+                }
+            }
+        }
+
+        Rc::new(Self {
+            name,
+            inputs,
+            outputs,
+            free,
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn inputs(&self) -> &[NodeCelled] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[NodeCelled] {
+        &self.outputs
+    }
+
+    /// Ports are only set when their value changes, so evaluating another
+    /// output at the same arguments reuses the subgraph's caches.
+    fn compute_tracked(&self, args: &[f32], port: usize) -> Result<Tracked, EvalError> {
+        for (input, arg) in self.inputs.iter().zip(args) {
+            input.borrow().bind_port(*arg);
+        }
+
+        self.outputs[port].borrow().compute_tracked()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Value,
+    /// Wall-clock or simulation time, moved by `advance_time`.
+    Time,
+    /// Fixed value, rejects `set`. Rewrites may fold and compare these.
+    Const,
+    /// Value its feed had at the last `step`, see `Node::create_delay`.
+    Delay,
+    /// Aggregate of the values its feed had at each `step`, see
+    /// `Node::create_accumulator`.
+    Accumulate(Accumulation),
+}
+
+/// How an accumulator folds in its feed's value on each `step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accumulation {
+    Sum,
+    Min,
+    Max,
+    /// Steps at which the feed was true.
+    Count,
+}
+
+impl Accumulation {
+    /// Value before the first `step` and after `reset`.
+    pub fn identity(self) -> f32 {
+        match self {
+            Self::Sum | Self::Count => 0.0,
+            Self::Min => f32::INFINITY,
+            Self::Max => f32::NEG_INFINITY,
+        }
+    }
+
+    pub(crate) fn fold(self, acc: f32, x: f32) -> f32 {
+        match self {
+            Self::Sum => acc + x,
+            Self::Min => acc.min(x),
+            Self::Max => acc.max(x),
+            Self::Count => acc + from_bool(truthy(x)),
+        }
+    }
 }
 
+/// Logical ops and `Select` read operands as booleans, where any nonzero value
+/// (NaN included) is true. Logical ops and comparisons yield `1` or `0`.
 #[derive(Debug, Clone)]
 pub enum BinaryOp {
     Add,
     Mul,
-    Pow,
+    Pow(PowPolicy),
+    And,
+    Or,
+    Compare(Comparison),
 }
 
-#[derive(Debug, Clone)]
+/// How `Pow` treats `0^0` and `0^negative`, where `powf` yields `1` and `inf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowPolicy {
+    /// Plain `powf`, no checks.
+    #[default]
+    Native,
+    /// Both cases fail with `EvalError::PowDomain`.
+    Error,
+    /// Both cases evaluate to NaN.
+    Nan,
+    /// `0^0` is `1`, `0^negative` fails with `EvalError::PowDomain`.
+    ZeroPowZeroIsOne,
+}
+
+/// Comparisons follow IEEE 754: anything involving NaN is false, except `Ne`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOp {
     Sin,
+    Cos,
+    Not,
+    /// Rounds to `digits` decimal places (negative ones round to tens,
+    /// hundreds, ...). The shortest decimal form of the operand is rounded,
+    /// so `2.675` becomes `2.68` although its `f32` is slightly less.
+    Round {
+        digits: i32,
+        mode: RoundMode,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundMode {
+    /// Halves away from zero.
+    #[default]
+    HalfUp,
+    /// Halves to the even neighbour, as in banking.
+    HalfEven,
+    /// Toward zero.
+    Trunc,
+}
+
+#[derive(Debug, Clone)]
+pub enum TernaryOp {
+    /// `a * b + c` with a single rounding.
+    MulAdd,
+    /// `b` if `a` is true, else `c`. Both branches are evaluated.
+    Select,
+}
+
+impl BinaryOp {
+    pub(crate) fn apply(&self, a: f32, b: f32, node: NodeId) -> Result<f32, EvalError> {
+        Ok(match self {
+            Self::Add => a + b,
+            Self::Mul => a * b,
+            Self::Pow(policy) => pow(*policy, a, b, node)?,
+            Self::And => from_bool(truthy(a) && truthy(b)),
+            Self::Or => from_bool(truthy(a) || truthy(b)),
+            Self::Compare(cmp) => from_bool(cmp.apply(a, b)),
+        })
+    }
+}
+
+impl Comparison {
+    pub(crate) fn apply<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Self::Lt => a < b,
+            Self::Le => a <= b,
+            Self::Gt => a > b,
+            Self::Ge => a >= b,
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+        }
+    }
+}
+
+impl UnaryOp {
+    pub(crate) fn apply(&self, x: f32) -> f32 {
+        match self {
+            Self::Sin => x.sin(),
+            Self::Cos => x.cos(),
+            Self::Not => from_bool(!truthy(x)),
+            Self::Round { digits, mode } => round(x, *digits, *mode),
+        }
+    }
+}
+
+impl RoundMode {
+    /// `x` rounded to a multiple of `unit`, `None` on overflow.
+    pub(crate) fn round_int(self, x: i128, unit: i128) -> Option<i128> {
+        let (quotient, remainder) = (x / unit, x % unit);
+        let twice = remainder.abs() * 2;
+        let away = match self {
+            Self::HalfUp => twice >= unit,
+            Self::HalfEven => twice > unit || (twice == unit && quotient % 2 != 0),
+            Self::Trunc => false,
+        };
+        let quotient = if away {
+            quotient + x.signum()
+        } else {
+            quotient
+        };
+
+        quotient.checked_mul(unit)
+    }
+}
+
+impl TernaryOp {
+    pub(crate) fn apply(&self, a: f32, b: f32, c: f32) -> f32 {
+        match self {
+            Self::MulAdd => a.mul_add(b, c),
+            Self::Select => {
+                if truthy(a) {
+                    b
+                } else {
+                    c
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Mul => "*",
+            Self::Pow(_) => "^",
+            Self::And => "&&",
+            Self::Or => "||",
+            Self::Compare(cmp) => return cmp.fmt(f),
+        })
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+        })
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sin => "sin",
+            Self::Cos => "cos",
+            Self::Not => "not",
+            Self::Round { .. } => "round",
+        })
+    }
+}
+
+impl fmt::Display for TernaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MulAdd => "mul_add",
+            Self::Select => "select",
+        })
+    }
+}
+
+/// Operands are shown by id, so this stays small on any graph.
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids = |nodes: &[NodeCelled]| -> Vec<NodeId> {
+            nodes.iter().map(|node| node.borrow().id()).collect()
+        };
+
+        match self {
+            Self::Input {
+                x,
+                kind,
+                source,
+                feed,
+                constraint,
+                default,
+                data,
+            } => f
+                .debug_struct("Input")
+                .field("x", &*x.borrow())
+                .field("kind", kind)
+                .field("bound", &source.borrow().is_some())
+                .field(
+                    "feed",
+                    &feed.borrow().as_ref().map(|feed| feed.borrow().id()),
+                )
+                .field("constraint", &*constraint.borrow())
+                .field("default", &default.get())
+                .field("data", data)
+                .finish(),
+            Self::Binary { op, data, .. } => f
+                .debug_struct("Binary")
+                .field("op", op)
+                .field("args", &ids(&self.children()))
+                .field("data", data)
+                .finish(),
+            Self::Unary { op, data, .. } => f
+                .debug_struct("Unary")
+                .field("op", op)
+                .field("args", &ids(&self.children()))
+                .field("data", data)
+                .finish(),
+            Self::Ternary { op, data, .. } => f
+                .debug_struct("Ternary")
+                .field("op", op)
+                .field("args", &ids(&self.children()))
+                .field("data", data)
+                .finish(),
+            Self::Custom { op, args, data } => f
+                .debug_struct("Custom")
+                .field("op", &op.name())
+                .field("args", &ids(args))
+                .field("data", data)
+                .finish(),
+            Self::Composite {
+                graph,
+                port,
+                args,
+                data,
+            } => f
+                .debug_struct("Composite")
+                .field("graph", &graph.name())
+                .field("port", port)
+                .field("args", &ids(args))
+                .field("data", data)
+                .finish(),
+        }
+    }
+}
+
+/// Infix expression such as `(x0 + (x1 * sin(x1)))`. Inputs print as `x<id>`,
+/// time nodes as `t<id>`, constants as their value. A precision limits the
+/// depth, e.g. `{:.2}` prints `...` for anything deeper than two levels, since
+/// shared subgraphs are printed at every use.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_expr(f, f.precision())
+    }
 }
 
 impl Node {
     pub fn create_input(x: f32) -> NodeCelled {
+        Self::create_input_node(InputKind::Value, x, None)
+    }
+
+    pub fn create_time(t: f32) -> NodeCelled {
+        Self::create_input_node(InputKind::Time, t, None)
+    }
+
+    pub fn create_const(x: f32) -> NodeCelled {
+        Self::create_input_node(InputKind::Const, x, None)
+    }
+
+    /// Node holding `initial` until the first `step`, and after each `step` the
+    /// value its feed had just before it. Set the feed with `feed`, possibly
+    /// to a node computed from the delay itself, e.g. `y = a * x + (1 - a) * d`
+    /// fed back into `d` for an exponential moving average.
+    pub fn create_delay(initial: f32) -> NodeCelled {
+        Self::create_input_node(InputKind::Delay, initial, None)
+    }
+
+    /// Running aggregate of `source` over steps, starting from
+    /// `accumulation.identity()`. Each `step` folds in the value `source` had
+    /// just before it, so the current step isn't included until the next one.
+    pub fn create_accumulator(source: NodeCelled, accumulation: Accumulation) -> NodeCelled {
+        let node = Self::create_input_node(
+            InputKind::Accumulate(accumulation),
+            accumulation.identity(),
+            None,
+        );
+        node.borrow().feed(source);
+        node
+    }
+
+    /// Input reading its value from `source`, now and on every `refresh`.
+    pub fn create_bound_input(source: impl Fn() -> f32 + 'static) -> NodeCelled {
+        let x = source();
+        Self::create_input_node(InputKind::Value, x, Some(Rc::new(source)))
+    }
+
+    pub(crate) fn create_input_node(
+        kind: InputKind,
+        x: f32,
+        source: Option<Rc<dyn Fn() -> f32>>,
+    ) -> NodeCelled {
+        let data = NodeData::new();
+        data.store(x, GENERATION.load(Ordering::Relaxed));
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Created {
+            node: data.id,
+            op: "input".to_string(),
+            operands: Vec::new(),
+        });
+
         Rc::new(RefCell::new(Self::Input {
             x: RefCell::new(x),
-            data: NodeData {
-                cache: RefCell::new(Some(x)),
-                dependents: RefCell::new(Vec::new()),
-            },
+            kind,
+            source: RefCell::new(source),
+            feed: RefCell::new(None),
+            constraint: RefCell::new(None),
+            default: Cell::new(x),
+            data,
         }))
     }
 
@@ -72,100 +868,1210 @@ impl Node {
         Self::create_unary_node(UnaryOp::Sin, x)
     }
 
+    pub fn create_cos(x: NodeCelled) -> NodeCelled {
+        Self::create_unary_node(UnaryOp::Cos, x)
+    }
+
     pub fn create_pow(a: NodeCelled, b: NodeCelled) -> NodeCelled {
-        Self::create_binary_node(BinaryOp::Pow, a, b)
+        Self::create_binary_node(BinaryOp::Pow(PowPolicy::Native), a, b)
     }
 
-    fn create_binary_node(op: BinaryOp, a: NodeCelled, b: NodeCelled) -> NodeCelled {
-        let res = Rc::new(RefCell::new(Self::Binary {
-            op,
-            a: a.clone(),
-            b: b.clone(),
-            data: NodeData {
-                cache: RefCell::new(None),
-                dependents: RefCell::new(Vec::new()),
-            },
-        }));
+    /// `Pow` that checks `0^0` and `0^negative` against `policy`.
+    pub fn create_strict_pow(a: NodeCelled, b: NodeCelled, policy: PowPolicy) -> NodeCelled {
+        Self::create_binary_node(BinaryOp::Pow(policy), a, b)
+    }
+
+    pub fn create_mul_add(a: NodeCelled, b: NodeCelled, c: NodeCelled) -> NodeCelled {
+        Self::create_ternary_node(TernaryOp::MulAdd, a, b, c)
+    }
+
+    pub fn create_and(a: NodeCelled, b: NodeCelled) -> NodeCelled {
+        Self::create_binary_node(BinaryOp::And, a, b)
+    }
 
-        a.borrow_mut().add_dependent(res.clone());
-        b.borrow_mut().add_dependent(res.clone());
+    pub fn create_or(a: NodeCelled, b: NodeCelled) -> NodeCelled {
+        Self::create_binary_node(BinaryOp::Or, a, b)
+    }
+
+    pub fn create_not(x: NodeCelled) -> NodeCelled {
+        Self::create_unary_node(UnaryOp::Not, x)
+    }
+
+    pub fn create_round(x: NodeCelled, digits: i32, mode: RoundMode) -> NodeCelled {
+        Self::create_unary_node(UnaryOp::Round { digits, mode }, x)
+    }
+
+    /// `1` if `a cmp b` holds, else `0`.
+    pub fn create_compare(a: NodeCelled, b: NodeCelled, cmp: Comparison) -> NodeCelled {
+        Self::create_binary_node(BinaryOp::Compare(cmp), a, b)
+    }
 
+    /// `then` where `condition` is true, else `otherwise`.
+    pub fn create_select(
+        condition: NodeCelled,
+        then: NodeCelled,
+        otherwise: NodeCelled,
+    ) -> NodeCelled {
+        Self::create_ternary_node(TernaryOp::Select, condition, then, otherwise)
+    }
+
+    pub fn create_custom(op: Arc<dyn CustomOp>, args: Vec<NodeCelled>) -> NodeCelled {
+        let data = NodeData::new();
+        data.policy.set(op.cache_policy());
+        Self::attach(Self::Custom { op, args, data })
+    }
+
+    /// One node per output port of `graph`, all reading `args`.
+    pub fn create_composite(graph: Rc<Subgraph>, args: Vec<NodeCelled>) -> Vec<NodeCelled> {
+        assert_eq!(
+            args.len(),
+            graph.inputs.len(),
+            "Wrong number of composite arguments"
+        );
+
+        (0..graph.outputs.len())
+            .map(|port| Self::create_composite_node(graph.clone(), port, args.clone()))
+            .collect()
+    }
+
+    pub(crate) fn create_composite_node(
+        graph: Rc<Subgraph>,
+        port: usize,
+        args: Vec<NodeCelled>,
+    ) -> NodeCelled {
+        let free = graph.free.clone();
+        let res = Self::attach(Self::Composite {
+            graph,
+            port,
+            args,
+            data: NodeData::new(),
+        });
+        if !res.borrow().data().pulls.get() {
+            for input in free {
+                input.borrow_mut().add_dependent(res.clone());
+            }
+        }
         res
     }
 
-    fn create_unary_node(op: UnaryOp, x: NodeCelled) -> NodeCelled {
-        let res = Rc::new(RefCell::new(Self::Unary {
+    pub(crate) fn create_binary_node(op: BinaryOp, a: NodeCelled, b: NodeCelled) -> NodeCelled {
+        Self::attach(Self::Binary {
             op,
-            x: x.clone(),
-            data: NodeData {
-                cache: RefCell::new(None),
-                dependents: RefCell::new(Vec::new()),
-            },
-        }));
+            a,
+            b,
+            data: NodeData::new(),
+        })
+    }
+
+    pub(crate) fn create_unary_node(op: UnaryOp, x: NodeCelled) -> NodeCelled {
+        Self::attach(Self::Unary {
+            op,
+            x,
+            data: NodeData::new(),
+        })
+    }
+
+    pub(crate) fn create_ternary_node(
+        op: TernaryOp,
+        a: NodeCelled,
+        b: NodeCelled,
+        c: NodeCelled,
+    ) -> NodeCelled {
+        Self::attach(Self::Ternary {
+            op,
+            a,
+            b,
+            c,
+            data: NodeData::new(),
+        })
+    }
 
-        x.borrow_mut().add_dependent(res.clone());
+    /// Wraps a new operation node, registering it with its operands unless
+    /// this thread uses `Tracking::Pull`.
+    fn attach(node: Self) -> NodeCelled {
+        let children = node.children();
+        let pull = TRACKING.with(Cell::get) == Tracking::Pull;
+        let pulls = pull
+            || children
+                .iter()
+                .any(|child| child.borrow().data().pulls.get());
+        node.data().pulls.set(pulls);
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Created {
+            node: node.id(),
+            op: node.op_name().to_string(),
+            operands: children.iter().map(|child| child.borrow().id()).collect(),
+        });
+
+        let res = Rc::new(RefCell::new(node));
+        if !pull {
+            for child in children {
+                child.borrow_mut().add_dependent(res.clone());
+            }
+        }
 
         res
     }
 
+    /// Tracking used by operation nodes created on this thread from now on.
+    /// Nodes keep the tracking they were created with, and graphs may mix both.
+    pub fn set_tracking(tracking: Tracking) {
+        TRACKING.with(|current| current.set(tracking));
+    }
+
+    pub fn tracking() -> Tracking {
+        TRACKING.with(Cell::get)
+    }
+
+    /// Order of operand evaluation on this thread from now on, by `compute`
+    /// and by `CompiledGraph::compile`.
+    pub fn set_eval_order(order: EvalOrder) {
+        EVAL_ORDER.with(|current| current.set(order));
+    }
+
+    pub fn eval_order() -> EvalOrder {
+        EVAL_ORDER.with(Cell::get)
+    }
+
+    /// Panics on evaluation errors, see `try_compute`.
     pub fn compute(&self) -> f32 {
-        match self {
-            Self::Input { x, .. } => *x.borrow(),
-            Self::Binary { op, a, b, data } => {
-                let cached = *data.cache.borrow();
+        self.try_compute().unwrap_or_else(|e| panic!("{e}"))
+    }
 
-                if let Some(cached) = cached {
-                    cached
-                } else {
-                    let computed = match op {
-                        BinaryOp::Add => a.borrow().compute() + b.borrow().compute(),
-                        BinaryOp::Mul => a.borrow().compute() * b.borrow().compute(),
-                        BinaryOp::Pow => a.borrow().compute().powf(b.borrow().compute()),
-                    };
-                    *data.cache.borrow_mut() = Some(computed);
+    /// Operands are evaluated depth first, each op's in `Node::eval_order`,
+    /// so random ops draw in the same order and the same error is reported
+    /// on every run. Results don't depend on the order otherwise: every op
+    /// rounds once, identically on every platform, except `sin`, `cos` and
+    /// `pow`, which come from the platform's math library.
+    pub fn try_compute(&self) -> Result<f32, EvalError> {
+        #[cfg(feature = "trace")]
+        let _span = crate::trace::enter(|| crate::trace::Span::Compute { node: self.id() });
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+        let res = self.compute_tracked().map(|tracked| tracked.value);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_evaluation(start.elapsed());
+        res
+    }
 
-                    computed
-                }
+    pub(crate) fn compute_tracked(&self) -> Result<Tracked, EvalError> {
+        let data = self.data();
+        if let Self::Input { x, .. } = self {
+            return Ok(Tracked {
+                value: *x.borrow(),
+                changed_at: data.cache.get().map_or(0, |cached| cached.changed_at),
+                volatile: data.policy.get() == CachePolicy::NoCache,
+            });
+        }
+
+        let generation = GENERATION.load(Ordering::Relaxed);
+        let cached = data.cached();
+        if let Some(cached) = cached {
+            let current = !data.pulls.get()
+                || cached.generation == generation
+                || data.policy.get() == CachePolicy::Sticky;
+            if current {
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_hit();
+                #[cfg(feature = "trace")]
+                crate::trace::event(|| crate::trace::Event::CacheHit { node: data.id });
+                return Ok(cached.into());
             }
-            Self::Unary { op, x, data } => {
-                let cached = *data.cache.borrow();
+        }
 
-                if let Some(cached) = cached {
-                    cached
-                } else {
-                    let computed = match op {
-                        UnaryOp::Sin => x.borrow().compute().sin(),
-                    };
-                    *data.cache.borrow_mut() = Some(computed);
+        let mut volatile = false;
+        let mut changed_at = 0;
+        let children = self.children();
+        let order = Self::eval_order();
+        let mut args = vec![0f32; children.len()];
+        for i in 0..children.len() {
+            let i = order.operand(i, children.len());
+            let tracked = children[i].borrow().compute_tracked()?;
+            volatile |= tracked.volatile;
+            changed_at = changed_at.max(tracked.changed_at);
+            args[i] = tracked.value;
+        }
+        // A composite also reads its body's free inputs.
+        if let Self::Composite { graph, .. } = self {
+            for input in &graph.free {
+                let tracked = input.borrow().compute_tracked()?;
+                volatile |= tracked.volatile;
+                changed_at = changed_at.max(tracked.changed_at);
+            }
+        }
 
-                    computed
-                }
+        // Pulled: still current if no operand changed since it was computed.
+        if let Some(cached) = cached {
+            if !volatile && changed_at <= cached.generation {
+                data.cache.set(Some(Cached {
+                    generation,
+                    ..cached
+                }));
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_hit();
+                #[cfg(feature = "trace")]
+                crate::trace::event(|| crate::trace::Event::CacheHit { node: data.id });
+                return Ok(cached.into());
             }
         }
+
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+        let (computed, subgraph_volatile) = self.apply(&args)?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_miss(self, start.elapsed());
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Computed {
+            node: data.id,
+            op: self.op_name().to_string(),
+            value: computed,
+        });
+        volatile |= subgraph_volatile;
+        data.check_watches(computed);
+
+        let volatile = match data.policy.get() {
+            CachePolicy::Cache | CachePolicy::Recompute => volatile,
+            CachePolicy::NoCache => true,
+            CachePolicy::Sticky => false,
+        };
+        if data.policy.get() == CachePolicy::Recompute && !volatile {
+            // The value can only have changed along with an operand.
+            return Ok(Tracked {
+                value: computed,
+                changed_at,
+                volatile,
+            });
+        }
+        if volatile {
+            return Ok(Tracked {
+                value: computed,
+                changed_at: generation,
+                volatile,
+            });
+        }
+
+        Ok(data.store(computed, generation).into())
+    }
+
+    /// This node's op applied to `args`, and whether a subgraph made the
+    /// result volatile.
+    pub(crate) fn apply(&self, args: &[f32]) -> Result<(f32, bool), EvalError> {
+        let id = self.data().id;
+        Ok(match self {
+            Self::Input { x, .. } => (*x.borrow(), false),
+            Self::Binary { op, .. } => (op.apply(args[0], args[1], id)?, false),
+            Self::Unary { op, .. } => (op.apply(args[0]), false),
+            Self::Ternary { op, .. } => (op.apply(args[0], args[1], args[2]), false),
+            Self::Custom { op, .. } => (apply_custom(op.as_ref(), args, id)?, false),
+            Self::Composite { graph, port, .. } => {
+                let tracked = graph.compute_tracked(args, *port)?;
+                (tracked.value, tracked.volatile)
+            }
+        })
     }
 
+    /// Panics on values the input's constraint rejects, see `try_set`.
     pub fn set(&self, new_value: f32) {
-        if let Self::Input { x, data } = self {
+        self.try_set(new_value).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Sets this input to `new_value` if its constraint, if any, accepts it.
+    pub fn try_set(&self, new_value: f32) -> Result<(), ConstraintError> {
+        let Self::Input { x, kind, data, .. } = self else {
+            panic!("Can only set to \"Input\"");
+        };
+        assert!(*kind != InputKind::Const, "Can't set a \"Const\"");
+        self.check_constraint(new_value)?;
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Set {
+            node: data.id,
+            old: *x.borrow(),
+            new: new_value,
+        });
+        crate::audit::record(self, crate::audit::AuditAction::Set, *x.borrow(), new_value);
+        self.restore(new_value);
+        data.check_watches(new_value);
+        Ok(())
+    }
+
+    /// `try_set` for a search of the crate's own, such as a solver trying a
+    /// value: not a change to the graph, so it isn't audited, traced or
+    /// watched. Where the search leaves the input is set with `try_set`.
+    pub(crate) fn probe(&self, new_value: f32) -> Result<(), ConstraintError> {
+        self.check_constraint(new_value)?;
+        self.restore(new_value);
+        Ok(())
+    }
+
+    /// `probe` putting back a value this input held, such as where a search
+    /// started. The constraint isn't checked: it may be newer than the value.
+    pub(crate) fn restore(&self, new_value: f32) {
+        if let Self::Input { x, kind, data, .. } = self {
+            assert!(*kind != InputKind::Const, "Can't set a \"Const\"");
             *x.borrow_mut() = new_value;
-            data.clear_cache();
+            let generation = data.invalidate(false);
+            data.store(new_value, generation);
         } else {
             panic!("Can only set to \"Input\"");
         }
     }
 
+    fn check_constraint(&self, value: f32) -> Result<(), ConstraintError> {
+        match self.constraint() {
+            Some(constraint) => constraint
+                .check(value)
+                .map_err(|violation| ConstraintError {
+                    node: self.id(),
+                    value,
+                    violation,
+                }),
+            None => Ok(()),
+        }
+    }
+
+    /// Binds a subgraph port to a composite's argument, if it changed. Unlike
+    /// `set`, this isn't a change to the graph: it isn't audited, traced or
+    /// watched, and doesn't move the epoch `Context`s check.
+    pub(crate) fn bind_port(&self, value: f32) {
+        if let Self::Input { x, data, .. } = self {
+            if x.borrow().to_bits() == value.to_bits() {
+                return;
+            }
+            *x.borrow_mut() = value;
+            let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+            data.mark_stale(generation, false);
+            data.store(value, generation);
+        }
+    }
+
+    /// Sets every time node this node depends on (or this node, if it is one)
+    /// to `t`, invalidating exactly what depends on time.
+    pub fn advance_time(&self, t: f32) {
+        if let Self::Input {
+            kind: InputKind::Time,
+            ..
+        } = self
+        {
+            return self.set(t);
+        }
+
+        let clocks = self.reachable(|node| {
+            matches!(
+                node,
+                Self::Input {
+                    kind: InputKind::Time,
+                    ..
+                }
+            )
+        });
+        for clock in clocks {
+            clock.borrow().set(t);
+        }
+    }
+
+    /// Re-reads every bound input this node depends on (or this node, if it
+    /// is one), invalidating what depends on the inputs whose value changed.
+    pub fn refresh(&self) {
+        if let Self::Input { x, source, .. } = self {
+            // Cloned so the source may read other nodes, or rebind this one.
+            let Some(source) = source.borrow().clone() else {
+                return;
+            };
+            let value = source();
+            if value.to_bits() != x.borrow().to_bits() {
+                self.set(value);
+            }
+            return;
+        }
+
+        let bound = self.reachable(
+            |node| matches!(node, Self::Input { source, .. } if source.borrow().is_some()),
+        );
+        for input in bound {
+            input.borrow().refresh();
+        }
+    }
+
+    /// Makes this input read its value from `source`, now and on every
+    /// `refresh`, as if created by `create_bound_input`.
+    pub fn bind(&self, source: impl Fn() -> f32 + 'static) {
+        match self {
+            Self::Input {
+                kind: InputKind::Value,
+                source: bound,
+                ..
+            } => *bound.borrow_mut() = Some(Rc::new(source)),
+            _ => panic!("Can only bind a value \"Input\""),
+        }
+        self.refresh();
+    }
+
+    /// Stops `refresh` from changing this input, which keeps its value.
+    pub fn unbind(&self) {
+        if let Self::Input { source, .. } = self {
+            *source.borrow_mut() = None;
+        }
+    }
+
+    /// Value `reset_inputs` restores this input to: the one it was created
+    /// with, unless changed by `set_default`.
+    pub fn default_value(&self) -> f32 {
+        match self {
+            Self::Input { default, .. } => default.get(),
+            _ => panic!("Only an \"Input\" has a default"),
+        }
+    }
+
+    /// Panics on values the input's constraint rejects.
+    pub fn set_default(&self, x: f32) {
+        match self {
+            Self::Input {
+                kind: InputKind::Value | InputKind::Time,
+                constraint,
+                default,
+                data,
+                ..
+            } => {
+                if let Some(constraint) = &*constraint.borrow() {
+                    if let Err(violation) = constraint.check(x) {
+                        let e = ConstraintError {
+                            node: data.id,
+                            value: x,
+                            violation,
+                        };
+                        panic!("{e}");
+                    }
+                }
+                default.set(x);
+            }
+            _ => panic!("Can only set the default of a \"Value\" or \"Time\""),
+        }
+    }
+
+    /// Restores every `Value` and `Time` input this node depends on (or this
+    /// node, if it is one) to its default, as one change: what depends on
+    /// several of them is invalidated once, and inputs already at their
+    /// default invalidate nothing.
+    pub fn reset_inputs(&self) {
+        let resettable = |node: &Self| {
+            matches!(
+                node,
+                Self::Input {
+                    kind: InputKind::Value | InputKind::Time,
+                    ..
+                }
+            )
+        };
+
+        EPOCH.fetch_add(1, Ordering::Relaxed);
+        let generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_invalidation();
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::Reset {
+            node: self.id(),
+            generation,
+        });
+        if resettable(self) {
+            self.restore_default(generation);
+        }
+        for input in self.reachable(resettable) {
+            input.borrow().restore_default(generation);
+        }
+    }
+
+    fn restore_default(&self, generation: u64) {
+        if let Self::Input {
+            x, default, data, ..
+        } = self
+        {
+            let default = default.get();
+            if x.borrow().to_bits() == default.to_bits() {
+                return;
+            }
+            crate::audit::record(self, crate::audit::AuditAction::Reset, *x.borrow(), default);
+            *x.borrow_mut() = default;
+            data.mark_stale(generation, false);
+            data.store(default, generation);
+            data.check_watches(default);
+        }
+    }
+
+    /// Makes this `Delay` or accumulator follow `source` from the next `step`
+    /// on.
+    pub fn feed(&self, source: NodeCelled) {
+        match self {
+            Self::Input {
+                kind: InputKind::Delay | InputKind::Accumulate(_),
+                feed,
+                ..
+            } => {
+                #[cfg(feature = "trace")]
+                crate::trace::event(|| crate::trace::Event::Fed {
+                    node: self.id(),
+                    source: source.borrow().id(),
+                });
+                *feed.borrow_mut() = Some(source);
+            }
+            _ => panic!("Can only feed a \"Delay\" or \"Accumulate\""),
+        }
+    }
+
+    /// Starts this accumulator over from its identity value.
+    pub fn reset(&self) {
+        match self {
+            Self::Input {
+                kind: InputKind::Accumulate(accumulation),
+                ..
+            } => self.set(accumulation.identity()),
+            _ => panic!("Can only reset an \"Accumulate\""),
+        }
+    }
+
+    /// Panics on evaluation errors, see `try_step`.
+    pub fn step(&self) {
+        self.try_step().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Moves every delay and accumulator this node depends on (or this node,
+    /// if it is one), including those their feeds depend on, one step forward.
+    /// All feeds are sampled before any of them changes. Unfed delays keep
+    /// their value.
+    pub fn try_step(&self) -> Result<(), EvalError> {
+        let is_stateful = |node: &Self| {
+            matches!(
+                node,
+                Self::Input {
+                    kind: InputKind::Delay | InputKind::Accumulate(_),
+                    ..
+                }
+            )
+        };
+
+        let feed_of = |node: &Self| match node {
+            Self::Input { feed, .. } => feed.borrow().clone(),
+            _ => unreachable!(),
+        };
+
+        // `None` stands for this node.
+        let mut pending: Vec<_> = self.reachable(is_stateful).into_iter().map(Some).collect();
+        let mut seen: HashSet<_> = pending
+            .iter()
+            .flatten()
+            .map(|delay| delay.borrow().id())
+            .collect();
+        if is_stateful(self) {
+            seen.insert(self.id());
+            pending.push(None);
+        }
+        let mut sampled = Vec::new();
+        while let Some(delay) = pending.pop() {
+            let feed = match &delay {
+                Some(delay) => feed_of(&delay.borrow()),
+                None => feed_of(self),
+            };
+            if let Some(feed) = &feed {
+                for node in Self::topo_order(feed) {
+                    let id = node.borrow().id();
+                    if is_stateful(&node.borrow()) && seen.insert(id) {
+                        pending.push(Some(node));
+                    }
+                }
+            }
+            sampled.push((delay, feed));
+        }
+
+        let mut values = Vec::with_capacity(sampled.len());
+        for (_, feed) in &sampled {
+            values.push(match feed {
+                Some(feed) => Some(feed.borrow().try_compute()?),
+                None => None,
+            });
+        }
+        for ((delay, _), value) in sampled.iter().zip(values) {
+            let Some(value) = value else {
+                continue;
+            };
+            let delay = delay.as_ref().map(|delay| delay.borrow());
+            let node = delay.as_deref().unwrap_or(self);
+            let current = node.compute();
+            let value = match node {
+                Self::Input {
+                    kind: InputKind::Accumulate(accumulation),
+                    ..
+                } => accumulation.fold(current, value),
+                _ => value,
+            };
+            if current.to_bits() != value.to_bits() {
+                node.set(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Nodes below this one matching `filter`, each once.
+    fn reachable(&self, filter: impl Fn(&Self) -> bool) -> Vec<NodeCelled> {
+        let mut res = Vec::new();
+        let mut seen = HashSet::new();
+        for child in self.children() {
+            for node in Self::topo_order(&child) {
+                if filter(&node.borrow()) && seen.insert(Rc::as_ptr(&node)) {
+                    res.push(node);
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.data().id
+    }
+
+    /// The op's name in metrics and traces: `add`, `sin`, a custom op's name,
+    /// and so on.
+    #[cfg(any(feature = "metrics", feature = "trace"))]
+    pub(crate) fn op_name(&self) -> &str {
+        match self {
+            Self::Input { .. } => "input",
+            Self::Binary { op, .. } => match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Mul => "mul",
+                BinaryOp::Pow(_) => "pow",
+                BinaryOp::And => "and",
+                BinaryOp::Or => "or",
+                BinaryOp::Compare(_) => "compare",
+            },
+            Self::Unary { op, .. } => match op {
+                UnaryOp::Sin => "sin",
+                UnaryOp::Cos => "cos",
+                UnaryOp::Not => "not",
+                UnaryOp::Round { .. } => "round",
+            },
+            Self::Ternary { op, .. } => match op {
+                TernaryOp::MulAdd => "mul-add",
+                TernaryOp::Select => "select",
+            },
+            Self::Custom { op, .. } => op.name(),
+            Self::Composite { .. } => "composite",
+        }
+    }
+
+    /// Name shown in place of this node's expression where it is an operand,
+    /// and in reports.
+    pub fn name(&self) -> Option<Rc<str>> {
+        self.data().name.borrow().clone()
+    }
+
+    pub fn set_name(&self, name: impl Into<Rc<str>>) {
+        *self.data().name.borrow_mut() = Some(name.into());
+    }
+
+    /// What this node stands for, for reports. On an output, what the graph
+    /// computes.
+    pub fn doc(&self) -> Option<Rc<str>> {
+        self.data().doc.borrow().clone()
+    }
+
+    pub fn set_doc(&self, doc: impl Into<Rc<str>>) {
+        *self.data().doc.borrow_mut() = Some(doc.into());
+    }
+
+    /// Where front-ends draw this node, as set by `set_position` or by
+    /// `layout`.
+    pub fn position(&self) -> Option<(f32, f32)> {
+        self.data().position.get()
+    }
+
+    pub fn set_position(&self, x: f32, y: f32) {
+        self.data().position.set(Some((x, y)));
+    }
+
+    pub(crate) fn watches(&self) -> &RefCell<Vec<Watch>> {
+        &self.data().watches
+    }
+
+    /// The value from the last computation, unless it has been marked stale
+    /// since, or an input's value. Doesn't compute. Under `Tracking::Pull`,
+    /// nothing is marked stale, so the value may be out of date.
+    pub fn cached_value(&self) -> Option<f32> {
+        match self {
+            Self::Input { x, .. } => Some(*x.borrow()),
+            _ => self.data().cached().map(|cached| cached.value),
+        }
+    }
+
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.data().policy.get()
+    }
+
+    pub fn set_cache_policy(&self, policy: CachePolicy) {
+        #[cfg(feature = "trace")]
+        crate::trace::event(|| crate::trace::Event::CachePolicy {
+            node: self.id(),
+            policy,
+        });
+        let old = self.data().policy.replace(policy);
+        // Changes didn't reach past a `Sticky` node, and won't past one
+        // leaving `Recompute` without a cache, so values from before them may
+        // be cached at and above it.
+        let behind = old != policy && matches!(old, CachePolicy::Sticky | CachePolicy::Recompute);
+        if policy == CachePolicy::NoCache || behind {
+            self.data().invalidate(false);
+        }
+        if policy == CachePolicy::Recompute {
+            self.data().cache.set(None);
+        }
+    }
+
+    /// Drops this node's cache and its dependents', including `Sticky` ones'.
+    pub fn invalidate(&self) {
+        self.data().invalidate(true);
+    }
+
+    /// Inputs a composite's body reads other than its ports, see `Subgraph`.
+    pub(crate) fn free_inputs(&self) -> &[NodeCelled] {
+        match self {
+            Self::Composite { graph, .. } => &graph.free,
+            _ => &[],
+        }
+    }
+
+    /// Direct operands, in evaluation order.
+    pub fn children(&self) -> Vec<NodeCelled> {
+        match self {
+            Self::Input { .. } => Vec::new(),
+            Self::Binary { a, b, .. } => vec![a.clone(), b.clone()],
+            Self::Unary { x, .. } => vec![x.clone()],
+            Self::Ternary { a, b, c, .. } => vec![a.clone(), b.clone(), c.clone()],
+            Self::Custom { args, .. } | Self::Composite { args, .. } => args.clone(),
+        }
+    }
+
+    /// Every node reachable from `this`, children before parents, each once.
+    pub fn topo_order(this: &NodeCelled) -> Vec<NodeCelled> {
+        let mut res = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(this.clone(), false)];
+
+        while let Some((node, expanded)) = stack.pop() {
+            if expanded {
+                res.push(node);
+                continue;
+            }
+            if !visited.insert(Rc::as_ptr(&node)) {
+                continue;
+            }
+            let children = node.borrow().children();
+            stack.push((node, true));
+            stack.extend(children.into_iter().rev().map(|child| (child, false)));
+        }
+
+        res
+    }
+
+    /// `Input` nodes reachable from `this`, in `topo_order`.
+    pub fn inputs(this: &NodeCelled) -> Vec<NodeCelled> {
+        Self::topo_order(this)
+            .into_iter()
+            .filter(|node| matches!(&*node.borrow(), Self::Input { .. }))
+            .collect()
+    }
+
+    /// Fresh node of the same kind over `children`. Inputs are returned as is,
+    /// so rebuilt graphs stay bound to the original inputs.
+    pub(crate) fn with_children(this: &NodeCelled, children: Vec<NodeCelled>) -> NodeCelled {
+        match &*this.borrow() {
+            Self::Custom { op, .. } => return Self::create_custom(op.clone(), children),
+            Self::Composite { graph, port, .. } => {
+                return Self::create_composite_node(graph.clone(), *port, children)
+            }
+            _ => {}
+        }
+
+        let mut children = children.into_iter();
+        let mut next = || {
+            children
+                .next()
+                .expect("child count must match the node kind")
+        };
+
+        match &*this.borrow() {
+            Self::Input { .. } => this.clone(),
+            Self::Binary { op, .. } => Self::create_binary_node(op.clone(), next(), next()),
+            Self::Unary { op, .. } => Self::create_unary_node(op.clone(), next()),
+            Self::Ternary { op, .. } => {
+                Self::create_ternary_node(op.clone(), next(), next(), next())
+            }
+            Self::Custom { .. } | Self::Composite { .. } => unreachable!(),
+        }
+    }
+
+    /// Whether both nodes apply the same op, operands aside. Constants are the
+    /// same op if their values are, other inputs only equal themselves.
+    pub(crate) fn same_op(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Input {
+                    x: a,
+                    kind: InputKind::Const,
+                    ..
+                },
+                Self::Input {
+                    x: b,
+                    kind: InputKind::Const,
+                    ..
+                },
+            ) => a.borrow().to_bits() == b.borrow().to_bits(),
+            (Self::Input { data: a, .. }, Self::Input { data: b, .. }) => a.id == b.id,
+            (Self::Binary { op: a, .. }, Self::Binary { op: b, .. }) => match (a, b) {
+                (BinaryOp::Pow(a), BinaryOp::Pow(b)) => a == b,
+                (BinaryOp::Compare(a), BinaryOp::Compare(b)) => a == b,
+                _ => mem::discriminant(a) == mem::discriminant(b),
+            },
+            (Self::Unary { op: a, .. }, Self::Unary { op: b, .. }) => a == b,
+            (Self::Ternary { op: a, .. }, Self::Ternary { op: b, .. }) => {
+                mem::discriminant(a) == mem::discriminant(b)
+            }
+            (Self::Custom { op: a, .. }, Self::Custom { op: b, .. }) => Arc::ptr_eq(a, b),
+            (
+                Self::Composite {
+                    graph: a_graph,
+                    port: a_port,
+                    ..
+                },
+                Self::Composite {
+                    graph: b_graph,
+                    port: b_port,
+                    ..
+                },
+            ) => Rc::ptr_eq(a_graph, b_graph) && a_port == b_port,
+            _ => false,
+        }
+    }
+
+    /// Unregisters `this` from its operands, for nodes that are dropped right
+    /// after creation.
+    pub(crate) fn detach(this: &NodeCelled) {
+        let mut operands = this.borrow().children();
+        if let Self::Composite { graph, .. } = &*this.borrow() {
+            operands.extend(graph.free.iter().cloned());
+        }
+        for child in operands {
+            let child = child.borrow();
+            child
+                .data()
+                .dependents
+                .borrow_mut()
+                .retain(|dependent| !Rc::ptr_eq(dependent, this));
+        }
+    }
+
+    /// Detaches the nodes created since `first` that none of `roots` uses,
+    /// for passes that build nodes and then discard them: found through the
+    /// dependents of the nodes kept, and of each other.
+    pub(crate) fn detach_unused(first: NodeId, roots: &[&NodeCelled]) {
+        let mut stack: Vec<_> = roots
+            .iter()
+            .flat_map(|root| Self::topo_order(root))
+            .collect();
+        let kept: HashSet<_> = stack.iter().map(Rc::as_ptr).collect();
+        let mut seen = HashSet::new();
+        while let Some(node) = stack.pop() {
+            let dependents = node.borrow().data().dependents.borrow().clone();
+            for dependent in dependents {
+                let ptr = Rc::as_ptr(&dependent);
+                if dependent.borrow().id() < first || kept.contains(&ptr) || !seen.insert(ptr) {
+                    continue;
+                }
+                Self::detach(&dependent);
+                stack.push(dependent);
+            }
+        }
+    }
+
+    fn fmt_expr(&self, f: &mut fmt::Formatter<'_>, depth: Option<usize>) -> fmt::Result {
+        if let Self::Input { x, kind, data, .. } = self {
+            if let Some(name) = &*data.name.borrow() {
+                return f.write_str(name);
+            }
+            return match kind {
+                InputKind::Value => write!(f, "x{}", data.id.0),
+                InputKind::Time => write!(f, "t{}", data.id.0),
+                InputKind::Const => write!(f, "{}", x.borrow()),
+                InputKind::Delay => write!(f, "d{}", data.id.0),
+                InputKind::Accumulate(accumulation) => {
+                    let name = match accumulation {
+                        Accumulation::Sum => "sum",
+                        Accumulation::Min => "min",
+                        Accumulation::Max => "max",
+                        Accumulation::Count => "count",
+                    };
+                    write!(f, "{name}{}", data.id.0)
+                }
+            };
+        }
+        if depth == Some(0) {
+            return f.write_str("...");
+        }
+
+        let depth = depth.map(|depth| depth - 1);
+        // Named operands stand for their expressions.
+        let operand = |f: &mut fmt::Formatter<'_>, node: &NodeCelled| {
+            let node = node.borrow();
+            match node.name() {
+                Some(name) => f.write_str(&name),
+                None => node.fmt_expr(f, depth),
+            }
+        };
+        let call = |f: &mut fmt::Formatter<'_>, name: &dyn fmt::Display, args: &[NodeCelled]| {
+            write!(f, "{name}(")?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                operand(f, arg)?;
+            }
+            f.write_str(")")
+        };
+
+        match self {
+            Self::Input { .. } => unreachable!(),
+            Self::Binary { op, a, b, .. } => {
+                f.write_str("(")?;
+                operand(f, a)?;
+                write!(f, " {op} ")?;
+                operand(f, b)?;
+                f.write_str(")")
+            }
+            Self::Unary { op, x, .. } => call(f, op, std::slice::from_ref(x)),
+            Self::Ternary { op, .. } => call(f, op, &self.children()),
+            Self::Custom { op, args, .. } => call(f, &op.name(), args),
+            Self::Composite {
+                graph, port, args, ..
+            } => {
+                let name = graph.name().unwrap_or("composite");
+                if graph.outputs.len() == 1 {
+                    call(f, &name, args)
+                } else {
+                    call(f, &format_args!("{name}.{port}"), args)
+                }
+            }
+        }
+    }
+
     fn add_dependent(&mut self, node: NodeCelled) {
         self.data().dependents.borrow_mut().push(node);
     }
 
+    #[cfg(test)]
+    pub(crate) fn dependents(&self) -> Vec<NodeCelled> {
+        self.data().dependents.borrow().clone()
+    }
+
     fn data(&self) -> &NodeData {
         match self {
-            Self::Input { data, .. } | Self::Binary { data, .. } | Self::Unary { data, .. } => data,
+            Self::Input { data, .. }
+            | Self::Binary { data, .. }
+            | Self::Unary { data, .. }
+            | Self::Ternary { data, .. }
+            | Self::Custom { data, .. }
+            | Self::Composite { data, .. } => data,
         }
     }
+}
 
-    fn data_mut(&mut self) -> &mut NodeData {
-        match self {
-            Self::Input { data, .. } | Self::Binary { data, .. } | Self::Unary { data, .. } => data,
+fn pow(policy: PowPolicy, base: f32, exponent: f32, node: NodeId) -> Result<f32, EvalError> {
+    if policy == PowPolicy::Native || base != 0f32 || exponent > 0f32 || exponent.is_nan() {
+        return Ok(base.powf(exponent));
+    }
+
+    let error = EvalError::PowDomain {
+        node,
+        base,
+        exponent,
+    };
+    match policy {
+        PowPolicy::Native => unreachable!(),
+        PowPolicy::Error => Err(error),
+        PowPolicy::Nan => Ok(f32::NAN),
+        PowPolicy::ZeroPowZeroIsOne if exponent == 0f32 => Ok(1f32),
+        PowPolicy::ZeroPowZeroIsOne => Err(error),
+    }
+}
+
+fn round(x: f32, digits: i32, mode: RoundMode) -> f32 {
+    if !x.is_finite() {
+        return x;
+    }
+
+    // `x` is `mantissa * 10^exponent` with all the digits of its shortest form.
+    let formatted = format!("{:e}", x.abs());
+    let (significand, exponent) = formatted.split_once('e').unwrap();
+    let (int, frac) = significand.split_once('.').unwrap_or((significand, ""));
+    let mantissa: i128 = format!("{int}{frac}").parse().unwrap();
+    let exponent = exponent.parse::<i32>().unwrap() - frac.len() as i32;
+
+    // In `i64`: `digits` may be any `i32`.
+    let dropped = -i64::from(digits) - i64::from(exponent);
+    if dropped <= 0 {
+        return x;
+    }
+    // Anything below a tenth of the unit rounds to zero in every mode.
+    let rounded = if dropped > (int.len() + frac.len()) as i64 {
+        0
+    } else {
+        mode.round_int(mantissa, 10i128.pow(dropped as u32))
+            .unwrap()
+    };
+
+    let res: f32 = format!("{rounded}e{exponent}").parse().unwrap();
+    res.copysign(x)
+}
+
+pub(crate) fn truthy(x: f32) -> bool {
+    x != 0f32
+}
+
+pub(crate) fn from_bool(b: bool) -> f32 {
+    if b {
+        1f32
+    } else {
+        0f32
+    }
+}
+
+pub(crate) fn apply_custom(
+    op: &dyn CustomOp,
+    args: &[f32],
+    node: NodeId,
+) -> Result<f32, EvalError> {
+    op.compute(args).map_err(|message| EvalError::Custom {
+        node,
+        op: op.name().to_string(),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::watch::{WatchCondition, WatchEvent};
+
+    fn scaled_by(k: &NodeCelled) -> (NodeCelled, NodeCelled) {
+        let f = Function::define("f", 1, |p| Node::create_mul(p[0].clone(), k.clone()));
+        let x = Node::create_input(3f32);
+        let call = f.call(vec![x.clone()]);
+        (x, call)
+    }
+
+    #[test]
+    fn composite_invalidated_by_free_input() {
+        let k = Node::create_input(2f32);
+        let (_, call) = scaled_by(&k);
+        assert_eq!(call.borrow().compute(), 6f32);
+        k.borrow().set(10f32);
+        assert_eq!(call.borrow().compute(), 30f32);
+    }
+
+    #[test]
+    fn pulled_composite_invalidated_by_free_input() {
+        Node::set_tracking(Tracking::Pull);
+        let k = Node::create_input(2f32);
+        let (x, call) = scaled_by(&k);
+        let out = Node::create_add(call, x);
+        Node::set_tracking(Tracking::Push);
+        assert_eq!(out.borrow().compute(), 9f32);
+        k.borrow().set(10f32);
+        assert_eq!(out.borrow().compute(), 33f32);
+    }
+
+    #[test]
+    fn nested_composite_invalidated_by_free_input() {
+        let k = Node::create_input(2f32);
+        let inner = Function::define("inner", 1, |p| Node::create_mul(p[0].clone(), k.clone()));
+        let outer = Function::define("outer", 1, |p| inner.call(vec![p[0].clone()]));
+        let call = outer.call(vec![Node::create_input(3f32)]);
+        assert_eq!(call.borrow().compute(), 6f32);
+        k.borrow().set(10f32);
+        assert_eq!(call.borrow().compute(), 30f32);
+    }
+
+    #[test]
+    fn invalidation_visits_shared_dependents_once() {
+        // Without a visited set, each level would double the walk.
+        let x = Node::create_input(1f32);
+        let mut top = x.clone();
+        for _ in 0..64 {
+            top = Node::create_add(top.clone(), top);
+            top.borrow().set_cache_policy(CachePolicy::Recompute);
         }
+        let out = Node::create_sin(top);
+        out.borrow().data().store(0f32, 0);
+        x.borrow().set(2f32);
+        assert!(out.borrow().data().stale_since.get() > 0);
+    }
+
+    #[test]
+    fn unsticking_catches_up_with_changes() {
+        let x = Node::create_input(1f32);
+        let sticky = Node::create_mul(x.clone(), Node::create_input(2f32));
+        let out = Node::create_add(sticky.clone(), Node::create_input(1f32));
+        sticky.borrow().set_cache_policy(CachePolicy::Sticky);
+        assert_eq!(out.borrow().compute(), 3f32);
+        x.borrow().set(2f32);
+        assert_eq!(out.borrow().compute(), 3f32);
+
+        sticky.borrow().set_cache_policy(CachePolicy::Cache);
+        assert_eq!(sticky.borrow().compute(), 4f32);
+        assert_eq!(out.borrow().compute(), 5f32);
+    }
+
+    #[test]
+    fn invalidating_drops_sticky_caches() {
+        let x = Node::create_input(1f32);
+        let sticky = Node::create_mul(x.clone(), Node::create_input(2f32));
+        let out = Node::create_add(sticky.clone(), Node::create_input(1f32));
+        sticky.borrow().set_cache_policy(CachePolicy::Sticky);
+        assert_eq!(out.borrow().compute(), 3f32);
+
+        x.borrow().set(2f32);
+        x.borrow().invalidate();
+        assert_eq!(out.borrow().compute(), 5f32);
+        x.borrow().set(3f32);
+        assert_eq!(out.borrow().compute(), 5f32);
+        sticky.borrow().invalidate();
+        assert_eq!(out.borrow().compute(), 7f32);
+        assert_eq!(sticky.borrow().cache_policy(), CachePolicy::Sticky);
+    }
+
+    #[test]
+    fn binding_ports_is_not_a_change() {
+        let param = Node::create_input(0f32);
+        let body = Node::create_mul(param.clone(), Node::create_input(2f32));
+        let f = Function::new("f", vec![param.clone()], body);
+        let x = Node::create_input(3f32);
+        let call = f.call(vec![x.clone()]);
+        let seen = Rc::new(Cell::new(0));
+        let counter = seen.clone();
+        let count = move |_: &WatchEvent| counter.set(counter.get() + 1);
+        param.borrow().watch(WatchCondition::ChangedBy(0f32), count);
+
+        assert_eq!(call.borrow().compute_with(&[(x, 5f32)]).unwrap(), 10f32);
+        assert_eq!(call.borrow().compute(), 6f32);
+        assert_eq!(seen.get(), 0);
+    }
+
+    #[test]
+    fn round_to_extreme_digits() {
+        let round = |x, digits| round(x, digits, RoundMode::HalfEven);
+        assert_eq!(round(1234.5f32, -2), 1200f32);
+        assert_eq!(round(1234.5f32, i32::MIN), 0f32);
+        assert_eq!(round(-1234.5f32, i32::MIN), -0f32);
+        assert_eq!(round(1234.5f32, i32::MAX), 1234.5f32);
+        let parsed = crate::parser::eval_str("round(1, -2147483648)", &[]).unwrap();
+        assert_eq!(parsed, 0f32);
     }
 }
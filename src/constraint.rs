@@ -0,0 +1,284 @@
+//! Values inputs accept, checked by `Node::set` so that invalid states are
+//! rejected where they would enter the graph.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::computational_graph::{InputKind, Node, NodeId};
+
+/// Why a value breaks a `Constraint`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    NotANumber,
+    BelowMin(f32),
+    AboveMax(f32),
+    /// Not a whole number of steps from the base; `nearest` is.
+    OffStep {
+        step: f32,
+        nearest: f32,
+    },
+    NotAllowed(Vec<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstraintError {
+    pub node: NodeId,
+    pub value: f32,
+    pub violation: Violation,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "node {}: {} ", self.node, self.value)?;
+        match &self.violation {
+            Violation::NotANumber => f.write_str("isn't a number"),
+            Violation::BelowMin(min) => write!(f, "is below the minimum {min}"),
+            Violation::AboveMax(max) => write!(f, "is above the maximum {max}"),
+            Violation::OffStep { step, nearest } => {
+                write!(f, "is off the grid of step {step}, {nearest} is on it")
+            }
+            Violation::NotAllowed(allowed) => write!(f, "isn't one of {allowed:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+/// Values an input accepts, see `Node::set_constraint`. Unconstrained by
+/// default; each `with_*` adds a condition. A constrained input rejects NaN.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Constraint {
+    min: Option<f32>,
+    max: Option<f32>,
+    step: Option<f32>,
+    allowed: Option<Vec<f32>>,
+}
+
+impl Constraint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min(mut self, min: f32) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub fn with_max(mut self, max: f32) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Only whole multiples of `step` away from the minimum, or from `0`
+    /// without one, up to rounding.
+    pub fn with_step(mut self, step: f32) -> Self {
+        assert!(step > 0.0, "Steps must be positive");
+        self.step = Some(step);
+        self
+    }
+
+    /// Only the values in `allowed`.
+    pub fn with_allowed(mut self, allowed: &[f32]) -> Self {
+        self.allowed = Some(allowed.to_vec());
+        self
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        self.max
+    }
+
+    pub fn step(&self) -> Option<f32> {
+        self.step
+    }
+
+    pub fn allowed(&self) -> Option<&[f32]> {
+        self.allowed.as_deref()
+    }
+
+    /// The accepted value closest to `x`, or `x` if there's none, e.g. with
+    /// no allowed value between the bounds. Steps past the maximum round down.
+    pub fn nearest(&self, x: f32) -> f32 {
+        if let Some(allowed) = &self.allowed {
+            return allowed
+                .iter()
+                .copied()
+                .filter(|value| self.check(*value).is_ok())
+                .min_by(|a, b| (a - x).abs().total_cmp(&(b - x).abs()))
+                .unwrap_or(x);
+        }
+        let mut res = x;
+        if let Some(min) = self.min {
+            res = res.max(min);
+        }
+        if let Some(max) = self.max {
+            res = res.min(max);
+        }
+        if let Some(step) = self.step {
+            let base = self.min.unwrap_or(0.0);
+            res = base + ((res - base) / step).round() * step;
+            if self.max.is_some_and(|max| res > max) {
+                res -= step;
+            }
+        }
+        res
+    }
+
+    /// What's wrong with `x`, if anything.
+    pub fn check(&self, x: f32) -> Result<(), Violation> {
+        if x.is_nan() {
+            return Err(Violation::NotANumber);
+        }
+        if let Some(min) = self.min.filter(|min| x < *min) {
+            return Err(Violation::BelowMin(min));
+        }
+        if let Some(max) = self.max.filter(|max| x > *max) {
+            return Err(Violation::AboveMax(max));
+        }
+        if let Some(step) = self.step {
+            let base = self.min.unwrap_or(0.0);
+            let nearest = base + ((x - base) / step).round() * step;
+            // Steps such as 0.1 aren't exact in binary.
+            let tolerance = (step * 1e-4).max(x.abs() * f32::EPSILON * 4.0);
+            if (x - nearest).abs() > tolerance {
+                return Err(Violation::OffStep { step, nearest });
+            }
+        }
+        match &self.allowed {
+            Some(allowed) if !allowed.contains(&x) => Err(Violation::NotAllowed(allowed.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Node {
+    /// Makes `set` on this `Value` input reject values `constraint` doesn't
+    /// accept. Its current value isn't checked. Bound inputs whose source
+    /// gives a rejected value panic on `refresh`.
+    pub fn set_constraint(&self, constraint: Constraint) {
+        match self {
+            Self::Input {
+                kind: InputKind::Value,
+                constraint: current,
+                ..
+            } => *current.borrow_mut() = Some(Rc::new(constraint)),
+            _ => panic!("Can only constrain a \"Value\" input"),
+        }
+    }
+
+    pub fn clear_constraint(&self) {
+        if let Self::Input { constraint, .. } = self {
+            *constraint.borrow_mut() = None;
+        }
+    }
+
+    pub fn constraint(&self) -> Option<Rc<Constraint>> {
+        match self {
+            Self::Input { constraint, .. } => constraint.borrow().clone(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_bounds_and_steps() {
+        let percent = Constraint::new().with_min(0.0).with_max(1.0).with_step(0.1);
+        assert_eq!(percent.check(0.3), Ok(()));
+        assert_eq!(percent.check(1.0), Ok(()));
+        assert_eq!(percent.check(f32::NAN), Err(Violation::NotANumber));
+        assert_eq!(percent.check(-0.1), Err(Violation::BelowMin(0.0)));
+        assert_eq!(percent.check(1.5), Err(Violation::AboveMax(1.0)));
+        assert!(matches!(
+            percent.check(0.25),
+            Err(Violation::OffStep { step, .. }) if step == 0.1
+        ));
+
+        // Steps count from the minimum, or from 0.
+        let odd = Constraint::new().with_min(1.0).with_step(2.0);
+        assert_eq!(odd.check(5.0), Ok(()));
+        assert_eq!(
+            odd.check(4.0),
+            Err(Violation::OffStep {
+                step: 2.0,
+                nearest: 5.0,
+            })
+        );
+        assert_eq!(Constraint::new().with_step(0.5).check(-1.5), Ok(()));
+        assert_eq!(Constraint::new().check(f32::INFINITY), Ok(()));
+    }
+
+    #[test]
+    fn checks_allowed_values() {
+        let sizes = Constraint::new()
+            .with_allowed(&[1.0, 2.0, 4.0])
+            .with_max(2.0);
+        assert_eq!(sizes.check(2.0), Ok(()));
+        assert_eq!(sizes.check(3.0), Err(Violation::AboveMax(2.0)));
+        assert_eq!(
+            sizes.check(1.5),
+            Err(Violation::NotAllowed(vec![1.0, 2.0, 4.0]))
+        );
+        assert_eq!(sizes.allowed(), Some(&[1.0, 2.0, 4.0][..]));
+    }
+
+    #[test]
+    fn snaps_to_the_nearest_accepted_value() {
+        let percent = Constraint::new()
+            .with_min(0.0)
+            .with_max(1.0)
+            .with_step(0.25);
+        assert_eq!(percent.nearest(0.3), 0.25);
+        assert_eq!(percent.nearest(0.9), 1.0);
+        assert_eq!(percent.nearest(-3.0), 0.0);
+        assert_eq!(percent.nearest(7.0), 1.0);
+        // The last step below the maximum.
+        let uneven = Constraint::new().with_min(0.0).with_max(1.0).with_step(0.4);
+        assert_eq!(uneven.nearest(0.95), 0.8);
+
+        let sizes = Constraint::new()
+            .with_allowed(&[1.0, 2.0, 4.0])
+            .with_max(3.0);
+        assert_eq!(sizes.nearest(3.9), 2.0);
+        assert_eq!(sizes.nearest(0.0), 1.0);
+        let none = Constraint::new().with_allowed(&[5.0]).with_max(3.0);
+        assert_eq!(none.nearest(2.0), 2.0);
+        assert_eq!(Constraint::new().nearest(-8.5), -8.5);
+    }
+
+    #[test]
+    fn inputs_reject_values() {
+        let x = Node::create_input(0.5f32);
+        x.borrow()
+            .set_constraint(Constraint::new().with_min(0.0).with_max(1.0));
+        assert_eq!(x.borrow().constraint().unwrap().max(), Some(1.0));
+
+        let error = x.borrow().try_set(2.0).unwrap_err();
+        assert_eq!(
+            error,
+            ConstraintError {
+                node: x.borrow().id(),
+                value: 2.0,
+                violation: Violation::AboveMax(1.0),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            format!("node {}: 2 is above the maximum 1", x.borrow().id())
+        );
+        assert_eq!(x.borrow().compute(), 0.5);
+        x.borrow().try_set(0.75).unwrap();
+        assert_eq!(x.borrow().compute(), 0.75);
+
+        x.borrow().clear_constraint();
+        assert!(x.borrow().constraint().is_none());
+        x.borrow().try_set(2.0).unwrap();
+        assert_eq!(x.borrow().compute(), 2.0);
+    }
+}
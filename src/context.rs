@@ -0,0 +1,246 @@
+//! Scenarios: alternative node values evaluated over one shared graph.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use crate::computational_graph::{generation, CachePolicy, EvalError, Node, NodeCelled, NodeId};
+
+/// One set of overridden node values (usually inputs) for a graph, such as a
+/// stress case next to the base case held by the graph itself. Nodes depending
+/// on an override are cached in the context, the rest use the graph's caches,
+/// so any number of contexts can share one topology.
+#[derive(Debug, Default)]
+pub struct Context {
+    overrides: HashMap<NodeId, f32>,
+    derived: RefCell<Derived>,
+}
+
+/// Values computed under the overrides, valid for one generation.
+#[derive(Debug, Default)]
+struct Derived {
+    generation: u64,
+    /// Value and whether it came from a `NoCache` node, in which case it's
+    /// dropped after the computation that needed it.
+    values: HashMap<NodeId, (f32, bool)>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides `node` in this context only.
+    pub fn set(&mut self, node: &NodeCelled, x: f32) {
+        self.overrides.insert(node.borrow().id(), x);
+        self.derived.get_mut().values.clear();
+    }
+
+    /// Reverts `node` to the graph's value.
+    pub fn unset(&mut self, node: &NodeCelled) {
+        self.overrides.remove(&node.borrow().id());
+        self.derived.get_mut().values.clear();
+    }
+
+    pub fn get(&self, node: &NodeCelled) -> Option<f32> {
+        self.overrides.get(&node.borrow().id()).copied()
+    }
+
+    /// Computes `node` unless neither an operand nor, for a composite, a free
+    /// input of its body is affected by the overrides.
+    fn evaluate(&self, node: &Node, derived: &Derived) -> Result<Option<(f32, bool)>, EvalError> {
+        let children = node.children();
+        let free: Vec<_> = node
+            .free_inputs()
+            .iter()
+            .filter_map(|input| {
+                let value = self.overrides.get(&input.borrow().id())?;
+                Some((input.clone(), *value))
+            })
+            .collect();
+        let affected = |child: &NodeCelled| {
+            let id = child.borrow().id();
+            match self.overrides.get(&id) {
+                Some(value) => Some((*value, false)),
+                None => derived.values.get(&id).copied(),
+            }
+        };
+        if free.is_empty() && !children.iter().any(|child| affected(child).is_some()) {
+            return Ok(None);
+        }
+
+        let mut volatile = node.cache_policy() == CachePolicy::NoCache;
+        let mut args = Vec::with_capacity(children.len());
+        for child in &children {
+            let (value, child_volatile) = match affected(child) {
+                Some(affected) => affected,
+                None => {
+                    let tracked = child.borrow().compute_tracked()?;
+                    (tracked.value, tracked.volatile)
+                }
+            };
+            volatile |= child_volatile;
+            args.push(value);
+        }
+        let (value, subgraph_volatile) = Self::apply(node, &args, &free)?;
+
+        Ok(Some((value, volatile || subgraph_volatile)))
+    }
+
+    /// `node` applied to `args`, with the `free` inputs of a composite's body
+    /// bound to their overrides for the call. Like ports, they're bound rather
+    /// than set, so the graph doesn't see a change.
+    fn apply(
+        node: &Node,
+        args: &[f32],
+        free: &[(NodeCelled, f32)],
+    ) -> Result<(f32, bool), EvalError> {
+        let held: Vec<_> = free
+            .iter()
+            .map(|(input, value)| {
+                let input = input.borrow();
+                let held = input.cached_value().expect("inputs hold a value");
+                input.bind_port(*value);
+                held
+            })
+            .collect();
+        let res = node.apply(args);
+        for ((input, _), held) in free.iter().zip(held) {
+            input.borrow().bind_port(held);
+        }
+        res
+    }
+}
+
+impl Node {
+    /// This node's value in the scenario `ctx`. The graph's inputs and caches
+    /// are left as they are.
+    pub fn compute_in(&self, ctx: &Context) -> Result<f32, EvalError> {
+        if let Some(value) = ctx.overrides.get(&self.id()) {
+            return Ok(*value);
+        }
+
+        let mut derived = ctx.derived.borrow_mut();
+        if derived.generation != generation() {
+            derived.values.clear();
+            derived.generation = generation();
+        }
+
+        let mut seen = HashSet::new();
+        for child in self.children() {
+            for node in Self::topo_order(&child) {
+                let node = node.borrow();
+                let id = node.id();
+                let known = ctx.overrides.contains_key(&id) || derived.values.contains_key(&id);
+                if known || !seen.insert(id) {
+                    continue;
+                }
+                if let Some(computed) = ctx.evaluate(&node, &derived)? {
+                    derived.values.insert(id, computed);
+                }
+            }
+        }
+
+        let res = match ctx.evaluate(self, &derived)? {
+            Some((value, _)) => value,
+            None => self.try_compute()?,
+        };
+        derived.values.retain(|_, (_, volatile)| !*volatile);
+
+        Ok(res)
+    }
+
+    /// Value this node would have if the nodes in `overrides` (usually inputs)
+    /// had the given values, as a one-off `Context`.
+    pub fn compute_with(&self, overrides: &[(NodeCelled, f32)]) -> Result<f32, EvalError> {
+        let mut ctx = Context::new();
+        for (node, value) in overrides {
+            ctx.set(node, *value);
+        }
+        self.compute_in(&ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+
+    #[test]
+    fn overrides_free_inputs_of_composites() {
+        let k = Node::create_input(2f32);
+        let f = Function::define("f", 1, |p| Node::create_mul(p[0].clone(), k.clone()));
+        let call = f.call(vec![Node::create_input(3f32)]);
+        let out = Node::create_add(call.clone(), Node::create_input(1f32));
+        assert_eq!(out.borrow().compute(), 7f32);
+
+        let mut ctx = Context::new();
+        ctx.set(&k, 10f32);
+        assert_eq!(call.borrow().compute_in(&ctx).unwrap(), 30f32);
+        assert_eq!(out.borrow().compute_in(&ctx).unwrap(), 31f32);
+        assert_eq!(out.borrow().compute(), 7f32);
+        assert_eq!(k.borrow().cached_value(), Some(2f32));
+    }
+
+    #[test]
+    fn computes_composites_with_free_inputs_overridden() {
+        let k = Node::create_input(2f32);
+        let inner = Function::define("inner", 1, |p| Node::create_mul(p[0].clone(), k.clone()));
+        let outer = Function::define("outer", 1, |p| inner.call(vec![p[0].clone()]));
+        let x = Node::create_input(3f32);
+        let call = outer.call(vec![x.clone()]);
+
+        let overrides = [(k.clone(), 10f32)];
+        assert_eq!(call.borrow().compute_with(&overrides).unwrap(), 30f32);
+        let overrides = [(k.clone(), 10f32), (x.clone(), 4f32)];
+        assert_eq!(call.borrow().compute_with(&overrides).unwrap(), 40f32);
+        assert_eq!(call.borrow().compute(), 6f32);
+    }
+
+    #[test]
+    fn scenarios_share_one_graph() {
+        let rate = Node::create_input(0.1f32);
+        let amount = Node::create_input(100f32);
+        let out = Node::create_mul(
+            amount.clone(),
+            Node::create_add(Node::create_input(1f32), rate.clone()),
+        );
+
+        let mut stress = Context::new();
+        stress.set(&rate, 0.5);
+        let mut big = Context::new();
+        big.set(&amount, 1000f32);
+        assert_eq!(stress.get(&rate), Some(0.5));
+        assert_eq!(stress.get(&amount), None);
+        assert_eq!(out.borrow().compute_in(&stress).unwrap(), 150f32);
+        assert_eq!(out.borrow().compute_in(&big).unwrap(), 1100f32);
+        assert_eq!(out.borrow().compute(), 110f32);
+
+        // The graph's own changes show through where nothing is overridden.
+        amount.borrow().set(200f32);
+        assert_eq!(out.borrow().compute_in(&stress).unwrap(), 300f32);
+        assert_eq!(out.borrow().compute_in(&big).unwrap(), 1100f32);
+
+        stress.unset(&rate);
+        assert_eq!(out.borrow().compute_in(&stress).unwrap(), 220f32);
+        assert_eq!(rate.borrow().compute_in(&big).unwrap(), 0.1f32);
+        assert_eq!(amount.borrow().compute_in(&big).unwrap(), 1000f32);
+    }
+
+    #[test]
+    fn overrides_feed_through_uncached_nodes() {
+        let x = Node::create_input(2f32);
+        let square = Node::create_mul(x.clone(), x.clone());
+        square.borrow().set_cache_policy(CachePolicy::NoCache);
+        let out = Node::create_add(square.clone(), Node::create_input(1f32));
+
+        let mut ctx = Context::new();
+        ctx.set(&x, 3f32);
+        assert_eq!(out.borrow().compute_in(&ctx).unwrap(), 10f32);
+        assert_eq!(out.borrow().compute_in(&ctx).unwrap(), 10f32);
+        assert_eq!(
+            out.borrow().compute_with(&[(x.clone(), 4f32)]).unwrap(),
+            17f32
+        );
+        assert_eq!(out.borrow().compute(), 5f32);
+    }
+}
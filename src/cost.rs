@@ -0,0 +1,152 @@
+//! Static cost estimates, for comparing formulations of the same expression.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::computational_graph::{BinaryOp, Node, TernaryOp, UnaryOp};
+
+/// Relative cost of one evaluation of each op. Inputs are free.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    pub add: u64,
+    pub mul: u64,
+    pub pow: u64,
+    pub sin: u64,
+    pub cos: u64,
+    pub mul_add: u64,
+    /// `And`, `Or` and `Not`.
+    pub logic: u64,
+    pub compare: u64,
+    pub select: u64,
+    pub round: u64,
+    /// Per-op costs of custom ops, by `CustomOp::name`.
+    pub custom: HashMap<String, u64>,
+    /// Cost of custom ops missing from `custom`.
+    pub custom_default: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            add: 1,
+            mul: 1,
+            pow: 15,
+            sin: 10,
+            cos: 10,
+            mul_add: 1,
+            logic: 1,
+            compare: 1,
+            select: 1,
+            round: 5,
+            custom: HashMap::new(),
+            custom_default: 100,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn with_custom(mut self, op: impl Into<String>, cost: u64) -> Self {
+        self.custom.insert(op.into(), cost);
+        self
+    }
+
+    /// Cost of `node` alone, not counting its operands.
+    pub fn op_cost(&self, node: &Node) -> u64 {
+        match node {
+            Node::Input { .. } => 0,
+            Node::Binary { op, .. } => match op {
+                BinaryOp::Add => self.add,
+                BinaryOp::Mul => self.mul,
+                BinaryOp::Pow(_) => self.pow,
+                BinaryOp::And | BinaryOp::Or => self.logic,
+                BinaryOp::Compare(_) => self.compare,
+            },
+            Node::Unary { op, .. } => match op {
+                UnaryOp::Sin => self.sin,
+                UnaryOp::Cos => self.cos,
+                UnaryOp::Not => self.logic,
+                UnaryOp::Round { .. } => self.round,
+            },
+            Node::Ternary { op, .. } => match op {
+                TernaryOp::MulAdd => self.mul_add,
+                TernaryOp::Select => self.select,
+            },
+            Node::Custom { op, .. } => *self.custom.get(op.name()).unwrap_or(&self.custom_default),
+            Node::Composite { graph, port, .. } => graph.outputs()[*port].borrow().cost_with(self),
+        }
+    }
+}
+
+impl Node {
+    /// `cost_with` under the default `CostModel`.
+    pub fn cost(&self) -> u64 {
+        self.cost_with(&CostModel::default())
+    }
+
+    /// Cost of evaluating this node from scratch. Shared subgraphs count once.
+    pub fn cost_with(&self, model: &CostModel) -> u64 {
+        let mut seen = HashSet::new();
+        let mut res = model.op_cost(self);
+        for child in self.children() {
+            for node in Self::topo_order(&child) {
+                if seen.insert(Rc::as_ptr(&node)) {
+                    res += model.op_cost(&node.borrow());
+                }
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::reduce::Summation;
+
+    #[test]
+    fn counts_shared_nodes_once() {
+        let x = Node::create_input(1f32);
+        let y = Node::create_input(2f32);
+        let sin = Node::create_sin(x.clone());
+        // sin(x) * sin(x) + y: one mul, one add, one sin.
+        let out = Node::create_add(Node::create_mul(sin.clone(), sin), y);
+        assert_eq!(out.borrow().cost(), 12);
+        assert_eq!(x.borrow().cost(), 0);
+
+        let model = CostModel {
+            sin: 3,
+            ..CostModel::default()
+        };
+        assert_eq!(out.borrow().cost_with(&model), 5);
+    }
+
+    #[test]
+    fn compares_formulations() {
+        let x = Node::create_input(2f32);
+        let two = Node::create_const(2f32);
+        let pow = Node::create_pow(x.clone(), two);
+        let mul = Node::create_mul(x.clone(), x);
+        assert_eq!(pow.borrow().cost(), 15);
+        assert_eq!(mul.borrow().cost(), 1);
+    }
+
+    #[test]
+    fn prices_custom_ops_and_composites() {
+        let args = vec![Node::create_input(1f32), Node::create_input(2f32)];
+        let sum = Node::create_sum(args, Summation::Naive);
+        assert_eq!(sum.borrow().cost(), 100);
+        let model = CostModel::default().with_custom("sum", 2);
+        assert_eq!(sum.borrow().cost_with(&model), 2);
+
+        let f = Function::define("f", 1, |p| {
+            Node::create_add(Node::create_cos(p[0].clone()), p[0].clone())
+        });
+        let call = f.call(vec![Node::create_mul(
+            Node::create_input(1f32),
+            Node::create_input(2f32),
+        )]);
+        assert_eq!(call.borrow().cost(), 12);
+    }
+}
@@ -0,0 +1,214 @@
+//! Stepping through a computation node by node, for tools showing how a
+//! formula arrives at its value: the debugger pauses before breakpoint
+//! nodes, exposes what has been computed so far, and resumes.
+//!
+//! The debugger computes the graph itself, in `Node::topo_order`, keeping
+//! the values apart like a `Context`: the graph's caches are left as they
+//! are. A composite node is one step.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::computational_graph::{EvalError, Node, NodeCelled, NodeId};
+
+/// Where `Debugger::resume` stopped.
+#[derive(Debug, Clone)]
+pub enum Stop {
+    /// Before computing this node.
+    Breakpoint(NodeCelled),
+    /// With the output's value.
+    Done(f32),
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    output: NodeCelled,
+    /// Operation nodes, operands first.
+    order: Vec<NodeCelled>,
+    /// Index in `order` of the node computed next.
+    next: usize,
+    values: HashMap<NodeId, f32>,
+    breakpoints: HashSet<NodeId>,
+    /// Whether `resume` stopped before `order[next]`, so it doesn't stop
+    /// there again.
+    paused: bool,
+}
+
+impl Debugger {
+    /// Paused before the first operation node `output` depends on.
+    pub fn new(output: NodeCelled) -> Self {
+        let order = Node::topo_order(&output)
+            .into_iter()
+            .filter(|node| !matches!(&*node.borrow(), Node::Input { .. }))
+            .collect();
+        Self {
+            output,
+            order,
+            next: 0,
+            values: HashMap::new(),
+            breakpoints: HashSet::new(),
+            paused: false,
+        }
+    }
+
+    pub fn with_breakpoint(mut self, node: &NodeCelled) -> Self {
+        self.add_breakpoint(node);
+        self
+    }
+
+    pub fn add_breakpoint(&mut self, node: &NodeCelled) {
+        self.breakpoints.insert(node.borrow().id());
+    }
+
+    pub fn remove_breakpoint(&mut self, node: &NodeCelled) {
+        self.breakpoints.remove(&node.borrow().id());
+    }
+
+    /// The node the next step computes, `None` once the output is computed.
+    pub fn current(&self) -> Option<&NodeCelled> {
+        self.order.get(self.next)
+    }
+
+    /// Nodes computed so far, in order.
+    pub fn computed(&self) -> &[NodeCelled] {
+        &self.order[..self.next]
+    }
+
+    /// `node`'s value so far: an input's value, or a computed node's, `None`
+    /// for nodes not computed yet.
+    pub fn value(&self, node: &NodeCelled) -> Option<f32> {
+        let node = node.borrow();
+        match &*node {
+            Node::Input { .. } => Some(node.compute()),
+            _ => self.values.get(&node.id()).copied(),
+        }
+    }
+
+    /// The output's value, once computed.
+    pub fn result(&self) -> Option<f32> {
+        self.value(&self.output)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next == self.order.len()
+    }
+
+    /// Computes the current node, returning its value, or `None` if the
+    /// output was computed already. On failure, the debugger stays before
+    /// the node.
+    pub fn step(&mut self) -> Result<Option<f32>, EvalError> {
+        let Some(node) = self.order.get(self.next) else {
+            return Ok(None);
+        };
+        let node = node.borrow();
+        let args: Vec<f32> = node
+            .children()
+            .iter()
+            .map(|child| self.value(child).expect("operands come first"))
+            .collect();
+        let (value, _) = node.apply(&args)?;
+        self.values.insert(node.id(), value);
+        drop(node);
+        self.next += 1;
+        self.paused = false;
+        Ok(Some(value))
+    }
+
+    /// Steps until before the next breakpoint node, or to the end.
+    pub fn resume(&mut self) -> Result<Stop, EvalError> {
+        if self.paused {
+            self.step()?;
+        }
+        while let Some(node) = self.current().cloned() {
+            if self.breakpoints.contains(&node.borrow().id()) {
+                self.paused = true;
+                return Ok(Stop::Breakpoint(node));
+            }
+            self.step()?;
+        }
+        Ok(Stop::Done(self.result().unwrap()))
+    }
+
+    /// Starts over from the first node, reading the inputs afresh.
+    pub fn restart(&mut self) {
+        self.next = 0;
+        self.values.clear();
+        self.paused = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    use crate::computational_graph::PowPolicy;
+
+    #[test]
+    fn steps_operands_first() {
+        let x = Node::create_input(2.0f32);
+        let sum = Node::create_add(x.clone(), Node::create_const(1.0));
+        let product = Node::create_mul(sum.clone(), x.clone());
+        let mut debugger = Debugger::new(product.clone());
+
+        assert!(Rc::ptr_eq(debugger.current().unwrap(), &sum));
+        assert_eq!(debugger.value(&x), Some(2.0));
+        assert_eq!(debugger.value(&sum), None);
+        assert_eq!(debugger.step().unwrap(), Some(3.0));
+        assert_eq!(debugger.computed().len(), 1);
+        assert_eq!(debugger.step().unwrap(), Some(6.0));
+        assert!(debugger.is_done());
+        assert_eq!(debugger.result(), Some(6.0));
+        assert_eq!(debugger.step().unwrap(), None);
+        // The graph's own caches aren't touched.
+        assert_eq!(product.borrow().cached_value(), None);
+    }
+
+    #[test]
+    fn pauses_before_breakpoints() {
+        let x = Node::create_input(1.0f32);
+        let a = Node::create_add(x.clone(), x.clone());
+        let b = Node::create_sin(a.clone());
+        let c = Node::create_mul(b.clone(), a.clone());
+        let mut debugger = Debugger::new(c.clone()).with_breakpoint(&b);
+        debugger.add_breakpoint(&c);
+
+        assert!(
+            matches!(debugger.resume().unwrap(), Stop::Breakpoint(node) if Rc::ptr_eq(&node, &b))
+        );
+        assert_eq!(debugger.value(&a), Some(2.0));
+        assert_eq!(debugger.value(&b), None);
+        assert!(
+            matches!(debugger.resume().unwrap(), Stop::Breakpoint(node) if Rc::ptr_eq(&node, &c))
+        );
+        assert_eq!(debugger.value(&b), Some(2f32.sin()));
+        debugger.remove_breakpoint(&b);
+        assert!(
+            matches!(debugger.resume().unwrap(), Stop::Done(value) if value == 2f32.sin() * 2.0)
+        );
+
+        // Restarting reads the inputs afresh.
+        x.borrow().set(0.0);
+        debugger.restart();
+        assert!(debugger.computed().is_empty());
+        assert!(
+            matches!(debugger.resume().unwrap(), Stop::Breakpoint(node) if Rc::ptr_eq(&node, &c))
+        );
+        assert!(matches!(debugger.resume().unwrap(), Stop::Done(0.0)));
+    }
+
+    #[test]
+    fn stays_before_failing_nodes() {
+        let x = Node::create_input(0.0f32);
+        let pow = Node::create_strict_pow(x.clone(), Node::create_const(0.0), PowPolicy::Error);
+        let mut debugger = Debugger::new(Node::create_add(pow.clone(), x.clone()));
+
+        assert!(matches!(
+            debugger.resume(),
+            Err(EvalError::PowDomain { .. })
+        ));
+        assert!(Rc::ptr_eq(debugger.current().unwrap(), &pow));
+        x.borrow().set(2.0);
+        assert_eq!(debugger.step().unwrap(), Some(1.0));
+        assert!(matches!(debugger.resume().unwrap(), Stop::Done(3.0)));
+    }
+}
@@ -0,0 +1,322 @@
+//! Fixed-point decimal evaluation of `CompiledGraph`s, for financial formulas
+//! where binary floating point rounding is unacceptable.
+//!
+//! Like `integer`, this reinterprets a compiled tape: node values stay `f32`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::compiled::{CompiledGraph, Instr};
+use crate::computational_graph::{BinaryOp, NodeId, PowPolicy, TernaryOp, UnaryOp};
+use crate::integer::round_to_unit;
+
+/// Digits after the decimal point every `Decimal` carries.
+pub const DECIMAL_DIGITS: u32 = 9;
+
+const ONE: i128 = 10i128.pow(DECIMAL_DIGITS);
+
+/// Decimal number with `DECIMAL_DIGITS` fractional digits. Sums are exact,
+/// products are rounded half-to-even to the last digit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Decimal(i128);
+
+impl Decimal {
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(ONE);
+
+    pub fn from_int(x: i64) -> Self {
+        Self(x as i128 * ONE)
+    }
+
+    /// `1` for true, `0` for false, as logical ops and comparisons yield.
+    pub fn from_bool(b: bool) -> Self {
+        if b {
+            Self::ONE
+        } else {
+            Self::ZERO
+        }
+    }
+
+    fn is_true(self) -> bool {
+        self != Self::ZERO
+    }
+
+    /// The decimal `x` prints as, so `0.1f32` becomes exactly `0.1`. `None`
+    /// for non-finite values and ones out of range.
+    pub fn from_f32(x: f32) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        x.to_string().parse().ok()
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / ONE as f32
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        let product = self.0.checked_mul(other.0)?;
+        let (quotient, remainder) = (product / ONE, product % ONE);
+        let twice = remainder.abs() * 2;
+        let round_away = twice > ONE || (twice == ONE && quotient % 2 != 0);
+        let rounded = if round_away {
+            quotient + product.signum()
+        } else {
+            quotient
+        };
+
+        Some(Self(rounded))
+    }
+
+    /// By squaring, so each product is rounded: the result may differ from
+    /// the exact power in the last digit or so.
+    pub fn checked_powi(self, mut exponent: u32) -> Option<Self> {
+        let (mut res, mut base) = (Self::ONE, self);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                res = res.checked_mul(base)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.checked_mul(base)?;
+            }
+        }
+        Some(res)
+    }
+
+    fn to_exponent(self) -> Option<u32> {
+        (self.0 % ONE == 0)
+            .then(|| u32::try_from(self.0 / ONE).ok())
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError;
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid decimal literal")
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+/// Plain `[-]digits[.digits]` or scientific notation (`1e-7`) as `f32`
+/// prints it. Digits beyond `DECIMAL_DIGITS` are rejected, not rounded.
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (
+                mantissa,
+                exponent.parse::<i32>().map_err(|_| ParseDecimalError)?,
+            ),
+            None => (s, 0),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, mantissa),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() && frac.is_empty() || !all_digits(int) || !all_digits(frac) {
+            return Err(ParseDecimalError);
+        }
+
+        let digits = format!("{int}{frac}");
+        let scale = frac.len() as i32 - exponent;
+        let mut raw: i128 = digits.parse().map_err(|_| ParseDecimalError)?;
+        let shift = DECIMAL_DIGITS as i32 - scale;
+        if shift >= 0 {
+            raw = 10i128
+                .checked_pow(shift as u32)
+                .and_then(|factor| raw.checked_mul(factor))
+                .ok_or(ParseDecimalError)?;
+        } else {
+            let factor = 10i128
+                .checked_pow(shift.unsigned_abs())
+                .ok_or(ParseDecimalError)?;
+            if raw % factor != 0 {
+                return Err(ParseDecimalError);
+            }
+            raw /= factor;
+        }
+
+        Ok(Self(if negative { -raw } else { raw }))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let (int, frac) = (self.0.abs() / ONE, self.0.abs() % ONE);
+        let frac = format!("{frac:0width$}", width = DECIMAL_DIGITS as usize);
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            write!(f, "{sign}{int}")
+        } else {
+            write!(f, "{sign}{int}.{frac}")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DecimalError {
+    Overflow {
+        node: NodeId,
+    },
+    /// `0^0` under a strict `PowPolicy`.
+    PowDomain {
+        node: NodeId,
+    },
+    /// A constant baked into the graph has no exact `Decimal` form.
+    Inexact {
+        node: NodeId,
+        value: f32,
+    },
+    Unsupported {
+        node: NodeId,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow { node } => write!(f, "node {node}: decimal overflow"),
+            Self::PowDomain { node } => write!(f, "node {node}: 0^0 is undefined"),
+            Self::Inexact { node, value } => {
+                write!(f, "node {node}: {value} has no exact decimal form")
+            }
+            Self::Unsupported { node, reason } => write!(f, "node {node}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+impl CompiledGraph {
+    /// Evaluates a single row in `Decimal` arithmetic. `Pow` exponents must be
+    /// non-negative integers.
+    pub fn eval_decimal(&self, inputs: &[Decimal]) -> Result<Decimal, DecimalError> {
+        assert_eq!(
+            inputs.len(),
+            self.input_count(),
+            "Wrong number of input values"
+        );
+
+        let mut slots: Vec<Decimal> = Vec::with_capacity(self.instrs.len());
+        for (instr, &node) in self.instrs.iter().zip(self.ids.iter()) {
+            let overflow = DecimalError::Overflow { node };
+            let value = match instr {
+                Instr::Input(i) => inputs[*i],
+                Instr::Const(x) => {
+                    Decimal::from_f32(*x).ok_or(DecimalError::Inexact { node, value: *x })?
+                }
+                Instr::Binary(BinaryOp::Add, a, b) => {
+                    slots[*a].checked_add(slots[*b]).ok_or(overflow)?
+                }
+                Instr::Binary(BinaryOp::Mul, a, b) => {
+                    slots[*a].checked_mul(slots[*b]).ok_or(overflow)?
+                }
+                Instr::Binary(BinaryOp::Pow(policy), a, b) => {
+                    let (base, exponent) = (slots[*a], slots[*b]);
+                    let Some(exponent) = exponent.to_exponent() else {
+                        return Err(DecimalError::Unsupported {
+                            node,
+                            reason: "decimal exponents must be non-negative integers",
+                        });
+                    };
+                    let strict_zero = matches!(policy, PowPolicy::Error | PowPolicy::Nan);
+                    if base == Decimal::ZERO && exponent == 0 && strict_zero {
+                        return Err(DecimalError::PowDomain { node });
+                    }
+                    base.checked_powi(exponent).ok_or(overflow)?
+                }
+                Instr::Binary(BinaryOp::And, a, b) => {
+                    Decimal::from_bool(slots[*a].is_true() && slots[*b].is_true())
+                }
+                Instr::Binary(BinaryOp::Or, a, b) => {
+                    Decimal::from_bool(slots[*a].is_true() || slots[*b].is_true())
+                }
+                Instr::Binary(BinaryOp::Compare(cmp), a, b) => {
+                    Decimal::from_bool(cmp.apply(slots[*a], slots[*b]))
+                }
+                Instr::Unary(UnaryOp::Not, x) => Decimal::from_bool(!slots[*x].is_true()),
+                Instr::Unary(UnaryOp::Round { digits, mode }, x) => {
+                    let places = (DECIMAL_DIGITS as i32).saturating_sub(*digits);
+                    round_to_unit(slots[*x].0, places, *mode)
+                        .map(Decimal)
+                        .ok_or(overflow)?
+                }
+                Instr::Ternary(TernaryOp::MulAdd, a, b, c) => slots[*a]
+                    .checked_mul(slots[*b])
+                    .and_then(|ab| ab.checked_add(slots[*c]))
+                    .ok_or(overflow)?,
+                Instr::Ternary(TernaryOp::Select, a, b, c) => {
+                    if slots[*a].is_true() {
+                        slots[*b]
+                    } else {
+                        slots[*c]
+                    }
+                }
+                Instr::Unary(..) => {
+                    return Err(DecimalError::Unsupported {
+                        node,
+                        reason: "trigonometric ops have no exact decimal form",
+                    })
+                }
+                Instr::Custom(..) => {
+                    return Err(DecimalError::Unsupported {
+                        node,
+                        reason: "custom ops compute in f32",
+                    })
+                }
+            };
+            slots.push(value);
+        }
+
+        Ok(slots[self.instrs.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::{Node, RoundMode};
+
+    #[test]
+    fn round_to_extreme_digits() {
+        let x = Node::create_input(0f32);
+        let value: Decimal = "1234.5".parse().unwrap();
+        for (digits, expected) in [(i32::MIN, "0"), (-2, "1200"), (i32::MAX, "1234.5")] {
+            let round = Node::create_round(x.clone(), digits, RoundMode::HalfEven);
+            let compiled = CompiledGraph::compile(&round, std::slice::from_ref(&x));
+            let res = compiled.eval_decimal(&[value]).unwrap();
+            assert_eq!(res, expected.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn powi_by_squaring() {
+        let two = Decimal::from_int(2);
+        assert_eq!(two.checked_powi(0), Some(Decimal::ONE));
+        assert_eq!(two.checked_powi(10), Some(Decimal::from_int(1024)));
+        assert_eq!(two.checked_powi(64), Some(Decimal((1i128 << 64) * ONE)));
+        assert_eq!(two.checked_powi(100), None);
+        let half: Decimal = "0.5".parse().unwrap();
+        assert_eq!(half.checked_powi(3), "0.125".parse().ok());
+        // Would take billions of multiplications one at a time.
+        assert_eq!(Decimal::ONE.checked_powi(u32::MAX), Some(Decimal::ONE));
+        assert_eq!(
+            Decimal::from_int(-1).checked_powi(u32::MAX),
+            Some(Decimal::from_int(-1))
+        );
+    }
+}
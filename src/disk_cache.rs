@@ -0,0 +1,302 @@
+//! Results persisted across process restarts, one file per
+//! (graph structure, input values) pair.
+//!
+//! An entry holds the result, the input values' bits and the graph as
+//! `serialize` writes it, so that a hash collision reads as a miss. Custom
+//! ops are told apart by name and `CustomOp::identity`, e.g. external ops
+//! running different commands; ops sharing both share entries. Files are
+//! named `<structure>-<inputs>.cgcache`; others in the directory are left
+//! alone.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::computational_graph::{CachePolicy, EvalError, Node, NodeCelled};
+use crate::hash::{input_values_hash, structure_hash};
+use crate::serialize::serialize;
+
+const EXTENSION: &str = "cgcache";
+
+#[derive(Debug)]
+pub enum DiskCacheError {
+    Io(io::Error),
+    Eval(EvalError),
+}
+
+impl fmt::Display for DiskCacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "disk cache: {e}"),
+            Self::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DiskCacheError {}
+
+impl From<io::Error> for DiskCacheError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<EvalError> for DiskCacheError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Returns the persisted result for `node`'s subgraph at its current input
+    /// values, computing and persisting it on a miss. Graphs with `NoCache`
+    /// nodes, such as random ones, are computed every time and never
+    /// persisted.
+    pub fn compute(&self, node: &NodeCelled) -> Result<f32, DiskCacheError> {
+        let volatile = Node::topo_order(node)
+            .iter()
+            .any(|node| node.borrow().cache_policy() == CachePolicy::NoCache);
+        if volatile {
+            return Ok(node.borrow().try_compute()?);
+        }
+
+        let path = self.entry_path(node);
+        let key = entry_key(node);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                if let Some(value) = read_entry(&text, &key) {
+                    return Ok(value);
+                }
+            }
+            // A truncated or colliding entry is treated as a miss, and
+            // overwritten.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::InvalidData
+                ) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let computed = node.borrow().compute_tracked()?;
+        // Composites may draw random numbers inside their subgraphs.
+        if computed.volatile {
+            return Ok(computed.value);
+        }
+        // Write-then-rename, so a concurrent reader never sees a partial
+        // entry. Each writer has its own temporary file.
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+        let tmp = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, format!("{:08x}\n{key}", computed.value.to_bits()))?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(computed.value)
+    }
+
+    /// Removes every persisted entry.
+    pub fn clear(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let persisted = path.extension().is_some_and(|ext| ext == EXTENSION);
+            if persisted && entry.file_type()?.is_file() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry_path(&self, node: &NodeCelled) -> PathBuf {
+        let name = format!(
+            "{:016x}-{:016x}.{EXTENSION}",
+            structure_hash(node),
+            input_values_hash(node)
+        );
+        self.dir.join(name)
+    }
+}
+
+/// What an entry must hold besides the value to be `node`'s: the bits of the
+/// input values on one line, then the serialized graph, then a line per
+/// custom op with an identity, which `serialize` leaves out.
+fn entry_key(node: &NodeCelled) -> String {
+    let mut res = String::new();
+    for (i, input) in Node::inputs(node).iter().enumerate() {
+        if i > 0 {
+            res.push(' ');
+        }
+        write!(res, "{:08x}", input.borrow().compute().to_bits()).unwrap();
+    }
+    res.push('\n');
+    res.push_str(&serialize(std::slice::from_ref(node)));
+    let mut identities = Vec::new();
+    custom_identities(node, &mut HashSet::new(), &mut identities);
+    for (name, identity) in identities {
+        writeln!(res, "identity {name:?} {identity:?}").unwrap();
+    }
+    res
+}
+
+/// Names and identities of the custom ops below `node`, in composites'
+/// bodies too, without repeats.
+fn custom_identities(
+    node: &NodeCelled,
+    seen: &mut HashSet<*const RefCell<Node>>,
+    res: &mut Vec<(String, String)>,
+) {
+    for node in Node::topo_order(node) {
+        if !seen.insert(Rc::as_ptr(&node)) {
+            continue;
+        }
+        match &*node.borrow() {
+            Node::Custom { op, .. } => {
+                let entry = (op.name().to_string(), op.identity());
+                if !entry.1.is_empty() && !res.contains(&entry) {
+                    res.push(entry);
+                }
+            }
+            Node::Composite { graph, .. } => {
+                for output in graph.outputs() {
+                    custom_identities(output, seen, res);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The value in the entry `text` if it's for `key`.
+fn read_entry(text: &str, key: &str) -> Option<f32> {
+    let (value, rest) = text.split_once('\n')?;
+    if rest != key || value.len() != 8 {
+        return None;
+    }
+    u32::from_str_radix(value, 16).ok().map(f32::from_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::external::{Executor, ExternalOp};
+    use crate::function::Function;
+
+    fn cache(name: &str) -> DiskCache {
+        let dir = std::env::temp_dir().join(format!("cg-disk-cache-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        DiskCache::open(dir).unwrap()
+    }
+
+    fn entries(cache: &DiskCache) -> Vec<PathBuf> {
+        fs::read_dir(&cache.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect()
+    }
+
+    #[test]
+    fn reads_entries_for_the_same_graph_only() {
+        let cache = cache("collision");
+        let x = Node::create_input(3.0);
+        let node = Node::create_mul(x.clone(), x);
+        assert_eq!(cache.compute(&node).unwrap(), 9.0);
+        let path = cache.entry_path(&node);
+        let key = entry_key(&node);
+        assert_eq!(entries(&cache), vec![path.clone()]);
+
+        // An entry for this graph is read back.
+        fs::write(&path, format!("{:08x}\n{key}", 1f32.to_bits())).unwrap();
+        assert_eq!(cache.compute(&node).unwrap(), 1.0);
+
+        // One for another graph under the same name isn't.
+        let y = Node::create_input(3.0);
+        let other = entry_key(&Node::create_add(y.clone(), y));
+        fs::write(&path, format!("{:08x}\n{other}", 1f32.to_bits())).unwrap();
+        assert_eq!(cache.compute(&node).unwrap(), 9.0);
+        assert!(fs::read_to_string(&path).unwrap().ends_with(&key));
+    }
+
+    #[test]
+    fn volatile_graphs_are_not_persisted() {
+        let cache = cache("volatile");
+        let x = Node::create_input(3.0);
+        let node = Node::create_add(x.clone(), x);
+        node.borrow().set_cache_policy(CachePolicy::NoCache);
+        assert_eq!(cache.compute(&node).unwrap(), 6.0);
+        assert!(entries(&cache).is_empty());
+    }
+
+    #[test]
+    fn clear_keeps_other_files() {
+        let cache = cache("clear");
+        let other = cache.dir.join("notes.txt");
+        fs::write(&other, "keep").unwrap();
+        cache.compute(&Node::create_input(1.0)).unwrap();
+        assert_eq!(entries(&cache).len(), 2);
+        cache.clear().unwrap();
+        assert_eq!(entries(&cache), vec![other]);
+    }
+
+    /// Returns its value, whatever the op's name.
+    #[derive(Debug)]
+    struct Constant(f32);
+
+    impl Executor for Constant {
+        fn execute(&self, _: &str, _: &[f32]) -> Result<f32, String> {
+            Ok(self.0)
+        }
+
+        fn identity(&self) -> String {
+            format!("constant {}", self.0)
+        }
+    }
+
+    #[test]
+    fn tells_custom_ops_of_one_name_apart() {
+        let cache = cache("identity");
+        let x = Node::create_input(1.0);
+        let call = |value| {
+            let op = ExternalOp::new("quote", Arc::new(Constant(value)));
+            Node::create_add(Node::create_custom(op, vec![x.clone()]), x.clone())
+        };
+        let (first, second) = (call(2.0), call(5.0));
+        assert_ne!(Node::fingerprint(&first), Node::fingerprint(&second));
+        assert_eq!(cache.compute(&first).unwrap(), 3.0);
+        assert_eq!(cache.compute(&second).unwrap(), 6.0);
+        assert_eq!(entries(&cache).len(), 2);
+        assert!(entry_key(&second).ends_with("identity \"quote\" \"constant 5\"\n"));
+
+        // Inside calls too.
+        let f = Function::define("f", 1, |p| {
+            let op = ExternalOp::new("quote", Arc::new(Constant(7.0)));
+            Node::create_custom(op, p.to_vec())
+        });
+        assert_eq!(cache.compute(&f.call(vec![x.clone()])).unwrap(), 7.0);
+        assert_eq!(entries(&cache).len(), 3);
+        assert_eq!(cache.compute(&call(2.0)).unwrap(), 3.0);
+        assert_eq!(entries(&cache).len(), 3);
+    }
+}
@@ -0,0 +1,337 @@
+//! Einstein summation over tensors, so contractions such as matrix products,
+//! traces or batched dot products are one call instead of chains of ops.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::computational_graph::{Node, NodeCelled};
+use crate::tensor::Tensor;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EinsumError {
+    /// The subscripts aren't of the form `ij,jk->ik`.
+    Syntax { at: usize, reason: &'static str },
+    /// The subscripts list `expected` operands but `found` were given.
+    Operands { expected: usize, found: usize },
+    /// Operand `operand` has `rank` dimensions but `labels` subscripts.
+    Rank {
+        operand: usize,
+        labels: usize,
+        rank: usize,
+    },
+    /// `label` stands for dimensions of different sizes.
+    Dimension { label: char, sizes: (usize, usize) },
+}
+
+impl fmt::Display for EinsumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { at, reason } => write!(f, "at byte {at}: {reason}"),
+            Self::Operands { expected, found } => {
+                write!(f, "{expected} operands expected, {found} given")
+            }
+            Self::Rank {
+                operand,
+                labels,
+                rank,
+            } => write!(
+                f,
+                "operand {operand} has rank {rank} but {labels} subscripts"
+            ),
+            Self::Dimension { label, sizes } => {
+                write!(f, "'{label}' is both {} and {} long", sizes.0, sizes.1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EinsumError {}
+
+impl Tensor {
+    /// Sum of products of `operands` as given by `subscripts`, such as
+    /// `ij,jk->ik` for a matrix product. Each operand gets one letter per
+    /// dimension; letters missing from the output after `->` are summed over,
+    /// and a letter repeated within an operand takes its diagonal, so `ii->`
+    /// is a trace. Without `->`, the output has the letters used once, in
+    /// alphabetical order, as in NumPy.
+    ///
+    /// Every output element is a fresh sum of products of the operands'
+    /// nodes, `0` if there is nothing to sum.
+    pub fn einsum(subscripts: &str, operands: &[&Tensor]) -> Result<Tensor, EinsumError> {
+        let (inputs, output) = parse(subscripts)?;
+        if inputs.len() != operands.len() {
+            return Err(EinsumError::Operands {
+                expected: inputs.len(),
+                found: operands.len(),
+            });
+        }
+
+        // Size of each letter, in order of first appearance.
+        let mut labels: Vec<(char, usize)> = Vec::new();
+        for (i, (letters, operand)) in inputs.iter().zip(operands).enumerate() {
+            if letters.len() != operand.shape().len() {
+                return Err(EinsumError::Rank {
+                    operand: i,
+                    labels: letters.len(),
+                    rank: operand.shape().len(),
+                });
+            }
+            for (label, size) in letters.iter().zip(operand.shape()) {
+                match labels.iter().find(|(other, _)| other == label) {
+                    Some((_, other)) if other != size => {
+                        return Err(EinsumError::Dimension {
+                            label: *label,
+                            sizes: (*other, *size),
+                        })
+                    }
+                    Some(_) => {}
+                    None => labels.push((*label, *size)),
+                }
+            }
+        }
+        let output = match output {
+            Some(output) => output,
+            None => implicit_output(&inputs),
+        };
+        let (summed, summed_sizes): (Vec<_>, Vec<_>) = labels
+            .iter()
+            .filter(|(label, _)| !output.contains(label))
+            .copied()
+            .unzip();
+        let shape: Vec<_> = output
+            .iter()
+            .map(|label| labels.iter().find(|(other, _)| other == label).unwrap().1)
+            .collect();
+
+        let nodes = Assignments::new(&output, &shape)
+            .map(|mut index| {
+                let terms: Vec<_> = Assignments::new(&summed, &summed_sizes)
+                    .map(|inner| {
+                        index.extend(&inner);
+                        let factors = inputs.iter().zip(operands).map(|(letters, operand)| {
+                            let at: Vec<_> = letters.iter().map(|label| index[label]).collect();
+                            operand.get(&at).unwrap().clone()
+                        });
+                        let product = product(factors);
+                        for label in inner.keys() {
+                            index.remove(label);
+                        }
+                        product
+                    })
+                    .collect();
+                sum(terms)
+            })
+            .collect();
+        Ok(Tensor::new(shape, nodes).unwrap())
+    }
+}
+
+type Subscripts = (Vec<Vec<char>>, Option<Vec<char>>);
+
+fn parse(subscripts: &str) -> Result<Subscripts, EinsumError> {
+    let (inputs, output) = match subscripts.split_once("->") {
+        Some((inputs, output)) => (inputs, Some((inputs.len() + 2, output))),
+        None => (subscripts, None),
+    };
+    let letters = |start: usize, part: &str| -> Result<Vec<char>, EinsumError> {
+        part.char_indices()
+            .filter(|(_, c)| !c.is_whitespace())
+            .map(|(at, c)| match c {
+                'a'..='z' | 'A'..='Z' => Ok(c),
+                _ => Err(EinsumError::Syntax {
+                    at: start + at,
+                    reason: "expected a letter",
+                }),
+            })
+            .collect()
+    };
+
+    let mut start = 0;
+    let mut operands = Vec::new();
+    for part in inputs.split(',') {
+        operands.push(letters(start, part)?);
+        start += part.len() + 1;
+    }
+    let output = match output {
+        Some((start, output)) => {
+            let output = letters(start, output)?;
+            for (i, label) in output.iter().enumerate() {
+                if output[..i].contains(label) {
+                    return Err(EinsumError::Syntax {
+                        at: start,
+                        reason: "output letters must be distinct",
+                    });
+                }
+                if !operands.iter().any(|letters| letters.contains(label)) {
+                    return Err(EinsumError::Syntax {
+                        at: start,
+                        reason: "output letter missing from the operands",
+                    });
+                }
+            }
+            Some(output)
+        }
+        None => None,
+    };
+    Ok((operands, output))
+}
+
+/// Letters used exactly once, alphabetically.
+fn implicit_output(inputs: &[Vec<char>]) -> Vec<char> {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for label in inputs.iter().flatten() {
+        *counts.entry(*label).or_default() += 1;
+    }
+    let mut res: Vec<_> = counts
+        .into_iter()
+        .filter(|(_, count)| *count == 1)
+        .map(|(label, _)| label)
+        .collect();
+    res.sort_unstable();
+    res
+}
+
+/// Every assignment of values below `sizes` to `labels`, the last letter
+/// varying fastest.
+struct Assignments {
+    labels: Vec<char>,
+    sizes: Vec<usize>,
+    next: Option<Vec<usize>>,
+}
+
+impl Assignments {
+    fn new(labels: &[char], sizes: &[usize]) -> Self {
+        let empty = sizes.contains(&0);
+        Self {
+            labels: labels.to_vec(),
+            sizes: sizes.to_vec(),
+            next: (!empty).then(|| vec![0; sizes.len()]),
+        }
+    }
+}
+
+impl Iterator for Assignments {
+    type Item = HashMap<char, usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        let res = self
+            .labels
+            .iter()
+            .copied()
+            .zip(current.iter().copied())
+            .collect();
+
+        let mut next = current;
+        for i in (0..next.len()).rev() {
+            next[i] += 1;
+            if next[i] < self.sizes[i] {
+                self.next = Some(next);
+                break;
+            }
+            next[i] = 0;
+        }
+        Some(res)
+    }
+}
+
+fn product(factors: impl Iterator<Item = NodeCelled>) -> NodeCelled {
+    factors
+        .reduce(Node::create_mul)
+        .unwrap_or_else(|| Node::create_const(1f32))
+}
+
+fn sum(terms: Vec<NodeCelled>) -> NodeCelled {
+    terms
+        .into_iter()
+        .reduce(Node::create_add)
+        .unwrap_or_else(|| Node::create_const(0f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensor(shape: &[usize], values: &[f32]) -> Tensor {
+        Tensor::inputs(shape.to_vec(), values).unwrap()
+    }
+
+    fn einsum(subscripts: &str, operands: &[&Tensor]) -> (Vec<usize>, Vec<f32>) {
+        let res = Tensor::einsum(subscripts, operands).unwrap();
+        (res.shape().to_vec(), res.values().unwrap())
+    }
+
+    #[test]
+    fn contracts_matrices() {
+        let a = tensor(&[2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let b = tensor(&[3, 2], &[7.0, 8.0, 9.0, 10.0, 11.0, 12.0]);
+        // [1 2 3; 4 5 6] [7 8; 9 10; 11 12] = [58 64; 139 154]
+        let product = (vec![2, 2], vec![58.0, 64.0, 139.0, 154.0]);
+        assert_eq!(einsum("ij,jk->ik", &[&a, &b]), product);
+        assert_eq!(einsum("ij,jk", &[&a, &b]), product);
+        assert_eq!(
+            einsum("ij->ji", &[&a]),
+            (vec![3, 2], vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0])
+        );
+        assert_eq!(einsum("ij->j", &[&a]), (vec![3], vec![5.0, 7.0, 9.0]));
+        assert_eq!(einsum("ij->", &[&a]), (vec![], vec![21.0]));
+    }
+
+    #[test]
+    fn takes_diagonals_and_products_of_vectors() {
+        let m = tensor(&[2, 2], &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(einsum("ii->", &[&m]), (vec![], vec![5.0]));
+        assert_eq!(einsum("ii->i", &[&m]), (vec![2], vec![1.0, 4.0]));
+
+        let u = tensor(&[2], &[1.0, 2.0]);
+        let v = tensor(&[3], &[3.0, 4.0, 5.0]);
+        assert_eq!(
+            einsum("i,j->ij", &[&u, &v]),
+            (vec![2, 3], vec![3.0, 4.0, 5.0, 6.0, 8.0, 10.0])
+        );
+        assert_eq!(einsum("i,i->", &[&u, &u]), (vec![], vec![5.0]));
+        // Batched dot products: rows of m with rows of m.
+        assert_eq!(einsum("bi,bi->b", &[&m, &m]), (vec![2], vec![5.0, 25.0]));
+        let empty = tensor(&[0], &[]);
+        assert_eq!(einsum("i->", &[&empty]), (vec![], vec![0.0]));
+    }
+
+    #[test]
+    fn rejects_bad_subscripts() {
+        let m = tensor(&[2, 3], &[0.0; 6]);
+        let v = tensor(&[2], &[0.0; 2]);
+        let error = |subscripts: &str, operands: &[&Tensor]| {
+            Tensor::einsum(subscripts, operands).unwrap_err()
+        };
+        assert_eq!(
+            error("i1->i", &[&v]),
+            EinsumError::Syntax {
+                at: 1,
+                reason: "expected a letter",
+            }
+        );
+        assert_eq!(
+            error("ij->k", &[&m]).to_string(),
+            "at byte 4: output letter missing from the operands"
+        );
+        assert_eq!(
+            error("ij->ii", &[&m]).to_string(),
+            "at byte 4: output letters must be distinct"
+        );
+        assert_eq!(
+            error("ij,j->i", &[&m]),
+            EinsumError::Operands {
+                expected: 2,
+                found: 1,
+            }
+        );
+        assert_eq!(
+            error("i->i", &[&m]).to_string(),
+            "operand 0 has rank 2 but 1 subscripts"
+        );
+        assert_eq!(
+            error("ij,j->i", &[&m, &v]).to_string(),
+            "'j' is both 3 and 2 long"
+        );
+    }
+}
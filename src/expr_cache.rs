@@ -0,0 +1,154 @@
+//! Compiled formulas by source text, for servers evaluating the same user
+//! formulas over and over.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::compiled::CompiledGraph;
+use crate::computational_graph::{Node, NodeCelled};
+use crate::parser::{FormulaError, ParseError, Parser};
+
+/// Formulas parsed and compiled once, keyed by their text, keeping at most
+/// `capacity` of them and forgetting the least recently used. Rows given to
+/// the compiled graphs hold the `variables` passed to `new`, in order;
+/// variables the parser already had are baked in with their current values.
+#[derive(Debug)]
+pub struct ExprCache {
+    parser: Parser,
+    variables: Vec<NodeCelled>,
+    capacity: usize,
+    /// Graph and last use by text.
+    graphs: HashMap<String, (CompiledGraph, u64)>,
+    /// Texts by last use, least recent first.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ExprCache {
+    pub fn new(parser: Parser, variables: &[&str], capacity: usize) -> Self {
+        assert!(capacity > 0, "An expression cache needs room for a graph");
+        let nodes: Vec<_> = variables.iter().map(|_| Node::create_input(0.0)).collect();
+        let parser = variables
+            .iter()
+            .zip(&nodes)
+            .fold(parser, |parser, (name, node)| {
+                parser.with_variable(name, node.clone())
+            });
+        Self {
+            parser,
+            variables: nodes,
+            capacity,
+            graphs: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// `text` compiled, from the cache if it was compiled before. Parse
+    /// errors aren't remembered.
+    pub fn get(&mut self, text: &str) -> Result<CompiledGraph, ParseError> {
+        self.clock += 1;
+        let now = self.clock;
+        if let Some((graph, used)) = self.graphs.get_mut(text) {
+            let last = std::mem::replace(used, now);
+            let key = self.recency.remove(&last).unwrap();
+            self.recency.insert(now, key);
+            self.hits += 1;
+            return Ok(graph.clone());
+        }
+
+        self.misses += 1;
+        let output = self.parser.parse(text)?;
+        let graph = CompiledGraph::compile(&output, &self.variables);
+        if self.graphs.len() == self.capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.graphs.remove(&oldest);
+        }
+        self.graphs.insert(text.to_string(), (graph.clone(), now));
+        self.recency.insert(now, text.to_string());
+        Ok(graph)
+    }
+
+    /// Value of `text` with the variables at `values`.
+    pub fn eval(&mut self, text: &str, values: &[f32]) -> Result<f32, FormulaError> {
+        Ok(self.get(text)?.eval(values)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.graphs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Calls that parsed the text.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.graphs.clear();
+        self.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::EvalError;
+
+    #[test]
+    fn compiles_each_text_once() {
+        let rate = Node::create_input(0.5f32);
+        let parser = Parser::new().with_variable("rate", rate.clone());
+        let mut cache = ExprCache::new(parser, &["x", "y"], 2);
+
+        assert_eq!(cache.eval("x * rate + y", &[4.0, 1.0]).unwrap(), 3f32);
+        assert_eq!(cache.eval("x * rate + y", &[2.0, 0.0]).unwrap(), 1f32);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        // Baked in when compiled.
+        rate.borrow().set(1f32);
+        assert_eq!(cache.eval("x * rate + y", &[2.0, 0.0]).unwrap(), 1f32);
+
+        cache.eval("x - y", &[1.0, 1.0]).unwrap();
+        cache.eval("x * rate + y", &[1.0, 1.0]).unwrap();
+        // Full: `x - y` was used longest ago.
+        cache.eval("y", &[1.0, 1.0]).unwrap();
+        assert_eq!(cache.len(), 2);
+        let misses = cache.misses();
+        cache.eval("x * rate + y", &[1.0, 1.0]).unwrap();
+        cache.eval("x - y", &[1.0, 1.0]).unwrap();
+        assert_eq!(cache.misses(), misses + 1);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn reports_errors_without_caching_them() {
+        let mut cache = ExprCache::new(Parser::new(), &["x"], 4);
+        assert!(matches!(
+            cache.eval("x +", &[1.0]),
+            Err(FormulaError::Parse(_))
+        ));
+        assert!(cache.is_empty());
+        let res = cache.eval("x + 1", &[1.0, 2.0]);
+        assert!(matches!(
+            res,
+            Err(FormulaError::Eval(EvalError::Inputs {
+                expected: 1,
+                found: 2
+            }))
+        ));
+        assert_eq!(cache.len(), 1);
+    }
+}
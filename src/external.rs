@@ -0,0 +1,302 @@
+//! Ops evaluated by something outside the graph (another process, a remote
+//! service), memoized by argument values so revisited points skip the call.
+//!
+//! Calls are synchronous: computing a node waits for its executor, as the
+//! graph evaluates on one thread. To overlap slow calls, `ExternalOp::prefetch`
+//! makes a batch of them at once, on threads of their own, and memoizes the
+//! results for the nodes that need them later.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::{panic, thread};
+
+use crate::computational_graph::CustomOp;
+use crate::memo::Entries;
+
+/// Backend performing the actual computation of an `ExternalOp`.
+pub trait Executor: fmt::Debug + Send + Sync {
+    fn execute(&self, op: &str, args: &[f32]) -> Result<f32, String>;
+
+    /// The op's `CustomOp::identity`: what tells this executor apart from
+    /// others backing ops of the same name.
+    fn identity(&self) -> String {
+        String::new()
+    }
+}
+
+/// `CustomOp` forwarding to an `Executor`. One instance can back any number of
+/// nodes, they all share its memo table.
+#[derive(Debug)]
+pub struct ExternalOp {
+    name: String,
+    executor: Arc<dyn Executor>,
+    capacity: Option<usize>,
+    memo: Mutex<Entries>,
+}
+
+impl ExternalOp {
+    /// Op with an unbounded memo table, which keeps a value for every point
+    /// it's called at until `clear_memo`.
+    pub fn new(name: impl Into<String>, executor: Arc<dyn Executor>) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.into(),
+            executor,
+            capacity: None,
+            memo: Mutex::default(),
+        })
+    }
+
+    /// Op keeping at most `capacity` values, forgetting the least recently
+    /// used.
+    pub fn with_capacity(
+        name: impl Into<String>,
+        executor: Arc<dyn Executor>,
+        capacity: usize,
+    ) -> Arc<Self> {
+        assert!(capacity > 0, "A memo table needs room for a value");
+        Arc::new(Self {
+            name: name.into(),
+            executor,
+            capacity: Some(capacity),
+            memo: Mutex::default(),
+        })
+    }
+
+    pub fn memo_len(&self) -> usize {
+        self.memo.lock().unwrap().len()
+    }
+
+    pub fn clear_memo(&self) {
+        self.memo.lock().unwrap().clear();
+    }
+
+    /// Calls the executor at every point of `points` not memoized yet, all at
+    /// once on scoped threads, and memoizes the results, so computing nodes at
+    /// these points doesn't wait. Returns the result at each point. With a
+    /// capacity below the number of points, early ones may be forgotten.
+    pub fn prefetch(&self, points: &[Vec<f32>]) -> Vec<Result<f32, String>> {
+        thread::scope(|scope| {
+            let calls: Vec<_> = points
+                .iter()
+                .map(|args| scope.spawn(move || self.compute(args)))
+                .collect();
+            calls
+                .into_iter()
+                .map(|call| {
+                    call.join()
+                        .unwrap_or_else(|panic| panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+}
+
+impl CustomOp for ExternalOp {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        let key: Vec<u32> = args.iter().map(|arg| arg.to_bits()).collect();
+        if let Some(memoized) = self.memo.lock().unwrap().get(&key) {
+            return Ok(memoized);
+        }
+
+        // Not holding the lock here: the call may be slow or re-enter the graph.
+        let computed = self.executor.execute(&self.name, args)?;
+        self.memo
+            .lock()
+            .unwrap()
+            .insert(key, computed, self.capacity);
+
+        Ok(computed)
+    }
+
+    fn identity(&self) -> String {
+        self.executor.identity()
+    }
+}
+
+/// Runs `program [leading args] <op> <args...>` and parses its trimmed stdout
+/// as the result.
+#[derive(Debug, Clone)]
+pub struct ProcessExecutor {
+    program: PathBuf,
+    leading_args: Vec<String>,
+}
+
+impl ProcessExecutor {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            leading_args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.leading_args.push(arg.into());
+        self
+    }
+}
+
+impl Executor for ProcessExecutor {
+    fn execute(&self, op: &str, args: &[f32]) -> Result<f32, String> {
+        let output = Command::new(&self.program)
+            .args(&self.leading_args)
+            .arg(op)
+            .args(args.iter().map(|arg| arg.to_string()))
+            .output()
+            .map_err(|e| format!("failed to run {}: {e}", self.program.display()))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "{} exited with {}: {}",
+                self.program.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        stdout
+            .trim()
+            .parse()
+            .map_err(|e| format!("unexpected output {:?}: {e}", stdout.trim()))
+    }
+
+    /// The command line before the op's name.
+    fn identity(&self) -> String {
+        let mut res = self.program.display().to_string();
+        for arg in &self.leading_args {
+            res.push(' ');
+            res.push_str(arg);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    use crate::computational_graph::Node;
+
+    /// Sums its operands, counting calls, after `delay`; fails on negative
+    /// sums.
+    #[derive(Debug, Default)]
+    struct Adder {
+        calls: Mutex<usize>,
+        delay: Duration,
+    }
+
+    impl Executor for Adder {
+        fn execute(&self, op: &str, args: &[f32]) -> Result<f32, String> {
+            *self.calls.lock().unwrap() += 1;
+            thread::sleep(self.delay);
+            let sum: f32 = args.iter().sum();
+            match sum < 0.0 {
+                true => Err(format!("{op} went negative")),
+                false => Ok(sum),
+            }
+        }
+    }
+
+    fn calls(adder: &Adder) -> usize {
+        *adder.calls.lock().unwrap()
+    }
+
+    #[test]
+    fn memoizes_calls_across_nodes() {
+        let adder = Arc::new(Adder::default());
+        let op = ExternalOp::new("add", adder.clone());
+        let x = Node::create_input(1f32);
+        let first = Node::create_custom(op.clone(), vec![x.clone(), Node::create_input(2f32)]);
+        let second = Node::create_custom(op.clone(), vec![Node::create_input(1f32), x.clone()]);
+        assert_eq!(first.borrow().compute(), 3f32);
+        assert_eq!(calls(&adder), 1);
+
+        x.borrow().set(2f32);
+        assert_eq!(first.borrow().compute(), 4f32);
+        x.borrow().set(1f32);
+        assert_eq!(first.borrow().compute(), 3f32);
+        assert_eq!(op.compute(&[1.0, 1.0]), Ok(2f32));
+        assert_eq!(second.borrow().compute(), 2f32);
+        assert_eq!((calls(&adder), op.memo_len()), (3, 3));
+
+        assert_eq!(op.compute(&[-1.0, 0.0]), Err("add went negative".into()));
+        assert_eq!(op.compute(&[-1.0, 0.0]), Err("add went negative".into()));
+        assert_eq!((calls(&adder), op.memo_len()), (5, 3));
+        op.clear_memo();
+        assert_eq!(op.memo_len(), 0);
+    }
+
+    #[test]
+    fn forgets_least_recently_used_values() {
+        let adder = Arc::new(Adder::default());
+        let op = ExternalOp::with_capacity("add", adder.clone(), 2);
+        op.compute(&[1.0]).unwrap();
+        op.compute(&[2.0]).unwrap();
+        op.compute(&[1.0]).unwrap();
+        op.compute(&[3.0]).unwrap();
+        assert_eq!((calls(&adder), op.memo_len()), (3, 2));
+        op.compute(&[1.0]).unwrap();
+        assert_eq!(calls(&adder), 3);
+        op.compute(&[2.0]).unwrap();
+        assert_eq!(calls(&adder), 4);
+    }
+
+    #[test]
+    fn prefetches_concurrently() {
+        let adder = Arc::new(Adder {
+            delay: Duration::from_millis(100),
+            ..Adder::default()
+        });
+        let op = ExternalOp::new("add", adder.clone());
+        let points: Vec<_> = (0..8).map(|i| vec![i as f32, 1.0]).collect();
+        let start = Instant::now();
+        let values = op.prefetch(&points);
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(values[7], Ok(8f32));
+        assert_eq!(
+            op.prefetch(&[vec![-2.0, 1.0]]),
+            [Err("add went negative".into())]
+        );
+
+        let node =
+            Node::create_custom(op, vec![Node::create_input(3f32), Node::create_input(1f32)]);
+        assert_eq!(node.borrow().compute(), 4f32);
+        assert_eq!(calls(&adder), 9);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_processes() {
+        let echo = ProcessExecutor::new("sh").arg("-c").arg("echo \" $1 \"");
+        assert_eq!(echo.execute("op", &[2.5, 1.0]), Ok(2.5f32));
+        assert_eq!(echo.identity(), "sh -c echo \" $1 \"");
+
+        let res = ProcessExecutor::new("sh")
+            .arg("-c")
+            .arg("echo no")
+            .execute("op", &[]);
+        assert_eq!(
+            res,
+            Err("unexpected output \"no\": invalid float literal".into())
+        );
+        let failing = ProcessExecutor::new("sh")
+            .arg("-c")
+            .arg("echo oops >&2; exit 3");
+        let error = failing.execute("op", &[]).unwrap_err();
+        assert!(
+            error.starts_with("sh exited with") && error.ends_with(": oops"),
+            "{error}"
+        );
+        let missing = ProcessExecutor::new("/nonexistent/program").execute("op", &[]);
+        assert!(missing
+            .unwrap_err()
+            .starts_with("failed to run /nonexistent/program"));
+    }
+}
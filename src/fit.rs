@@ -0,0 +1,334 @@
+//! Calibrating parameters of a graph to observed data, by Levenberg-Marquardt
+//! over the graph's own gradients.
+
+use crate::autodiff::{gradient, GradError};
+use crate::computational_graph::NodeCelled;
+use crate::constraint::ConstraintError;
+
+/// Result of a fit. The parameter inputs are left at `params`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fit {
+    pub params: Vec<f32>,
+    /// Sum of squared residuals at `params`.
+    pub cost: f32,
+    pub iterations: usize,
+    /// Whether the cost stopped improving before `max_iterations`.
+    pub converged: bool,
+}
+
+/// Levenberg-Marquardt settings.
+#[derive(Debug, Clone)]
+pub struct LeastSquares {
+    max_iterations: usize,
+    tolerance: f32,
+}
+
+impl Default for LeastSquares {
+    fn default() -> Self {
+        Self {
+            max_iterations: 100,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+impl LeastSquares {
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Converged once a step improves the cost by less than this fraction.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Values of the `params` inputs minimizing the squared differences
+    /// between `output` and each sample's target, with `inputs` set to the
+    /// sample's values. Starts from the parameters' current values, and
+    /// restores `inputs` afterwards. Steps taking a parameter to a value its
+    /// constraint rejects are refused like ones raising the cost, and sample
+    /// values an input's constraint rejects fail the fit.
+    pub fn fit(
+        &self,
+        output: &NodeCelled,
+        params: &[NodeCelled],
+        inputs: &[NodeCelled],
+        data: &[(Vec<f32>, f32)],
+    ) -> Result<Fit, GradError> {
+        let saved: Vec<_> = inputs
+            .iter()
+            .map(|input| input.borrow().compute())
+            .collect();
+        let res = self.minimize(output, params, inputs, data);
+        for (input, x) in inputs.iter().zip(saved) {
+            input.borrow().restore(x);
+        }
+        res
+    }
+
+    fn minimize(
+        &self,
+        output: &NodeCelled,
+        params: &[NodeCelled],
+        inputs: &[NodeCelled],
+        data: &[(Vec<f32>, f32)],
+    ) -> Result<Fit, GradError> {
+        let problem = Problem {
+            output,
+            params,
+            inputs,
+            data,
+        };
+        let start: Vec<f32> = params
+            .iter()
+            .map(|param| param.borrow().compute())
+            .collect();
+        let mut current = start.clone();
+        let mut cost = problem.cost(&current)?;
+        let mut damping = 1e-3;
+
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+            let (normal, rhs) = problem.normal_equations(&current)?;
+
+            // Raise the damping until a step improves the cost, which it
+            // eventually does as the step shrinks toward the gradient.
+            loop {
+                if damping > 1e12 {
+                    // No step helps: a minimum, as far as `f32` can tell.
+                    converged = true;
+                    break;
+                }
+                let mut damped = normal.clone();
+                for (i, row) in damped.iter_mut().enumerate() {
+                    row[i] += damping * normal[i][i].max(1e-12);
+                }
+                let Some(step) = solve(damped, rhs.clone()) else {
+                    damping *= 10.0;
+                    continue;
+                };
+
+                let candidate: Vec<_> = current
+                    .iter()
+                    .zip(&step)
+                    .map(|(x, dx)| x + *dx as f32)
+                    .collect();
+                let candidate_cost = problem.cost(&candidate)?;
+                if candidate_cost <= cost {
+                    converged = cost - candidate_cost <= self.tolerance * cost;
+                    current = candidate;
+                    cost = candidate_cost;
+                    damping = (damping / 10.0).max(1e-12);
+                    break;
+                }
+                damping *= 10.0;
+            }
+        }
+
+        // Probes aren't changes: the parameters move from the start to the
+        // fit in one.
+        for ((param, start), x) in params.iter().zip(start).zip(&current) {
+            param.borrow().restore(start);
+            param.borrow().try_set(*x)?;
+        }
+        Ok(Fit {
+            params: current,
+            cost,
+            iterations,
+            converged,
+        })
+    }
+}
+
+/// `LeastSquares::fit` with the default settings.
+pub fn least_squares(
+    output: &NodeCelled,
+    params: &[NodeCelled],
+    inputs: &[NodeCelled],
+    data: &[(Vec<f32>, f32)],
+) -> Result<Fit, GradError> {
+    LeastSquares::default().fit(output, params, inputs, data)
+}
+
+struct Problem<'a> {
+    output: &'a NodeCelled,
+    params: &'a [NodeCelled],
+    inputs: &'a [NodeCelled],
+    data: &'a [(Vec<f32>, f32)],
+}
+
+impl Problem<'_> {
+    fn set_params(&self, values: &[f32]) -> Result<(), ConstraintError> {
+        for (param, x) in self.params.iter().zip(values) {
+            param.borrow().probe(*x)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the inputs to a sample's values, returning its target.
+    fn set_sample(&self, sample: &(Vec<f32>, f32)) -> Result<f32, ConstraintError> {
+        assert_eq!(sample.0.len(), self.inputs.len(), "One value per input");
+        for (input, x) in self.inputs.iter().zip(&sample.0) {
+            input.borrow().probe(*x)?;
+        }
+        Ok(sample.1)
+    }
+
+    /// Infinite at parameters their constraints reject.
+    fn cost(&self, params: &[f32]) -> Result<f32, GradError> {
+        if self.set_params(params).is_err() {
+            return Ok(f32::INFINITY);
+        }
+        let mut res = 0.0;
+        for sample in self.data {
+            let target = self.set_sample(sample)?;
+            let residual = self.output.borrow().try_compute()? - target;
+            res += residual * residual;
+        }
+        Ok(res)
+    }
+
+    /// `JᵀJ` and `-Jᵀr` for the Jacobian `J` of the residuals `r`.
+    fn normal_equations(&self, params: &[f32]) -> Result<(Vec<Vec<f64>>, Vec<f64>), GradError> {
+        self.set_params(params)?;
+        let n = params.len();
+        let mut normal = vec![vec![0.0; n]; n];
+        let mut rhs = vec![0.0; n];
+        for sample in self.data {
+            let target = self.set_sample(sample)?;
+            let residual = (self.output.borrow().try_compute()? - target) as f64;
+            let row = gradient(self.output, self.params)?;
+            for i in 0..n {
+                rhs[i] -= row[i] as f64 * residual;
+                for j in 0..n {
+                    normal[i][j] += row[i] as f64 * row[j] as f64;
+                }
+            }
+        }
+        Ok((normal, rhs))
+    }
+}
+
+/// `a x = b` by Gaussian elimination with partial pivoting, `None` if `a` is
+/// singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let factor = a[row][col] / pivot_row[col];
+            for (x, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+    use crate::constraint::{Constraint, Violation};
+
+    /// `a * x + b`, with its parameters and input.
+    fn line() -> (NodeCelled, [NodeCelled; 2], NodeCelled) {
+        let a = Node::create_input(1.0f32);
+        let b = Node::create_input(0.0f32);
+        let x = Node::create_input(5.0f32);
+        let y = Node::create_add(Node::create_mul(a.clone(), x.clone()), b.clone());
+        (y, [a, b], x)
+    }
+
+    fn samples(f: impl Fn(f32) -> f32) -> Vec<(Vec<f32>, f32)> {
+        (0..10).map(|i| (vec![i as f32], f(i as f32))).collect()
+    }
+
+    #[test]
+    fn recovers_known_parameters() {
+        let (y, params, x) = line();
+        let fit = least_squares(
+            &y,
+            &params,
+            std::slice::from_ref(&x),
+            &samples(|x| 2.0 * x - 1.0),
+        )
+        .unwrap();
+        assert!(fit.converged);
+        assert!((fit.params[0] - 2.0).abs() < 1e-4, "{fit:?}");
+        assert!((fit.params[1] + 1.0).abs() < 1e-4, "{fit:?}");
+        assert!(fit.cost < 1e-6);
+
+        // Parameters are left at the fit, inputs as they were.
+        assert_eq!(params[0].borrow().compute(), fit.params[0]);
+        assert_eq!(x.borrow().compute(), 5.0);
+        assert!((y.borrow().compute() - 9.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fits_nonlinear_models() {
+        // `amplitude * sin(frequency * x)`, from near the answer.
+        let amplitude = Node::create_input(1.0f32);
+        let frequency = Node::create_input(0.45f32);
+        let x = Node::create_input(0.0f32);
+        let y = Node::create_mul(
+            amplitude.clone(),
+            Node::create_sin(Node::create_mul(frequency.clone(), x.clone())),
+        );
+        let data = samples(|x| 3.0 * (0.5 * x).sin());
+        let fit = LeastSquares::default()
+            .with_max_iterations(200)
+            .fit(&y, &[amplitude, frequency], &[x], &data)
+            .unwrap();
+        assert!((fit.params[0] - 3.0).abs() < 1e-3, "{fit:?}");
+        assert!((fit.params[1] - 0.5).abs() < 1e-4, "{fit:?}");
+    }
+
+    #[test]
+    fn keeps_parameters_within_constraints() {
+        let (y, params, x) = line();
+        params[0]
+            .borrow()
+            .set_constraint(Constraint::new().with_max(1.5));
+        let data = samples(|x| 2.0 * x - 1.0);
+        let fit = least_squares(&y, &params, std::slice::from_ref(&x), &data).unwrap();
+        assert!(fit.params[0] <= 1.5, "{fit:?}");
+        assert!(fit.cost > 1.0);
+        assert_eq!(params[0].borrow().compute(), fit.params[0]);
+    }
+
+    #[test]
+    fn fails_on_samples_inputs_reject() {
+        let (y, params, x) = line();
+        x.borrow().set_constraint(Constraint::new().with_max(8.0));
+        let data = samples(|x| 2.0 * x - 1.0);
+        let res = least_squares(&y, &params, std::slice::from_ref(&x), &data);
+        assert!(matches!(
+            res,
+            Err(GradError::Constraint(ConstraintError {
+                value: 9.0,
+                violation: Violation::AboveMax(8.0),
+                ..
+            }))
+        ));
+        assert_eq!(x.borrow().compute(), 5.0);
+        assert_eq!(params[0].borrow().compute(), 1.0);
+    }
+}
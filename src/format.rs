@@ -0,0 +1,341 @@
+//! Text rendering of node values, for reports.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::computational_graph::{EvalError, NodeCelled};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// `{` or `}` without its partner at byte `at`.
+    Unbalanced { at: usize },
+    /// A placeholder that isn't `{}`, `{N}`, `{:.P}` or `{N:.P}`.
+    BadPlaceholder { placeholder: String },
+    /// A placeholder refers to argument `index`, past the last one.
+    MissingArg { index: usize },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unbalanced { at } => write!(f, "unbalanced brace at byte {at}"),
+            Self::BadPlaceholder { placeholder } => {
+                write!(f, "invalid placeholder {{{placeholder}}}")
+            }
+            Self::MissingArg { index } => write!(f, "no argument {index}"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// How numbers are written for end users: the decimal separator, and the
+/// separator between groups of three digits if any, e.g. `1.234,5` with
+/// `Locale::new(',', Some('.'))`. The default is `1234.5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    decimal: char,
+    thousands: Option<char>,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal: '.',
+            thousands: None,
+        }
+    }
+}
+
+impl Locale {
+    pub fn new(decimal: char, thousands: Option<char>) -> Self {
+        let valid = |c: char| !c.is_alphanumeric() && !matches!(c, '-' | '+');
+        assert!(
+            valid(decimal) && thousands.is_none_or(valid) && thousands != Some(decimal),
+            "Separators must be distinct and can't be letters, digits or signs"
+        );
+        Self { decimal, thousands }
+    }
+
+    pub fn decimal(&self) -> char {
+        self.decimal
+    }
+
+    pub fn thousands(&self) -> Option<char> {
+        self.thousands
+    }
+
+    /// What separates function arguments in formulas: `;` where `,` is the
+    /// decimal separator, as in spreadsheets, and `,` otherwise.
+    pub fn separator(&self) -> char {
+        if self.decimal == ',' {
+            ';'
+        } else {
+            ','
+        }
+    }
+
+    /// `x` with `precision` decimals if given, or as many as needed.
+    pub fn format(&self, x: f32, precision: Option<usize>) -> String {
+        let text = match precision {
+            Some(precision) => format!("{x:.precision$}"),
+            None => x.to_string(),
+        };
+        if !x.is_finite() {
+            return text;
+        }
+
+        let (sign, digits) = text.split_at(usize::from(text.starts_with('-')));
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let mut res = sign.to_string();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                res.extend(self.thousands);
+            }
+            res.push(digit);
+        }
+        if !fraction.is_empty() {
+            res.push(self.decimal);
+            res.push_str(fraction);
+        }
+        res
+    }
+
+    /// Number written in this locale, without a sign. Thousands separators
+    /// are optional, but must be followed by exactly three digits.
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        if text.is_empty() || self.number_len(text) != text.len() {
+            return None;
+        }
+        let text: String = text
+            .chars()
+            .filter(|c| Some(*c) != self.thousands)
+            .map(|c| if c == self.decimal { '.' } else { c })
+            .collect();
+        text.parse().ok()
+    }
+
+    /// Length of the digits and separators starting `text`. Decimal
+    /// separators are all taken, so that `1.2.3` is one bad number rather
+    /// than two.
+    pub(crate) fn number_len(&self, text: &str) -> usize {
+        let mut len = 0;
+        let mut fraction = false;
+        while let Some(c) = text[len..].chars().next() {
+            let after = &text[len + c.len_utf8()..];
+            let group = || after.bytes().take_while(u8::is_ascii_digit).count() == 3;
+            if c.is_ascii_digit() {
+                len += 1;
+            } else if c == self.decimal {
+                fraction = true;
+                len += c.len_utf8();
+            } else if Some(c) == self.thousands && !fraction && len > 0 && group() {
+                len += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+        len
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Arg {
+        index: usize,
+        precision: Option<usize>,
+    },
+}
+
+/// Template string over the values of `args`, such as
+/// `"total: {0:.2} ({1} items)"`. `{}` takes the next argument, `{{` and `}}`
+/// are literal braces. Rendering reuses the last string while no argument
+/// value changed, and node caches spare recomputing the arguments.
+#[derive(Debug)]
+pub struct Format {
+    pieces: Vec<Piece>,
+    args: Vec<NodeCelled>,
+    locale: Locale,
+    /// Argument values the string was rendered from.
+    cache: RefCell<Option<(Vec<u32>, Rc<str>)>>,
+}
+
+impl Format {
+    pub fn new(template: &str, args: Vec<NodeCelled>) -> Result<Self, FormatError> {
+        let pieces = parse(template)?;
+        for piece in &pieces {
+            if let Piece::Arg { index, .. } = piece {
+                if *index >= args.len() {
+                    return Err(FormatError::MissingArg { index: *index });
+                }
+            }
+        }
+
+        Ok(Self {
+            pieces,
+            args,
+            locale: Locale::default(),
+            cache: RefCell::new(None),
+        })
+    }
+
+    /// Writes values with `locale`'s separators.
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        *self.cache.get_mut() = None;
+        self
+    }
+
+    pub fn args(&self) -> &[NodeCelled] {
+        &self.args
+    }
+
+    /// Panics on evaluation errors, see `try_render`.
+    pub fn render(&self) -> Rc<str> {
+        self.try_render().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_render(&self) -> Result<Rc<str>, EvalError> {
+        let values = self
+            .args
+            .iter()
+            .map(|arg| arg.borrow().try_compute())
+            .collect::<Result<Vec<_>, _>>()?;
+        let bits: Vec<_> = values.iter().map(|value| value.to_bits()).collect();
+
+        if let Some((cached_bits, text)) = &*self.cache.borrow() {
+            if *cached_bits == bits {
+                return Ok(text.clone());
+            }
+        }
+
+        let mut text = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(s) => text.push_str(s),
+                Piece::Arg { index, precision } => {
+                    text.push_str(&self.locale.format(values[*index], *precision))
+                }
+            }
+        }
+
+        let text: Rc<str> = text.into();
+        *self.cache.borrow_mut() = Some((bits, text.clone()));
+        Ok(text)
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Piece>, FormatError> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut next_arg = 0;
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|&(_, c)| c == '{').is_some() => text.push('{'),
+            '}' if chars.next_if(|&(_, c)| c == '}').is_some() => text.push('}'),
+            '}' => return Err(FormatError::Unbalanced { at }),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => placeholder.push(c),
+                        None => return Err(FormatError::Unbalanced { at }),
+                    }
+                }
+
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(parse_placeholder(&placeholder, &mut next_arg)?);
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn parse_placeholder(placeholder: &str, next_arg: &mut usize) -> Result<Piece, FormatError> {
+    let bad = || FormatError::BadPlaceholder {
+        placeholder: placeholder.to_string(),
+    };
+
+    let (index, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+    let index = if index.is_empty() {
+        *next_arg += 1;
+        *next_arg - 1
+    } else {
+        index.parse().map_err(|_| bad())?
+    };
+    let precision = match spec {
+        "" => None,
+        spec => {
+            let precision = spec.strip_prefix('.').ok_or_else(bad)?;
+            Some(precision.parse().map_err(|_| bad())?)
+        }
+    };
+
+    Ok(Piece::Arg { index, precision })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    #[test]
+    fn renders_templates() {
+        let (total, count) = (Node::create_input(1234.5f32), Node::create_input(3f32));
+        let format = Format::new(
+            "total: {0:.2} ({1} items), {{{}}}",
+            vec![total.clone(), count.clone()],
+        )
+        .unwrap();
+        assert_eq!(&*format.render(), "total: 1234.50 (3 items), {1234.5}");
+
+        let rendered = format.render();
+        assert!(Rc::ptr_eq(&rendered, &format.render()));
+        count.borrow().set(4f32);
+        assert_eq!(&*format.render(), "total: 1234.50 (4 items), {1234.5}");
+
+        let format = format.with_locale(Locale::new(',', Some('.')));
+        assert_eq!(&*format.render(), "total: 1.234,50 (4 items), {1.234,5}");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        let args = || vec![Node::create_input(1f32)];
+        let error = |template| Format::new(template, args()).unwrap_err();
+        assert_eq!(error("a {0"), FormatError::Unbalanced { at: 2 });
+        assert_eq!(error("a }"), FormatError::Unbalanced { at: 2 });
+        assert_eq!(error("{} {}").to_string(), "no argument 1");
+        assert_eq!(error("{x}").to_string(), "invalid placeholder {x}");
+        assert_eq!(error("{0:2}").to_string(), "invalid placeholder {0:2}");
+    }
+
+    #[test]
+    fn formats_and_parses_in_locales() {
+        let german = Locale::new(',', Some('.'));
+        assert_eq!(german.format(-1234567.5, Some(2)), "-1.234.567,50");
+        assert_eq!(german.format(999.0, None), "999");
+        assert_eq!(german.format(f32::INFINITY, Some(2)), "inf");
+        assert_eq!(Locale::default().format(1234.5, None), "1234.5");
+        assert_eq!(german.separator(), ';');
+        assert_eq!(Locale::default().separator(), ',');
+
+        assert_eq!(german.parse("1.234,5"), Some(1234.5));
+        assert_eq!(german.parse("1234,5"), Some(1234.5));
+        assert_eq!(german.parse("1.23,5"), None);
+        assert_eq!(german.parse("1,2,3"), None);
+        assert_eq!(german.parse("-1"), None);
+        assert_eq!(german.number_len("12,5 + 1"), 4);
+    }
+}
@@ -0,0 +1,94 @@
+//! Named functions defined once as a graph and called from many places.
+
+use std::rc::Rc;
+
+use crate::computational_graph::{Node, NodeCelled, Subgraph};
+
+/// `f(a, b) = a * sin(b)` style definition. Every call site is a separate
+/// `Composite` node with its own cache, all sharing this one body.
+#[derive(Debug, Clone)]
+pub struct Function {
+    graph: Rc<Subgraph>,
+}
+
+impl Function {
+    /// `params` are the `Input` nodes `body` reads its arguments from.
+    pub fn new(name: impl Into<String>, params: Vec<NodeCelled>, body: NodeCelled) -> Self {
+        Self {
+            graph: Subgraph::named(name, params, vec![body]),
+        }
+    }
+
+    /// Creates `arity` fresh parameters and hands them to `body`.
+    pub fn define(
+        name: impl Into<String>,
+        arity: usize,
+        body: impl FnOnce(&[NodeCelled]) -> NodeCelled,
+    ) -> Self {
+        let params: Vec<_> = (0..arity).map(|_| Node::create_input(0f32)).collect();
+        let body = body(&params);
+        Self::new(name, params, body)
+    }
+
+    pub fn name(&self) -> &str {
+        self.graph.name().unwrap()
+    }
+
+    pub fn arity(&self) -> usize {
+        self.graph.inputs().len()
+    }
+
+    /// New call site applying the function to `args`.
+    pub fn call(&self, args: Vec<NodeCelled>) -> NodeCelled {
+        let mut ports = Node::create_composite(self.graph.clone(), args);
+        ports.pop().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaled_sine() -> Function {
+        Function::define("scaled_sine", 2, |p| {
+            Node::create_mul(p[0].clone(), Node::create_sin(p[1].clone()))
+        })
+    }
+
+    #[test]
+    fn calls_share_one_body() {
+        let f = scaled_sine();
+        assert_eq!((f.name(), f.arity()), ("scaled_sine", 2));
+        let (a, b) = (Node::create_input(2f32), Node::create_input(1f32));
+        let first = f.call(vec![a.clone(), b.clone()]);
+        let second = f.call(vec![Node::create_input(3f32), a.clone()]);
+        assert_eq!(first.borrow().compute(), 2.0 * 1f32.sin());
+        assert_eq!(second.borrow().compute(), 3.0 * 2f32.sin());
+
+        a.borrow().set(4f32);
+        assert_eq!(first.borrow().compute(), 4.0 * 1f32.sin());
+        assert_eq!(second.borrow().compute(), 3.0 * 4f32.sin());
+    }
+
+    #[test]
+    fn calls_nest() {
+        let f = scaled_sine();
+        let twice = Function::define("twice", 1, |p| {
+            f.call(vec![Node::create_const(2f32), p[0].clone()])
+        });
+        let x = Node::create_input(0.5f32);
+        let call = twice.call(vec![x.clone()]);
+        assert_eq!(call.borrow().compute(), 2.0 * 0.5f32.sin());
+        x.borrow().set(1f32);
+        assert_eq!(call.borrow().compute(), 2.0 * 1f32.sin());
+
+        let params = vec![Node::create_input(0f32)];
+        let negate = Function::new(
+            "negate",
+            params.clone(),
+            Node::create_mul(params[0].clone(), Node::create_const(-1f32)),
+        );
+        let call = negate.call(vec![call]);
+        assert_eq!(call.borrow().compute(), -2.0 * 1f32.sin());
+    }
+}
@@ -0,0 +1,254 @@
+//! Random valid graphs of configurable size and shape, for fuzzing evaluators,
+//! rewrites and serialization. Enabled by the `arbitrary` feature.
+
+use std::rc::Rc;
+
+use crate::computational_graph::{Comparison, Node, NodeCelled, RoundMode};
+use crate::random::Rng;
+use crate::testing::Fixture;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    Add,
+    Mul,
+    Pow,
+    Sin,
+    Cos,
+    MulAdd,
+    And,
+    Or,
+    Not,
+    Compare,
+    Select,
+    Round,
+}
+
+impl OpKind {
+    pub const ALL: &'static [Self] = &[
+        Self::Add,
+        Self::Mul,
+        Self::Pow,
+        Self::Sin,
+        Self::Cos,
+        Self::MulAdd,
+        Self::And,
+        Self::Or,
+        Self::Not,
+        Self::Compare,
+        Self::Select,
+        Self::Round,
+    ];
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Sin | Self::Cos | Self::Not | Self::Round => 1,
+            Self::Add | Self::Mul | Self::Pow | Self::And | Self::Or | Self::Compare => 2,
+            Self::MulAdd | Self::Select => 3,
+        }
+    }
+}
+
+/// Builds graphs of `ops` op nodes over `inputs` inputs, every node reaching
+/// the output. The same settings and seed build the same graph.
+#[derive(Debug, Clone)]
+pub struct GraphGenerator {
+    inputs: usize,
+    ops: usize,
+    /// Relative frequency of each op, indexed like `OpKind::ALL`.
+    weights: Vec<u32>,
+    sharing: f32,
+    constants: f32,
+}
+
+impl GraphGenerator {
+    /// Every op equally likely, a quarter of operands shared and none constant.
+    pub fn new(inputs: usize, ops: usize) -> Self {
+        assert!(inputs > 0, "A graph needs an input");
+        Self {
+            inputs,
+            ops,
+            weights: vec![1; OpKind::ALL.len()],
+            sharing: 0.25,
+            constants: 0.0,
+        }
+    }
+
+    /// Makes `op` `weight` times as likely as an op of weight 1, `0` disables
+    /// it.
+    pub fn with_weight(mut self, op: OpKind, weight: u32) -> Self {
+        let i = OpKind::ALL.iter().position(|kind| *kind == op).unwrap();
+        self.weights[i] = weight;
+        self
+    }
+
+    /// Probability of an operand being any earlier node, which may already
+    /// have users. Otherwise it's the newest node without one, if any, so `0`
+    /// builds graphs as close to trees as the inputs allow and `1` densely
+    /// shared ones.
+    pub fn with_sharing(mut self, sharing: f32) -> Self {
+        self.sharing = sharing;
+        self
+    }
+
+    /// Probability of an operand being a new constant from `-2` to `2`, so
+    /// that rewrites have something to fold.
+    pub fn with_constants(mut self, constants: f32) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    pub fn generate(&self, rng: &Rng) -> Fixture {
+        let total: u32 = self.weights.iter().sum();
+        assert!(total > 0, "At least one op must have a weight");
+
+        let inputs: Vec<_> = (0..self.inputs)
+            .map(|_| Node::create_input(rng.next_f32() * 2.0 - 1.0))
+            .collect();
+        let mut nodes = inputs.clone();
+        // Nodes nothing reads yet, newest last.
+        let mut unused = inputs.clone();
+        for _ in 0..self.ops {
+            let mut pick = rng.next_u64() % total as u64;
+            let op = OpKind::ALL
+                .iter()
+                .zip(&self.weights)
+                .find(|(_, weight)| {
+                    let found = pick < **weight as u64;
+                    pick = pick.saturating_sub(**weight as u64);
+                    found
+                })
+                .map(|(op, _)| *op)
+                .unwrap();
+
+            let args: Vec<_> = (0..op.arity())
+                .map(|_| {
+                    if rng.next_f32() < self.constants {
+                        return Node::create_const((rng.next_u64() % 5) as f32 - 2.0);
+                    }
+                    if rng.next_f32() >= self.sharing {
+                        if let Some(node) = unused.pop() {
+                            return node;
+                        }
+                    }
+                    let node = nodes[(rng.next_u64() % nodes.len() as u64) as usize].clone();
+                    unused.retain(|unused| !Rc::ptr_eq(unused, &node));
+                    node
+                })
+                .collect();
+
+            let node = build(op, args);
+            nodes.push(node.clone());
+            unused.push(node);
+        }
+
+        // Joins what nothing reads yet, so the whole graph is live.
+        let mut unused = unused.into_iter().rev();
+        let last = unused.next().unwrap();
+        let output = unused.fold(last, |output, node| Node::create_add(node, output));
+        Fixture { inputs, output }
+    }
+}
+
+fn build(op: OpKind, args: Vec<NodeCelled>) -> NodeCelled {
+    let mut args = args.into_iter();
+    let mut next = || args.next().unwrap();
+    match op {
+        OpKind::Add => Node::create_add(next(), next()),
+        OpKind::Mul => Node::create_mul(next(), next()),
+        OpKind::Pow => Node::create_pow(next(), next()),
+        OpKind::Sin => Node::create_sin(next()),
+        OpKind::Cos => Node::create_cos(next()),
+        OpKind::MulAdd => Node::create_mul_add(next(), next(), next()),
+        OpKind::And => Node::create_and(next(), next()),
+        OpKind::Or => Node::create_or(next(), next()),
+        OpKind::Not => Node::create_not(next()),
+        OpKind::Compare => Node::create_compare(next(), next(), Comparison::Lt),
+        OpKind::Select => Node::create_select(next(), next(), next()),
+        OpKind::Round => Node::create_round(next(), 2, RoundMode::HalfUp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::{BinaryOp, UnaryOp};
+
+    fn op_nodes(fixture: &Fixture) -> Vec<NodeCelled> {
+        Node::topo_order(&fixture.output)
+            .into_iter()
+            .filter(|node| !matches!(&*node.borrow(), Node::Input { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn seeds_determine_graphs() {
+        let generator = GraphGenerator::new(3, 40).with_constants(0.1);
+        let (a, b) = (
+            generator.generate(&Rng::new(9)),
+            generator.generate(&Rng::new(9)),
+        );
+        assert_eq!(Node::fingerprint(&a.output), Node::fingerprint(&b.output));
+        let (a, b) = (a.output.borrow().compute(), b.output.borrow().compute());
+        assert_eq!(a.to_bits(), b.to_bits());
+        let other = generator.generate(&Rng::new(10));
+        assert_ne!(
+            Node::fingerprint(&generator.generate(&Rng::new(9)).output),
+            Node::fingerprint(&other.output)
+        );
+    }
+
+    #[test]
+    fn every_node_reaches_the_output() {
+        let fixture = GraphGenerator::new(4, 30).generate(&Rng::new(1));
+        let order = Node::topo_order(&fixture.output);
+        for input in &fixture.inputs {
+            assert!(order.iter().any(|node| Rc::ptr_eq(node, input)));
+        }
+        assert!(op_nodes(&fixture).len() >= 30);
+    }
+
+    /// A generator of `ops` ops over `inputs` inputs, every op disabled.
+    fn disabled(inputs: usize, ops: usize) -> GraphGenerator {
+        OpKind::ALL
+            .iter()
+            .fold(GraphGenerator::new(inputs, ops), |generator, op| {
+                generator.with_weight(*op, 0)
+            })
+    }
+
+    #[test]
+    fn weighs_ops() {
+        let generator = disabled(2, 20).with_weight(OpKind::Sin, 1);
+        let fixture = generator.generate(&Rng::new(2));
+        // Sines, and additions joining what nothing reads.
+        for node in op_nodes(&fixture) {
+            assert!(matches!(
+                &*node.borrow(),
+                Node::Unary {
+                    op: UnaryOp::Sin,
+                    ..
+                } | Node::Binary {
+                    op: BinaryOp::Add,
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn builds_trees_without_sharing() {
+        // With unary ops only, one input is enough to never share.
+        let generator = [OpKind::Sin, OpKind::Cos, OpKind::Not, OpKind::Round]
+            .iter()
+            .fold(disabled(1, 25), |generator, op| {
+                generator.with_weight(*op, 1)
+            })
+            .with_sharing(0.0);
+        for seed in 0..5 {
+            let fixture = generator.generate(&Rng::new(seed));
+            assert!(Node::shared(&fixture.output).is_empty());
+        }
+        let shared = GraphGenerator::new(1, 25).with_sharing(1.0);
+        assert!(!Node::shared(&shared.generate(&Rng::new(0)).output).is_empty());
+    }
+}
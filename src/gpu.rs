@@ -0,0 +1,262 @@
+//! Running `CompiledGraph`s on the GPU: the shader `to_wgsl` generates is
+//! dispatched through wgpu over a batch of input rows, and the outputs are
+//! read back.
+
+use std::fmt;
+use std::sync::mpsc;
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::compiled::CompiledGraph;
+use crate::wgsl::{WgslError, WORKGROUP_SIZE};
+
+#[derive(Debug, Clone)]
+pub enum GpuError {
+    Wgsl(WgslError),
+    /// No adapter or device to run on.
+    Unavailable(String),
+    /// wgpu rejected the shader, or running it failed.
+    Device(String),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wgsl(e) => e.fmt(f),
+            Self::Unavailable(reason) => write!(f, "no GPU available: {reason}"),
+            Self::Device(reason) => write!(f, "GPU error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+impl From<WgslError> for GpuError {
+    fn from(e: WgslError) -> Self {
+        Self::Wgsl(e)
+    }
+}
+
+/// Evaluates one `CompiledGraph` on the default adapter, its shader compiled
+/// once. Results follow shader float semantics: nothing is reported, so
+/// domain errors come out as NaN or infinity, and `sin`, `cos` and `pow` may
+/// differ from the host's in the last bits.
+#[derive(Debug)]
+pub struct GpuEvaluator {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    input_count: usize,
+}
+
+impl GpuEvaluator {
+    pub fn new(graph: &CompiledGraph) -> Result<Self, GpuError> {
+        pollster::block_on(Self::create(graph))
+    }
+
+    async fn create(graph: &CompiledGraph) -> Result<Self, GpuError> {
+        let shader = graph.to_wgsl()?;
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .map_err(|e| GpuError::Unavailable(e.to_string()))?;
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default())
+            .await
+            .map_err(|e| GpuError::Unavailable(e.to_string()))?;
+
+        let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("computational-graph"),
+            source: wgpu::ShaderSource::Wgsl(shader.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("computational-graph"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        if let Some(e) = scope.pop().await {
+            return Err(GpuError::Device(e.to_string()));
+        }
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            input_count: graph.input_count(),
+        })
+    }
+
+    /// Outputs for `rows`, like `CompiledGraph::eval_batch`. Batches larger
+    /// than the device takes at once are split into several dispatches.
+    pub fn eval_batch(&self, rows: &[&[f32]]) -> Result<Vec<f32>, GpuError> {
+        for row in rows {
+            assert_eq!(row.len(), self.input_count, "Wrong number of input values");
+        }
+
+        let limits = self.device.limits();
+        let row_bytes = 4 * self.input_count.max(1) as u64;
+        let max_rows = (limits.max_compute_workgroups_per_dimension as u64 * WORKGROUP_SIZE as u64)
+            .min(limits.max_storage_buffer_binding_size / row_bytes)
+            .max(1) as usize;
+
+        let mut res = Vec::with_capacity(rows.len());
+        for chunk in rows.chunks(max_rows) {
+            res.extend(self.dispatch(chunk)?);
+        }
+        Ok(res)
+    }
+
+    fn dispatch(&self, rows: &[&[f32]]) -> Result<Vec<f32>, GpuError> {
+        let mut inputs: Vec<u8> = rows
+            .iter()
+            .flat_map(|row| row.iter().flat_map(|x| x.to_le_bytes()))
+            .collect();
+        // Bindings can't be empty, even for graphs without inputs.
+        if inputs.is_empty() {
+            inputs.resize(4, 0);
+        }
+        let size = 4 * rows.len() as u64;
+
+        let inputs = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("inputs"),
+            contents: &inputs,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let outputs = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("outputs"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: inputs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: outputs.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(rows.len().div_ceil(WORKGROUP_SIZE as usize) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&outputs, 0, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |res| {
+            let _ = sender.send(res);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(|e| GpuError::Device(e.to_string()))?;
+        receiver
+            .recv()
+            .map_err(|e| GpuError::Device(e.to_string()))?
+            .map_err(|e| GpuError::Device(e.to_string()))?;
+
+        let view = readback
+            .get_mapped_range(..)
+            .map_err(|e| GpuError::Device(e.to_string()))?;
+        let res = view
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        drop(view);
+        readback.unmap();
+        Ok(res)
+    }
+}
+
+impl CompiledGraph {
+    /// Handle evaluating this graph on the GPU, see `GpuEvaluator`.
+    pub fn gpu_evaluator(&self) -> Result<GpuEvaluator, GpuError> {
+        GpuEvaluator::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::naga;
+
+    use crate::computational_graph::{Comparison, Node};
+
+    /// `max(x^2, y) + c`, with the constant `c` spelled out in the shader.
+    fn graph(constant: f32) -> CompiledGraph {
+        let x = Node::create_input(0.0);
+        let y = Node::create_input(0.0);
+        let squared = Node::create_pow(x.clone(), Node::create_const(2.0));
+        let larger = Node::create_select(
+            Node::create_compare(squared.clone(), y.clone(), Comparison::Gt),
+            squared,
+            y.clone(),
+        );
+        let output = Node::create_add(larger, Node::create_const(constant));
+        CompiledGraph::compile(&output, &[x, y])
+    }
+
+    #[test]
+    fn shaders_validate() {
+        for constant in [1.5, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let shader = graph(constant).to_wgsl().unwrap();
+            let module = naga::front::wgsl::parse_str(&shader)
+                .unwrap_or_else(|e| panic!("{}\n{shader}", e.emit_to_string(&shader)));
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::all(),
+            )
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{e:?}\n{shader}"));
+        }
+    }
+
+    #[test]
+    fn matches_the_host() {
+        let graph = graph(1.5);
+        // Without an adapter, e.g. on CI machines, there is nothing to run.
+        let gpu = match graph.gpu_evaluator() {
+            Ok(gpu) => gpu,
+            Err(GpuError::Unavailable(reason)) => {
+                eprintln!("skipped: {reason}");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+
+        let values: Vec<[f32; 2]> = (0..1000)
+            .map(|i| [i as f32 / 100.0 - 5.0, (i % 7) as f32])
+            .collect();
+        let rows: Vec<&[f32]> = values.iter().map(|row| &row[..]).collect();
+        let expected = graph.eval_batch(&rows).unwrap();
+        let actual = gpu.eval_batch(&rows).unwrap();
+        for (actual, expected) in actual.iter().zip(&expected) {
+            assert!((actual - expected).abs() <= 1e-4 * expected.abs().max(1.0));
+        }
+        assert_eq!(actual.len(), expected.len());
+        assert!(gpu.eval_batch(&[]).unwrap().is_empty());
+    }
+}
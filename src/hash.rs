@@ -0,0 +1,397 @@
+//! Stable structural hashing. `std`'s `DefaultHasher` may change between Rust
+//! releases, so anything persisted goes through FNV-1a instead.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::computational_graph::{
+    Accumulation, BinaryOp, Comparison, InputKind, Node, NodeCelled, PowPolicy, RoundMode,
+    TernaryOp, UnaryOp,
+};
+
+pub(crate) struct Fnv64(u64);
+
+impl Fnv64 {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn write_u64(&mut self, x: u64) {
+        self.write(&x.to_le_bytes());
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+const SHA256_H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256, for signatures, where FNV-1a is far too weak.
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    /// Bytes in `block`.
+    filled: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: SHA256_H,
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let n = (64 - self.filled).min(bytes.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&bytes[..n]);
+            self.filled += n;
+            bytes = &bytes[n..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.write(&[0x80]);
+        while self.filled != 56 {
+            self.write(&[0]);
+        }
+        self.write(&bits.to_be_bytes());
+
+        let mut res = [0; 32];
+        for (bytes, word) in res.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        res
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(self.block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, w) in SHA256_K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, x) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(x);
+        }
+    }
+}
+
+/// HMAC-SHA-256 of `message` under `key` (RFC 2104).
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        let mut hasher = Sha256::new();
+        hasher.write(key);
+        block[..32].copy_from_slice(&hasher.finish());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.write(&block.map(|byte| byte ^ 0x36));
+    inner.write(message);
+    let mut outer = Sha256::new();
+    outer.write(&block.map(|byte| byte ^ 0x5c));
+    outer.write(&inner.finish());
+    outer.finish()
+}
+
+/// Hash of topology, op kinds and constants. Other inputs contribute only
+/// their position in `Node::inputs` order, never their values.
+pub(crate) fn structure_hash(output: &NodeCelled) -> u64 {
+    graph_hash(std::slice::from_ref(output), &[])[0]
+}
+
+/// `structure_hash` of each of `outputs`, with the inputs in `ports` told
+/// apart by their index there rather than by position.
+fn graph_hash(outputs: &[NodeCelled], ports: &[NodeCelled]) -> Vec<u64> {
+    let mut hashes: HashMap<*const (), u64> = ports
+        .iter()
+        .enumerate()
+        .map(|(i, port)| {
+            let mut hasher = Fnv64::new();
+            hasher.write(b"port");
+            hasher.write_u64(i as u64);
+            (Rc::as_ptr(port) as *const (), hasher.finish())
+        })
+        .collect();
+    let mut input_count = 0u64;
+    let mut res = Vec::with_capacity(outputs.len());
+
+    for output in outputs {
+        for node in Node::topo_order(output) {
+            let key = Rc::as_ptr(&node) as *const ();
+            if hashes.contains_key(&key) {
+                continue;
+            }
+
+            let mut hasher = Fnv64::new();
+            let node_ref = node.borrow();
+            write_op(&mut hasher, &node_ref);
+            if let Node::Input { .. } = &*node_ref {
+                hasher.write_u64(input_count);
+                input_count += 1;
+            }
+            for child in node_ref.children() {
+                hasher.write_u64(hashes[&(Rc::as_ptr(&child) as *const ())]);
+            }
+            drop(node_ref);
+
+            hashes.insert(key, hasher.finish());
+        }
+        res.push(hashes[&(Rc::as_ptr(output) as *const ())]);
+    }
+
+    res
+}
+
+/// Hashes what kind of node `node` is, ignoring its operands. Of values, only
+/// those of constants are included.
+pub(crate) fn write_op(hasher: &mut Fnv64, node: &Node) {
+    match node {
+        Node::Input { x, kind, .. } => match kind {
+            InputKind::Value => hasher.write(b"input"),
+            InputKind::Time => hasher.write(b"time"),
+            InputKind::Delay => hasher.write(b"delay"),
+            InputKind::Accumulate(accumulation) => {
+                hasher.write(b"accumulate");
+                hasher.write(match accumulation {
+                    Accumulation::Sum => b"sum",
+                    Accumulation::Min => b"min",
+                    Accumulation::Max => b"max",
+                    Accumulation::Count => b"count",
+                });
+            }
+            InputKind::Const => {
+                hasher.write(b"const");
+                hasher.write_u64(x.borrow().to_bits() as u64);
+            }
+        },
+        Node::Unary { op, .. } => match op {
+            UnaryOp::Sin => hasher.write(b"sin"),
+            UnaryOp::Cos => hasher.write(b"cos"),
+            UnaryOp::Not => hasher.write(b"not"),
+            UnaryOp::Round { digits, mode } => {
+                hasher.write(b"round");
+                hasher.write_u64(*digits as u64);
+                hasher.write(match mode {
+                    RoundMode::HalfUp => b"half-up",
+                    RoundMode::HalfEven => b"half-even",
+                    RoundMode::Trunc => b"trunc",
+                });
+            }
+        },
+        Node::Binary { op, .. } => match op {
+            BinaryOp::Add => hasher.write(b"add"),
+            BinaryOp::Mul => hasher.write(b"mul"),
+            BinaryOp::Pow(policy) => {
+                hasher.write(b"pow");
+                hasher.write(match policy {
+                    PowPolicy::Native => b"native",
+                    PowPolicy::Error => b"error",
+                    PowPolicy::Nan => b"nan",
+                    PowPolicy::ZeroPowZeroIsOne => b"zero-pow-zero-is-one",
+                });
+            }
+            BinaryOp::And => hasher.write(b"and"),
+            BinaryOp::Or => hasher.write(b"or"),
+            BinaryOp::Compare(cmp) => {
+                hasher.write(b"compare");
+                hasher.write(match cmp {
+                    Comparison::Lt => b"lt",
+                    Comparison::Le => b"le",
+                    Comparison::Gt => b"gt",
+                    Comparison::Ge => b"ge",
+                    Comparison::Eq => b"eq",
+                    Comparison::Ne => b"ne",
+                });
+            }
+        },
+        Node::Ternary { op, .. } => hasher.write(match op {
+            TernaryOp::MulAdd => b"mul-add",
+            TernaryOp::Select => b"select",
+        }),
+        Node::Custom { op, .. } => {
+            hasher.write(b"custom");
+            hasher.write(op.name().as_bytes());
+            let identity = op.identity();
+            if !identity.is_empty() {
+                hasher.write(b"identity");
+                hasher.write(identity.as_bytes());
+            }
+        }
+        Node::Composite { graph, port, .. } => {
+            hasher.write(b"composite");
+            for output in graph_hash(graph.outputs(), graph.inputs()) {
+                hasher.write_u64(output);
+            }
+            hasher.write_u64(*port as u64);
+        }
+    }
+}
+
+/// Hash of the current values of `Node::inputs(output)`.
+pub(crate) fn input_values_hash(output: &NodeCelled) -> u64 {
+    let mut hasher = Fnv64::new();
+    for input in Node::inputs(output) {
+        hasher.write_u64(input.borrow().compute().to_bits() as u64);
+    }
+
+    hasher.finish()
+}
+
+impl Node {
+    /// Stable content hash of the graph computing `this`: topology, op kinds
+    /// and constants, but not the values of other inputs. Equal across runs
+    /// and platforms, so it can key persisted artifacts.
+    pub fn fingerprint(this: &NodeCelled) -> u64 {
+        structure_hash(this)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn sha256(message: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.write(message);
+        hasher.finish()
+    }
+
+    #[test]
+    fn sha256_matches_fips_180_vectors() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    /// Test cases 1 to 7 of RFC 4231, with case 5 truncated to 128 bits.
+    #[test]
+    fn hmac_sha256_matches_rfc_4231_vectors() {
+        let key_first = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        let key_and_data = b"This is a test using a larger than block-size key and a \
+            larger than block-size data. The key needs to be hashed before being used by \
+            the HMAC algorithm.";
+        let cases: [(&[u8], &[u8], &str); 7] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &[
+                    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+                    23, 24, 25,
+                ],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            (
+                &[0x0c; 20],
+                b"Test With Truncation",
+                "a3b6167473100ee06e0c796c2955552b",
+            ),
+            (
+                &[0xaa; 131],
+                key_first,
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                key_and_data,
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for (key, data, expected) in cases {
+            let mac = hex(&hmac_sha256(key, data));
+            assert_eq!(&mac[..expected.len()], expected);
+        }
+    }
+}
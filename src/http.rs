@@ -0,0 +1,539 @@
+//! Formula microservice: a small HTTP/1.1 server holding one graph, in the
+//! `serialize` format, and evaluating it on request.
+//!
+//! | Request                 | Body                  | Response                      |
+//! |-------------------------|-----------------------|-------------------------------|
+//! | `PUT /graph`            | serialized graph      | the number of inputs          |
+//! | `PUT /inputs`           | every input's value   | nothing                       |
+//! | `PUT /inputs/<input>`   | one value             | nothing                       |
+//! | `GET /inputs`           |                       | every input's value           |
+//! | `GET /inputs/<input>`   |                       | the input's value             |
+//! | `GET /names`            |                       | every input's name            |
+//! | `GET /compute`          |                       | the output's value            |
+//! | `GET /gradient`         |                       | the derivative for each input |
+//!
+//! Inputs are the graph's value inputs in `Node::inputs` order. A single one
+//! is addressed by its name, as serialized and percent-encoded, or else by
+//! its position. Unnamed inputs are listed by `/names` as empty lines.
+//! Values are written one per line and read whitespace-separated.
+//! Errors have the reason as the body. Bodies are plain text throughout.
+//!
+//! With `Server::with_signing_key`, graphs must be signed, see `signature`,
+//! and are refused with `403` otherwise.
+//!
+//! HTTP is served by axum on hyper, on a thread of its own. Graphs aren't
+//! `Send`, so each request is handed to the thread calling `Server::serve`
+//! and answered there, one at a time. Reading a request times out, see
+//! `Server::with_timeout`, so a stalled client is dropped after that long.
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::TcpListener;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use tokio::sync::oneshot;
+
+use crate::autodiff;
+use crate::computational_graph::{InputKind, Node, NodeCelled};
+use crate::constraint::ConstraintError;
+use crate::serialize::{decode, Deserializer};
+use crate::signature::SignatureError;
+
+/// Bodies above this are rejected with `413`.
+const MAX_BODY: usize = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn ok(body: impl Into<String>) -> Self {
+        Self {
+            status: 200,
+            body: body.into(),
+        }
+    }
+
+    fn error(status: u16, reason: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: reason.into(),
+        }
+    }
+}
+
+struct Loaded {
+    output: NodeCelled,
+    inputs: Vec<NodeCelled>,
+}
+
+impl Loaded {
+    /// The input named `key`, percent-encoded, or else at position `key`.
+    fn input(&self, key: &str) -> Option<&NodeCelled> {
+        let name = decode(key)?;
+        self.inputs
+            .iter()
+            .find(|input| input.borrow().name().as_deref() == Some(name.as_str()))
+            .or_else(|| self.inputs.get(key.parse::<usize>().ok()?))
+    }
+}
+
+/// Serves one graph at a time, replaced by each `PUT /graph`.
+pub struct Server {
+    deserializer: Deserializer,
+    key: Option<Vec<u8>>,
+    timeout: Duration,
+    graph: Option<Loaded>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            deserializer: Deserializer::default(),
+            key: None,
+            timeout: Duration::from_secs(30),
+            graph: None,
+        }
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a client may take to send a request's headers, and then its
+    /// body, before it's dropped, 30 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        assert!(!timeout.is_zero(), "Timeouts must be positive");
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reads graphs with `deserializer`, e.g. to register custom ops.
+    pub fn with_deserializer(mut self, deserializer: Deserializer) -> Self {
+        self.deserializer = deserializer;
+        self
+    }
+
+    /// Only loads graphs signed under `key`, as `serialize_signed` writes.
+    pub fn with_signing_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Answers connections on `listener` until it fails. A connection
+    /// failing only drops that connection.
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let (sender, jobs) = mpsc::channel();
+        let shared = Shared {
+            jobs: sender,
+            timeout: self.timeout,
+        };
+        let transport = thread::spawn(move || accept(listener, shared));
+        // Ends once the transport fails, dropping the last sender.
+        for (request, reply) in jobs {
+            let _ = reply.send(self.handle(&request));
+        }
+        transport
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+
+    /// Answers `request`, see the module docs.
+    pub fn handle(&mut self, request: &Request) -> Response {
+        let path = request.path.trim_end_matches('/');
+        match (request.method.as_str(), path) {
+            ("PUT", "/graph") => self.load(&request.body),
+            (_, "/graph") => Response::error(405, "use PUT"),
+            (method, path) => {
+                let Some(graph) = &self.graph else {
+                    return Response::error(409, "no graph, PUT /graph first");
+                };
+                match (method, path) {
+                    ("GET", "/inputs") => Response::ok(lines(
+                        graph
+                            .inputs
+                            .iter()
+                            .map(|input| input.borrow().cached_value().unwrap_or(f32::NAN)),
+                    )),
+                    ("PUT", "/inputs") => set_all(graph, &request.body),
+                    ("GET", "/names") => {
+                        let mut names = String::new();
+                        for input in &graph.inputs {
+                            let name = input.borrow().name();
+                            writeln!(names, "{}", name.as_deref().unwrap_or("")).unwrap();
+                        }
+                        Response::ok(names)
+                    }
+                    (method, path) if path.starts_with("/inputs/") => {
+                        let key = &path["/inputs/".len()..];
+                        let Some(input) = graph.input(key) else {
+                            return Response::error(404, format!("no input \"{key}\""));
+                        };
+                        match method {
+                            "GET" => {
+                                let value = input.borrow().cached_value().unwrap_or(f32::NAN);
+                                Response::ok(format!("{value}\n"))
+                            }
+                            "PUT" => set_one(input, &request.body),
+                            _ => Response::error(405, "method not allowed"),
+                        }
+                    }
+                    ("GET", "/compute") => match graph.output.borrow().try_compute() {
+                        Ok(value) => Response::ok(format!("{value}\n")),
+                        Err(e) => Response::error(400, e.to_string()),
+                    },
+                    ("GET", "/gradient") => {
+                        let gradient = graph
+                            .output
+                            .borrow()
+                            .try_compute()
+                            .map_err(Into::into)
+                            .and_then(|_| autodiff::gradient(&graph.output, &graph.inputs));
+                        match gradient {
+                            Ok(gradient) => Response::ok(lines(gradient)),
+                            Err(e) => Response::error(400, e.to_string()),
+                        }
+                    }
+                    (_, "/inputs" | "/names" | "/compute" | "/gradient") => {
+                        Response::error(405, "method not allowed")
+                    }
+                    ("PUT", _) | ("GET", _) => Response::error(404, "not found"),
+                    _ => Response::error(405, "method not allowed"),
+                }
+            }
+        }
+    }
+
+    fn load(&mut self, text: &str) -> Response {
+        let outputs = match &self.key {
+            Some(key) => self.deserializer.deserialize_signed(text, key),
+            None => self.deserializer.deserialize(text).map_err(Into::into),
+        };
+        let output = match outputs {
+            Ok(outputs) => match outputs.into_iter().next() {
+                Some(output) => output,
+                None => return Response::error(400, "the graph has no output"),
+            },
+            Err(SignatureError::Load(e)) => return Response::error(400, e.to_string()),
+            Err(e) => return Response::error(403, e.to_string()),
+        };
+        let inputs: Vec<_> = Node::inputs(&output)
+            .into_iter()
+            .filter(|input| {
+                matches!(
+                    &*input.borrow(),
+                    Node::Input {
+                        kind: InputKind::Value,
+                        ..
+                    }
+                )
+            })
+            .collect();
+        let count = inputs.len();
+        self.graph = Some(Loaded { output, inputs });
+        Response::ok(format!("{count}\n"))
+    }
+}
+
+fn lines(values: impl IntoIterator<Item = f32>) -> String {
+    let mut res = String::new();
+    for value in values {
+        writeln!(res, "{value}").unwrap();
+    }
+    res
+}
+
+fn parse_values(body: &str) -> Result<Vec<f32>, Response> {
+    body.split_whitespace()
+        .map(|word| {
+            word.parse()
+                .map_err(|_| Response::error(400, format!("not a number: \"{word}\"")))
+        })
+        .collect()
+}
+
+fn set_all(graph: &Loaded, body: &str) -> Response {
+    let values = match parse_values(body) {
+        Ok(values) => values,
+        Err(response) => return response,
+    };
+    if values.len() != graph.inputs.len() {
+        return Response::error(
+            400,
+            format!(
+                "expected {} values, got {}",
+                graph.inputs.len(),
+                values.len()
+            ),
+        );
+    }
+    // Checked first so a rejected value leaves every input as it was.
+    for (input, value) in graph.inputs.iter().zip(&values) {
+        let input = input.borrow();
+        if let Some(constraint) = input.constraint() {
+            if let Err(violation) = constraint.check(*value) {
+                let error = ConstraintError {
+                    node: input.id(),
+                    value: *value,
+                    violation,
+                };
+                return Response::error(400, error.to_string());
+            }
+        }
+    }
+    for (input, value) in graph.inputs.iter().zip(values) {
+        input.borrow().set(value);
+    }
+    Response::ok("")
+}
+
+fn set_one(input: &NodeCelled, body: &str) -> Response {
+    match parse_values(body).as_deref() {
+        Ok([value]) => match input.borrow().try_set(*value) {
+            Ok(()) => Response::ok(""),
+            Err(e) => Response::error(400, e.to_string()),
+        },
+        Ok(_) => Response::error(400, "expected one value"),
+        Err(response) => response.clone(),
+    }
+}
+
+/// A request to answer on the graph's thread, with where to send the
+/// response.
+type Job = (Request, oneshot::Sender<Response>);
+
+#[derive(Clone)]
+struct Shared {
+    jobs: mpsc::Sender<Job>,
+    timeout: Duration,
+}
+
+/// Accepts connections on `listener`, serving each with hyper on a task of
+/// its own, until accepting fails.
+fn accept(listener: TcpListener, shared: Shared) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let timeout = shared.timeout;
+        let router = Router::new().fallback(forward).with_state(shared);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let service = TowerToHyperService::new(router.clone());
+            tokio::spawn(async move {
+                // Failures only concern this connection.
+                let _ = http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(timeout)
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    })
+}
+
+/// Reads the body of `request` and hands it to the graph's thread.
+async fn forward(State(shared): State<Shared>, request: axum::extract::Request) -> Response {
+    let (parts, body) = request.into_parts();
+    let length = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > MAX_BODY) {
+        return Response::error(413, "body too large");
+    }
+    let body =
+        match tokio::time::timeout(shared.timeout, axum::body::to_bytes(body, MAX_BODY)).await {
+            Ok(Ok(body)) => body,
+            Ok(Err(e)) => return Response::error(400, e.to_string()),
+            Err(_) => return Response::error(408, "timed out reading the body"),
+        };
+    let Ok(body) = String::from_utf8(body.into()) else {
+        return Response::error(400, "body isn't UTF-8");
+    };
+
+    let request = Request {
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        body,
+    };
+    let (reply, response) = oneshot::channel();
+    if shared.jobs.send((request, reply)).is_err() {
+        return Response::error(503, "shutting down");
+    }
+    response
+        .await
+        .unwrap_or_else(|_| Response::error(500, "the request wasn't answered"))
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> axum::response::Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, self.body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::serialize;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Instant;
+
+    fn request(server: &mut Server, method: &str, path: &str, body: &str) -> Response {
+        server.handle(&Request {
+            method: method.into(),
+            path: path.into(),
+            body: body.into(),
+        })
+    }
+
+    #[test]
+    fn addresses_inputs_by_name() {
+        let rate = Node::create_input(0.5);
+        rate.borrow().set_name("interest rate");
+        let price = Node::create_input(10.0);
+        let output = Node::create_mul(price, rate);
+        let mut server = Server::new();
+        let loaded = request(&mut server, "PUT", "/graph", &serialize(&[output]));
+        assert_eq!(loaded, Response::ok("2\n"));
+
+        assert_eq!(
+            request(&mut server, "GET", "/names", ""),
+            Response::ok("\ninterest rate\n")
+        );
+        let res = request(&mut server, "PUT", "/inputs/interest%20rate", "2");
+        assert_eq!(res, Response::ok(""));
+        assert_eq!(
+            request(&mut server, "PUT", "/inputs/0", "3"),
+            Response::ok("")
+        );
+        assert_eq!(
+            request(&mut server, "GET", "/compute", ""),
+            Response::ok("6\n")
+        );
+        let res = request(&mut server, "GET", "/inputs/interest%20rate", "");
+        assert_eq!(res, Response::ok("2\n"));
+        assert_eq!(request(&mut server, "GET", "/inputs/rate", "").status, 404);
+        assert_eq!(request(&mut server, "DELETE", "/inputs/0", "").status, 405);
+    }
+
+    /// A server timing out after 200ms, on a thread of its own.
+    fn spawn() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut server = Server::new().with_timeout(Duration::from_millis(200));
+            server.serve(listener)
+        });
+        address
+    }
+
+    /// Sends `request` as is, returning what the server writes back.
+    fn exchange(address: SocketAddr, request: &str) -> String {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_over_http() {
+        let address = spawn();
+        let x = Node::create_input(1.0);
+        let graph = serialize(&[Node::create_mul(x.clone(), x)]);
+        let response = exchange(
+            address,
+            &format!(
+                "PUT /graph HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{graph}",
+                graph.len()
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("\r\n\r\n1\n"), "{response}");
+
+        // Chunked bodies, and several requests on one connection.
+        let response = exchange(
+            address,
+            "PUT /inputs HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n3\n\r\n0\r\n\r\n\
+             GET /compute HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2, "{response}");
+        assert!(response.ends_with("\r\n\r\n9\n"), "{response}");
+
+        let response = exchange(
+            address,
+            "GET /nothing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    }
+
+    #[test]
+    fn caps_bodies_and_headers() {
+        let address = spawn();
+        let response = exchange(
+            address,
+            &format!(
+                "PUT /graph HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY + 1
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+
+        let headers: String = (0..200).map(|i| format!("X-Filler-{i}: x\r\n")).collect();
+        let response = exchange(address, &format!("GET /names HTTP/1.1\r\n{headers}\r\n"));
+        assert!(response.starts_with("HTTP/1.1 431"), "{response}");
+    }
+
+    #[test]
+    fn stalled_clients_time_out() {
+        let address = spawn();
+        let start = Instant::now();
+        let mut idle = TcpStream::connect(address).unwrap();
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled
+            .write_all(b"PUT /inputs HTTP/1.1\r\nContent-Length: 10\r\n\r\n1")
+            .unwrap();
+
+        // Neither holds up other clients.
+        let response = exchange(
+            address,
+            "GET /compute HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 409"), "{response}");
+
+        let mut response = String::new();
+        stalled.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+        // The idle connection is closed, with nothing to answer.
+        let mut response = String::new();
+        idle.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,253 @@
+//! Exact `i64` evaluation of `CompiledGraph`s, for counting and accounting
+//! formulas where `f32` rounding is unacceptable.
+//!
+//! Node values stay `f32`; this only reinterprets a compiled tape. Constants
+//! must be integral, and ops without an integer meaning are rejected.
+
+use std::fmt;
+
+use crate::compiled::{CompiledGraph, Instr};
+use crate::computational_graph::{BinaryOp, NodeId, PowPolicy, RoundMode, TernaryOp, UnaryOp};
+
+/// What `Add`, `Mul` and `Pow` do when the result doesn't fit an `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Fail with `IntError::Overflow`.
+    #[default]
+    Checked,
+    Saturating,
+    Wrapping,
+}
+
+#[derive(Debug, Clone)]
+pub enum IntError {
+    Overflow {
+        node: NodeId,
+    },
+    /// `0^0` under a strict `PowPolicy`, or a negative exponent.
+    PowDomain {
+        node: NodeId,
+        base: i64,
+        exponent: i64,
+    },
+    /// A constant baked into the graph isn't an integer.
+    NotInteger {
+        node: NodeId,
+        value: f32,
+    },
+    Unsupported {
+        node: NodeId,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for IntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow { node } => write!(f, "node {node}: integer overflow"),
+            Self::PowDomain {
+                node,
+                base,
+                exponent,
+            } => write!(f, "node {node}: {base}^{exponent} is undefined"),
+            Self::NotInteger { node, value } => write!(f, "node {node}: {value} is not an integer"),
+            Self::Unsupported { node, reason } => write!(f, "node {node}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for IntError {}
+
+impl CompiledGraph {
+    /// Evaluates a single row in `i64` arithmetic.
+    pub fn eval_i64(&self, inputs: &[i64], overflow: Overflow) -> Result<i64, IntError> {
+        assert_eq!(
+            inputs.len(),
+            self.input_count(),
+            "Wrong number of input values"
+        );
+
+        let mut slots = Vec::with_capacity(self.instrs.len());
+        for (instr, &node) in self.instrs.iter().zip(self.ids.iter()) {
+            let value = match instr {
+                Instr::Input(i) => inputs[*i],
+                Instr::Const(x) => {
+                    if x.fract() != 0f32 || !x.is_finite() {
+                        return Err(IntError::NotInteger { node, value: *x });
+                    }
+                    // `as` would saturate. `i64::MAX as f32` is `2^63`.
+                    if !(i64::MIN as f32..i64::MAX as f32).contains(x) {
+                        return Err(IntError::Overflow { node });
+                    }
+                    *x as i64
+                }
+                Instr::Binary(BinaryOp::Add, a, b) => {
+                    add(slots[*a], slots[*b], overflow).ok_or(IntError::Overflow { node })?
+                }
+                Instr::Binary(BinaryOp::Mul, a, b) => {
+                    mul(slots[*a], slots[*b], overflow).ok_or(IntError::Overflow { node })?
+                }
+                Instr::Binary(BinaryOp::Pow(policy), a, b) => {
+                    pow(*policy, slots[*a], slots[*b], overflow, node)?
+                }
+                Instr::Binary(BinaryOp::And, a, b) => i64::from(slots[*a] != 0 && slots[*b] != 0),
+                Instr::Binary(BinaryOp::Or, a, b) => i64::from(slots[*a] != 0 || slots[*b] != 0),
+                Instr::Binary(BinaryOp::Compare(cmp), a, b) => {
+                    i64::from(cmp.apply(slots[*a], slots[*b]))
+                }
+                Instr::Unary(UnaryOp::Not, x) => i64::from(slots[*x] == 0),
+                Instr::Unary(UnaryOp::Round { digits, mode }, x) => {
+                    round_to_unit(slots[*x] as i128, 0i32.saturating_sub(*digits), *mode)
+                        .and_then(|rounded| i64::try_from(rounded).ok())
+                        .ok_or(IntError::Overflow { node })?
+                }
+                Instr::Ternary(TernaryOp::MulAdd, a, b, c) => mul(slots[*a], slots[*b], overflow)
+                    .and_then(|ab| add(ab, slots[*c], overflow))
+                    .ok_or(IntError::Overflow { node })?,
+                Instr::Ternary(TernaryOp::Select, a, b, c) => {
+                    if slots[*a] != 0 {
+                        slots[*b]
+                    } else {
+                        slots[*c]
+                    }
+                }
+                Instr::Unary(..) => {
+                    return Err(IntError::Unsupported {
+                        node,
+                        reason: "trigonometric ops have no integer form",
+                    })
+                }
+                Instr::Custom(..) => {
+                    return Err(IntError::Unsupported {
+                        node,
+                        reason: "custom ops compute in f32",
+                    })
+                }
+            };
+            slots.push(value);
+        }
+
+        Ok(slots[self.instrs.len() - 1])
+    }
+}
+
+fn add(a: i64, b: i64, overflow: Overflow) -> Option<i64> {
+    match overflow {
+        Overflow::Checked => a.checked_add(b),
+        Overflow::Saturating => Some(a.saturating_add(b)),
+        Overflow::Wrapping => Some(a.wrapping_add(b)),
+    }
+}
+
+fn mul(a: i64, b: i64, overflow: Overflow) -> Option<i64> {
+    match overflow {
+        Overflow::Checked => a.checked_mul(b),
+        Overflow::Saturating => Some(a.saturating_mul(b)),
+        Overflow::Wrapping => Some(a.wrapping_mul(b)),
+    }
+}
+
+/// `x` rounded to a multiple of `10^places`, `None` on overflow.
+pub(crate) fn round_to_unit(x: i128, places: i32, mode: RoundMode) -> Option<i128> {
+    if places <= 0 {
+        return Some(x);
+    }
+    match 10i128.checked_pow(places as u32) {
+        Some(unit) => mode.round_int(x, unit),
+        None => Some(0),
+    }
+}
+
+fn pow(
+    policy: PowPolicy,
+    base: i64,
+    exponent: i64,
+    overflow: Overflow,
+    node: NodeId,
+) -> Result<i64, IntError> {
+    let domain = IntError::PowDomain {
+        node,
+        base,
+        exponent,
+    };
+    let strict_zero = matches!(policy, PowPolicy::Error | PowPolicy::Nan);
+    if exponent < 0 || (base == 0 && exponent == 0 && strict_zero) {
+        return Err(domain);
+    }
+
+    if overflow == Overflow::Wrapping {
+        return Ok(wrapping_pow(base, exponent as u64));
+    }
+    // Exponents past `u32::MAX` overflow for any base but -1, 0 and 1, for
+    // which only the parity matters.
+    let exponent = u32::try_from(exponent).unwrap_or(u32::MAX - 1 + (exponent % 2) as u32);
+    match overflow {
+        Overflow::Checked => base
+            .checked_pow(exponent)
+            .ok_or(IntError::Overflow { node }),
+        Overflow::Saturating => Ok(base.saturating_pow(exponent)),
+        Overflow::Wrapping => unreachable!(),
+    }
+}
+
+/// `i64::wrapping_pow` for exponents of any size, by squaring.
+fn wrapping_pow(mut base: i64, mut exponent: u64) -> i64 {
+    let mut res = 1i64;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            res = res.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    #[test]
+    fn out_of_range_constants_overflow() {
+        let x = Node::create_input(0f32);
+        let add = |c| {
+            CompiledGraph::compile(
+                &Node::create_add(x.clone(), Node::create_const(c)),
+                std::slice::from_ref(&x),
+            )
+        };
+        assert_eq!(
+            add(-9.223372e18).eval_i64(&[0], Overflow::Checked).unwrap(),
+            i64::MIN
+        );
+        let res = add(9.223372e18).eval_i64(&[0], Overflow::Checked);
+        assert!(matches!(res, Err(IntError::Overflow { .. })));
+        let res = add(1e30).eval_i64(&[0], Overflow::Saturating);
+        assert!(matches!(res, Err(IntError::Overflow { .. })));
+    }
+
+    #[test]
+    fn round_to_extreme_digits() {
+        let x = Node::create_input(0f32);
+        for (digits, expected) in [(i32::MIN, 0), (-2, 1200), (i32::MAX, 1234)] {
+            let round = Node::create_round(x.clone(), digits, RoundMode::HalfEven);
+            let compiled = CompiledGraph::compile(&round, std::slice::from_ref(&x));
+            let res = compiled.eval_i64(&[1234], Overflow::Checked).unwrap();
+            assert_eq!(res, expected);
+        }
+    }
+
+    #[test]
+    fn wrapping_pow_past_u32_exponents() {
+        let node = Node::create_input(0f32).borrow().id();
+        let pow = |base, exponent| pow(PowPolicy::Native, base, exponent, Overflow::Wrapping, node);
+        assert_eq!(pow(3, 5).unwrap(), 243);
+        assert_eq!(pow(3, 70).unwrap(), 3i64.wrapping_pow(70));
+        // 3 has order 2^62 modulo 2^64.
+        assert_eq!(pow(3, 1 << 62).unwrap(), 1);
+        assert_eq!(pow(3, (1 << 62) + 5).unwrap(), 243);
+        assert_eq!(pow(2, 1 << 40).unwrap(), 0);
+        assert_eq!(pow(-1, (1 << 40) + 1).unwrap(), -1);
+    }
+}
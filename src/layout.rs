@@ -0,0 +1,263 @@
+//! Layered drawing of graphs, in the manner of Sugiyama: nodes in columns by
+//! depth, data flowing left to right, ordered within columns to keep edges
+//! from crossing. Front-ends read the positions from the nodes' metadata.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::computational_graph::{Node, NodeCelled, NodeId};
+
+/// Spacing and effort of `Node::layout`.
+#[derive(Debug, Clone)]
+pub struct LayoutOptions {
+    layer_gap: f32,
+    node_gap: f32,
+    sweeps: usize,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            layer_gap: 100.0,
+            node_gap: 50.0,
+            sweeps: 4,
+        }
+    }
+}
+
+impl LayoutOptions {
+    /// Distance between columns, and between nodes within a column.
+    pub fn with_spacing(mut self, layer_gap: f32, node_gap: f32) -> Self {
+        self.layer_gap = layer_gap;
+        self.node_gap = node_gap;
+        self
+    }
+
+    /// Rounds of reordering columns to reduce crossings, each one pass
+    /// forward and one back.
+    pub fn with_sweeps(mut self, sweeps: usize) -> Self {
+        self.sweeps = sweeps;
+        self
+    }
+}
+
+/// Edge from an operand to a node reading it, through the points where it
+/// bends around the columns it crosses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutEdge {
+    pub from: NodeId,
+    pub to: NodeId,
+    /// From the operand's position to the reader's.
+    pub points: Vec<(f32, f32)>,
+}
+
+/// Result of `Node::layout`.
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub positions: HashMap<NodeId, (f32, f32)>,
+    pub edges: Vec<LayoutEdge>,
+    /// Column count: the longest path from an input to the output, plus one.
+    pub layers: usize,
+}
+
+impl Node {
+    /// Positions for every node below `output`, also stored as each node's
+    /// `position`. Inputs go in the first column and every other node in the
+    /// column after its deepest operand, so `x` is the depth. Within a
+    /// column, nodes are ordered by the mean position of their neighbours,
+    /// edges spanning several columns taking a slot in each, and centred on
+    /// `y = 0`.
+    pub fn layout(output: &NodeCelled, options: &LayoutOptions) -> Layout {
+        let order = Self::topo_order(output);
+        let index: HashMap<_, _> = order
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (Rc::as_ptr(node), i))
+            .collect();
+        let children: Vec<Vec<usize>> = order
+            .iter()
+            .map(|node| {
+                let node = node.borrow();
+                node.children()
+                    .iter()
+                    .map(|child| index[&Rc::as_ptr(child)])
+                    .collect()
+            })
+            .collect();
+
+        let mut layer = vec![0; order.len()];
+        for (i, operands) in children.iter().enumerate() {
+            layer[i] = operands.iter().map(|j| layer[*j] + 1).max().unwrap_or(0);
+        }
+        let layers = layer.iter().max().map_or(0, |deepest| deepest + 1);
+
+        // Vertices past the nodes are bends of long edges. Each edge is
+        // a chain of vertices, one per column from the operand's to the
+        // reader's.
+        let mut vertex_layer = layer.clone();
+        let mut chains = Vec::new();
+        for (to, operands) in children.iter().enumerate() {
+            for from in operands {
+                let mut chain = vec![*from];
+                for l in layer[*from] + 1..layer[to] {
+                    chain.push(vertex_layer.len());
+                    vertex_layer.push(l);
+                }
+                chain.push(to);
+                chains.push((*from, to, chain));
+            }
+        }
+        let mut before = vec![Vec::new(); vertex_layer.len()];
+        let mut after = vec![Vec::new(); vertex_layer.len()];
+        for (_, _, chain) in &chains {
+            for pair in chain.windows(2) {
+                after[pair[0]].push(pair[1]);
+                before[pair[1]].push(pair[0]);
+            }
+        }
+
+        let mut columns = vec![Vec::new(); layers];
+        for (vertex, l) in vertex_layer.iter().enumerate() {
+            columns[*l].push(vertex);
+        }
+        let mut slot = vec![0.0; vertex_layer.len()];
+        for column in &columns {
+            for (i, vertex) in column.iter().enumerate() {
+                slot[*vertex] = i as f32;
+            }
+        }
+        for _ in 0..options.sweeps {
+            for column in columns.iter_mut().skip(1) {
+                reorder(column, &before, &mut slot);
+            }
+            for column in columns.iter_mut().rev().skip(1) {
+                reorder(column, &after, &mut slot);
+            }
+        }
+
+        let mut points = vec![(0.0, 0.0); vertex_layer.len()];
+        for (l, column) in columns.iter().enumerate() {
+            let middle = (column.len() as f32 - 1.0) / 2.0;
+            for (i, vertex) in column.iter().enumerate() {
+                points[*vertex] = (
+                    l as f32 * options.layer_gap,
+                    (i as f32 - middle) * options.node_gap,
+                );
+            }
+        }
+
+        let mut res = Layout {
+            layers,
+            ..Layout::default()
+        };
+        for (i, node) in order.iter().enumerate() {
+            let node = node.borrow();
+            let (x, y) = points[i];
+            node.set_position(x, y);
+            res.positions.insert(node.id(), (x, y));
+        }
+        for (from, to, chain) in chains {
+            res.edges.push(LayoutEdge {
+                from: order[from].borrow().id(),
+                to: order[to].borrow().id(),
+                points: chain.iter().map(|vertex| points[*vertex]).collect(),
+            });
+        }
+        res
+    }
+}
+
+/// Sorts `column` by the mean slot of each vertex's `neighbours` in the
+/// adjacent column, keeping the current slot for vertices without any, and
+/// renumbers it.
+fn reorder(column: &mut [usize], neighbours: &[Vec<usize>], slot: &mut [f32]) {
+    let barycenter = |vertex: usize| {
+        let around = &neighbours[vertex];
+        if around.is_empty() {
+            slot[vertex]
+        } else {
+            around.iter().map(|v| slot[*v]).sum::<f32>() / around.len() as f32
+        }
+    };
+    let mut keyed: Vec<_> = column.iter().map(|v| (barycenter(*v), *v)).collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (i, (_, vertex)) in keyed.into_iter().enumerate() {
+        column[i] = vertex;
+        slot[vertex] = i as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::testing::Fixture;
+
+    /// Pairs of edge segments between the same columns that cross.
+    fn crossings(layout: &Layout) -> usize {
+        let segments: Vec<_> = layout
+            .edges
+            .iter()
+            .flat_map(|edge| edge.points.windows(2).map(|pair| (pair[0], pair[1])))
+            .collect();
+        let mut res = 0;
+        for (i, (a, b)) in segments.iter().enumerate() {
+            for (c, d) in &segments[i + 1..] {
+                if a.0 == c.0 && (a.1 - c.1) * (b.1 - d.1) < 0.0 {
+                    res += 1;
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn columns_follow_depth() {
+        // `sin(x) * y + x`, `x` reaching the sum past two columns.
+        let x = Node::create_input(1f32);
+        let y = Node::create_input(2f32);
+        let product = Node::create_mul(Node::create_sin(x.clone()), y.clone());
+        let output = Node::create_add(product.clone(), x.clone());
+        let layout = Node::layout(&output, &LayoutOptions::default().with_spacing(10.0, 4.0));
+
+        assert_eq!(layout.layers, 4);
+        assert_eq!(layout.positions.len(), 5);
+        let x_of = |node: &NodeCelled| layout.positions[&node.borrow().id()].0;
+        assert_eq!(x_of(&x), 0.0);
+        assert_eq!(x_of(&y), 0.0);
+        assert_eq!(x_of(&product), 20.0);
+        assert_eq!(x_of(&output), 30.0);
+        // Columns are centred.
+        assert_eq!(layout.positions[&output.borrow().id()], (30.0, 0.0));
+        let ys = [&x, &y].map(|input| layout.positions[&input.borrow().id()].1);
+        assert_eq!(ys[0] + ys[1], 0.0);
+        assert_eq!((ys[0] - ys[1]).abs(), 4.0);
+        assert_eq!(
+            output.borrow().position(),
+            Some(layout.positions[&output.borrow().id()])
+        );
+
+        assert_eq!(layout.edges.len(), 5);
+        let long = layout
+            .edges
+            .iter()
+            .find(|edge| edge.from == x.borrow().id() && edge.to == output.borrow().id())
+            .unwrap();
+        let columns: Vec<_> = long.points.iter().map(|point| point.0).collect();
+        assert_eq!(columns, [0.0, 10.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn sweeps_reduce_crossings() {
+        let mut improved = false;
+        for seed in 0..20 {
+            let fixture = Fixture::random(&Rng::new(seed), 4, 12);
+            let unordered = Node::layout(&fixture.output, &LayoutOptions::default().with_sweeps(0));
+            let ordered = Node::layout(&fixture.output, &LayoutOptions::default().with_sweeps(8));
+            assert_eq!(ordered.layers, unordered.layers);
+            assert!(crossings(&ordered) <= crossings(&unordered), "seed {seed}");
+            improved |= crossings(&ordered) < crossings(&unordered);
+        }
+        assert!(improved);
+    }
+}
@@ -0,0 +1,76 @@
+pub mod arena;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod attribution;
+pub mod audit;
+pub mod autodiff;
+pub mod bounds;
+pub mod checkpoint;
+pub mod columnar;
+pub mod compiled;
+pub mod computational_graph;
+pub mod constraint;
+pub mod context;
+pub mod cost;
+pub mod debugger;
+pub mod decimal;
+pub mod disk_cache;
+pub mod einsum;
+pub mod expr_cache;
+pub mod external;
+pub mod fit;
+pub mod format;
+pub mod function;
+#[cfg(feature = "arbitrary")]
+pub mod generator;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+mod hash;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod integer;
+#[cfg(feature = "layout")]
+pub mod layout;
+pub mod linear;
+pub mod losses;
+pub mod memo;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod monte_carlo;
+pub mod noise;
+pub mod optimize;
+pub mod overridable;
+pub mod pareto;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod parser;
+#[cfg(feature = "polars")]
+pub mod polars;
+pub mod pool;
+pub mod precision;
+pub mod proto;
+pub mod provider;
+pub mod random;
+pub mod reduce;
+pub mod registry;
+pub mod report;
+pub mod rewrite;
+pub mod scenario;
+pub mod scheduler;
+pub mod serialize;
+pub mod sheet;
+pub mod signal;
+pub mod signature;
+pub mod solve;
+pub mod template;
+pub mod tensor;
+pub mod testing;
+#[cfg(feature = "trace")]
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod types;
+pub mod versions;
+pub mod watch;
+#[cfg(feature = "wgsl")]
+pub mod wgsl;
@@ -0,0 +1,275 @@
+//! Affine forms of graphs, for LP solvers and cheap repeated evaluation.
+
+use std::collections::HashMap;
+
+use crate::computational_graph::{
+    truthy, BinaryOp, EvalError, Node, NodeCelled, NodeId, TernaryOp,
+};
+
+/// `coeffs · x + bias` over the inputs a graph was linearized in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linear {
+    pub coeffs: Vec<f32>,
+    pub bias: f32,
+}
+
+impl Linear {
+    /// The form at `values`, one per input in linearization order.
+    pub fn eval(&self, values: &[f32]) -> f32 {
+        assert_eq!(values.len(), self.coeffs.len(), "One value per input");
+        self.coeffs
+            .iter()
+            .zip(values)
+            .fold(self.bias, |sum, (coeff, x)| coeff.mul_add(*x, sum))
+    }
+}
+
+/// A node's form, `None` for nodes not depending on the chosen inputs, which
+/// act as constants at their current value.
+type Form = Option<Linear>;
+
+impl Node {
+    /// This node as an affine function of `inputs`, with every other input at
+    /// its current value, or `None` if it isn't one. Only sums and products
+    /// and powers with a factor or exponent independent of `inputs`, and
+    /// `Select`s with such a condition, are followed; anything else depending
+    /// on `inputs` gives `None`, even if it happens to be affine.
+    pub fn linearize(&self, inputs: &[NodeCelled]) -> Result<Option<Linear>, EvalError> {
+        // `None` for nodes that aren't affine, which only matters if they are
+        // used: a `Select` may not take them.
+        let mut forms: HashMap<NodeId, Option<Form>> = HashMap::new();
+        for (i, input) in inputs.iter().enumerate() {
+            let mut coeffs = vec![0.0; inputs.len()];
+            coeffs[i] = 1.0;
+            forms.insert(
+                input.borrow().id(),
+                Some(Some(Linear { coeffs, bias: 0.0 })),
+            );
+        }
+
+        for child in self.children() {
+            for node in Node::topo_order(&child) {
+                let node = node.borrow();
+                if !forms.contains_key(&node.id()) {
+                    let form = node.linear_form(&forms, inputs.len())?;
+                    forms.insert(node.id(), form);
+                }
+            }
+        }
+
+        let form = match forms.get(&self.id()) {
+            Some(form) => form.clone(),
+            None => self.linear_form(&forms, inputs.len())?,
+        };
+        Ok(match form {
+            Some(Some(form)) => Some(form),
+            Some(None) => Some(Linear {
+                coeffs: vec![0.0; inputs.len()],
+                bias: self.try_compute()?,
+            }),
+            None => None,
+        })
+    }
+
+    /// This node's form given its operands' ones, `None` if it isn't affine.
+    fn linear_form(
+        &self,
+        forms: &HashMap<NodeId, Option<Form>>,
+        n: usize,
+    ) -> Result<Option<Form>, EvalError> {
+        let children = self.children();
+        let operands: Vec<Option<&Form>> = children
+            .iter()
+            .map(|child| forms[&child.borrow().id()].as_ref())
+            .collect();
+        if operands.iter().all(|form| matches!(form, Some(None))) {
+            return Ok(Some(None));
+        }
+        if let Self::Ternary {
+            op: TernaryOp::Select,
+            ..
+        } = self
+        {
+            let taken = match operands[0] {
+                Some(None) if truthy(children[0].borrow().try_compute()?) => 1,
+                Some(None) => 2,
+                _ => return Ok(None),
+            };
+            return Ok(match operands[taken] {
+                Some(Some(form)) => Some(Some(form.clone())),
+                Some(None) => Some(Some(Linear {
+                    coeffs: vec![0.0; n],
+                    bias: children[taken].borrow().try_compute()?,
+                })),
+                None => None,
+            });
+        }
+        if operands.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        // An operand's form, constants included.
+        let form = |i: usize| -> Result<Linear, EvalError> {
+            Ok(match operands[i] {
+                Some(Some(form)) => form.clone(),
+                _ => Linear {
+                    coeffs: vec![0.0; n],
+                    bias: children[i].borrow().try_compute()?,
+                },
+            })
+        };
+        let constant = |i: usize| -> Result<Option<f32>, EvalError> {
+            match operands[i] {
+                Some(Some(_)) => Ok(None),
+                _ => children[i].borrow().try_compute().map(Some),
+            }
+        };
+
+        Ok(match self {
+            Self::Binary {
+                op: BinaryOp::Add, ..
+            } => Some(Some(add(form(0)?, &form(1)?))),
+            Self::Binary {
+                op: BinaryOp::Mul, ..
+            } => match (constant(0)?, constant(1)?) {
+                (Some(k), _) => Some(Some(scale(form(1)?, k))),
+                (_, Some(k)) => Some(Some(scale(form(0)?, k))),
+                _ => None,
+            },
+            Self::Binary {
+                op: BinaryOp::Pow(_),
+                ..
+            } => match constant(1)? {
+                Some(1.0) => Some(Some(form(0)?)),
+                _ => None,
+            },
+            Self::Ternary {
+                op: TernaryOp::MulAdd,
+                ..
+            } => {
+                let product = match (constant(0)?, constant(1)?) {
+                    (Some(k), _) => scale(form(1)?, k),
+                    (_, Some(k)) => scale(form(0)?, k),
+                    _ => return Ok(None),
+                };
+                Some(Some(add(product, &form(2)?)))
+            }
+            _ => None,
+        })
+    }
+}
+
+fn add(mut a: Linear, b: &Linear) -> Linear {
+    for (coeff, other) in a.coeffs.iter_mut().zip(&b.coeffs) {
+        *coeff += other;
+    }
+    a.bias += b.bias;
+    a
+}
+
+fn scale(mut form: Linear, k: f32) -> Linear {
+    for coeff in &mut form.coeffs {
+        *coeff *= k;
+    }
+    form.bias *= k;
+    form
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Comparison;
+
+    #[test]
+    fn linearizes_affine_graphs() {
+        let (x, y, z) = (
+            Node::create_input(1f32),
+            Node::create_input(2f32),
+            Node::create_input(5f32),
+        );
+        // 3 * (x + 2y) + z * x + sin(z), z held at 5.
+        let sum = Node::create_add(
+            x.clone(),
+            Node::create_mul(Node::create_const(2f32), y.clone()),
+        );
+        let out = Node::create_add(
+            Node::create_mul_add(
+                Node::create_const(3f32),
+                sum,
+                Node::create_mul(z.clone(), x.clone()),
+            ),
+            Node::create_sin(z.clone()),
+        );
+        let form = out
+            .borrow()
+            .linearize(&[x.clone(), y.clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(form.coeffs, [8.0, 6.0]);
+        assert_eq!(form.bias, 5f32.sin());
+        assert_eq!(form.eval(&[1.0, 2.0]), out.borrow().compute());
+        assert_eq!(form.eval(&[-1.0, 0.5]), -8.0 + 3.0 + 5f32.sin());
+
+        // Not affine in z.
+        assert_eq!(
+            out.borrow().linearize(&[x.clone(), z.clone()]).unwrap(),
+            None
+        );
+        let constant = z
+            .borrow()
+            .linearize(std::slice::from_ref(&x))
+            .unwrap()
+            .unwrap();
+        assert_eq!((constant.coeffs, constant.bias), (vec![0.0], 5.0));
+    }
+
+    #[test]
+    fn follows_selects_with_fixed_conditions() {
+        let (x, k) = (Node::create_input(1f32), Node::create_input(0f32));
+        let condition = Node::create_compare(k.clone(), Node::create_const(0f32), Comparison::Gt);
+        let doubled = Node::create_mul(x.clone(), Node::create_const(2f32));
+        let select = Node::create_select(condition, doubled, Node::create_const(7f32));
+        let form = select.borrow().linearize(std::slice::from_ref(&x)).unwrap();
+        assert_eq!(
+            form,
+            Some(Linear {
+                coeffs: vec![0.0],
+                bias: 7.0
+            })
+        );
+        k.borrow().set(1f32);
+        let form = select.borrow().linearize(std::slice::from_ref(&x)).unwrap();
+        assert_eq!(
+            form,
+            Some(Linear {
+                coeffs: vec![2.0],
+                bias: 0.0
+            })
+        );
+
+        // A condition on x isn't followed.
+        let condition = Node::create_compare(x.clone(), Node::create_const(0f32), Comparison::Gt);
+        let select = Node::create_select(condition, x.clone(), Node::create_const(0f32));
+        assert_eq!(
+            select.borrow().linearize(std::slice::from_ref(&x)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_nonlinear_ops() {
+        let x = Node::create_input(3f32);
+        let inputs = [x.clone()];
+        let square = Node::create_mul(x.clone(), x.clone());
+        assert_eq!(square.borrow().linearize(&inputs).unwrap(), None);
+        let pow = Node::create_pow(x.clone(), Node::create_const(2f32));
+        assert_eq!(pow.borrow().linearize(&inputs).unwrap(), None);
+        let identity = Node::create_pow(x.clone(), Node::create_const(1f32));
+        let form = identity.borrow().linearize(&inputs).unwrap().unwrap();
+        assert_eq!(form.coeffs, [1.0]);
+        assert_eq!(
+            Node::create_sin(x).borrow().linearize(&inputs).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Loss functions between predictions and targets, as graphs to minimize with
+//! `autodiff`. Predictions and targets broadcast against each other, and
+//! elementwise losses are averaged over all elements.
+
+use crate::computational_graph::{Comparison, Node, NodeCelled};
+use crate::tensor::{ShapeError, Tensor};
+
+/// Mean squared error.
+pub fn mse(predictions: &Tensor, targets: &Tensor) -> Result<NodeCelled, ShapeError> {
+    let errors = predictions.zip_with(targets, |p, t| {
+        Node::create_pow(difference(p, t), Node::create_const(2f32))
+    })?;
+    errors.flatten().mean()
+}
+
+/// Mean absolute error.
+pub fn mae(predictions: &Tensor, targets: &Tensor) -> Result<NodeCelled, ShapeError> {
+    let errors = predictions.zip_with(targets, |p, t| abs(difference(p, t)))?;
+    errors.flatten().mean()
+}
+
+/// Mean Huber loss: squared error halved within `delta` of the target,
+/// growing linearly beyond, so outliers weigh less than in `mse`.
+pub fn huber(predictions: &Tensor, targets: &Tensor, delta: f32) -> Result<NodeCelled, ShapeError> {
+    let errors = predictions.zip_with(targets, |p, t| {
+        let error = abs(difference(p, t));
+        let quadratic = Node::create_mul(
+            Node::create_const(0.5),
+            Node::create_pow(error.clone(), Node::create_const(2f32)),
+        );
+        let linear = Node::create_mul(
+            Node::create_const(delta),
+            Node::create_add(error.clone(), Node::create_const(-0.5 * delta)),
+        );
+        let within = Node::create_compare(error, Node::create_const(delta), Comparison::Le);
+        Node::create_select(within, quadratic, linear)
+    })?;
+    errors.flatten().mean()
+}
+
+/// Cross-entropy of the softmax of vector `logits` against the class
+/// probabilities `targets`, such as a one-hot vector. Taking logits rather
+/// than probabilities keeps it finite for confident predictions.
+pub fn cross_entropy(logits: &Tensor, targets: &Tensor) -> Result<NodeCelled, ShapeError> {
+    // `-sum t * (x - lse(x))`, which is `lse(x) * sum t - sum t * x`.
+    logits.vector()?;
+    let targets = targets.broadcast_to(logits.shape())?;
+    let total = Node::create_mul(logits.log_sum_exp()?, targets.sum()?);
+    let dot = logits.mul(&targets)?.sum()?;
+    Ok(difference(total, dot))
+}
+
+/// Mean binary cross-entropy of `sigmoid(logits)` against probabilities
+/// `targets`, each `0` or `1` for hard labels.
+pub fn binary_cross_entropy(logits: &Tensor, targets: &Tensor) -> Result<NodeCelled, ShapeError> {
+    // `ln(1 + e^x) - t * x`.
+    let errors = logits.zip_with(targets, |x, t| {
+        let softplus = Node::create_log_sum_exp(vec![Node::create_const(0f32), x.clone()]);
+        difference(softplus, Node::create_mul(t, x))
+    })?;
+    errors.flatten().mean()
+}
+
+fn difference(a: NodeCelled, b: NodeCelled) -> NodeCelled {
+    Node::create_add(a, Node::create_mul(b, Node::create_const(-1f32)))
+}
+
+fn abs(x: NodeCelled) -> NodeCelled {
+    let negative = Node::create_compare(x.clone(), Node::create_const(0f32), Comparison::Lt);
+    let minus_x = Node::create_mul(x.clone(), Node::create_const(-1f32));
+    Node::create_select(negative, minus_x, x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(values: &[f32]) -> Tensor {
+        Tensor::inputs(vec![values.len()], values).unwrap()
+    }
+
+    fn value(loss: Result<NodeCelled, ShapeError>) -> f32 {
+        loss.unwrap().borrow().compute()
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-5, "{value} isn't {expected}");
+    }
+
+    #[test]
+    fn regression_losses() {
+        let (predictions, targets) = (vector(&[1.0, 2.0, 3.0]), vector(&[1.0, 1.0, 1.0]));
+        assert_close(value(mse(&predictions, &targets)), 5.0 / 3.0);
+        assert_close(value(mae(&predictions, &targets)), 1.0);
+        // 0, 1/2 within delta and 2 - 1/2 beyond it.
+        assert_close(value(huber(&predictions, &targets, 1.0)), 2.0 / 3.0);
+        assert_close(value(huber(&predictions, &targets, 10.0)), 5.0 / 6.0);
+
+        // Targets broadcast, and signs don't matter.
+        let target = Tensor::scalar(Node::create_const(2f32));
+        assert_close(value(mae(&predictions, &target)), 2.0 / 3.0);
+        assert!(mse(&predictions, &vector(&[1.0, 2.0])).is_err());
+    }
+
+    #[test]
+    fn cross_entropies() {
+        let one_hot = vector(&[1.0, 0.0]);
+        assert_close(
+            value(cross_entropy(&vector(&[0.0, 0.0]), &one_hot)),
+            2f32.ln(),
+        );
+        let confident = value(cross_entropy(&vector(&[1000.0, 0.0]), &one_hot));
+        assert_close(confident, 0.0);
+        let wrong = value(cross_entropy(&vector(&[0.0, 1000.0]), &one_hot));
+        assert_close(wrong, 1000.0);
+        let matrix = Tensor::inputs(vec![1, 2], &[0.0, 0.0]).unwrap();
+        assert!(cross_entropy(&matrix, &one_hot).is_err());
+
+        let labels = vector(&[1.0, 0.0]);
+        let bce = value(binary_cross_entropy(&vector(&[0.0, 0.0]), &labels));
+        assert_close(bce, 2f32.ln());
+        // ln(1 + e^-2) for the first, ln(1 + e^100) = 100 for the second.
+        let bce = value(binary_cross_entropy(&vector(&[2.0, 100.0]), &labels));
+        assert_close(bce, ((-2f32).exp().ln_1p() + 100.0) / 2.0);
+    }
+}
@@ -1,12 +1,4 @@
-use crate::computational_graph::Node;
-
-mod computational_graph;
-
-// round to decimal digits
-fn round(x: f32, precision: u32) -> f32 {
-    let m = 10i32.pow(precision) as f32;
-    (x * m).round() / m
-}
+use computational_graph::computational_graph::{Node, RoundMode};
 
 fn main() {
     // x1, x2, x3 are input nodes of the computational graph:
@@ -15,8 +7,8 @@ fn main() {
     let x3 = Node::create_input(3f32);
     let x4 = Node::create_input(3f32);
 
-    // graph variable is the output node of the graph:
-    let graph = Node::create_add(
+    // graph variable is the output node of the graph, rounded to 5 digits:
+    let sum = Node::create_add(
         x1.clone(),
         Node::create_mul(
             x2.clone(),
@@ -26,18 +18,17 @@ fn main() {
             )),
         ),
     );
+    let graph = Node::create_round(sum, 5, RoundMode::HalfUp);
 
     let mut result = graph.borrow().compute();
-    result = round(result, 5);
     println!("Graph output = {}", result);
-    assert_eq!(round(result, 5), -0.32727);
+    assert_eq!(result, -0.32727);
 
     x1.borrow().set(2f32);
     x2.borrow().set(3f32);
     x3.borrow().set(4f32);
     x4.borrow().set(3f32);
     result = graph.borrow().compute();
-    result = round(result, 5);
     println!("Graph output = {}", result);
-    assert_eq!(round(result, 5), -0.56656);
+    assert_eq!(result, -0.56656);
 }
@@ -0,0 +1,182 @@
+//! Whole-graph results remembered by input values, for callers such as
+//! optimizers that come back to points they evaluated before.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::computational_graph::{EvalError, Node, NodeCelled};
+
+/// Output values of one graph keyed by the values of all of its inputs, so
+/// that evaluating at a point seen before costs a lookup, however far the
+/// inputs moved in between. Node caches only help when just a few inputs
+/// changed since the last computation.
+///
+/// The graph must be a function of its inputs: random ops and edits to the
+/// graph aren't noticed.
+#[derive(Debug)]
+pub struct MemoTable {
+    output: NodeCelled,
+    inputs: Vec<NodeCelled>,
+    capacity: Option<usize>,
+    entries: RefCell<Entries>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+/// Values by the bits of their arguments, forgetting the least recently used
+/// past a capacity.
+#[derive(Debug, Default)]
+pub(crate) struct Entries {
+    /// Value and last use by key.
+    values: HashMap<Vec<u32>, (f32, u64)>,
+    /// Keys by last use, least recent first.
+    recency: BTreeMap<u64, Vec<u32>>,
+    clock: u64,
+}
+
+impl Entries {
+    /// The value under `key`, which becomes the most recently used.
+    pub(crate) fn get(&mut self, key: &[u32]) -> Option<f32> {
+        self.clock += 1;
+        let (value, used) = self.values.get_mut(key)?;
+        let last = std::mem::replace(used, self.clock);
+        let key = self.recency.remove(&last).unwrap();
+        self.recency.insert(self.clock, key);
+        Some(*value)
+    }
+
+    /// Keeps `value` under `key`, forgetting the least recently used value
+    /// first if `capacity` are kept already.
+    pub(crate) fn insert(&mut self, key: Vec<u32>, value: f32, capacity: Option<usize>) {
+        self.clock += 1;
+        if let Some((_, used)) = self.values.get(&key) {
+            self.recency.remove(used);
+        } else if capacity == Some(self.values.len()) {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.values.remove(&oldest);
+        }
+        self.values.insert(key.clone(), (value, self.clock));
+        self.recency.insert(self.clock, key);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.recency.clear();
+    }
+}
+
+impl MemoTable {
+    /// Unbounded table for `output`, keyed by every input it reads.
+    pub fn new(output: NodeCelled) -> Self {
+        Self {
+            inputs: Node::inputs(&output),
+            output,
+            capacity: None,
+            entries: RefCell::default(),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    /// Keeps at most `capacity` values, forgetting the least recently used.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "A memo table needs room for a value");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// The output at the current input values, remembered from an earlier
+    /// call at the same values (bit for bit) if possible. Errors aren't
+    /// remembered.
+    pub fn compute(&self) -> Result<f32, EvalError> {
+        let key: Vec<u32> = self
+            .inputs
+            .iter()
+            .map(|input| input.borrow().compute().to_bits())
+            .collect();
+
+        let mut entries = self.entries.borrow_mut();
+        if let Some(value) = entries.get(&key) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(value);
+        }
+
+        self.misses.set(self.misses.get() + 1);
+        let value = self.output.borrow().try_compute()?;
+        entries.insert(key, value, self.capacity);
+        Ok(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls answered from the table.
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    /// Calls that computed the graph.
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::PowPolicy;
+
+    #[test]
+    fn remembers_points_least_recent_first_out() {
+        let (x, y) = (Node::create_input(1f32), Node::create_input(2f32));
+        let table = MemoTable::new(Node::create_mul(x.clone(), y.clone())).with_capacity(2);
+        let at = |a: f32, b: f32| {
+            x.borrow().set(a);
+            y.borrow().set(b);
+            table.compute().unwrap()
+        };
+
+        assert_eq!(at(1f32, 2f32), 2f32);
+        assert_eq!(at(3f32, 2f32), 6f32);
+        assert_eq!(at(1f32, 2f32), 2f32);
+        assert_eq!((table.hits(), table.misses()), (1, 2));
+        // Full: the point used longest ago, (3, 2), makes room.
+        assert_eq!(at(1f32, 5f32), 5f32);
+        assert_eq!(table.len(), 2);
+        assert_eq!(at(1f32, 2f32), 2f32);
+        assert_eq!(at(3f32, 2f32), 6f32);
+        assert_eq!((table.hits(), table.misses()), (2, 4));
+
+        table.clear();
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn forgets_errors() {
+        let x = Node::create_input(0f32);
+        let reciprocal =
+            Node::create_strict_pow(x.clone(), Node::create_input(-1f32), PowPolicy::Error);
+        let table = MemoTable::new(reciprocal);
+        assert!(table.compute().is_err());
+        assert!(table.compute().is_err());
+        assert_eq!((table.hits(), table.misses()), (0, 2));
+        assert!(table.is_empty());
+
+        x.borrow().set(4f32);
+        assert_eq!(table.compute().unwrap(), 0.25f32);
+        assert_eq!(table.len(), 1);
+    }
+}
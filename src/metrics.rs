@@ -0,0 +1,330 @@
+//! Process-wide counters and timings of `Node` computations, for services to
+//! monitor: top-level computations, cache hits and misses, invalidations,
+//! and the time each op takes. `snapshot().to_prometheus()` renders them in
+//! the Prometheus text format.
+//!
+//! Compiled graphs aren't counted. Timing an op excludes its operands,
+//! except that a composite node's includes its subgraph.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::computational_graph::Node;
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BOUNDS: [f64; 8] = [1e-7, 1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0];
+
+static EVALUATIONS: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static INVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+static TIMINGS: Mutex<Timings> = Mutex::new(Timings {
+    evaluations: Histogram::new(),
+    ops: BTreeMap::new(),
+});
+
+struct Timings {
+    evaluations: Histogram,
+    ops: BTreeMap<String, Histogram>,
+}
+
+/// Distribution of durations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Observations per bucket of `BOUNDS`, and above the last.
+    counts: [u64; BOUNDS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            counts: [0; BOUNDS.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        let bucket = BOUNDS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BOUNDS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Total of the observations, in seconds.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Upper bound of each bucket, in seconds, with the number of
+    /// observations up to it, ending with infinity and `count`.
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        BOUNDS
+            .iter()
+            .chain([&f64::INFINITY])
+            .zip(self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+/// The metrics at one point in time, see `snapshot`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    /// `try_compute` calls, `compute` ones included.
+    pub evaluations: u64,
+    /// Nodes whose cached value was current.
+    pub cache_hits: u64,
+    /// Nodes whose op ran.
+    pub cache_misses: u64,
+    /// Input changes and explicit invalidations, each marking nodes stale.
+    pub invalidations: u64,
+    pub evaluation_seconds: Histogram,
+    /// By op: `add`, `sin`, a custom op's name, and so on.
+    pub op_seconds: BTreeMap<String, Histogram>,
+}
+
+impl MetricsSnapshot {
+    /// Share of node evaluations served from the cache, NaN before any.
+    pub fn hit_rate(&self) -> f64 {
+        self.cache_hits as f64 / (self.cache_hits + self.cache_misses) as f64
+    }
+
+    /// The Prometheus text exposition format, metric names prefixed with
+    /// `graph_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("evaluations", "Top-level computations.", self.evaluations),
+            (
+                "cache_hits",
+                "Nodes whose cached value was current.",
+                self.cache_hits,
+            ),
+            ("cache_misses", "Nodes whose op ran.", self.cache_misses),
+            (
+                "invalidations",
+                "Changes marking nodes stale.",
+                self.invalidations,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP graph_{name}_total {help}").unwrap();
+            writeln!(out, "# TYPE graph_{name}_total counter").unwrap();
+            writeln!(out, "graph_{name}_total {value}").unwrap();
+        }
+
+        writeln!(
+            out,
+            "# HELP graph_evaluation_seconds Duration of top-level computations."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE graph_evaluation_seconds histogram").unwrap();
+        write_histogram(
+            &mut out,
+            "graph_evaluation_seconds",
+            "",
+            &self.evaluation_seconds,
+        );
+        writeln!(
+            out,
+            "# HELP graph_op_seconds Duration of ops, excluding their operands."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE graph_op_seconds histogram").unwrap();
+        for (op, histogram) in &self.op_seconds {
+            let label = format!("op=\"{}\",", escape(op));
+            write_histogram(&mut out, "graph_op_seconds", &label, histogram);
+        }
+        out
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (bound, count) in histogram.buckets() {
+        let bound = if bound.is_infinite() {
+            "+Inf".to_string()
+        } else {
+            format!("{bound:e}")
+        };
+        writeln!(out, "{name}_bucket{{{labels}le=\"{bound}\"}} {count}").unwrap();
+    }
+    let labels = labels.trim_end_matches(',');
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    };
+    writeln!(out, "{name}_sum{labels} {}", histogram.sum()).unwrap();
+    writeln!(out, "{name}_count{labels} {}", histogram.count()).unwrap();
+}
+
+/// `text` as a label value: backslashes, quotes and line breaks escaped.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn snapshot() -> MetricsSnapshot {
+    let timings = TIMINGS.lock().unwrap();
+    MetricsSnapshot {
+        evaluations: EVALUATIONS.load(Ordering::Relaxed),
+        cache_hits: CACHE_HITS.load(Ordering::Relaxed),
+        cache_misses: CACHE_MISSES.load(Ordering::Relaxed),
+        invalidations: INVALIDATIONS.load(Ordering::Relaxed),
+        evaluation_seconds: timings.evaluations.clone(),
+        op_seconds: timings.ops.clone(),
+    }
+}
+
+/// Sets every metric back to zero.
+pub fn reset() {
+    let mut timings = TIMINGS.lock().unwrap();
+    for counter in [&EVALUATIONS, &CACHE_HITS, &CACHE_MISSES, &INVALIDATIONS] {
+        counter.store(0, Ordering::Relaxed);
+    }
+    timings.evaluations = Histogram::new();
+    timings.ops.clear();
+}
+
+pub(crate) fn record_evaluation(elapsed: Duration) {
+    EVALUATIONS.fetch_add(1, Ordering::Relaxed);
+    let mut timings = TIMINGS.lock().unwrap();
+    timings.evaluations.observe(elapsed.as_secs_f64());
+}
+
+pub(crate) fn record_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_miss(node: &Node, elapsed: Duration) {
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    let mut timings = TIMINGS.lock().unwrap();
+    let op = node.op_name();
+    match timings.ops.get_mut(op) {
+        Some(histogram) => histogram.observe(elapsed.as_secs_f64()),
+        None => {
+            let mut histogram = Histogram::new();
+            histogram.observe(elapsed.as_secs_f64());
+            timings.ops.insert(op.to_string(), histogram);
+        }
+    }
+}
+
+pub(crate) fn record_invalidation() {
+    INVALIDATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::computational_graph::CustomOp;
+
+    /// The identity, under a name no other test computes, so that its
+    /// timings count only this module's computations.
+    #[derive(Debug)]
+    struct Probe;
+
+    impl CustomOp for Probe {
+        fn name(&self) -> &str {
+            "metrics \"probe\""
+        }
+
+        fn compute(&self, args: &[f32]) -> Result<f32, String> {
+            Ok(args[0])
+        }
+    }
+
+    fn histogram(seconds: &[f64]) -> Histogram {
+        let mut res = Histogram::new();
+        for x in seconds {
+            res.observe(*x);
+        }
+        res
+    }
+
+    #[test]
+    fn buckets_durations() {
+        let histogram = histogram(&[5e-8, 1e-6, 2e-6, 5.0]);
+        assert_eq!(histogram.count(), 4);
+        assert!((histogram.sum() - 5.000_003_05).abs() < 1e-12);
+        let buckets = histogram.buckets();
+        assert_eq!(buckets.len(), BOUNDS.len() + 1);
+        assert_eq!(buckets[0], (1e-7, 1));
+        assert_eq!(buckets[1], (1e-6, 2));
+        assert_eq!(buckets[2], (1e-5, 3));
+        assert_eq!(buckets[8], (f64::INFINITY, 4));
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let snapshot = MetricsSnapshot {
+            evaluations: 2,
+            cache_hits: 3,
+            cache_misses: 1,
+            invalidations: 0,
+            evaluation_seconds: histogram(&[1e-3]),
+            op_seconds: BTreeMap::from([("say \"hi\"".to_string(), histogram(&[0.5]))]),
+        };
+        assert_eq!(snapshot.hit_rate(), 0.75);
+        let text = snapshot.to_prometheus();
+        assert!(
+            text.contains("# TYPE graph_evaluations_total counter\ngraph_evaluations_total 2\n")
+        );
+        assert!(text.contains("graph_cache_hits_total 3\n"));
+        assert!(text.contains("graph_evaluation_seconds_bucket{le=\"1e-4\"} 0\n"));
+        assert!(text.contains("graph_evaluation_seconds_bucket{le=\"1e-3\"} 1\n"));
+        assert!(text.contains("graph_evaluation_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("graph_evaluation_seconds_sum 0.001\n"));
+        assert!(text.contains("graph_op_seconds_bucket{op=\"say \\\"hi\\\"\",le=\"1e0\"} 1\n"));
+        assert!(text.contains("graph_op_seconds_count{op=\"say \\\"hi\\\"\"} 1\n"));
+
+        let empty = MetricsSnapshot {
+            cache_hits: 0,
+            cache_misses: 0,
+            ..snapshot
+        };
+        assert!(empty.hit_rate().is_nan());
+    }
+
+    #[test]
+    fn counts_computations() {
+        // Other tests compute concurrently, so only the probe's timings are
+        // exact, and the counters at least this test's share.
+        let x = Node::create_input(1f32);
+        let probe = Node::create_custom(Arc::new(Probe), vec![x.clone()]);
+        let output = Node::create_add(probe.clone(), x.clone());
+        let before = snapshot();
+        output.borrow().compute();
+        output.borrow().compute();
+        x.borrow().set(2.0);
+        output.borrow().compute();
+        let after = snapshot();
+
+        assert!(after.evaluations >= before.evaluations + 3);
+        assert!(after.cache_hits > before.cache_hits);
+        assert!(after.cache_misses >= before.cache_misses + 4);
+        assert!(after.invalidations > before.invalidations);
+        assert_eq!(after.op_seconds["metrics \"probe\""].count(), 2);
+        assert!(after.op_seconds["add"].count() >= 2);
+
+        reset();
+        assert!(!snapshot().op_seconds.contains_key("metrics \"probe\""));
+    }
+}
@@ -0,0 +1,615 @@
+//! Monte Carlo evaluation: the distribution of an output given distributions
+//! of its inputs.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::computational_graph::{EvalError, NodeCelled};
+use crate::random::{Distribution, Rng};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorrelationError {
+    /// The matrix isn't `inputs` by `inputs`.
+    Shape { inputs: usize },
+    /// Entry `(row, col)` isn't in `[-1, 1]`, or is on the diagonal and not
+    /// `1`, or differs from `(col, row)`.
+    Entry { row: usize, col: usize },
+    /// No set of variables has these correlations.
+    NotPositiveSemiDefinite,
+}
+
+impl fmt::Display for CorrelationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Shape { inputs } => {
+                write!(f, "the correlation matrix must be {inputs} by {inputs}")
+            }
+            Self::Entry { row, col } => write!(f, "invalid correlation at ({row}, {col})"),
+            Self::NotPositiveSemiDefinite => {
+                f.write_str("the correlation matrix isn't positive semi-definite")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CorrelationError {}
+
+/// How draws spread over the inputs' distributions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampling {
+    /// Independent draws.
+    #[default]
+    Plain,
+    /// Draws in pairs mirrored around each input's median, which cancels
+    /// much of the noise for outputs monotonic in their inputs.
+    Antithetic,
+    /// Each input's range split into as many equally likely strata as there
+    /// are draws, each stratum drawn from once, paired at random across
+    /// inputs. Covers every input's distribution evenly at any sample size.
+    LatinHypercube,
+}
+
+#[derive(Debug, Clone)]
+struct Uncertain {
+    node: NodeCelled,
+    distribution: Distribution,
+    a: f32,
+    b: f32,
+}
+
+/// Uncertain inputs of a graph, drawn for each sample of an output. Draws
+/// aren't changes to the graph, so they aren't audited, traced or watched;
+/// random ops inside the graph draw from their own generators as usual.
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    inputs: Vec<Uncertain>,
+    sampling: Sampling,
+    /// Cholesky factor of the inputs' correlations, lower triangular.
+    correlation: Option<Vec<Vec<f64>>>,
+    seed: u64,
+}
+
+impl MonteCarlo {
+    /// Draws are determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inputs: Vec::new(),
+            sampling: Sampling::default(),
+            correlation: None,
+            seed,
+        }
+    }
+
+    /// Draws `input` from `distribution`, with `a` and `b` its operands as
+    /// for `RandomOp`: the range of a uniform, or the mean and standard
+    /// deviation of a normal.
+    pub fn with_input(
+        mut self,
+        input: NodeCelled,
+        distribution: Distribution,
+        a: f32,
+        b: f32,
+    ) -> Self {
+        assert!(
+            self.correlation.is_none(),
+            "Correlations must come after every input"
+        );
+        self.inputs.push(Uncertain {
+            node: input,
+            distribution,
+            a,
+            b,
+        });
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Correlates the inputs, in the order they were added, by a Gaussian
+    /// copula: normal inputs get exactly these correlations, others the
+    /// same rank structure. `matrix` must be symmetric with a unit diagonal
+    /// and positive semi-definite, as correlations of actual variables are.
+    pub fn with_correlation(mut self, matrix: &[Vec<f32>]) -> Result<Self, CorrelationError> {
+        let n = self.inputs.len();
+        if matrix.len() != n || matrix.iter().any(|row| row.len() != n) {
+            return Err(CorrelationError::Shape { inputs: n });
+        }
+        for (row, entries) in matrix.iter().enumerate() {
+            for (col, x) in entries.iter().enumerate() {
+                let valid = if row == col {
+                    *x == 1.0
+                } else {
+                    (-1.0..=1.0).contains(x) && *x == matrix[col][row]
+                };
+                if !valid {
+                    return Err(CorrelationError::Entry { row, col });
+                }
+            }
+        }
+        self.correlation = Some(cholesky(matrix)?);
+        Ok(self)
+    }
+
+    /// `output` at `n` draws of the inputs, which are restored afterwards.
+    /// Draws an input's constraint rejects give NaN, see `Summary::nans`.
+    pub fn sample(&self, output: &NodeCelled, n: usize) -> Result<Samples, EvalError> {
+        let saved: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|input| input.node.borrow().compute())
+            .collect();
+        let res = self
+            .draws(n)
+            .into_iter()
+            .map(|row| {
+                let mut rejected = false;
+                for (input, u) in self.inputs.iter().zip(row) {
+                    rejected |= input.node.borrow().probe(input.value(u)).is_err();
+                }
+                match rejected {
+                    true => Ok(f32::NAN),
+                    false => output.borrow().try_compute(),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Samples::new);
+        for (input, x) in self.inputs.iter().zip(saved) {
+            input.node.borrow().restore(x);
+        }
+        res
+    }
+
+    /// `n` rows of one probability in `(0, 1)` per input.
+    fn draws(&self, n: usize) -> Vec<Vec<f64>> {
+        let mut res = self.independent_draws(n);
+        if let Some(factor) = &self.correlation {
+            for row in &mut res {
+                let z: Vec<_> = row.iter().map(|u| normal_quantile(*u)).collect();
+                for (u, weights) in row.iter_mut().zip(factor) {
+                    let correlated: f64 = weights.iter().zip(&z).map(|(w, z)| w * z).sum();
+                    *u = normal_cdf(correlated).clamp(f64::MIN_POSITIVE, 1.0 - f64::EPSILON);
+                }
+            }
+        }
+        res
+    }
+
+    fn independent_draws(&self, n: usize) -> Vec<Vec<f64>> {
+        let rng = Rng::new(self.seed);
+        let width = self.inputs.len();
+        match self.sampling {
+            Sampling::Plain => (0..n)
+                .map(|_| (0..width).map(|_| open_unit(&rng)).collect())
+                .collect(),
+            Sampling::Antithetic => {
+                let mut res: Vec<Vec<f64>> = Vec::with_capacity(n);
+                for i in 0..n {
+                    let row = if i % 2 == 1 {
+                        res[i - 1].iter().map(|u| 1.0 - u).collect()
+                    } else {
+                        (0..width).map(|_| open_unit(&rng)).collect()
+                    };
+                    res.push(row);
+                }
+                res
+            }
+            Sampling::LatinHypercube => {
+                let columns: Vec<Vec<f64>> = (0..width)
+                    .map(|_| {
+                        shuffled(n, &rng)
+                            .into_iter()
+                            .map(|stratum| (stratum as f64 + open_unit(&rng)) / n as f64)
+                            .collect()
+                    })
+                    .collect();
+                (0..n)
+                    .map(|i| columns.iter().map(|column| column[i]).collect())
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Sampled values of an output, in draw order, with statistics of their
+/// distribution. NaNs are kept in `values` but left out of the statistics.
+#[derive(Debug, Clone)]
+pub struct Samples {
+    values: Vec<f32>,
+    /// The values other than NaN, ascending.
+    sorted: Vec<f32>,
+}
+
+/// Moments and extremes of `Samples`, without NaNs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub nans: usize,
+    pub mean: f32,
+    /// Sample standard deviation, with Bessel's correction.
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Counts of values per bin. Bin `i` holds values from `edges[i]` up to
+/// `edges[i + 1]`, the last one including its upper edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub edges: Vec<f32>,
+    pub counts: Vec<usize>,
+    /// Values outside the edges.
+    pub below: usize,
+    pub above: usize,
+}
+
+impl Samples {
+    pub fn new(values: Vec<f32>) -> Self {
+        let mut sorted: Vec<_> = values.iter().copied().filter(|x| !x.is_nan()).collect();
+        sorted.sort_by(f32::total_cmp);
+        Self { values, sorted }
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<f32> {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Value below which a fraction `p` of the samples fall, interpolating
+    /// between neighbouring samples. NaN without samples.
+    pub fn quantile(&self, p: f32) -> f32 {
+        assert!((0.0..=1.0).contains(&p), "Quantiles are between 0 and 1");
+        let Some(last) = self.sorted.len().checked_sub(1) else {
+            return f32::NAN;
+        };
+        let at = p as f64 * last as f64;
+        let (below, fraction) = (at.floor() as usize, at.fract() as f32);
+        match self.sorted.get(below + 1) {
+            Some(next) if fraction > 0.0 => {
+                let x = self.sorted[below];
+                x + (next - x) * fraction
+            }
+            _ => self.sorted[below],
+        }
+    }
+
+    /// Percentile `percent`, e.g. `95.0`.
+    pub fn percentile(&self, percent: f32) -> f32 {
+        self.quantile(percent / 100.0)
+    }
+
+    pub fn summary(&self) -> Summary {
+        let count = self.sorted.len();
+        let mean = self.sorted.iter().map(|x| *x as f64).sum::<f64>() / count as f64;
+        let squares: f64 = self.sorted.iter().map(|x| (*x as f64 - mean).powi(2)).sum();
+        Summary {
+            count,
+            nans: self.values.len() - count,
+            mean: mean as f32,
+            std_dev: (squares / count.saturating_sub(1) as f64).sqrt() as f32,
+            min: self.sorted.first().copied().unwrap_or(f32::NAN),
+            max: self.sorted.last().copied().unwrap_or(f32::NAN),
+        }
+    }
+
+    /// `bins` equally wide bins from the smallest value to the largest.
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let (lo, hi) = match (self.sorted.first(), self.sorted.last()) {
+            (Some(lo), Some(hi)) => (*lo, *hi),
+            _ => (0.0, 0.0),
+        };
+        self.histogram_over(bins, lo, hi)
+    }
+
+    /// `bins` equally wide bins from `lo` to `hi`, so that histograms of
+    /// different runs line up.
+    pub fn histogram_over(&self, bins: usize, lo: f32, hi: f32) -> Histogram {
+        assert!(bins > 0, "A histogram needs a bin");
+        assert!(lo <= hi, "Histograms go from low to high");
+        let width = (hi - lo) / bins as f32;
+        let edges: Vec<_> = (0..=bins)
+            .map(|i| if i == bins { hi } else { lo + width * i as f32 })
+            .collect();
+
+        let mut res = Histogram {
+            edges,
+            counts: vec![0; bins],
+            below: 0,
+            above: 0,
+        };
+        for x in &self.sorted {
+            if *x < lo {
+                res.below += 1;
+            } else if *x > hi {
+                res.above += 1;
+            } else {
+                // Rounding may put values just past an edge.
+                let mut bin = if width > 0.0 {
+                    (((x - lo) / width) as usize).min(bins - 1)
+                } else {
+                    0
+                };
+                while bin > 0 && *x < res.edges[bin] {
+                    bin -= 1;
+                }
+                while bin + 1 < bins && *x >= res.edges[bin + 1] {
+                    bin += 1;
+                }
+                res.counts[bin] += 1;
+            }
+        }
+        res
+    }
+}
+
+impl Uncertain {
+    /// The value at probability `u` of the distribution.
+    fn value(&self, u: f64) -> f32 {
+        match self.distribution {
+            Distribution::Uniform => self.a + (self.b - self.a) * u as f32,
+            Distribution::Normal => self.a + self.b * normal_quantile(u) as f32,
+        }
+    }
+}
+
+/// Uniform in `(0, 1)`, so that quantiles stay finite.
+fn open_unit(rng: &Arc<Rng>) -> f64 {
+    ((rng.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+}
+
+/// `0..n` in random order, by Fisher-Yates.
+fn shuffled(n: usize, rng: &Arc<Rng>) -> Vec<usize> {
+    let mut res: Vec<_> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+        res.swap(i, j);
+    }
+    res
+}
+
+/// Lower triangular `L` with `L Lᵀ = matrix`. Columns of a semi-definite
+/// matrix that depend on earlier ones get no weight of their own.
+fn cholesky(matrix: &[Vec<f32>]) -> Result<Vec<Vec<f64>>, CorrelationError> {
+    const TOLERANCE: f64 = 1e-6;
+    let n = matrix.len();
+    let mut res = vec![vec![0.0; n]; n];
+    for j in 0..n {
+        let pivot = matrix[j][j] as f64 - (0..j).map(|k| res[j][k] * res[j][k]).sum::<f64>();
+        if pivot < -TOLERANCE {
+            return Err(CorrelationError::NotPositiveSemiDefinite);
+        }
+        let diagonal = pivot.max(0.0).sqrt();
+        res[j][j] = diagonal;
+        for i in j + 1..n {
+            let rest = matrix[i][j] as f64 - (0..j).map(|k| res[i][k] * res[j][k]).sum::<f64>();
+            if diagonal > TOLERANCE {
+                res[i][j] = rest / diagonal;
+            } else if rest.abs() > TOLERANCE {
+                return Err(CorrelationError::NotPositiveSemiDefinite);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Standard normal CDF, from the complementary error function with the
+/// Chebyshev fit of Numerical Recipes' `erfcc`, to a relative `1.2e-7`.
+fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let coefficients = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ];
+    let polynomial = coefficients.iter().rev().fold(0.0, |acc, c| acc * t + c);
+    let erfc = t * (-z * z + polynomial).exp();
+    if x >= 0.0 {
+        1.0 - 0.5 * erfc
+    } else {
+        0.5 * erfc
+    }
+}
+
+/// Inverse of the standard normal CDF, by Acklam's rational approximation,
+/// with a relative error below `1.2e-9`.
+pub(crate) fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+    use crate::constraint::Constraint;
+
+    /// `2x + y`, with `x` and `y`.
+    fn graph() -> (NodeCelled, NodeCelled, NodeCelled) {
+        let x = Node::create_input(0.5f32);
+        let y = Node::create_input(-1f32);
+        let output = Node::create_mul_add(x.clone(), Node::create_const(2.0), y.clone());
+        (output, x, y)
+    }
+
+    fn simulation(seed: u64, x: &NodeCelled, y: &NodeCelled) -> MonteCarlo {
+        MonteCarlo::new(seed)
+            .with_input(x.clone(), Distribution::Uniform, 0.0, 1.0)
+            .with_input(y.clone(), Distribution::Normal, 3.0, 0.5)
+    }
+
+    #[test]
+    fn seeds_determine_samples() {
+        let (output, x, y) = graph();
+        let first = simulation(7, &x, &y).sample(&output, 100).unwrap();
+        let again = simulation(7, &x, &y).sample(&output, 100).unwrap();
+        let other = simulation(8, &x, &y).sample(&output, 100).unwrap();
+        assert_eq!(first.values(), again.values());
+        assert_ne!(first.values(), other.values());
+        assert_eq!((x.borrow().compute(), y.borrow().compute()), (0.5, -1.0));
+    }
+
+    #[test]
+    fn means_converge() {
+        // `E[2x + y] = 4`, its standard deviation `√(1/3 + 1/4)`.
+        let (output, x, y) = graph();
+        let error = |sampling, n| {
+            let samples = simulation(1, &x, &y)
+                .with_sampling(sampling)
+                .sample(&output, n)
+                .unwrap();
+            (samples.summary().mean - 4.0).abs()
+        };
+        for sampling in [
+            Sampling::Plain,
+            Sampling::Antithetic,
+            Sampling::LatinHypercube,
+        ] {
+            assert!(error(sampling, 100_000) < 0.01, "{sampling:?}");
+        }
+        assert!(error(Sampling::Plain, 100_000) < error(Sampling::Plain, 100));
+        // Mirrored pairs of a linear output average exactly.
+        assert!(error(Sampling::Antithetic, 10) < 1e-5);
+
+        let summary = simulation(1, &x, &y)
+            .sample(&output, 100_000)
+            .unwrap()
+            .summary();
+        assert!((summary.std_dev - (7f32 / 12.0).sqrt()).abs() < 0.01);
+        assert_eq!((summary.count, summary.nans), (100_000, 0));
+    }
+
+    #[test]
+    fn correlates_inputs() {
+        let x = Node::create_input(0f32);
+        let y = Node::create_input(0f32);
+        let simulation = MonteCarlo::new(3)
+            .with_input(x.clone(), Distribution::Normal, 0.0, 1.0)
+            .with_input(y.clone(), Distribution::Normal, 0.0, 1.0);
+        let correlated = simulation
+            .clone()
+            .with_correlation(&[vec![1.0, 0.8], vec![0.8, 1.0]])
+            .unwrap();
+        // `Var(x + y) = 2 + 2ρ`.
+        let sum = Node::create_add(x, y);
+        let variance = correlated
+            .sample(&sum, 50_000)
+            .unwrap()
+            .summary()
+            .std_dev
+            .powi(2);
+        assert!((variance - 3.6).abs() < 0.1, "{variance}");
+
+        let res = simulation.clone().with_correlation(&[vec![1.0, 0.8]]);
+        assert_eq!(res.unwrap_err(), CorrelationError::Shape { inputs: 2 });
+        let res = simulation
+            .clone()
+            .with_correlation(&[vec![1.0, 0.8], vec![0.7, 1.0]]);
+        assert_eq!(res.unwrap_err(), CorrelationError::Entry { row: 0, col: 1 });
+        let three = simulation.with_input(sum, Distribution::Uniform, 0.0, 1.0);
+        let res = three.with_correlation(&[
+            vec![1.0, 0.9, -0.9],
+            vec![0.9, 1.0, 0.9],
+            vec![-0.9, 0.9, 1.0],
+        ]);
+        assert_eq!(res.unwrap_err(), CorrelationError::NotPositiveSemiDefinite);
+    }
+
+    #[test]
+    fn rejected_draws_are_nan() {
+        let (output, x, y) = graph();
+        x.borrow().set_constraint(Constraint::new().with_max(0.5));
+        let samples = simulation(5, &x, &y).sample(&output, 1000).unwrap();
+        let summary = samples.summary();
+        assert_eq!(summary.count + summary.nans, 1000);
+        assert!(summary.nans > 400 && summary.nans < 600, "{summary:?}");
+        assert!(summary.max < 2.0 * 0.5 + 5.0);
+    }
+
+    #[test]
+    fn summarizes_samples() {
+        let samples = Samples::new(vec![4.0, f32::NAN, 1.0, 3.0, 2.0]);
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples.quantile(0.0), 1.0);
+        assert_eq!(samples.quantile(0.5), 2.5);
+        assert_eq!(samples.percentile(100.0), 4.0);
+        assert_eq!(
+            samples.histogram(3),
+            Histogram {
+                edges: vec![1.0, 2.0, 3.0, 4.0],
+                counts: vec![1, 1, 2],
+                below: 0,
+                above: 0,
+            }
+        );
+        let histogram = samples.histogram_over(2, 2.0, 3.0);
+        assert_eq!(
+            (histogram.counts, histogram.below, histogram.above),
+            (vec![1, 1], 1, 1)
+        );
+        assert!(Samples::new(Vec::new()).quantile(0.5).is_nan());
+    }
+}
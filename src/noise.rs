@@ -0,0 +1,175 @@
+//! Gradient noise over coordinate nodes, for procedural terrain and textures.
+
+use std::sync::Arc;
+
+use crate::computational_graph::{CustomOp, Node, NodeCelled};
+use crate::random::Rng;
+
+/// Perlin's improved noise in 1 or 2 dimensions, by operand count. Values are
+/// roughly in `[-1, 1]`, zero at integer coordinates and continuous with a
+/// continuous derivative. Unlike `RandomOp`, the result only depends on the
+/// coordinates, so one instance can back any number of nodes.
+#[derive(Debug)]
+pub struct Perlin {
+    /// A permutation of `0..256`, twice, to index without wrapping.
+    perm: [u8; 512],
+}
+
+impl Perlin {
+    /// Noise with its own permutation, the same for the same `seed`.
+    pub fn new(seed: u64) -> Arc<Self> {
+        let rng = Rng::new(seed);
+        let mut table: Vec<u8> = (0..=255).collect();
+        for i in (1..table.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i % 256];
+        }
+        Arc::new(Self { perm })
+    }
+
+    pub fn noise1(&self, x: f32) -> f32 {
+        let (i, x) = split(x);
+        let u = fade(x);
+        let a = grad1(self.perm[i], x);
+        let b = grad1(self.perm[i + 1], x - 1.0);
+        // Slopes of up to 8 keep values within `[-4, 4]`.
+        lerp(u, a, b) / 4.0
+    }
+
+    pub fn noise2(&self, x: f32, y: f32) -> f32 {
+        let (i, x) = split(x);
+        let (j, y) = split(y);
+        let (u, v) = (fade(x), fade(y));
+        let hash = |di: usize, dj: usize| self.perm[self.perm[i + di] as usize + j + dj];
+
+        let bottom = lerp(u, grad2(hash(0, 0), x, y), grad2(hash(1, 0), x - 1.0, y));
+        let top = lerp(
+            u,
+            grad2(hash(0, 1), x, y - 1.0),
+            grad2(hash(1, 1), x - 1.0, y - 1.0),
+        );
+        lerp(v, bottom, top)
+    }
+}
+
+impl CustomOp for Perlin {
+    fn name(&self) -> &str {
+        "perlin"
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        match args {
+            [x] => Ok(self.noise1(*x)),
+            [x, y] => Ok(self.noise2(*x, *y)),
+            _ => Err(format!("expected 1 or 2 operands, got {}", args.len())),
+        }
+    }
+}
+
+impl Node {
+    pub fn create_perlin1(noise: &Arc<Perlin>, x: NodeCelled) -> NodeCelled {
+        Self::create_custom(noise.clone(), vec![x])
+    }
+
+    pub fn create_perlin2(noise: &Arc<Perlin>, x: NodeCelled, y: NodeCelled) -> NodeCelled {
+        Self::create_custom(noise.clone(), vec![x, y])
+    }
+}
+
+/// Lattice cell, wrapped to the permutation's period, and offset into it.
+fn split(x: f32) -> (usize, f32) {
+    let floor = x.floor();
+    ((floor as i64).rem_euclid(256) as usize, x - floor)
+}
+
+/// `6t^5 - 15t^4 + 10t^3`, flat at both ends.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+fn grad1(hash: u8, x: f32) -> f32 {
+    let slope = (hash & 7) as f32 + 1.0;
+    if hash & 8 == 0 {
+        slope * x
+    } else {
+        -slope * x
+    }
+}
+
+/// Dot product with one of 8 gradients: the axes and the diagonals.
+fn grad2(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points of a grid over a few cells, not on the lattice.
+    fn points() -> impl Iterator<Item = (f32, f32)> {
+        (0..40).flat_map(|i| (0..40).map(move |j| (i as f32 * 0.173 - 3.0, j as f32 * 0.291 - 5.0)))
+    }
+
+    #[test]
+    fn seeds_determine_noise() {
+        let (a, b, other) = (Perlin::new(5), Perlin::new(5), Perlin::new(6));
+        assert!(points().all(|(x, y)| a.noise2(x, y) == b.noise2(x, y)));
+        assert!(points().any(|(x, y)| a.noise2(x, y) != other.noise2(x, y)));
+        assert!(points().all(|(x, _)| a.noise1(x) == b.noise1(x)));
+    }
+
+    #[test]
+    fn noise_is_bounded_and_zero_on_the_lattice() {
+        let noise = Perlin::new(1);
+        for i in -300..300 {
+            assert_eq!(noise.noise1(i as f32), 0f32);
+            assert_eq!(noise.noise2(i as f32, (i / 3) as f32), 0f32);
+        }
+        for (x, y) in points() {
+            assert!((-1.0..=1.0).contains(&noise.noise1(x)));
+            assert!((-1.0..=1.0).contains(&noise.noise2(x, y)));
+        }
+        assert!(points().any(|(x, y)| noise.noise2(x, y).abs() > 0.1));
+    }
+
+    #[test]
+    fn noise_is_continuous() {
+        let noise = Perlin::new(2);
+        for (x, y) in points() {
+            let step = 1e-3;
+            assert!((noise.noise1(x + step) - noise.noise1(x)).abs() < 0.01);
+            assert!((noise.noise2(x + step, y) - noise.noise2(x, y)).abs() < 0.01);
+            assert!((noise.noise2(x, y + step) - noise.noise2(x, y)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn computes_nodes_by_operand_count() {
+        let noise = Perlin::new(3);
+        let (x, y) = (Node::create_input(0.5f32), Node::create_input(1.25f32));
+        let one = Node::create_perlin1(&noise, x.clone());
+        let two = Node::create_perlin2(&noise, x, y);
+        assert_eq!(one.borrow().compute(), noise.noise1(0.5));
+        assert_eq!(two.borrow().compute(), noise.noise2(0.5, 1.25));
+        let error = noise.compute(&[1.0, 2.0, 3.0]).unwrap_err();
+        assert_eq!(error, "expected 1 or 2 operands, got 3");
+    }
+}
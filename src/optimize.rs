@@ -0,0 +1,538 @@
+//! Graph-to-graph rewrites. Passes never mutate their input: they return a new
+//! output node built over the same `Input` nodes, so `set()` keeps driving both.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::computational_graph::{
+    BinaryOp, CachePolicy, EvalError, InputKind, Node, NodeCelled, NodeId, PowPolicy, TernaryOp,
+};
+use crate::hash::{write_op, Fnv64};
+
+/// Rebuilds the graph bottom-up, offering every node (with already rewritten
+/// children) to `rewrite`. Shared subgraphs are rewritten once and stay shared.
+/// Nodes built along the way that the result doesn't use are detached.
+pub(crate) fn transform(
+    output: &NodeCelled,
+    rewrite: &mut impl FnMut(&NodeCelled) -> Option<NodeCelled>,
+) -> NodeCelled {
+    let first = NodeId::upcoming();
+    let mut memo = HashMap::new();
+    let res = transform_node(output, rewrite, &mut memo);
+    Node::detach_unused(first, &[output, &res]);
+    res
+}
+
+fn transform_node(
+    node: &NodeCelled,
+    rewrite: &mut impl FnMut(&NodeCelled) -> Option<NodeCelled>,
+    memo: &mut HashMap<*const (), NodeCelled>,
+) -> NodeCelled {
+    let key = Rc::as_ptr(node) as *const ();
+    if let Some(done) = memo.get(&key) {
+        return done.clone();
+    }
+
+    let children = node.borrow().children();
+    let new_children: Vec<_> = children
+        .iter()
+        .map(|child| transform_node(child, rewrite, memo))
+        .collect();
+    let unchanged = children
+        .iter()
+        .zip(&new_children)
+        .all(|(old, new)| Rc::ptr_eq(old, new));
+
+    let rebuilt = if unchanged {
+        node.clone()
+    } else {
+        Node::with_children(node, new_children)
+    };
+    let res = rewrite(&rebuilt).unwrap_or(rebuilt);
+
+    memo.insert(key, res.clone());
+    res
+}
+
+/// Fuses every `Add` with a `Mul` operand into a single-rounding `MulAdd`.
+pub fn fuse_mul_add(output: &NodeCelled) -> NodeCelled {
+    transform(output, &mut |node| {
+        let Node::Binary {
+            op: BinaryOp::Add,
+            a,
+            b,
+            ..
+        } = &*node.borrow()
+        else {
+            return None;
+        };
+
+        let (product, addend) = if is_mul(a) {
+            (a, b)
+        } else if is_mul(b) {
+            (b, a)
+        } else {
+            return None;
+        };
+        let factors = product.borrow().children();
+
+        Some(Node::create_mul_add(
+            factors[0].clone(),
+            factors[1].clone(),
+            addend.clone(),
+        ))
+    })
+}
+
+/// Replaces `Pow(x, c)` by multiplications for constant `c` of 1 to 4. The
+/// exponent is positive, so no `PowPolicy` check could have fired. Results may
+/// differ from `powf` in the last bit.
+pub fn reduce_pow(output: &NodeCelled) -> NodeCelled {
+    transform(output, &mut |node| {
+        let Node::Binary {
+            op: BinaryOp::Pow(_),
+            a,
+            b,
+            ..
+        } = &*node.borrow()
+        else {
+            return None;
+        };
+
+        let square = || Node::create_mul(a.clone(), a.clone());
+        match small_exponent(b)? {
+            1 => Some(a.clone()),
+            2 => Some(square()),
+            3 => Some(Node::create_mul(square(), a.clone())),
+            4 => {
+                let square = square();
+                Some(Node::create_mul(square.clone(), square))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Replaces the inputs in `fixed` by constants of the given values and folds
+/// every node whose operands are all constant, leaving a graph over the other
+/// inputs. `NoCache` nodes are kept, their value may change between calls.
+/// Fails if a folded node fails to evaluate.
+pub fn partial_eval(
+    output: &NodeCelled,
+    fixed: &[(NodeCelled, f32)],
+) -> Result<NodeCelled, EvalError> {
+    let fixed: HashMap<_, _> = fixed
+        .iter()
+        .map(|(input, value)| {
+            assert!(
+                matches!(&*input.borrow(), Node::Input { .. }),
+                "Only \"Input\" nodes can be fixed"
+            );
+            (Rc::as_ptr(input), *value)
+        })
+        .collect();
+    let is_const = |node: &NodeCelled| {
+        matches!(
+            &*node.borrow(),
+            Node::Input {
+                kind: InputKind::Const,
+                ..
+            }
+        )
+    };
+
+    let mut error = None;
+    let res = transform(output, &mut |node| {
+        if let Some(value) = fixed.get(&Rc::as_ptr(node)) {
+            return Some(Node::create_const(*value));
+        }
+
+        let node_ref = node.borrow();
+        let foldable = !matches!(&*node_ref, Node::Input { .. })
+            && node_ref.cache_policy() != CachePolicy::NoCache
+            && node_ref.children().iter().all(is_const);
+        if !foldable || error.is_some() {
+            return None;
+        }
+        match node_ref.try_compute() {
+            Ok(value) => Some(Node::create_const(value)),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(res),
+    }
+}
+
+fn is_mul(node: &NodeCelled) -> bool {
+    matches!(
+        &*node.borrow(),
+        Node::Binary {
+            op: BinaryOp::Mul,
+            ..
+        }
+    )
+}
+
+/// Flattens `Add` and `Mul` chains and rebuilds them left-leaning with
+/// operands sorted by structure, and orders the factors of `MulAdd` the same
+/// way, so equal expressions built in different orders come out identical.
+/// The order is that of a hash of each operand's structure, telling inputs
+/// apart by id and constants by value: stable for the same nodes, but not
+/// meaningful otherwise. Reassociated chains may round differently.
+pub fn canonicalize(output: &NodeCelled) -> NodeCelled {
+    let mut keys = HashMap::new();
+    transform(output, &mut |node| {
+        let op = match &*node.borrow() {
+            Node::Binary {
+                op: op @ (BinaryOp::Add | BinaryOp::Mul),
+                ..
+            } => op.clone(),
+            Node::Ternary {
+                op: TernaryOp::MulAdd,
+                a,
+                b,
+                c,
+                ..
+            } => {
+                if canonical_key(a, &mut keys) <= canonical_key(b, &mut keys) {
+                    return None;
+                }
+                return Some(Node::create_mul_add(b.clone(), a.clone(), c.clone()));
+            }
+            _ => return None,
+        };
+
+        let mut operands = Vec::new();
+        flatten(node, &op, &mut operands);
+        let sorted = operands
+            .windows(2)
+            .all(|w| canonical_key(&w[0], &mut keys) <= canonical_key(&w[1], &mut keys));
+        // Children are canonical already, so a sorted chain whose right operand
+        // isn't a nested chain is left-leaning as is.
+        if sorted && !is_op(&node.borrow().children()[1], &op) {
+            return None;
+        }
+
+        operands.sort_by_cached_key(|operand| canonical_key(operand, &mut keys));
+        let mut operands = operands.into_iter();
+        let first = operands.next().unwrap();
+        Some(operands.fold(first, |acc, operand| {
+            Node::with_children(node, vec![acc, operand])
+        }))
+    })
+}
+
+fn flatten(node: &NodeCelled, op: &BinaryOp, operands: &mut Vec<NodeCelled>) {
+    if !is_op(node, op) {
+        operands.push(node.clone());
+        return;
+    }
+    for child in node.borrow().children() {
+        flatten(&child, op, operands);
+    }
+}
+
+fn is_op(node: &NodeCelled, op: &BinaryOp) -> bool {
+    match &*node.borrow() {
+        Node::Binary { op: node_op, .. } => {
+            std::mem::discriminant(node_op) == std::mem::discriminant(op)
+        }
+        _ => false,
+    }
+}
+
+/// Like `structure_hash`, but inputs are told apart by identity rather than
+/// position, so the key doesn't depend on operand order. Memoized entries hold
+/// their node, so a freed node's address can't alias a later one.
+fn canonical_key(node: &NodeCelled, keys: &mut HashMap<*const (), (NodeCelled, u64)>) -> u64 {
+    let ptr = Rc::as_ptr(node) as *const ();
+    if let Some((_, key)) = keys.get(&ptr) {
+        return *key;
+    }
+
+    let mut hasher = Fnv64::new();
+    write_op(&mut hasher, &node.borrow());
+    if let Node::Input { kind, .. } = &*node.borrow() {
+        if *kind != InputKind::Const {
+            hasher.write_u64(node.borrow().id().index() as u64);
+        }
+    }
+    for child in node.borrow().children() {
+        hasher.write_u64(canonical_key(&child, keys));
+    }
+    let key = hasher.finish();
+
+    keys.insert(ptr, (node.clone(), key));
+    key
+}
+
+/// Highest degree `horner` expands to, so `x^1000` stays a `Pow`.
+pub const MAX_HORNER_DEGREE: usize = 32;
+
+/// Rewrites polynomials in a single variable into Horner form, one `MulAdd`
+/// per degree, wherever that takes fewer ops than the original. The variable
+/// can be any node (`sin(x)` works too) and must be shared, not repeated, see
+/// `canonicalize`. Only constants (`Node::create_const`) count as coefficients.
+pub fn horner(output: &NodeCelled) -> NodeCelled {
+    let mut polys = HashMap::new();
+    transform(output, &mut |node| {
+        if let Node::Ternary { .. } = &*node.borrow() {
+            return None;
+        }
+
+        let poly = polynomial(node, &mut polys);
+        let var = poly.var.as_ref()?;
+        let degree = poly.coeffs.len() - 1;
+        if degree < 2 || degree >= poly.ops {
+            return None;
+        }
+
+        let mut res = Node::create_const(poly.coeffs[degree]);
+        for &coeff in poly.coeffs[..degree].iter().rev() {
+            res = if coeff == 0f32 {
+                Node::create_mul(res, var.clone())
+            } else {
+                Node::create_mul_add(res, var.clone(), Node::create_const(coeff))
+            };
+        }
+        Some(res)
+    })
+}
+
+/// `coeffs[i]` is the coefficient of `var^i`, with no trailing zeros.
+#[derive(Clone)]
+struct Polynomial {
+    var: Option<NodeCelled>,
+    coeffs: Vec<f32>,
+    /// Ops the polynomial takes as written.
+    ops: usize,
+}
+
+impl Polynomial {
+    fn constant(c: f32) -> Self {
+        Self {
+            var: None,
+            coeffs: vec![c],
+            ops: 0,
+        }
+    }
+
+    fn var(node: &NodeCelled) -> Self {
+        Self {
+            var: Some(node.clone()),
+            coeffs: vec![0f32, 1f32],
+            ops: 0,
+        }
+    }
+
+    /// `None` if the operands are in different variables.
+    fn combine(
+        &self,
+        other: &Self,
+        coeffs: impl FnOnce(&[f32], &[f32]) -> Vec<f32>,
+    ) -> Option<Self> {
+        let var = match (&self.var, &other.var) {
+            (Some(a), Some(b)) if !Rc::ptr_eq(a, b) => return None,
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        let mut coeffs = coeffs(&self.coeffs, &other.coeffs);
+        while coeffs.len() > 1 && coeffs.last() == Some(&0f32) {
+            coeffs.pop();
+        }
+        if coeffs.len() > MAX_HORNER_DEGREE + 1 {
+            return None;
+        }
+
+        Some(Self {
+            var,
+            coeffs,
+            ops: self.ops + other.ops + 1,
+        })
+    }
+
+    fn add(&self, other: &Self) -> Option<Self> {
+        self.combine(other, |a, b| {
+            (0..a.len().max(b.len()))
+                .map(|i| a.get(i).unwrap_or(&0f32) + b.get(i).unwrap_or(&0f32))
+                .collect()
+        })
+    }
+
+    fn mul(&self, other: &Self) -> Option<Self> {
+        self.combine(other, |a, b| {
+            let mut res = vec![0f32; a.len() + b.len() - 1];
+            for (i, a) in a.iter().enumerate() {
+                for (j, b) in b.iter().enumerate() {
+                    res[i + j] += a * b;
+                }
+            }
+            res
+        })
+    }
+
+    fn pow(&self, exponent: usize) -> Option<Self> {
+        let mut res = Self::constant(1f32);
+        for _ in 0..exponent {
+            res = res.mul(self)?;
+        }
+        res.ops = self.ops + 1;
+        Some(res)
+    }
+}
+
+/// Reads `node` as a polynomial, falling back to treating it as the variable.
+/// Memoized entries hold their node, like in `canonical_key`.
+fn polynomial(
+    node: &NodeCelled,
+    polys: &mut HashMap<*const (), (NodeCelled, Polynomial)>,
+) -> Polynomial {
+    let ptr = Rc::as_ptr(node) as *const ();
+    if let Some((_, poly)) = polys.get(&ptr) {
+        return poly.clone();
+    }
+
+    let mut operand = |node: &NodeCelled| polynomial(node, polys);
+    let poly = match &*node.borrow() {
+        Node::Input {
+            x,
+            kind: InputKind::Const,
+            ..
+        } => Some(Polynomial::constant(*x.borrow())),
+        Node::Binary {
+            op: BinaryOp::Add,
+            a,
+            b,
+            ..
+        } => operand(a).add(&operand(b)),
+        Node::Binary {
+            op: BinaryOp::Mul,
+            a,
+            b,
+            ..
+        } => operand(a).mul(&operand(b)),
+        Node::Binary {
+            op: BinaryOp::Pow(PowPolicy::Native),
+            a,
+            b,
+            ..
+        } => small_exponent(b).and_then(|exponent| operand(a).pow(exponent)),
+        Node::Ternary {
+            op: TernaryOp::MulAdd,
+            a,
+            b,
+            c,
+            ..
+        } => operand(a).mul(&operand(b)).and_then(|ab| {
+            let mut res = ab.add(&operand(c))?;
+            res.ops -= 1;
+            Some(res)
+        }),
+        _ => None,
+    }
+    .unwrap_or_else(|| Polynomial::var(node));
+
+    polys.insert(ptr, (node.clone(), poly.clone()));
+    poly
+}
+
+fn small_exponent(node: &NodeCelled) -> Option<usize> {
+    match &*node.borrow() {
+        Node::Input {
+            x,
+            kind: InputKind::Const,
+            ..
+        } => {
+            let x = *x.borrow();
+            let in_range = x >= 0f32 && x <= MAX_HORNER_DEGREE as f32 && x.fract() == 0f32;
+            in_range.then_some(x as usize)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewrite::{rewrite, CombineLikeTerms, Factor, RuleSet};
+
+    /// Asserts that every node `graphs` use is depended on by theirs only,
+    /// none that a pass built and discarded.
+    fn assert_no_strays(graphs: &[&NodeCelled]) {
+        let nodes: Vec<_> = graphs
+            .iter()
+            .flat_map(|graph| Node::topo_order(graph))
+            .collect();
+        for node in &nodes {
+            for dependent in node.borrow().dependents() {
+                assert!(
+                    nodes.iter().any(|node| Rc::ptr_eq(node, &dependent)),
+                    "{} is a stray dependent of {}",
+                    dependent.borrow(),
+                    node.borrow()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fuse_mul_add_detaches_what_it_discards() {
+        let x = Node::create_input(1.0);
+        let y = Node::create_input(2.0);
+        let inner = Node::create_add(Node::create_mul(x.clone(), y), x.clone());
+        let output = Node::create_add(Node::create_mul(inner, Node::create_const(2.0)), x);
+
+        let fused = fuse_mul_add(&output);
+        assert_no_strays(&[&output, &fused]);
+    }
+
+    #[test]
+    fn canonicalize_detaches_what_it_discards() {
+        // Each nested chain is rebuilt, then flattened into its parent's.
+        let [w, x, y, z] = [1.0, 2.0, 3.0, 4.0].map(Node::create_input);
+        let output = Node::create_add(w, Node::create_add(z, Node::create_add(y, x)));
+
+        let canonical = canonicalize(&output);
+        assert_no_strays(&[&output, &canonical]);
+    }
+
+    #[test]
+    fn rewrite_detaches_earlier_passes() {
+        // Factoring out `x` leaves `2 + 3` for the next pass to combine.
+        let x = Node::create_input(1.0);
+        x.borrow().set_name("x");
+        let output = Node::create_add(
+            Node::create_mul(x.clone(), Node::create_const(2.0)),
+            Node::create_mul(x, Node::create_const(3.0)),
+        );
+
+        let rules = RuleSet::new().with(Factor).with(CombineLikeTerms);
+        let simplified = rewrite(&output, &rules);
+        assert_eq!(simplified.borrow().to_string(), "(x * 5)");
+        assert_no_strays(&[&output, &simplified]);
+    }
+
+    #[test]
+    fn canonicalize_ignores_operand_order() {
+        let x = Node::create_input(1.0);
+        let y = Node::create_input(2.0);
+        let two = || Node::create_const(2.0);
+        let left = Node::create_add(
+            Node::create_add(x.clone(), two()),
+            Node::create_mul(y.clone(), x.clone()),
+        );
+        let right = Node::create_add(
+            Node::create_mul(x.clone(), y.clone()),
+            Node::create_add(two(), x.clone()),
+        );
+
+        let left = canonicalize(&left);
+        let right = canonicalize(&right);
+        assert_eq!(left.borrow().to_string(), right.borrow().to_string());
+    }
+}
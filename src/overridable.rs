@@ -0,0 +1,78 @@
+//! Values that are normally computed but can be entered by hand, as planning
+//! tools allow for any forecast line.
+
+use crate::computational_graph::{Comparison, Node, NodeCelled};
+
+/// Node following `computed` until pinned to a manual value, and again once
+/// unpinned. It's a `select` between `computed` and an input holding the
+/// manual value, so every evaluator handles it, and pinning, unpinning and
+/// changes below `computed` invalidate what depends on it like any input
+/// would. While pinned, changes below `computed` still recompute it, but
+/// leave the value unchanged. `Node::reset_inputs` unpins it.
+#[derive(Debug, Clone)]
+pub struct Overridable {
+    node: NodeCelled,
+    computed: NodeCelled,
+    pinned: NodeCelled,
+    manual: NodeCelled,
+}
+
+impl Overridable {
+    pub fn new(computed: NodeCelled) -> Self {
+        let pinned = Node::create_input(0.0);
+        let manual = Node::create_input(0.0);
+        // Compared, so that the condition is a boolean for `check_types`.
+        let is_pinned =
+            Node::create_compare(pinned.clone(), Node::create_const(0.0), Comparison::Ne);
+        let node = Node::create_select(is_pinned, manual.clone(), computed.clone());
+        Self {
+            node,
+            computed,
+            pinned,
+            manual,
+        }
+    }
+
+    /// The node to build on.
+    pub fn node(&self) -> &NodeCelled {
+        &self.node
+    }
+
+    pub fn computed(&self) -> &NodeCelled {
+        &self.computed
+    }
+
+    /// Holds the node at `x` until `unpin`. Pinning again replaces the value.
+    pub fn pin(&self, x: f32) {
+        self.manual.borrow().set(x);
+        self.pinned.borrow().set(1.0);
+    }
+
+    /// Makes the node follow `computed` again.
+    pub fn unpin(&self) {
+        self.pinned.borrow().set(0.0);
+    }
+
+    /// The manual value, while pinned.
+    pub fn pinned(&self) -> Option<f32> {
+        let pinned = self.pinned.borrow().compute() != 0.0;
+        pinned.then(|| self.manual.borrow().compute())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_checks() {
+        let x = Node::create_input(2.0);
+        let overridable = Overridable::new(Node::create_mul(x.clone(), x));
+        assert!(overridable.node().borrow().check_types().is_ok());
+        assert_eq!(overridable.node().borrow().compute(), 4.0);
+        overridable.pin(7.0);
+        assert_eq!(overridable.node().borrow().compute(), 7.0);
+        overridable.unpin();
+        assert_eq!(overridable.node().borrow().compute(), 4.0);
+    }
+}
@@ -0,0 +1,235 @@
+//! Trade-offs between two outputs of one graph: the input values for which
+//! neither output can improve without the other getting worse.
+
+use crate::computational_graph::{EvalError, NodeCelled};
+
+/// Which way an objective improves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Goal {
+    #[default]
+    Minimize,
+    Maximize,
+}
+
+impl Goal {
+    /// `x` as a cost, lower being better.
+    fn cost(self, x: f32) -> f32 {
+        match self {
+            Self::Minimize => x,
+            Self::Maximize => -x,
+        }
+    }
+}
+
+/// Grid point on the frontier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoPoint {
+    pub inputs: Vec<f32>,
+    pub objectives: (f32, f32),
+}
+
+/// Grid sweep settings.
+#[derive(Debug, Clone)]
+pub struct ParetoSweep {
+    samples: usize,
+    goals: (Goal, Goal),
+}
+
+impl Default for ParetoSweep {
+    fn default() -> Self {
+        Self {
+            samples: 11,
+            goals: (Goal::Minimize, Goal::Minimize),
+        }
+    }
+}
+
+impl ParetoSweep {
+    /// Evenly spaced values per input, ends included. The grid has
+    /// `samples^inputs` points.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        assert!(samples >= 2, "A sweep needs both ends of each range");
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_goals(mut self, first: Goal, second: Goal) -> Self {
+        self.goals = (first, second);
+        self
+    }
+
+    /// Grid points over `ranges` of `inputs` that no other grid point beats
+    /// on both `outputs`, ordered by the first output, best first. Points
+    /// where an output is NaN, or an input's constraint rejects its value,
+    /// are skipped, and of equal points the first found is kept.
+    ///
+    /// The grid is walked so that consecutive points differ in one input,
+    /// which leaves caches of nodes not depending on it valid. `inputs` are
+    /// restored afterwards.
+    pub fn frontier(
+        &self,
+        outputs: (&NodeCelled, &NodeCelled),
+        inputs: &[NodeCelled],
+        ranges: &[(f32, f32)],
+    ) -> Result<Vec<ParetoPoint>, EvalError> {
+        assert_eq!(inputs.len(), ranges.len(), "One range per input");
+        let saved: Vec<_> = inputs
+            .iter()
+            .map(|input| input.borrow().compute())
+            .collect();
+        let res = self.sweep(outputs, inputs, ranges);
+        for (input, x) in inputs.iter().zip(saved) {
+            input.borrow().restore(x);
+        }
+        Ok(self.dominant(res?))
+    }
+
+    /// Every grid point, in walking order.
+    fn sweep(
+        &self,
+        (first, second): (&NodeCelled, &NodeCelled),
+        inputs: &[NodeCelled],
+        ranges: &[(f32, f32)],
+    ) -> Result<Vec<ParetoPoint>, EvalError> {
+        let value = |k: usize, i: usize| {
+            let (lo, hi) = ranges[k];
+            lo + (hi - lo) * (i as f32 / (self.samples - 1) as f32)
+        };
+        let mut index = vec![0; inputs.len()];
+        let mut forward = vec![true; inputs.len()];
+        // Inputs left at an earlier value, their constraint rejecting the
+        // current one.
+        let mut rejected: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(k, input)| input.borrow().probe(value(k, 0)).is_err())
+            .collect();
+
+        let mut res = Vec::new();
+        loop {
+            let objectives = match rejected.contains(&true) {
+                true => (f32::NAN, f32::NAN),
+                false => (
+                    first.borrow().try_compute()?,
+                    second.borrow().try_compute()?,
+                ),
+            };
+            res.push(ParetoPoint {
+                inputs: (0..inputs.len()).map(|k| value(k, index[k])).collect(),
+                objectives,
+            });
+
+            // Move the last input that can go on in its direction, turning
+            // the later ones around, like a boustrophedon.
+            let Some(k) = (0..inputs.len()).rev().find(|k| {
+                if forward[*k] {
+                    index[*k] + 1 < self.samples
+                } else {
+                    index[*k] > 0
+                }
+            }) else {
+                return Ok(res);
+            };
+            for later in &mut forward[k + 1..] {
+                *later = !*later;
+            }
+            if forward[k] {
+                index[k] += 1;
+            } else {
+                index[k] -= 1;
+            }
+            rejected[k] = inputs[k].borrow().probe(value(k, index[k])).is_err();
+        }
+    }
+
+    fn dominant(&self, mut points: Vec<ParetoPoint>) -> Vec<ParetoPoint> {
+        let (a, b) = self.goals;
+        let costs = |point: &ParetoPoint| (a.cost(point.objectives.0), b.cost(point.objectives.1));
+        points.retain(|point| !point.objectives.0.is_nan() && !point.objectives.1.is_nan());
+        // Stable, so the first found of equal points comes first.
+        points.sort_by(|p, q| {
+            let ((p0, p1), (q0, q1)) = (costs(p), costs(q));
+            p0.total_cmp(&q0).then(p1.total_cmp(&q1))
+        });
+
+        let mut res: Vec<ParetoPoint> = Vec::new();
+        for point in points {
+            let better = res
+                .last()
+                .is_none_or(|last| costs(&point).1 < costs(last).1);
+            if better {
+                res.push(point);
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+    use crate::constraint::Constraint;
+
+    #[test]
+    fn skips_points_constraints_reject() {
+        let x = Node::create_input(0.5f32);
+        x.borrow()
+            .set_constraint(Constraint::new().with_max(0.5f32));
+        let cost = Node::create_mul(x.clone(), Node::create_input(-1f32));
+        let res = ParetoSweep::default()
+            .with_samples(5)
+            .frontier((&cost, &x), std::slice::from_ref(&x), &[(0f32, 1f32)])
+            .unwrap();
+        let inputs: Vec<_> = res.iter().map(|point| point.inputs[0]).collect();
+        assert_eq!(inputs, [0.5f32, 0.25, 0f32]);
+        assert_eq!(x.borrow().compute(), 0.5f32);
+    }
+
+    fn square(x: NodeCelled) -> NodeCelled {
+        Node::create_mul(x.clone(), x)
+    }
+
+    #[test]
+    fn finds_the_front_of_known_problems() {
+        // Schaffer's problem: `x²` against `(x - 2)²`, traded off on [0, 2].
+        let x = Node::create_input(7f32);
+        let first = square(x.clone());
+        let second = square(Node::create_add(x.clone(), Node::create_const(-2.0)));
+        let res = ParetoSweep::default()
+            .with_samples(9)
+            .frontier((&first, &second), std::slice::from_ref(&x), &[(-1.0, 3.0)])
+            .unwrap();
+        let inputs: Vec<_> = res.iter().map(|point| point.inputs[0]).collect();
+        assert_eq!(inputs, [0.0, 0.5, 1.0, 1.5, 2.0]);
+        assert_eq!(res[1].objectives, (0.25, 2.25));
+        assert_eq!(x.borrow().compute(), 7.0);
+
+        // Return against risk: more of `x` is better until `x²` gets worse.
+        let res = ParetoSweep::default()
+            .with_samples(5)
+            .with_goals(Goal::Maximize, Goal::Minimize)
+            .frontier((&x, &first), std::slice::from_ref(&x), &[(-1.0, 1.0)])
+            .unwrap();
+        let inputs: Vec<_> = res.iter().map(|point| point.inputs[0]).collect();
+        assert_eq!(inputs, [1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn sweeps_every_input() {
+        // `y` only costs, so the front keeps it at its best.
+        let x = Node::create_input(0f32);
+        let y = Node::create_input(0f32);
+        let first = Node::create_add(x.clone(), y.clone());
+        let second = Node::create_add(
+            Node::create_mul(x.clone(), Node::create_const(-1.0)),
+            y.clone(),
+        );
+        let res = ParetoSweep::default()
+            .with_samples(3)
+            .frontier((&first, &second), &[x, y], &[(0.0, 1.0), (2.0, 3.0)])
+            .unwrap();
+        let inputs: Vec<_> = res.iter().map(|point| point.inputs.clone()).collect();
+        assert_eq!(inputs, [[0.0, 2.0], [0.5, 2.0], [1.0, 2.0]]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Parquet files as columns: reading a file's numeric columns into a
+//! `ColumnBatch`, as `arrow` reads a record batch's, and writing a batch out.
+
+use std::io::Write;
+
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::{ArrowWriter, ProjectionMask};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::reader::ChunkReader;
+
+use crate::arrow::floats;
+use crate::columnar::ColumnBatch;
+
+impl ColumnBatch {
+    /// The numeric columns of the Parquet file `file`, e.g. a `File`. Other
+    /// columns aren't read.
+    pub fn read_parquet(file: impl ChunkReader + 'static) -> Result<Self, ParquetError> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let fields: Vec<_> = builder
+            .schema()
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.data_type().is_numeric())
+            .map(|(i, field)| (i, field.name().clone()))
+            .collect();
+        let mask = ProjectionMask::roots(builder.parquet_schema(), fields.iter().map(|(i, _)| *i));
+
+        let mut columns = vec![Vec::new(); fields.len()];
+        for batch in builder.with_projection(mask).build()? {
+            let batch = batch?;
+            for (values, column) in columns.iter_mut().zip(batch.columns()) {
+                values.extend_from_slice(&floats(column).expect("only numeric columns are read"));
+            }
+        }
+
+        let mut res = Self::new();
+        for ((_, name), values) in fields.into_iter().zip(columns) {
+            res.push_column(name, values)
+                .expect("Parquet files have unique, equal-length columns");
+        }
+        Ok(res)
+    }
+
+    /// Writes the columns to `writer` as a Parquet file of non-nullable
+    /// `Float32` columns, see `to_record_batch`.
+    pub fn write_parquet(&self, writer: impl Write + Send) -> Result<(), ParquetError> {
+        let batch = self.to_record_batch();
+        let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray};
+
+    #[test]
+    fn round_trips_through_files() {
+        let path = std::env::temp_dir().join(format!("cg-parquet-{}", std::process::id()));
+        let batch = ColumnBatch::new()
+            .with_column("x", vec![1.0, f32::NAN, 3.0])
+            .unwrap()
+            .with_column("y", vec![4.0, 5.0, 6.0])
+            .unwrap();
+        batch.write_parquet(File::create(&path).unwrap()).unwrap();
+        let read = ColumnBatch::read_parquet(File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.names(), ["x", "y"]);
+        assert_eq!(read.column("y"), Some(&[4.0, 5.0, 6.0][..]));
+        let x = read.column("x").unwrap();
+        assert_eq!((x[0], x[2]), (1.0, 3.0));
+        assert!(x[1].is_nan());
+    }
+
+    #[test]
+    fn reads_only_numeric_columns() {
+        let path = std::env::temp_dir().join(format!("cg-parquet-mixed-{}", std::process::id()));
+        let batch = RecordBatch::try_from_iter([
+            (
+                "id",
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+            ),
+            ("rate", Arc::new(Float64Array::from(vec![Some(0.5), None]))),
+        ])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let read = ColumnBatch::read_parquet(File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.names(), ["rate"]);
+        let rate = read.column("rate").unwrap();
+        assert_eq!(rate[0], 0.5);
+        assert!(rate[1].is_nan());
+    }
+}
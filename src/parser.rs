@@ -0,0 +1,1110 @@
+//! Formulas as text, parsed into graphs by a grammar embedders can adjust to
+//! match their own formula language: operator symbols, precedence and
+//! associativity, and functions backed by custom ops.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::computational_graph::{
+    Comparison, CustomOp, EvalError, InputKind, Node, NodeCelled, RoundMode,
+};
+use crate::format::Locale;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Bytes of the text at fault, empty at the end of the text.
+    pub span: Range<usize>,
+    pub kind: ParseErrorKind,
+    /// What would have been accepted at `span`.
+    pub expected: Vec<Expected>,
+    /// Known names close to an unknown one, closest first.
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidNumber,
+    UnexpectedCharacter,
+    /// A token, or the end of the text, where none of `expected` came.
+    UnexpectedToken,
+    UnknownName(String),
+    UnknownFunction(String),
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// `round` needs a literal whole number of digits.
+    RoundDigits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// A number, name, prefix operator or `(`.
+    Operand,
+    /// An infix operator.
+    Operator,
+    /// `(`, `)` or the argument separator.
+    Punct(char),
+    End,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at bytes {}..{}: ", self.span.start, self.span.end)?;
+        match &self.kind {
+            ParseErrorKind::InvalidNumber => f.write_str("invalid number")?,
+            ParseErrorKind::UnexpectedCharacter => f.write_str("unexpected character")?,
+            ParseErrorKind::UnexpectedToken if self.span.is_empty() => {
+                f.write_str("unexpected end")?
+            }
+            ParseErrorKind::UnexpectedToken => f.write_str("unexpected token")?,
+            ParseErrorKind::UnknownName(name) => write!(f, "unknown name `{name}`")?,
+            ParseErrorKind::UnknownFunction(name) => write!(f, "unknown function `{name}`")?,
+            ParseErrorKind::Arity {
+                function,
+                expected,
+                found,
+            } => {
+                let s = if *expected == 1 { "" } else { "s" };
+                write!(f, "{function} takes {expected} argument{s}, {found} given")?
+            }
+            ParseErrorKind::RoundDigits => f.write_str("round takes a whole number of digits")?,
+        }
+        for (i, expected) in self.expected.iter().enumerate() {
+            f.write_str(match i {
+                0 => ", expected ",
+                _ if i + 1 == self.expected.len() => " or ",
+                _ => ", ",
+            })?;
+            match expected {
+                Expected::Operand => f.write_str("an operand")?,
+                Expected::Operator => f.write_str("an operator")?,
+                Expected::Punct(c) => write!(f, "`{c}`")?,
+                Expected::End => f.write_str("the end")?,
+            }
+        }
+        if let Some((last, rest)) = self.suggestions.split_last() {
+            f.write_str(", did you mean ")?;
+            for suggestion in rest {
+                write!(f, "`{suggestion}`, ")?;
+            }
+            if !rest.is_empty() {
+                f.write_str("or ")?;
+            }
+            write!(f, "`{last}`?")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Error of `eval_str`.
+#[derive(Debug, Clone)]
+pub enum FormulaError {
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => e.fmt(f),
+            Self::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+impl From<ParseError> for FormulaError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<EvalError> for FormulaError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+/// Error of `Parser::parse_formulas`.
+#[derive(Debug, Clone)]
+pub enum FormulasError {
+    Parse {
+        name: String,
+        error: Box<ParseError>,
+    },
+    /// Formulas referring to each other in a loop: each to the next, and the
+    /// last to the first.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for FormulasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { name, error } => write!(f, "formula `{name}`: {error}"),
+            Self::Cycle(names) => {
+                f.write_str("formulas refer to each other in a loop: ")?;
+                for name in names {
+                    write!(f, "{name} -> ")?;
+                }
+                f.write_str(&names[0])
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormulasError {}
+
+/// Value of `text` in the default grammar with `variables` set to the given
+/// values, for one-off formulas. The graph is built and dropped inside, so
+/// to evaluate a formula repeatedly, parse it once and set its inputs.
+pub fn eval_str(text: &str, variables: &[(&str, f32)]) -> Result<f32, FormulaError> {
+    let parser = variables
+        .iter()
+        .fold(Parser::default(), |parser, (name, x)| {
+            parser.with_variable(name, Node::create_input(*x))
+        });
+    Ok(parser.parse(text)?.borrow().try_compute()?)
+}
+
+/// What an infix operator computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Infix {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    And,
+    Or,
+    Compare(Comparison),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Operator {
+    infix: Infix,
+    precedence: u8,
+    assoc: Assoc,
+}
+
+#[derive(Debug, Clone)]
+enum Function {
+    Sin,
+    Cos,
+    Sqrt,
+    Round,
+    Select,
+    MulAdd,
+    /// `None` takes any number of arguments.
+    Custom(Arc<dyn CustomOp>, Option<usize>),
+}
+
+impl Function {
+    fn arity(&self) -> Option<usize> {
+        match self {
+            Self::Sin | Self::Cos | Self::Sqrt => Some(1),
+            Self::Round => Some(2),
+            Self::Select | Self::MulAdd => Some(3),
+            Self::Custom(_, arity) => *arity,
+        }
+    }
+}
+
+/// Grammar and names of a formula language. The default one reads
+///
+/// ```text
+/// select(x > 0, sqrt(x), -x) * 2 ^ -n
+/// ```
+///
+/// with, loosest first: `||`, `&&`, comparisons, `+ -`, `* /`, prefix
+/// `- !`, and `^` (right associative, so `-2^2` is `-4`). Functions are
+/// `sin`, `cos`, `sqrt`, `round(x, digits)` with a literal number of digits,
+/// `select(c, a, b)` and `mul_add(a, b, c)`. Names must be declared with
+/// `with_variable`.
+#[derive(Debug, Clone)]
+pub struct Parser {
+    operators: HashMap<String, Operator>,
+    prefix_precedence: u8,
+    functions: HashMap<String, Function>,
+    variables: HashMap<String, NodeCelled>,
+    implicit_multiplication: bool,
+    /// Scale factors by suffix.
+    suffixes: Vec<(String, f32)>,
+    locale: Locale,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        let mut res = Self {
+            operators: HashMap::new(),
+            prefix_precedence: 6,
+            functions: HashMap::new(),
+            variables: HashMap::new(),
+            implicit_multiplication: false,
+            suffixes: Vec::new(),
+            locale: Locale::default(),
+        };
+        let operators = [
+            ("||", Infix::Or, 1),
+            ("&&", Infix::And, 2),
+            ("<", Infix::Compare(Comparison::Lt), 3),
+            ("<=", Infix::Compare(Comparison::Le), 3),
+            (">", Infix::Compare(Comparison::Gt), 3),
+            (">=", Infix::Compare(Comparison::Ge), 3),
+            ("==", Infix::Compare(Comparison::Eq), 3),
+            ("!=", Infix::Compare(Comparison::Ne), 3),
+            ("+", Infix::Add, 4),
+            ("-", Infix::Sub, 4),
+            ("*", Infix::Mul, 5),
+            ("/", Infix::Div, 5),
+        ];
+        for (symbol, infix, precedence) in operators {
+            res = res.with_operator(symbol, infix, precedence, Assoc::Left);
+        }
+        res = res.with_operator("^", Infix::Pow, 7, Assoc::Right);
+
+        let functions = [
+            ("sin", Function::Sin),
+            ("cos", Function::Cos),
+            ("sqrt", Function::Sqrt),
+            ("round", Function::Round),
+            ("select", Function::Select),
+            ("mul_add", Function::MulAdd),
+        ];
+        for (name, function) in functions {
+            res.functions.insert(name.to_string(), function);
+        }
+        res
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `symbol` an infix operator computing `infix`, replacing what it
+    /// meant before. Operators of higher `precedence` bind tighter. Symbols
+    /// that are identifiers, such as `and`, can't be variable names anymore.
+    pub fn with_operator(
+        mut self,
+        symbol: &str,
+        infix: Infix,
+        precedence: u8,
+        assoc: Assoc,
+    ) -> Self {
+        let symbolic = !symbol.is_empty() && !symbol.contains(|c| is_word(c) || is_reserved(c));
+        assert!(
+            symbolic || is_name(symbol),
+            "Operators are identifiers, or symbols without letters, digits, spaces, brackets or separators"
+        );
+        let operator = Operator {
+            infix,
+            precedence,
+            assoc,
+        };
+        self.operators.insert(symbol.to_string(), operator);
+        self
+    }
+
+    pub fn without_operator(mut self, symbol: &str) -> Self {
+        self.operators.remove(symbol);
+        self
+    }
+
+    /// Precedence of prefix `-` and `!`: operators binding tighter apply to
+    /// the operand first.
+    pub fn with_prefix_precedence(mut self, precedence: u8) -> Self {
+        self.prefix_precedence = precedence;
+        self
+    }
+
+    /// Reads operands next to each other as a product, as in `2x`, `3(x + 1)`
+    /// or `(a)(b)`, binding like `*`. A variable followed by `(` is then
+    /// multiplied rather than called, unless it names a function.
+    pub fn with_implicit_multiplication(mut self, implicit_multiplication: bool) -> Self {
+        self.implicit_multiplication = implicit_multiplication;
+        self
+    }
+
+    /// Scales numbers written directly followed by `suffix` by `factor`,
+    /// e.g. `("k", 1e3)` for `5k` or `("%", 0.01)` for `20%`. Suffixes take
+    /// precedence over names and operators, so `5%3` is `0.05 * 3` with
+    /// implicit multiplication; `5 % 3` has no suffix.
+    pub fn with_suffix(mut self, suffix: &str, factor: f32) -> Self {
+        assert!(
+            !suffix.is_empty() && !suffix.contains(|c: char| c.is_ascii_digit() || is_reserved(c)),
+            "Suffixes can't be empty or contain digits, spaces, brackets or separators"
+        );
+        self.suffixes.retain(|(other, _)| other != suffix);
+        self.suffixes.push((suffix.to_string(), factor));
+        self
+    }
+
+    /// Reads numbers with `locale`'s separators, and function arguments
+    /// separated by `Locale::separator`, e.g. `round(1.234,5; 1)` for
+    /// `Locale::new(',', Some('.'))`. A thousands separator must be followed
+    /// by three digits, so that `max(1,5)` still has two arguments where
+    /// `,` separates thousands.
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Makes `name(...)` apply `op`, to `arity` arguments or, for `None`, to
+    /// any number. Replaces a built-in function of the same name.
+    pub fn with_function(
+        mut self,
+        name: &str,
+        op: Arc<dyn CustomOp>,
+        arity: Option<usize>,
+    ) -> Self {
+        assert!(is_name(name), "Function names must be identifiers");
+        self.functions
+            .insert(name.to_string(), Function::Custom(op, arity));
+        self
+    }
+
+    /// Makes `name` read `node`.
+    pub fn with_variable(mut self, name: &str, node: NodeCelled) -> Self {
+        assert!(is_name(name), "Variable names must be identifiers");
+        self.variables.insert(name.to_string(), node);
+        self
+    }
+
+    /// Fresh nodes computing `text`, over the variables' nodes.
+    pub fn parse(&self, text: &str) -> Result<NodeCelled, ParseError> {
+        let tokens = self.tokenize(text)?;
+        let mut parse = Parse {
+            parser: self,
+            tokens: &tokens,
+            next: 0,
+            end: text.len(),
+            implicit: self.implicit_multiplication.then(|| Operator {
+                infix: Infix::Mul,
+                precedence: self
+                    .operators
+                    .values()
+                    .filter(|operator| operator.infix == Infix::Mul)
+                    .map(|operator| operator.precedence)
+                    .max()
+                    .unwrap_or(5),
+                assoc: Assoc::Left,
+            }),
+        };
+        let res = parse.expr(0)?;
+        parse.expect(&[Expected::Operator, Expected::End])?;
+        Ok(res)
+    }
+
+    /// Variables, functions and word operators starting with `prefix`, for
+    /// completing the name being typed, sorted by name. Kinds are the ones
+    /// `tokens` gives the names.
+    pub fn complete(&self, prefix: &str) -> Vec<Completion> {
+        let variables = self.variables.keys().map(|name| Completion {
+            name: name.clone(),
+            kind: TokenKind::Identifier,
+            arity: None,
+        });
+        let functions = self.functions.iter().map(|(name, function)| Completion {
+            name: name.clone(),
+            kind: TokenKind::Function,
+            arity: function.arity(),
+        });
+        let operators = self
+            .operators
+            .keys()
+            .filter(|symbol| is_name(symbol))
+            .map(|symbol| Completion {
+                name: symbol.clone(),
+                kind: TokenKind::Operator,
+                arity: None,
+            });
+        let mut res: Vec<_> = variables
+            .chain(functions)
+            .chain(operators)
+            .filter(|completion| completion.name.starts_with(prefix))
+            .collect();
+        res.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then((a.kind as u8).cmp(&(b.kind as u8)))
+        });
+        res
+    }
+
+    /// The tokens of `text` for syntax highlighting, categorized the way
+    /// `parse` reads them. Never fails: what `parse` would reject as a
+    /// number or symbol comes out as `TokenKind::Invalid`.
+    pub fn tokens(&self, text: &str) -> Vec<Token> {
+        let lexemes = self.lex(text);
+        lexemes
+            .iter()
+            .enumerate()
+            .map(|(i, lexeme)| {
+                let kind = match &lexeme.kind {
+                    LexemeKind::Number(_) => TokenKind::Number,
+                    LexemeKind::Name | LexemeKind::Symbol
+                        if self.operators.contains_key(&lexeme.text) =>
+                    {
+                        TokenKind::Operator
+                    }
+                    LexemeKind::Name => {
+                        let called = lexemes
+                            .get(i + 1)
+                            .is_some_and(|next| next.kind == LexemeKind::Punct('('));
+                        let variable = self.variables.contains_key(&lexeme.text);
+                        let function = self.functions.contains_key(&lexeme.text);
+                        if function && (called || !variable) {
+                            TokenKind::Function
+                        } else {
+                            TokenKind::Identifier
+                        }
+                    }
+                    LexemeKind::Symbol => TokenKind::Operator,
+                    LexemeKind::Punct(_) => TokenKind::Punctuation,
+                    LexemeKind::Invalid(_) => TokenKind::Invalid,
+                };
+                Token {
+                    kind,
+                    span: lexeme.span(),
+                }
+            })
+            .collect()
+    }
+
+    /// Names `text` reads as variables, each once, in order, whether
+    /// declared or not: what to declare with `with_variable` for names bound
+    /// on demand, like a spreadsheet's cells.
+    pub fn references(&self, text: &str) -> Vec<String> {
+        let lexemes = self.lex(text);
+        let mut res: Vec<String> = Vec::new();
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            if lexeme.kind != LexemeKind::Name || self.operators.contains_key(&lexeme.text) {
+                continue;
+            }
+            // Mirrors `Parse::prefix`.
+            let called = lexemes
+                .get(i + 1)
+                .is_some_and(|next| next.kind == LexemeKind::Punct('('));
+            let call = called
+                && (!self.implicit_multiplication
+                    || self.functions.contains_key(&lexeme.text)
+                    || !self.variables.contains_key(&lexeme.text));
+            if !call && !res.contains(&lexeme.text) {
+                res.push(lexeme.text.clone());
+            }
+        }
+        res
+    }
+
+    /// Named formulas that may use each other's values by name, like
+    /// variables, parsed into one graph: a formula used by others is a single
+    /// node all of them read, and changing an input recomputes each formula
+    /// depending on it once. Formula names take precedence over variables.
+    pub fn parse_formulas(
+        &self,
+        formulas: &[(&str, &str)],
+    ) -> Result<HashMap<String, NodeCelled>, FormulasError> {
+        let index: HashMap<&str, usize> = formulas
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (*name, i))
+            .collect();
+        let references: Vec<Vec<usize>> = formulas
+            .iter()
+            .map(|(_, text)| {
+                self.lex(text)
+                    .iter()
+                    .filter(|lexeme| lexeme.kind == LexemeKind::Name)
+                    .filter_map(|lexeme| index.get(lexeme.text.as_str()).copied())
+                    .collect()
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(formulas.len());
+        let mut visited = vec![Visit::New; formulas.len()];
+        for i in 0..formulas.len() {
+            let mut path = Vec::new();
+            if let Err(start) = visit(i, &references, &mut visited, &mut path, &mut order) {
+                let cycle = path[start..]
+                    .iter()
+                    .map(|j| formulas[*j].0.to_string())
+                    .collect();
+                return Err(FormulasError::Cycle(cycle));
+            }
+        }
+
+        let mut parser = self.clone();
+        let mut res = HashMap::new();
+        for i in order {
+            let (name, text) = formulas[i];
+            let node = parser.parse(text).map_err(|error| FormulasError::Parse {
+                name: name.to_string(),
+                error: Box::new(error),
+            })?;
+            parser.variables.insert(name.to_string(), node.clone());
+            res.insert(name.to_string(), node);
+        }
+        Ok(res)
+    }
+
+    fn tokenize(&self, text: &str) -> Result<Vec<Lexeme>, ParseError> {
+        let res = self.lex(text);
+        for lexeme in &res {
+            if let LexemeKind::Invalid(kind) = &lexeme.kind {
+                return Err(error(lexeme.span(), kind.clone()));
+            }
+        }
+        Ok(res)
+    }
+
+    fn lex(&self, text: &str) -> Vec<Lexeme> {
+        let mut res = Vec::new();
+        let mut at = 0;
+        while let Some(c) = text[at..].chars().next() {
+            let rest = &text[at..];
+            let (kind, len) = if c.is_whitespace() {
+                at += c.len_utf8();
+                continue;
+            } else if c.is_ascii_digit() || c == self.locale.decimal() {
+                let len = self.locale.number_len(rest);
+                match (self.locale.parse(&rest[..len]), self.suffix(&rest[len..])) {
+                    (Some(x), Some((suffix, factor))) => {
+                        (LexemeKind::Number(x * factor), len + suffix)
+                    }
+                    (Some(x), None) => (LexemeKind::Number(x), len),
+                    (None, _) => (LexemeKind::Invalid(ParseErrorKind::InvalidNumber), len),
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+                (LexemeKind::Name, len)
+            } else if matches!(c, '(' | ')') || c == self.locale.separator() {
+                (LexemeKind::Punct(c), 1)
+            } else {
+                // Longest symbol first, so `<=` isn't read as `<`.
+                let len = self
+                    .operators
+                    .keys()
+                    .map(String::as_str)
+                    .chain(["-", "!"])
+                    .filter(|symbol| rest.starts_with(symbol))
+                    .map(str::len)
+                    .max();
+                match len {
+                    Some(len) => (LexemeKind::Symbol, len),
+                    None => (
+                        LexemeKind::Invalid(ParseErrorKind::UnexpectedCharacter),
+                        c.len_utf8(),
+                    ),
+                }
+            };
+            res.push(Lexeme {
+                kind,
+                at,
+                text: rest[..len].to_string(),
+            });
+            at += len;
+        }
+        res
+    }
+
+    /// Length and factor of the longest suffix starting `rest`. Suffixes
+    /// ending in a letter must end the word, so `2km` isn't `2k` and `m`.
+    fn suffix(&self, rest: &str) -> Option<(usize, f32)> {
+        self.suffixes
+            .iter()
+            .filter(|(suffix, _)| {
+                rest.strip_prefix(suffix.as_str())
+                    .is_some_and(|after| !suffix.ends_with(is_word) || !after.starts_with(is_word))
+            })
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map(|(suffix, factor)| (suffix.len(), *factor))
+    }
+}
+
+/// Category of a `Token`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A number with its suffix, if any.
+    Number,
+    /// A variable name, or a name the parser doesn't know.
+    Identifier,
+    /// A known function's name, unless it's a variable's too and isn't
+    /// followed by `(`.
+    Function,
+    /// An infix operator, or prefix `-` and `!`.
+    Operator,
+    /// `(`, `)` or the argument separator.
+    Punctuation,
+    /// A malformed number or an unknown symbol.
+    Invalid,
+}
+
+/// Token of a formula, see `Parser::tokens`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Bytes of the formula it covers.
+    pub span: Range<usize>,
+}
+
+/// Name offered by `Parser::complete`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub name: String,
+    /// `Identifier` for variables, `Function` or `Operator`.
+    pub kind: TokenKind,
+    /// Number of arguments of a function taking a fixed number.
+    pub arity: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LexemeKind {
+    Number(f32),
+    Name,
+    Symbol,
+    /// `(`, `)` or the argument separator.
+    Punct(char),
+    /// What `parse` reports for it.
+    Invalid(ParseErrorKind),
+}
+
+#[derive(Debug, Clone)]
+struct Lexeme {
+    kind: LexemeKind,
+    at: usize,
+    text: String,
+}
+
+impl Lexeme {
+    fn span(&self) -> Range<usize> {
+        self.at..self.at + self.text.len()
+    }
+}
+
+/// State of one `Parser::parse` call: precedence climbing over the tokens.
+struct Parse<'a> {
+    parser: &'a Parser,
+    tokens: &'a [Lexeme],
+    next: usize,
+    /// Where the text ends, for errors about missing tokens.
+    end: usize,
+    /// How operands next to each other combine, if they may.
+    implicit: Option<Operator>,
+}
+
+impl Parse<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.tokens.get(self.next)
+    }
+
+    /// Bytes of the next token, or the empty end of the text.
+    fn span(&self) -> Range<usize> {
+        self.peek().map_or(self.end..self.end, |token| token.span())
+    }
+
+    /// An error unless the next token is the first of `expected` that is
+    /// punctuation or the end; the others are only listed.
+    fn expect(&mut self, expected: &[Expected]) -> Result<(), ParseError> {
+        let found = expected.iter().any(|expected| match expected {
+            Expected::Punct(c) => self.eat(*c),
+            Expected::End => self.peek().is_none(),
+            Expected::Operand | Expected::Operator => false,
+        });
+        if found {
+            return Ok(());
+        }
+        Err(ParseError {
+            expected: expected.to_vec(),
+            ..error(self.span(), ParseErrorKind::UnexpectedToken)
+        })
+    }
+
+    /// Consumes the punctuation `c` if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        let found = matches!(self.peek(), Some(token) if token.kind == LexemeKind::Punct(c));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    /// Operators binding at least as tight as `min_precedence`, over prefix
+    /// expressions.
+    fn expr(&mut self, min_precedence: u8) -> Result<NodeCelled, ParseError> {
+        let mut res = self.prefix()?;
+        while let Some(token) = self.peek() {
+            let (operator, implicit) = match (self.operator(token), self.implicit) {
+                (Some(operator), _) => (operator, false),
+                (None, Some(implicit)) if starts_operand(token) => (implicit, true),
+                _ => break,
+            };
+            if operator.precedence < min_precedence {
+                break;
+            }
+            if !implicit {
+                self.next += 1;
+            }
+            let next_min = match operator.assoc {
+                Assoc::Left => operator.precedence.saturating_add(1),
+                Assoc::Right => operator.precedence,
+            };
+            let rhs = self.expr(next_min)?;
+            res = build_infix(operator.infix, res, rhs);
+        }
+        Ok(res)
+    }
+
+    fn operator(&self, token: &Lexeme) -> Option<Operator> {
+        match token.kind {
+            LexemeKind::Symbol | LexemeKind::Name => {
+                self.parser.operators.get(&token.text).copied()
+            }
+            _ => None,
+        }
+    }
+
+    fn prefix(&mut self) -> Result<NodeCelled, ParseError> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.expect(&[Expected::Operand]).unwrap_err());
+        };
+        self.next += 1;
+
+        match (&token.kind, token.text.as_str()) {
+            (LexemeKind::Symbol, "-") => {
+                let operand = self.expr(self.parser.prefix_precedence)?;
+                Ok(match constant(&operand) {
+                    Some(x) => Node::create_const(-x),
+                    None => Node::create_mul(Node::create_const(-1f32), operand),
+                })
+            }
+            (LexemeKind::Symbol, "!") => {
+                let operand = self.expr(self.parser.prefix_precedence)?;
+                Ok(Node::create_not(operand))
+            }
+            (LexemeKind::Number(x), _) => Ok(Node::create_const(*x)),
+            (LexemeKind::Punct('('), _) => {
+                let res = self.expr(0)?;
+                self.expect(&[Expected::Operator, Expected::Punct(')')])?;
+                Ok(res)
+            }
+            (LexemeKind::Name, name) => {
+                let callable = self.implicit.is_none()
+                    || self.parser.functions.contains_key(name)
+                    || !self.parser.variables.contains_key(name);
+                if callable && self.eat('(') {
+                    return self.call(&token);
+                }
+                self.parser.variables.get(name).cloned().ok_or_else(|| {
+                    let known = self.parser.variables.keys();
+                    ParseError {
+                        suggestions: suggestions(name, known),
+                        ..error(token.span(), ParseErrorKind::UnknownName(name.to_string()))
+                    }
+                })
+            }
+            _ => {
+                self.next -= 1;
+                Err(self.expect(&[Expected::Operand]).unwrap_err())
+            }
+        }
+    }
+
+    /// Arguments and result of a call to `name`, whose `(` was consumed.
+    fn call(&mut self, name: &Lexeme) -> Result<NodeCelled, ParseError> {
+        let Some(function) = self.parser.functions.get(&name.text) else {
+            let known = self.parser.functions.keys();
+            return Err(ParseError {
+                suggestions: suggestions(&name.text, known),
+                ..error(
+                    name.span(),
+                    ParseErrorKind::UnknownFunction(name.text.clone()),
+                )
+            });
+        };
+        let mut args = Vec::new();
+        if !self.eat(')') {
+            loop {
+                args.push(self.expr(0)?);
+                if self.eat(')') {
+                    break;
+                }
+                let expected = [
+                    Expected::Operator,
+                    Expected::Punct(self.parser.locale.separator()),
+                    Expected::Punct(')'),
+                ];
+                self.expect(&expected)?;
+            }
+        }
+        if let Some(arity) = function.arity().filter(|arity| *arity != args.len()) {
+            let kind = ParseErrorKind::Arity {
+                function: name.text.clone(),
+                expected: arity,
+                found: args.len(),
+            };
+            return Err(error(name.span(), kind));
+        }
+
+        let mut args = args.into_iter();
+        let mut next = || args.next().unwrap();
+        Ok(match function {
+            Function::Sin => Node::create_sin(next()),
+            Function::Cos => Node::create_cos(next()),
+            Function::Sqrt => Node::create_pow(next(), Node::create_const(0.5f32)),
+            Function::Round => {
+                let x = next();
+                let digits = match constant(&next()) {
+                    Some(digits) if digits.fract() == 0.0 => digits as i32,
+                    _ => return Err(error(name.span(), ParseErrorKind::RoundDigits)),
+                };
+                Node::create_round(x, digits, RoundMode::HalfUp)
+            }
+            Function::Select => Node::create_select(next(), next(), next()),
+            Function::MulAdd => Node::create_mul_add(next(), next(), next()),
+            Function::Custom(op, _) => Node::create_custom(op.clone(), args.collect()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    New,
+    /// On the path being followed.
+    Open,
+    Done,
+}
+
+/// Depth-first walk of formula references, pushing formulas to `order` after
+/// those they refer to. On finding a loop, `path` holds the formulas being
+/// followed and the error tells where the loop starts in it.
+fn visit(
+    i: usize,
+    references: &[Vec<usize>],
+    visited: &mut [Visit],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> Result<(), usize> {
+    match visited[i] {
+        Visit::Done => return Ok(()),
+        Visit::Open => return Err(path.iter().position(|j| *j == i).unwrap()),
+        Visit::New => {}
+    }
+    visited[i] = Visit::Open;
+    path.push(i);
+    for j in &references[i] {
+        visit(*j, references, visited, path, order)?;
+    }
+    path.pop();
+    visited[i] = Visit::Done;
+    order.push(i);
+    Ok(())
+}
+
+fn starts_operand(token: &Lexeme) -> bool {
+    matches!(
+        token.kind,
+        LexemeKind::Number(_) | LexemeKind::Name | LexemeKind::Punct('(')
+    )
+}
+
+fn build_infix(infix: Infix, a: NodeCelled, b: NodeCelled) -> NodeCelled {
+    let minus_one = || Node::create_const(-1f32);
+    match infix {
+        Infix::Add => Node::create_add(a, b),
+        Infix::Sub => Node::create_add(a, Node::create_mul(minus_one(), b)),
+        Infix::Mul => Node::create_mul(a, b),
+        Infix::Div => Node::create_mul(a, Node::create_pow(b, minus_one())),
+        Infix::Pow => Node::create_pow(a, b),
+        Infix::And => Node::create_and(a, b),
+        Infix::Or => Node::create_or(a, b),
+        Infix::Compare(cmp) => Node::create_compare(a, b, cmp),
+    }
+}
+
+/// The value of a literal, possibly negated.
+fn constant(node: &NodeCelled) -> Option<f32> {
+    match &*node.borrow() {
+        Node::Input {
+            kind: InputKind::Const,
+            ..
+        } => Some(node.borrow().compute()),
+        _ => None,
+    }
+}
+
+fn error(span: Range<usize>, kind: ParseErrorKind) -> ParseError {
+    ParseError {
+        span,
+        kind,
+        expected: Vec::new(),
+        suggestions: Vec::new(),
+    }
+}
+
+/// Up to three of `known` within a few edits of `name`, closest first. Names
+/// sharing nothing with `name`, such as single letters, aren't suggested.
+fn suggestions<'a>(name: &str, known: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let len = name.chars().count();
+    let max = (len / 3).max(1);
+    let mut res: Vec<_> = known
+        .map(|other| (edit_distance(name, other), other))
+        .filter(|(distance, _)| *distance <= max && *distance < len)
+        .collect();
+    res.sort();
+    res.into_iter()
+        .take(3)
+        .map(|(_, other)| other.clone())
+        .collect()
+}
+
+/// Levenshtein distance, counting a swap of neighbours as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut best = substitution.min(rows[i - 1][j] + 1).min(rows[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_reserved(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | ',' | ';' | '.')
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_') && name.chars().all(is_word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> f32 {
+        eval_str(text, &[("x", 2.0), ("y", 3.0)]).unwrap()
+    }
+
+    fn parse_error(parser: &Parser, text: &str) -> ParseError {
+        parser.parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("-2 ^ 2"), -4.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("8 - 4 - 2"), 2.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("x < y && !(x == 3) || 0"), 1.0);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("sqrt(16) + mul_add(x, y, 1)"), 11.0);
+        assert_eq!(eval("select(x > y, x, y)"), 3.0);
+        assert_eq!(eval("round(2.345, 2)"), 2.35);
+        assert_eq!(eval("sin(0) + cos(0)"), 1.0);
+    }
+
+    #[test]
+    fn errors() {
+        let parser = Parser::new().with_variable("price", Node::create_input(1.0));
+
+        let e = parse_error(&parser, "1 +");
+        assert_eq!((e.span, e.kind), (3..3, ParseErrorKind::UnexpectedToken));
+        assert_eq!(e.expected, [Expected::Operand]);
+
+        let e = parse_error(&parser, "(1 + 2");
+        assert_eq!(e.expected, [Expected::Operator, Expected::Punct(')')]);
+
+        let e = parse_error(&parser, "prise * 2");
+        assert_eq!(e.kind, ParseErrorKind::UnknownName("prise".into()));
+        assert_eq!(e.suggestions, ["price"]);
+
+        let e = parse_error(&parser, "sin(1, 2)");
+        let arity = ParseErrorKind::Arity {
+            function: "sin".into(),
+            expected: 1,
+            found: 2,
+        };
+        assert_eq!((e.span, e.kind), (0..3, arity));
+
+        let e = parse_error(&parser, "round(price, price)");
+        assert_eq!(e.kind, ParseErrorKind::RoundDigits);
+        let e = parse_error(&parser, "1 # 2");
+        assert_eq!(e.kind, ParseErrorKind::UnexpectedCharacter);
+    }
+
+    #[test]
+    fn custom_grammar() {
+        let x = Node::create_input(2.0);
+        let parser = Parser::new()
+            .with_operator("and", Infix::And, 2, Assoc::Left)
+            .with_implicit_multiplication(true)
+            .with_suffix("%", 0.01)
+            .with_variable("x", x);
+        let value = |text| parser.parse(text).unwrap().borrow().compute();
+        assert_eq!(value("3x + 50%"), 6.5);
+        assert_eq!(value("x(x + 1)"), 6.0);
+        assert_eq!(value("x and 0"), 0.0);
+    }
+
+    #[test]
+    fn references() {
+        let parser = Parser::new();
+        assert_eq!(
+            parser.references("sin(a) + b * a + round(c, 2)"),
+            ["a", "b", "c"]
+        );
+        let implicit = Parser::new()
+            .with_implicit_multiplication(true)
+            .with_variable("a", Node::create_input(1.0));
+        assert_eq!(implicit.references("a(b) + f(c)"), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn formulas_share_nodes() {
+        let x = Node::create_input(1.0);
+        let parser = Parser::new().with_variable("x", x.clone());
+        let formulas = parser
+            .parse_formulas(&[("total", "base * 2"), ("base", "x + 1")])
+            .unwrap();
+        assert_eq!(formulas["total"].borrow().compute(), 4.0);
+        x.borrow().set(2.0);
+        assert_eq!(formulas["total"].borrow().compute(), 6.0);
+
+        let cycle = parser.parse_formulas(&[("a", "b"), ("b", "a + 1")]);
+        assert!(matches!(cycle, Err(FormulasError::Cycle(names)) if names == ["a", "b"]));
+    }
+}
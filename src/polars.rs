@@ -0,0 +1,172 @@
+//! Compiled graphs in Polars pipelines: a `ColumnExpr` becomes a Polars
+//! expression over the columns it reads, and a `DataFrame` is `Columns`.
+//!
+//! Integer and float columns are read as `f32`, nulls as NaN. Other columns,
+//! e.g. of strings, aren't read.
+
+use std::borrow::Cow;
+
+use ::polars::prelude::{
+    col, lit, Column, DataFrame, DataType, Expr, Field, PlSmallStr, PolarsError, PolarsResult,
+};
+
+use crate::columnar::{ColumnError, ColumnExpr, Columns};
+
+/// `column` as `f32`s, `None` if it isn't numeric.
+fn floats(column: &Column) -> Option<Cow<'_, [f32]>> {
+    if !column.dtype().is_primitive_numeric() {
+        return None;
+    }
+    if column.dtype() == &DataType::Float32 {
+        if let Ok(values) = column.f32().ok()?.cont_slice() {
+            return Some(Cow::Borrowed(values));
+        }
+    }
+    let column = column.cast(&DataType::Float32).ok()?;
+    Some(Cow::Owned(
+        column
+            .f32()
+            .ok()?
+            .iter()
+            .map(|value| value.unwrap_or(f32::NAN))
+            .collect(),
+    ))
+}
+
+impl Columns for DataFrame {
+    fn rows(&self) -> usize {
+        self.height()
+    }
+
+    fn column(&self, name: &str) -> Option<Cow<'_, [f32]>> {
+        floats(DataFrame::column(self, name).ok()?)
+    }
+}
+
+impl ColumnExpr {
+    /// This expression as a Polars one, reading its input columns with
+    /// `col` and named after it: e.g. for `LazyFrame::with_column`.
+    /// Evaluation errors, and input columns that aren't numeric, fail the
+    /// query with a `ComputeError`.
+    pub fn to_polars(&self) -> Expr {
+        let mut inputs: Vec<Expr> = self
+            .inputs()
+            .iter()
+            .map(|name| col(name.as_str()))
+            .collect();
+        let count = inputs.len();
+        if inputs.is_empty() {
+            // A graph without inputs is one value, broadcast like a literal.
+            inputs.push(lit(0.0f32));
+        }
+
+        let graph = self.graph().clone();
+        let name = PlSmallStr::from(self.name());
+        let output = name.clone();
+        let function = move |columns: &mut [Column]| -> PolarsResult<Column> {
+            let columns = columns[..count]
+                .iter()
+                .map(|column| {
+                    floats(column).ok_or_else(|| {
+                        PolarsError::ComputeError(
+                            format!("column \"{}\" isn't numeric", column.name()).into(),
+                        )
+                    })
+                })
+                .collect::<PolarsResult<Vec<_>>>()?;
+            let columns: Vec<&[f32]> = columns.iter().map(|column| &column[..]).collect();
+            let values = match columns.is_empty() {
+                true => graph.eval(&[]).map(|value| vec![value]),
+                false => graph.eval_columns(&columns),
+            };
+            let values = values.map_err(|e| PolarsError::ComputeError(e.to_string().into()))?;
+            Ok(Column::new(name.clone(), values))
+        };
+        let first = inputs.remove(0);
+        first
+            .map_many(function, &inputs, move |_, _| {
+                Ok(Field::new(output.clone(), DataType::Float32))
+            })
+            .alias(self.name())
+    }
+
+    /// `frame` with this expression's column added, as a `Float32` column
+    /// under its name.
+    pub fn extend_frame(&self, frame: &DataFrame) -> Result<DataFrame, ColumnError> {
+        if DataFrame::column(frame, self.name()).is_ok() {
+            return Err(ColumnError::Duplicate(self.name().to_string()));
+        }
+        let values = self.apply(frame)?;
+        let column = Column::new(self.name().into(), values);
+        Ok(frame.hstack(&[column]).expect("the column has every row"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::polars::df;
+    use ::polars::prelude::IntoLazy;
+
+    use crate::parser::Parser;
+
+    fn frame() -> DataFrame {
+        df!(
+            "id" => ["a", "b", "c"],
+            "price" => [10.0f32, 20.0, 30.0],
+            "quantity" => [Some(1i64), None, Some(3)],
+        )
+        .unwrap()
+    }
+
+    fn total() -> ColumnExpr {
+        ColumnExpr::parse(Parser::new(), "price * quantity", &["price", "quantity"])
+            .unwrap()
+            .with_name("total")
+    }
+
+    fn assert_totals(values: &[f32]) {
+        assert_eq!((values[0], values[2]), (10.0, 90.0));
+        assert!(values[1].is_nan());
+    }
+
+    #[test]
+    fn reads_numeric_columns() {
+        let frame = frame();
+        assert!(matches!(
+            Columns::column(&frame, "price"),
+            Some(Cow::Borrowed([10.0, 20.0, 30.0]))
+        ));
+        assert!(Columns::column(&frame, "id").is_none());
+
+        let frame = total().extend_frame(&frame).unwrap();
+        assert_totals(&Columns::column(&frame, "total").unwrap());
+        let res = total().extend_frame(&frame);
+        assert!(matches!(res, Err(ColumnError::Duplicate(name)) if name == "total"));
+    }
+
+    #[test]
+    fn runs_in_lazy_queries() {
+        let frame = frame()
+            .lazy()
+            .with_column(total().to_polars())
+            .collect()
+            .unwrap();
+        assert_totals(&Columns::column(&frame, "total").unwrap());
+
+        let constant = ColumnExpr::parse(Parser::new(), "2 + 3", &[])
+            .unwrap()
+            .with_name("five");
+        let frame = frame
+            .lazy()
+            .with_column(constant.to_polars())
+            .collect()
+            .unwrap();
+        assert_eq!(Columns::column(&frame, "five").unwrap()[..], [5.0; 3]);
+
+        let ids = ColumnExpr::parse(Parser::new(), "id + 1", &["id"]).unwrap();
+        let res = frame.lazy().with_column(ids.to_polars()).collect();
+        let error = res.unwrap_err().to_string();
+        assert!(error.contains("column \"id\" isn't numeric"), "{error}");
+    }
+}
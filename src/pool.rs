@@ -0,0 +1,164 @@
+//! Hash-consing of nodes shared by many formulas.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::computational_graph::{Node, NodeCelled, NodeId};
+use crate::hash::{write_op, Fnv64};
+use crate::optimize::transform;
+
+/// Arena in which equal subexpressions exist once: two nodes applying the same
+/// op to the same (pooled) operands are one node. Graphs added under a name
+/// share every common part with the ones added before.
+#[derive(Debug, Default)]
+pub struct GraphPool {
+    nodes: HashMap<(u64, Vec<NodeId>), Vec<NodeCelled>>,
+    graphs: HashMap<String, NodeCelled>,
+}
+
+impl GraphPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pool with room for `capacity` nodes before its table reallocates.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: HashMap::with_capacity(capacity),
+            graphs: HashMap::new(),
+        }
+    }
+
+    /// Makes room for `additional` more nodes.
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+    }
+
+    /// The pooled node equal to `node`, or `node` itself after pooling it.
+    /// Meant for freshly created nodes over pooled operands, e.g.
+    /// `pool.intern(Node::create_add(a, b))`; a duplicate is unregistered from
+    /// its operands and should be dropped.
+    pub fn intern(&mut self, node: NodeCelled) -> NodeCelled {
+        let res = self.find_or_insert(&node);
+        if !Rc::ptr_eq(&res, &node) {
+            Node::detach(&node);
+        }
+
+        res
+    }
+
+    /// Pools every node of the graph computing `output` and registers the
+    /// pooled output under `name`, replacing any graph of that name.
+    pub fn insert(&mut self, name: impl Into<String>, output: &NodeCelled) -> NodeCelled {
+        let res = self.intern_graph(output);
+        self.graphs.insert(name.into(), res.clone());
+        res
+    }
+
+    /// Pools every node of the graph computing `output`, returning the pooled
+    /// output without registering it under a name.
+    pub fn intern_graph(&mut self, output: &NodeCelled) -> NodeCelled {
+        transform(output, &mut |node| Some(self.find_or_insert(node)))
+    }
+
+    pub fn get(&self, name: &str) -> Option<NodeCelled> {
+        self.graphs.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.graphs.keys().map(String::as_str)
+    }
+
+    /// Number of distinct pooled nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Forgets the nodes `keep` rejects and shrinks the table.
+    pub(crate) fn retain(&mut self, keep: impl Fn(&NodeCelled) -> bool) {
+        self.nodes.retain(|_, bucket| {
+            bucket.retain(&keep);
+            !bucket.is_empty()
+        });
+        self.nodes.shrink_to_fit();
+    }
+
+    fn find_or_insert(&mut self, node: &NodeCelled) -> NodeCelled {
+        let node_ref = node.borrow();
+        let children = node_ref.children();
+        let mut hasher = Fnv64::new();
+        write_op(&mut hasher, &node_ref);
+        let key = (
+            hasher.finish(),
+            children.iter().map(|child| child.borrow().id()).collect(),
+        );
+
+        let bucket = self.nodes.entry(key).or_default();
+        let found = bucket.iter().find(|pooled| {
+            let pooled = pooled.borrow();
+            pooled.same_op(&node_ref)
+                && pooled
+                    .children()
+                    .iter()
+                    .zip(&children)
+                    .all(|(a, b)| Rc::ptr_eq(a, b))
+        });
+        if let Some(found) = found {
+            return found.clone();
+        }
+
+        bucket.push(node.clone());
+        node.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_equal_nodes() {
+        let mut pool = GraphPool::new();
+        let x = Node::create_input(2f32);
+        let a = pool.intern(Node::create_sin(x.clone()));
+        let duplicate = Node::create_sin(x.clone());
+        let b = pool.intern(duplicate.clone());
+        assert!(Rc::ptr_eq(&a, &b));
+        // The duplicate no longer hears of changes to `x`.
+        assert_eq!(x.borrow().dependents().len(), 1);
+        let cos = pool.intern(Node::create_cos(x.clone()));
+        assert!(!Rc::ptr_eq(&a, &cos));
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn shares_common_parts_of_graphs() {
+        let mut pool = GraphPool::new();
+        let (x, y) = (Node::create_input(2f32), Node::create_input(3f32));
+        let product = |x: &NodeCelled| Node::create_mul(x.clone(), Node::create_const(4f32));
+        let first = Node::create_add(product(&x), y.clone());
+        let second = Node::create_sin(product(&x));
+        let other = Node::create_sin(product(&y));
+
+        let first = pool.insert("first", &first);
+        let second = pool.insert("second", &second);
+        pool.insert("other", &other);
+        // Inputs, one constant, two products, the sum and two sines.
+        assert_eq!(pool.len(), 8);
+        assert!(Rc::ptr_eq(
+            &first.borrow().children()[0],
+            &second.borrow().children()[0]
+        ));
+        assert_eq!(first.borrow().compute(), 11f32);
+
+        let mut names: Vec<_> = pool.names().collect();
+        names.sort();
+        assert_eq!(names, ["first", "other", "second"]);
+        assert!(Rc::ptr_eq(&pool.get("second").unwrap(), &second));
+        assert!(pool.get("third").is_none());
+    }
+}
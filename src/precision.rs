@@ -0,0 +1,265 @@
+//! Evaluation of `CompiledGraph`s in `f32` and `f64` side by side, to find
+//! where a long formula loses precision.
+//!
+//! Like `integer` and `decimal`, this reinterprets a compiled tape.
+
+use std::fmt;
+
+use crate::compiled::{CompiledGraph, Instr};
+use crate::computational_graph::{
+    apply_custom, BinaryOp, EvalError, NodeId, PowPolicy, RoundMode, TernaryOp, UnaryOp,
+};
+
+/// One node's value in both precisions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodePrecision {
+    pub node: NodeId,
+    pub single: f32,
+    pub double: f64,
+    /// `|single - double| / |double|`, or the absolute difference where
+    /// `double` is `0`. Infinite if only one of them is NaN.
+    pub error: f64,
+    /// How much `error` exceeds the largest error among the operands, i.e.
+    /// the precision lost at this node rather than inherited.
+    pub introduced: f64,
+}
+
+/// Result of `CompiledGraph::compare_precision`, in tape order.
+#[derive(Debug, Clone)]
+pub struct PrecisionReport {
+    nodes: Vec<NodePrecision>,
+}
+
+impl PrecisionReport {
+    /// Every node of the tape, operands before the nodes reading them.
+    pub fn nodes(&self) -> &[NodePrecision] {
+        &self.nodes
+    }
+
+    pub fn output(&self) -> &NodePrecision {
+        self.nodes.last().unwrap()
+    }
+
+    /// The node introducing the most error, where precision loss originates.
+    pub fn worst(&self) -> &NodePrecision {
+        self.nodes
+            .iter()
+            .max_by(|a, b| a.introduced.total_cmp(&b.introduced))
+            .unwrap()
+    }
+}
+
+impl fmt::Display for PrecisionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "node {}: {} vs {}, error {:e} (introduced {:e})",
+                node.node, node.single, node.double, node.error, node.introduced
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl CompiledGraph {
+    /// Evaluates a single row in `f32` and in `f64` and compares every node.
+    /// Both start from the same `f32` inputs and constants. Custom ops only
+    /// compute in `f32`, so their `f64` value is their `f32` result for the
+    /// `f64` operands, and they introduce no error of their own.
+    pub fn compare_precision(&self, inputs: &[f32]) -> Result<PrecisionReport, EvalError> {
+        assert_eq!(
+            inputs.len(),
+            self.input_count(),
+            "Wrong number of input values"
+        );
+
+        let mut singles: Vec<f32> = Vec::with_capacity(self.instrs.len());
+        let mut doubles: Vec<f64> = Vec::with_capacity(self.instrs.len());
+        let mut nodes: Vec<NodePrecision> = Vec::with_capacity(self.instrs.len());
+        for (instr, &node) in self.instrs.iter().zip(self.ids.iter()) {
+            let single = instr.eval(node, inputs, |slot| singles[slot])?;
+            let slot = |i: usize| doubles[i];
+            let double = match instr {
+                Instr::Input(i) => inputs[*i] as f64,
+                Instr::Const(x) => *x as f64,
+                Instr::Unary(op, x) => match op {
+                    UnaryOp::Sin => slot(*x).sin(),
+                    UnaryOp::Cos => slot(*x).cos(),
+                    UnaryOp::Not => from_bool(slot(*x) == 0.0),
+                    UnaryOp::Round { digits, mode } => round(slot(*x), *digits, *mode),
+                },
+                Instr::Binary(op, a, b) => {
+                    let (a, b) = (slot(*a), slot(*b));
+                    match op {
+                        BinaryOp::Add => a + b,
+                        BinaryOp::Mul => a * b,
+                        BinaryOp::Pow(policy) => pow(*policy, a, b, node)?,
+                        BinaryOp::And => from_bool(a != 0.0 && b != 0.0),
+                        BinaryOp::Or => from_bool(a != 0.0 || b != 0.0),
+                        BinaryOp::Compare(cmp) => from_bool(cmp.apply(a, b)),
+                    }
+                }
+                Instr::Ternary(TernaryOp::MulAdd, a, b, c) => slot(*a).mul_add(slot(*b), slot(*c)),
+                Instr::Ternary(TernaryOp::Select, a, b, c) => {
+                    if slot(*a) != 0.0 {
+                        slot(*b)
+                    } else {
+                        slot(*c)
+                    }
+                }
+                Instr::Custom(op, args) => {
+                    let args: Vec<_> = args.iter().map(|arg| slot(*arg) as f32).collect();
+                    apply_custom(op.as_ref(), &args, node)? as f64
+                }
+            };
+
+            let error = relative_error(single, double);
+            let inherited = instr
+                .operands()
+                .iter()
+                .map(|operand| nodes[*operand].error)
+                .fold(0.0, f64::max);
+            let introduced = match instr {
+                Instr::Custom(..) => 0.0,
+                _ => (error - inherited).max(0.0),
+            };
+            singles.push(single);
+            doubles.push(double);
+            nodes.push(NodePrecision {
+                node,
+                single,
+                double,
+                error,
+                introduced,
+            });
+        }
+
+        Ok(PrecisionReport { nodes })
+    }
+}
+
+fn relative_error(single: f32, double: f64) -> f64 {
+    let single = single as f64;
+    match (single.is_nan(), double.is_nan()) {
+        (true, true) => return 0.0,
+        (true, false) | (false, true) => return f64::INFINITY,
+        (false, false) => {}
+    }
+    if single == double {
+        return 0.0;
+    }
+    let difference = (single - double).abs();
+    if double == 0.0 {
+        difference
+    } else {
+        difference / double.abs()
+    }
+}
+
+fn from_bool(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// `BinaryOp::Pow` in `f64`, with the same policies.
+fn pow(policy: PowPolicy, base: f64, exponent: f64, node: NodeId) -> Result<f64, EvalError> {
+    if policy == PowPolicy::Native || base != 0.0 || exponent > 0.0 || exponent.is_nan() {
+        return Ok(base.powf(exponent));
+    }
+
+    let error = EvalError::PowDomain {
+        node,
+        base: base as f32,
+        exponent: exponent as f32,
+    };
+    match policy {
+        PowPolicy::Native => unreachable!(),
+        PowPolicy::Error => Err(error),
+        PowPolicy::Nan => Ok(f64::NAN),
+        PowPolicy::ZeroPowZeroIsOne if exponent == 0.0 => Ok(1.0),
+        PowPolicy::ZeroPowZeroIsOne => Err(error),
+    }
+}
+
+/// `UnaryOp::Round` in `f64`, by scaling, which is exact enough for a
+/// reference value.
+fn round(x: f64, digits: i32, mode: RoundMode) -> f64 {
+    if !x.is_finite() {
+        return x;
+    }
+    let scale = 10f64.powi(digits);
+    let scaled = x * scale;
+    let rounded = match mode {
+        RoundMode::HalfUp => scaled.round(),
+        RoundMode::HalfEven => scaled.round_ties_even(),
+        RoundMode::Trunc => scaled.trunc(),
+    };
+    rounded / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::{Node, NodeCelled};
+
+    fn compare(output: &NodeCelled, inputs: &[NodeCelled], values: &[f32]) -> PrecisionReport {
+        CompiledGraph::compile(output, inputs)
+            .compare_precision(values)
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_where_precision_is_lost() {
+        // (x + 1e8) - 1e8 cancels in `f32` but not in `f64`.
+        let x = Node::create_input(1f32);
+        let shifted = Node::create_add(x.clone(), Node::create_const(1e8));
+        let out = Node::create_add(shifted.clone(), Node::create_const(-1e8));
+        let report = compare(&out, std::slice::from_ref(&x), &[1.0]);
+
+        let output = report.output();
+        assert_eq!((output.single, output.double), (0.0, 1.0));
+        assert_eq!(output.error, 1.0);
+        assert_eq!(report.worst().node, out.borrow().id());
+        let shifted = report
+            .nodes()
+            .iter()
+            .find(|node| node.node == shifted.borrow().id())
+            .unwrap();
+        assert!(shifted.error > 0.0 && shifted.error < 1e-7);
+        assert!(report.to_string().contains("0 vs 1, error 1e0"));
+    }
+
+    #[test]
+    fn compares_powers_and_rounding() {
+        let (x, y) = (Node::create_input(1.1f32), Node::create_input(10f32));
+        let pow = Node::create_pow(x.clone(), y.clone());
+        let report = compare(&pow, &[x.clone(), y.clone()], &[1.1, 10.0]);
+        let output = report.output();
+        assert_eq!(output.double, (1.1f32 as f64).powf(10.0));
+        assert!(output.error < 1e-6);
+
+        let strict = Node::create_strict_pow(x.clone(), y.clone(), PowPolicy::Error);
+        let graph = CompiledGraph::compile(&strict, &[x.clone(), y.clone()]);
+        let res = graph.compare_precision(&[0.0, -1.0]);
+        assert!(matches!(res, Err(EvalError::PowDomain { .. })));
+        let lenient = Node::create_strict_pow(x.clone(), y.clone(), PowPolicy::ZeroPowZeroIsOne);
+        let report = compare(&lenient, &[x.clone(), y], &[0.0, 0.0]);
+        assert_eq!((report.output().single, report.output().double), (1.0, 1.0));
+
+        // Rounding can turn a tiny error into none, or into a whole digit:
+        // 0.35 rounds up as written, but its `f32` is a little less.
+        let round = Node::create_round(x.clone(), 0, RoundMode::HalfUp);
+        let report = compare(&round, std::slice::from_ref(&x), &[2.5]);
+        assert_eq!(report.output().error, 0.0);
+        let round = Node::create_round(x.clone(), 1, RoundMode::HalfUp);
+        let report = compare(&round, std::slice::from_ref(&x), &[0.35]);
+        let output = report.output();
+        assert_eq!((output.single, output.double), (0.4, 0.3));
+        assert!((output.introduced - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(report.worst().node, round.borrow().id());
+    }
+}
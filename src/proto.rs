@@ -0,0 +1,635 @@
+//! Protobuf encoding of graphs and values, for building graphs in other
+//! languages and evaluating them here. The schema is `SCHEMA`, shipped as
+//! `proto/graph.proto` for code generators.
+//!
+//! The crate has no dependencies, so this reads and writes the wire format
+//! itself. Unknown fields are skipped, as protobuf readers do.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::computational_graph::{BinaryOp, InputKind, NodeCelled, TernaryOp, UnaryOp};
+use crate::serialize::{self, Deserializer, Item, Keyword, LoadError, Op, FORMAT_VERSION};
+
+pub const SCHEMA: &str = include_str!("../proto/graph.proto");
+
+/// `Op` enum of the schema: the op named `OPS[i]` is `i + 1`.
+const OPS: [&str; 19] = [
+    "input",
+    "time",
+    "const",
+    "delay",
+    "accumulate",
+    "add",
+    "mul",
+    "pow",
+    "and",
+    "or",
+    "compare",
+    "sin",
+    "cos",
+    "not",
+    "round",
+    "mul-add",
+    "select",
+    "custom",
+    "composite",
+];
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const BYTES: u8 = 2;
+const FIXED32: u8 = 5;
+
+#[derive(Debug, Clone)]
+pub enum ProtoError {
+    /// Not a well-formed protobuf message, at byte `offset`.
+    Wire { offset: usize, reason: &'static str },
+    /// A message that isn't a valid graph. Its `line` is the position of the
+    /// offending node, subgraph, feed or output in the message, from 1.
+    Load(LoadError),
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wire { offset, reason } => write!(f, "byte {offset}: {reason}"),
+            Self::Load(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+impl From<LoadError> for ProtoError {
+    fn from(e: LoadError) -> Self {
+        Self::Load(e)
+    }
+}
+
+/// A `Graph` message for the graphs computing `outputs`, with the same
+/// content as `serialize::serialize`.
+pub fn encode_graph(outputs: &[NodeCelled]) -> Vec<u8> {
+    let items = serialize::items(outputs);
+    let mut names = HashMap::new();
+    let mut docs = HashMap::new();
+    for item in &items {
+        match item {
+            Item::Name { id, name, .. } => names.insert(*id, name),
+            Item::Doc { id, doc, .. } => docs.insert(*id, doc),
+            _ => None,
+        };
+    }
+
+    let mut graph = Writer::default();
+    graph.varint(1, FORMAT_VERSION as u64);
+    for item in &items {
+        match item {
+            Item::Node {
+                id, op, operands, ..
+            } => graph.message(2, |node| {
+                encode_node(node, *id, op, operands);
+                if let Some(name) = names.get(id) {
+                    node.bytes(13, name.as_bytes());
+                }
+                if let Some(doc) = docs.get(id) {
+                    node.bytes(14, doc.as_bytes());
+                }
+            }),
+            Item::Subgraph {
+                id,
+                name,
+                inputs,
+                outputs,
+                ..
+            } => graph.message(3, |subgraph| {
+                subgraph.varint(1, *id as u64);
+                if let Some(name) = name {
+                    subgraph.bytes(2, name.as_bytes());
+                }
+                subgraph.packed(3, inputs);
+                subgraph.packed(4, outputs);
+            }),
+            Item::Feed { delay, source, .. } => graph.message(4, |feed| {
+                feed.varint(1, *delay as u64);
+                feed.varint(2, *source as u64);
+            }),
+            Item::Output { .. } | Item::Name { .. } | Item::Doc { .. } => {}
+        }
+    }
+    let outputs: Vec<_> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Output { id, .. } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    graph.packed(5, &outputs);
+    graph.out
+}
+
+fn encode_node(node: &mut Writer, id: usize, op: &Op, operands: &[usize]) {
+    node.varint(1, id as u64);
+    let number = OPS.iter().position(|name| *name == op.name()).unwrap() + 1;
+    node.varint(2, number as u64);
+    node.packed(3, operands);
+    match op {
+        Op::Input(kind, x) => {
+            node.float(4, *x);
+            if let InputKind::Accumulate(accumulation) = kind {
+                node.varint(5, keyword_number(*accumulation));
+            }
+        }
+        Op::Binary(BinaryOp::Pow(policy)) => node.varint(6, keyword_number(*policy)),
+        Op::Binary(BinaryOp::Compare(cmp)) => node.varint(7, keyword_number(*cmp)),
+        Op::Unary(UnaryOp::Round { digits, mode }) => {
+            node.varint(8, zigzag(*digits));
+            node.varint(9, keyword_number(*mode));
+        }
+        Op::Custom(name) => node.bytes(10, name.as_bytes()),
+        Op::Composite { graph, port } => {
+            node.varint(11, *graph as u64);
+            node.varint(12, *port as u64);
+        }
+        Op::Binary(_) | Op::Unary(_) | Op::Ternary(_) => {}
+    }
+}
+
+/// A `Values` message.
+pub fn encode_values(values: &[f32]) -> Vec<u8> {
+    let mut message = Writer::default();
+    let mut packed = Vec::with_capacity(values.len() * 4);
+    for value in values {
+        packed.extend_from_slice(&value.to_le_bytes());
+    }
+    message.bytes(1, &packed);
+    message.out
+}
+
+/// The values of a `Values` message.
+pub fn decode_values(bytes: &[u8]) -> Result<Vec<f32>, ProtoError> {
+    let mut res = Vec::new();
+    let mut message = Reader::new(bytes, 0);
+    while let Some((number, field)) = message.field()? {
+        if number != 1 {
+            continue;
+        }
+        match field {
+            Field::Fixed32(bits) => res.push(f32::from_bits(bits)),
+            Field::Bytes(mut packed) => {
+                while !packed.is_empty() {
+                    res.push(f32::from_bits(packed.fixed32()?));
+                }
+            }
+            _ => return Err(message.error("expected floats")),
+        }
+    }
+    Ok(res)
+}
+
+impl Deserializer {
+    /// The outputs of a `Graph` message, in order. Custom ops are resolved as
+    /// by `deserialize`.
+    pub fn decode_graph(&self, bytes: &[u8]) -> Result<Vec<NodeCelled>, ProtoError> {
+        let mut items = Vec::new();
+        let mut version = 0;
+        let mut graph = Reader::new(bytes, 0);
+        // Nodes' names and docs are items of their own, on the node's line,
+        // so they're not counted.
+        let mut labels = 0;
+        while let Some((number, field)) = graph.field()? {
+            let line = items.len() - labels + 1;
+            match (number, field) {
+                (1, Field::Varint(x)) => version = x,
+                (2, Field::Bytes(node)) => {
+                    let node = decode_node(node, line)?;
+                    labels += node.len() - 1;
+                    items.extend(node);
+                }
+                (3, Field::Bytes(subgraph)) => items.push(decode_subgraph(subgraph, line)?),
+                (4, Field::Bytes(mut feed)) => {
+                    let (mut delay, mut source) = (0, 0);
+                    while let Some((number, field)) = feed.field()? {
+                        match (number, field) {
+                            (1, Field::Varint(x)) => delay = feed.id(x)?,
+                            (2, Field::Varint(x)) => source = feed.id(x)?,
+                            (1 | 2, _) => return Err(feed.error("expected a varint")),
+                            _ => {}
+                        }
+                    }
+                    items.push(Item::Feed {
+                        line,
+                        delay,
+                        source,
+                    });
+                }
+                (5, field) => {
+                    let mut ids = Vec::new();
+                    graph.ids(field, &mut ids)?;
+                    for (i, id) in ids.into_iter().enumerate() {
+                        items.push(Item::Output { line: line + i, id });
+                    }
+                }
+                (1, _) => return Err(graph.error("expected a varint")),
+                (2..=4, _) => return Err(graph.error("expected a message")),
+                _ => {}
+            }
+        }
+
+        if version == 0 {
+            return Err(LoadError::MissingHeader.into());
+        }
+        if version > FORMAT_VERSION as u64 {
+            return Err(LoadError::UnsupportedVersion {
+                version: version.try_into().unwrap_or(u32::MAX),
+            }
+            .into());
+        }
+        Ok(self.build_items(&items)?)
+    }
+}
+
+/// The node, followed by its name and doc if it has them.
+fn decode_node(mut node: Reader, line: usize) -> Result<Vec<Item>, ProtoError> {
+    let mut id = 0;
+    let mut op = 0;
+    let mut operands = Vec::new();
+    let mut value = 0.0;
+    let (mut accumulation, mut policy, mut cmp, mut mode) = (0, 0, 0, 0);
+    let mut digits = 0;
+    let mut custom = String::new();
+    let (mut graph, mut port) = (0, 0);
+    let (mut name, mut doc) = (None, None);
+    while let Some((number, field)) = node.field()? {
+        match (number, field) {
+            (3, field) => node.ids(field, &mut operands)?,
+            (4, Field::Fixed32(bits)) => value = f32::from_bits(bits),
+            (10, Field::Bytes(name)) => {
+                custom = String::from_utf8(name.bytes.to_vec())
+                    .map_err(|_| name.error("custom op name isn't UTF-8"))?;
+            }
+            (13, Field::Bytes(bytes)) => {
+                let decoded = String::from_utf8(bytes.bytes.to_vec())
+                    .map_err(|_| bytes.error("node name isn't UTF-8"))?;
+                name = Some(decoded);
+            }
+            (14, Field::Bytes(bytes)) => {
+                let decoded = String::from_utf8(bytes.bytes.to_vec())
+                    .map_err(|_| bytes.error("node doc isn't UTF-8"))?;
+                doc = Some(decoded);
+            }
+            (1 | 2 | 5..=9 | 11 | 12, Field::Varint(x)) => match number {
+                1 => id = node.id(x)?,
+                2 => op = x,
+                5 => accumulation = x,
+                6 => policy = x,
+                7 => cmp = x,
+                8 => digits = x,
+                9 => mode = x,
+                11 => graph = node.id(x)?,
+                _ => port = node.id(x)?,
+            },
+            (1..=14, _) => return Err(node.error("unexpected wire type")),
+            _ => {}
+        }
+    }
+
+    let op_name = match op {
+        1..=19 => OPS[op as usize - 1],
+        0 => {
+            return Err(LoadError::Malformed {
+                line,
+                reason: "missing op",
+            }
+            .into())
+        }
+        _ => {
+            return Err(LoadError::UnknownOp {
+                line,
+                op: format!("op {op}"),
+            }
+            .into())
+        }
+    };
+    let op = match op_name {
+        "input" => Op::Input(InputKind::Value, value),
+        "time" => Op::Input(InputKind::Time, value),
+        "const" => Op::Input(InputKind::Const, value),
+        "delay" => Op::Input(InputKind::Delay, value),
+        "accumulate" => Op::Input(
+            InputKind::Accumulate(keyword(
+                accumulation,
+                line,
+                ("accumulation", "missing accumulation"),
+            )?),
+            value,
+        ),
+        "add" => Op::Binary(BinaryOp::Add),
+        "mul" => Op::Binary(BinaryOp::Mul),
+        "pow" => Op::Binary(BinaryOp::Pow(keyword(
+            policy,
+            line,
+            ("pow policy", "missing pow policy"),
+        )?)),
+        "and" => Op::Binary(BinaryOp::And),
+        "or" => Op::Binary(BinaryOp::Or),
+        "compare" => Op::Binary(BinaryOp::Compare(keyword(
+            cmp,
+            line,
+            ("comparison", "missing comparison"),
+        )?)),
+        "sin" => Op::Unary(UnaryOp::Sin),
+        "cos" => Op::Unary(UnaryOp::Cos),
+        "not" => Op::Unary(UnaryOp::Not),
+        "round" => Op::Unary(UnaryOp::Round {
+            digits: unzigzag(digits).ok_or(LoadError::Malformed {
+                line,
+                reason: "digit count out of range",
+            })?,
+            mode: keyword(mode, line, ("round mode", "missing round mode"))?,
+        }),
+        "mul-add" => Op::Ternary(TernaryOp::MulAdd),
+        "select" => Op::Ternary(TernaryOp::Select),
+        "custom" => Op::Custom(custom),
+        _ => Op::Composite { graph, port },
+    };
+    let mut res = vec![Item::Node {
+        line,
+        id,
+        op,
+        operands,
+    }];
+    if let Some(name) = name {
+        res.push(Item::Name { line, id, name });
+    }
+    if let Some(doc) = doc {
+        res.push(Item::Doc { line, id, doc });
+    }
+    Ok(res)
+}
+
+fn decode_subgraph(mut subgraph: Reader, line: usize) -> Result<Item, ProtoError> {
+    let mut id = 0;
+    let mut name = None;
+    let (mut inputs, mut outputs) = (Vec::new(), Vec::new());
+    while let Some((number, field)) = subgraph.field()? {
+        match (number, field) {
+            (1, Field::Varint(x)) => id = subgraph.id(x)?,
+            (2, Field::Bytes(bytes)) => {
+                let decoded = String::from_utf8(bytes.bytes.to_vec())
+                    .map_err(|_| bytes.error("subgraph name isn't UTF-8"))?;
+                name = Some(decoded);
+            }
+            (3, field) => subgraph.ids(field, &mut inputs)?,
+            (4, field) => subgraph.ids(field, &mut outputs)?,
+            (1 | 2, _) => return Err(subgraph.error("unexpected wire type")),
+            _ => {}
+        }
+    }
+    Ok(Item::Subgraph {
+        line,
+        id,
+        name,
+        inputs,
+        outputs,
+    })
+}
+
+/// Number of `x` in its schema enum, where 0 is unspecified.
+fn keyword_number<T: Keyword>(x: T) -> u64 {
+    T::ALL.iter().position(|variant| *variant == x).unwrap() as u64 + 1
+}
+
+/// The variant numbered `number`, reporting it as the `what` of the node on
+/// `line`, missing if 0.
+fn keyword<T: Keyword>(
+    number: u64,
+    line: usize,
+    (what, missing): (&'static str, &'static str),
+) -> Result<T, LoadError> {
+    match number {
+        0 => Err(LoadError::Malformed {
+            line,
+            reason: missing,
+        }),
+        _ => T::ALL
+            .get(number as usize - 1)
+            .copied()
+            .ok_or(LoadError::UnknownOp {
+                line,
+                op: format!("{what} {number}"),
+            }),
+    }
+}
+
+fn zigzag(x: i32) -> u64 {
+    ((x << 1) ^ (x >> 31)) as u32 as u64
+}
+
+fn unzigzag(x: u64) -> Option<i32> {
+    let x = u32::try_from(x).ok()?;
+    Some((x >> 1) as i32 ^ -((x & 1) as i32))
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn raw_varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.out.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.out.push(x as u8);
+    }
+
+    fn tag(&mut self, number: u32, wire: u8) {
+        self.raw_varint(((number as u64) << 3) | wire as u64);
+    }
+
+    /// Omitted when 0, the default.
+    fn varint(&mut self, number: u32, x: u64) {
+        if x != 0 {
+            self.tag(number, VARINT);
+            self.raw_varint(x);
+        }
+    }
+
+    /// Omitted when `+0.0`, the default.
+    fn float(&mut self, number: u32, x: f32) {
+        if x.to_bits() != 0 {
+            self.tag(number, FIXED32);
+            self.out.extend_from_slice(&x.to_bits().to_le_bytes());
+        }
+    }
+
+    fn bytes(&mut self, number: u32, bytes: &[u8]) {
+        self.tag(number, BYTES);
+        self.raw_varint(bytes.len() as u64);
+        self.out.extend_from_slice(bytes);
+    }
+
+    /// Omitted when empty.
+    fn packed(&mut self, number: u32, ids: &[usize]) {
+        if ids.is_empty() {
+            return;
+        }
+        let mut packed = Self::default();
+        for id in ids {
+            packed.raw_varint(*id as u64);
+        }
+        self.bytes(number, &packed.out);
+    }
+
+    fn message(&mut self, number: u32, write: impl FnOnce(&mut Self)) {
+        let mut message = Self::default();
+        write(&mut message);
+        self.bytes(number, &message.out);
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    /// Skipped: no field of the schema is 64-bit.
+    Fixed64,
+    Fixed32(u32),
+    Bytes(Reader<'a>),
+}
+
+/// Reads a message, keeping offsets from the start of the outermost one.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], offset: usize) -> Self {
+        Self { bytes, offset }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn error(&self, reason: &'static str) -> ProtoError {
+        ProtoError::Wire {
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProtoError> {
+        if len > self.bytes.len() {
+            return Err(self.error("truncated message"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        self.offset += len;
+        Ok(taken)
+    }
+
+    fn raw_varint(&mut self) -> Result<u64, ProtoError> {
+        let mut res = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            res |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(res);
+            }
+        }
+        Err(self.error("varint too long"))
+    }
+
+    fn fixed32(&mut self) -> Result<u32, ProtoError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// The next field and its number, or `None` at the end.
+    fn field(&mut self) -> Result<Option<(u32, Field<'a>)>, ProtoError> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.raw_varint()?;
+        let number = u32::try_from(tag >> 3).map_err(|_| self.error("field number too large"))?;
+        let field = match (tag & 7) as u8 {
+            VARINT => Field::Varint(self.raw_varint()?),
+            FIXED64 => {
+                self.take(8)?;
+                Field::Fixed64
+            }
+            FIXED32 => Field::Fixed32(self.fixed32()?),
+            BYTES => {
+                let len = self.raw_varint()?;
+                let len = usize::try_from(len).map_err(|_| self.error("truncated message"))?;
+                let offset = self.offset;
+                Field::Bytes(Reader::new(self.take(len)?, offset))
+            }
+            _ => return Err(self.error("unsupported wire type")),
+        };
+        Ok(Some((number, field)))
+    }
+
+    fn id(&self, x: u64) -> Result<usize, ProtoError> {
+        usize::try_from(x).map_err(|_| self.error("id out of range"))
+    }
+
+    /// Appends a repeated id field, packed or not, to `ids`.
+    fn ids(&self, field: Field, ids: &mut Vec<usize>) -> Result<(), ProtoError> {
+        match field {
+            Field::Varint(x) => ids.push(self.id(x)?),
+            Field::Bytes(mut packed) => {
+                while !packed.is_empty() {
+                    let x = packed.raw_varint()?;
+                    ids.push(packed.id(x)?);
+                }
+            }
+            _ => return Err(self.error("expected ids")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    #[test]
+    fn round_trips_names_and_docs() {
+        let x = Node::create_input(3.0);
+        x.borrow().set_name("x");
+        x.borrow().set_doc("The input.");
+        let output = Node::create_mul(x.clone(), x);
+        output.borrow().set_name("square");
+
+        let bytes = encode_graph(std::slice::from_ref(&output));
+        let loaded = Deserializer::new().decode_graph(&bytes).unwrap();
+        assert_eq!(
+            serialize::serialize(&loaded),
+            serialize::serialize(&[output])
+        );
+        assert_eq!(loaded[0].borrow().name().as_deref(), Some("square"));
+    }
+
+    #[test]
+    fn reports_positions_in_the_message() {
+        let x = Node::create_input(3.0);
+        x.borrow().set_name("x");
+        let mut bytes = encode_graph(&[Node::create_add(x.clone(), x)]);
+        // A second output referring to no node, after the two nodes.
+        let mut extra = Writer::default();
+        extra.packed(5, &[7]);
+        bytes.extend(extra.out);
+        let Err(ProtoError::Load(LoadError::Invalid(violations))) =
+            Deserializer::new().decode_graph(&bytes)
+        else {
+            panic!("expected violations");
+        };
+        assert_eq!(
+            violations,
+            vec![serialize::Violation::Dangling { line: 4, id: 7 }]
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Input values from the deployment environment: variables, config files or
+//! key-value stores, bound to named inputs and re-read on `refresh`.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use crate::computational_graph::{InputKind, Node, NodeCelled};
+
+/// Source of input values by key. Implement this for a key-value store
+/// client to bind inputs to it.
+pub trait InputProvider {
+    /// The current value of `key`, `None` if it has none.
+    fn get(&self, key: &str) -> Option<f32>;
+}
+
+impl Node {
+    /// Binds every named value input below `this` (or `this`, if it is one)
+    /// whose name `provider` has a value for, setting it to that value. On
+    /// `refresh`, bound inputs re-read their key, keeping their last value
+    /// while the key has none or one their constraint rejects. Returns the
+    /// bound inputs.
+    pub fn bind_inputs(this: &NodeCelled, provider: Rc<dyn InputProvider>) -> Vec<NodeCelled> {
+        let mut bound = Vec::new();
+        for input in Self::inputs(this) {
+            let node = input.borrow();
+            if !matches!(
+                &*node,
+                Self::Input {
+                    kind: InputKind::Value,
+                    ..
+                }
+            ) {
+                continue;
+            }
+            let Some(key) = node.name() else {
+                continue;
+            };
+            if provider.get(&key).is_none() {
+                continue;
+            }
+
+            let last = Cell::new(node.compute());
+            let provider = provider.clone();
+            // Weak, as the input owns its source.
+            let weak = Rc::downgrade(&input);
+            node.bind(move || {
+                if let Some(value) = provider.get(&key) {
+                    let constraint = weak.upgrade().and_then(|input| input.borrow().constraint());
+                    if constraint.is_none_or(|constraint| constraint.check(value).is_ok()) {
+                        last.set(value);
+                    }
+                }
+                last.get()
+            });
+            drop(node);
+            bound.push(input);
+        }
+        bound
+    }
+}
+
+/// Environment variables, named by a prefix and the key.
+#[derive(Debug, Clone, Default)]
+pub struct EnvProvider {
+    prefix: String,
+}
+
+impl EnvProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads `key` from the variable `<prefix><key>`.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+impl InputProvider for EnvProvider {
+    /// `None` for unset variables and ones that aren't numbers.
+    fn get(&self, key: &str) -> Option<f32> {
+        std::env::var(format!("{}{key}", self.prefix))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+/// File of `key = value` lines, `#` starting comments, re-read when it
+/// changes.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    values: RefCell<HashMap<String, f32>>,
+    stamp: Cell<Option<(SystemTime, u64)>>,
+}
+
+impl ConfigFile {
+    /// Fails if the file can't be read or has a line that isn't a setting.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = Self {
+            path: path.as_ref().to_path_buf(),
+            values: RefCell::new(HashMap::new()),
+            stamp: Cell::new(None),
+        };
+        file.reload()?;
+        Ok(file)
+    }
+
+    /// Re-reads the file if its modification time or length changed. On
+    /// failure, the values read last are kept. Returns whether it re-read.
+    pub fn reload(&self) -> io::Result<bool> {
+        let metadata = fs::metadata(&self.path)?;
+        let stamp = (metadata.modified()?, metadata.len());
+        if self.stamp.get() == Some(stamp) {
+            return Ok(false);
+        }
+
+        let text = fs::read_to_string(&self.path)?;
+        let mut values = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let setting = line
+                .split_once('=')
+                .and_then(|(key, value)| Some((key.trim(), value.trim().parse().ok()?)));
+            match setting {
+                Some((key, value)) if !key.is_empty() => {
+                    values.insert(key.to_string(), value);
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: expected \"<key> = <number>\"", i + 1),
+                    ))
+                }
+            }
+        }
+        *self.values.borrow_mut() = values;
+        self.stamp.set(Some(stamp));
+        Ok(true)
+    }
+}
+
+impl InputProvider for ConfigFile {
+    /// Reloads first, keeping the last values if that fails.
+    fn get(&self, key: &str) -> Option<f32> {
+        let _ = self.reload();
+        self.values.borrow().get(key).copied()
+    }
+}
+
+/// In-memory key-value store, shared by its clones, e.g. filled from a
+/// remote store by a background task's handler.
+#[derive(Debug, Clone, Default)]
+pub struct KeyValueStore {
+    values: Rc<RefCell<HashMap<String, f32>>>,
+}
+
+impl KeyValueStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes effect on the bound inputs' next `refresh`.
+    pub fn set(&self, key: impl Into<String>, value: f32) {
+        self.values.borrow_mut().insert(key.into(), value);
+    }
+
+    pub fn remove(&self, key: &str) -> Option<f32> {
+        self.values.borrow_mut().remove(key)
+    }
+}
+
+impl InputProvider for KeyValueStore {
+    fn get(&self, key: &str) -> Option<f32> {
+        self.values.borrow().get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint::Constraint;
+
+    #[derive(Default)]
+    struct Values(RefCell<HashMap<String, f32>>);
+
+    impl InputProvider for Values {
+        fn get(&self, key: &str) -> Option<f32> {
+            self.0.borrow().get(key).copied()
+        }
+    }
+
+    #[test]
+    fn constrained_input_keeps_last_valid_value() {
+        let values = Rc::new(Values::default());
+        values.0.borrow_mut().insert("rate".into(), 0.5);
+        let rate = Node::create_input(0.0);
+        rate.borrow().set_name("rate");
+        rate.borrow()
+            .set_constraint(Constraint::new().with_min(0.0).with_max(1.0));
+        let bound = Node::bind_inputs(&rate, values.clone());
+        assert_eq!(bound.len(), 1);
+        assert_eq!(rate.borrow().compute(), 0.5);
+
+        values.0.borrow_mut().insert("rate".into(), 2.0);
+        rate.borrow().refresh();
+        assert_eq!(rate.borrow().compute(), 0.5);
+
+        values.0.borrow_mut().insert("rate".into(), 0.25);
+        rate.borrow().refresh();
+        assert_eq!(rate.borrow().compute(), 0.25);
+    }
+
+    #[test]
+    fn constrained_input_ignores_invalid_initial_value() {
+        let values = Rc::new(Values::default());
+        values.0.borrow_mut().insert("rate".into(), -1.0);
+        let rate = Node::create_input(0.5);
+        rate.borrow().set_name("rate");
+        rate.borrow()
+            .set_constraint(Constraint::new().with_min(0.0));
+        Node::bind_inputs(&rate, values);
+        assert_eq!(rate.borrow().compute(), 0.5);
+    }
+}
@@ -0,0 +1,186 @@
+//! Random ops drawing from a seedable generator, for procedural generation.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::computational_graph::{CachePolicy, CustomOp, Node, NodeCelled};
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64 generator, usually one per graph and shared by its `RandomOp`s.
+/// The sequence depends only on the seed and on the order of the draws, which
+/// is the order nodes are evaluated in.
+#[derive(Debug)]
+pub struct Rng {
+    state: AtomicU64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Arc<Self> {
+        Arc::new(Self {
+            state: AtomicU64::new(seed),
+        })
+    }
+
+    /// Restarts the sequence, as if created with `seed`.
+    pub fn reseed(&self, seed: u64) {
+        self.state.store(seed, Ordering::Relaxed);
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f32(&self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    /// Operands `low` and `high`, draws from `[low, high)`.
+    Uniform,
+    /// Operands `mean` and `std_dev`.
+    Normal,
+}
+
+/// `CustomOp` drawing a new value on every evaluation, so its nodes are
+/// `NoCache`. Each node should get its own instance: pools merge nodes of the
+/// same instance over the same operands, which would make them one draw.
+#[derive(Debug)]
+pub struct RandomOp {
+    rng: Arc<Rng>,
+    distribution: Distribution,
+}
+
+impl RandomOp {
+    pub fn new(rng: Arc<Rng>, distribution: Distribution) -> Arc<Self> {
+        Arc::new(Self { rng, distribution })
+    }
+}
+
+impl CustomOp for RandomOp {
+    fn name(&self) -> &str {
+        match self.distribution {
+            Distribution::Uniform => "random-uniform",
+            Distribution::Normal => "random-normal",
+        }
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        let [a, b] = args else {
+            return Err(format!("expected 2 operands, got {}", args.len()));
+        };
+        Ok(match self.distribution {
+            Distribution::Uniform => {
+                let x = a + (b - a) * self.rng.next_f32();
+                // The sum can round up to `high`, which the range excludes.
+                if x >= *b && a < b {
+                    b.next_down()
+                } else {
+                    x
+                }
+            }
+            Distribution::Normal => {
+                // Box-Muller, with `u` in `(0, 1]` to keep the log finite.
+                let u = 1.0 - self.rng.next_f32();
+                let v = self.rng.next_f32();
+                let z = (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos();
+                a + b * z
+            }
+        })
+    }
+
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy::NoCache
+    }
+}
+
+impl Node {
+    /// Uniform draw from `[low, high)` on every `compute()`.
+    pub fn create_uniform(rng: &Arc<Rng>, low: NodeCelled, high: NodeCelled) -> NodeCelled {
+        let op = RandomOp::new(rng.clone(), Distribution::Uniform);
+        Self::create_custom(op, vec![low, high])
+    }
+
+    /// Normal draw on every `compute()`.
+    pub fn create_normal(rng: &Arc<Rng>, mean: NodeCelled, std_dev: NodeCelled) -> NodeCelled {
+        let op = RandomOp::new(rng.clone(), Distribution::Normal);
+        Self::create_custom(op, vec![mean, std_dev])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draws(node: &NodeCelled, n: usize) -> Vec<f32> {
+        (0..n).map(|_| node.borrow().compute()).collect()
+    }
+
+    #[test]
+    fn seeds_determine_draws() {
+        let rng = Rng::new(7);
+        let first: Vec<_> = (0..5).map(|_| rng.next_u64()).collect();
+        rng.reseed(7);
+        let again: Vec<_> = (0..5).map(|_| rng.next_u64()).collect();
+        assert_eq!(first, again);
+        assert_ne!(
+            first,
+            (0..5).map(|_| Rng::new(8).next_u64()).collect::<Vec<_>>()
+        );
+
+        let rng = Rng::new(1);
+        let low = Node::create_input(-1f32);
+        let uniform = Node::create_uniform(&rng, low.clone(), Node::create_input(1f32));
+        let normal = Node::create_normal(&rng, low, Node::create_input(2f32));
+        let first = (draws(&uniform, 3), draws(&normal, 3));
+        rng.reseed(1);
+        assert_eq!((draws(&uniform, 3), draws(&normal, 3)), first);
+        assert_eq!(uniform.borrow().cache_policy(), CachePolicy::NoCache);
+    }
+
+    #[test]
+    fn uniform_draws_stay_in_range() {
+        let rng = Rng::new(3);
+        let uniform =
+            Node::create_uniform(&rng, Node::create_input(2f32), Node::create_input(5f32));
+        let values = draws(&uniform, 10_000);
+        assert!(values.iter().all(|x| (2.0..5.0).contains(x)));
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((mean - 3.5).abs() < 0.05, "{mean}");
+
+        // Half of these would round to `high`.
+        let high = 1f32.next_up();
+        let uniform =
+            Node::create_uniform(&rng, Node::create_input(1f32), Node::create_input(high));
+        assert!(draws(&uniform, 100).iter().all(|x| *x == 1f32));
+        assert!((0..1000).all(|_| (0.0..1.0).contains(&rng.next_f32())));
+    }
+
+    #[test]
+    fn normal_draws_have_their_moments() {
+        let rng = Rng::new(11);
+        let normal = Node::create_normal(&rng, Node::create_input(10f32), Node::create_input(3f32));
+        let values = draws(&normal, 20_000);
+        let n = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / n;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+        assert!((mean - 10.0).abs() < 0.1, "{mean}");
+        assert!((variance.sqrt() - 3.0).abs() < 0.1, "{variance}");
+        assert!(values.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn rejects_wrong_operand_counts() {
+        let op = RandomOp::new(Rng::new(0), Distribution::Uniform);
+        assert_eq!(op.compute(&[1.0]), Err("expected 2 operands, got 1".into()));
+    }
+}
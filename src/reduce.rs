@@ -0,0 +1,284 @@
+//! Reductions of vectors to a scalar, and softmax, computed so that large or
+//! very negative values don't overflow and long sums don't pile up rounding
+//! errors. All have derivatives for `autodiff`.
+
+use std::sync::Arc;
+
+use crate::computational_graph::{CustomOp, Node, NodeCelled};
+use crate::tensor::{ShapeError, Tensor};
+
+/// `ln(sum of e^x)` over any number of operands, shifted by their maximum so
+/// that no exponential overflows. `-inf` for no operands.
+#[derive(Debug)]
+pub struct LogSumExp;
+
+impl CustomOp for LogSumExp {
+    fn name(&self) -> &str {
+        "log-sum-exp"
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        Ok(log_sum_exp(args))
+    }
+
+    /// The softmax of the operands.
+    fn derivatives(&self, args: &[f32]) -> Option<Vec<f32>> {
+        let total = log_sum_exp(args);
+        Some(args.iter().map(|x| (x - total).exp()).collect())
+    }
+}
+
+/// `e^x`, exact to the rounding of `f32::exp` where `e` raised by `Pow`
+/// would carry the rounding of `e` itself, growing with `x`.
+#[derive(Debug)]
+pub struct Exp;
+
+impl CustomOp for Exp {
+    fn name(&self) -> &str {
+        "exp"
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        match args {
+            [x] => Ok(x.exp()),
+            _ => Err(format!("exp takes 1 operand, not {}", args.len())),
+        }
+    }
+
+    fn derivatives(&self, args: &[f32]) -> Option<Vec<f32>> {
+        Some(args.iter().map(|x| x.exp()).collect())
+    }
+}
+
+/// How a `Sum` adds up its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Summation {
+    /// Left to right, the error growing with the number of operands.
+    Naive,
+    /// Left to right, carrying the rounding error of every addition along
+    /// (Neumaier's variant of Kahan summation), so the error doesn't grow
+    /// with the number of operands.
+    #[default]
+    Compensated,
+    /// Halves summed recursively, the error growing with the logarithm of
+    /// the number of operands. Cheaper than `Compensated`.
+    Pairwise,
+}
+
+/// Sum of any number of operands as a single node, `0` for none.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sum(pub Summation);
+
+impl CustomOp for Sum {
+    fn name(&self) -> &str {
+        match self.0 {
+            Summation::Naive => "sum",
+            Summation::Compensated => "compensated-sum",
+            Summation::Pairwise => "pairwise-sum",
+        }
+    }
+
+    fn compute(&self, args: &[f32]) -> Result<f32, String> {
+        Ok(match self.0 {
+            Summation::Naive => args.iter().sum(),
+            Summation::Compensated => compensated_sum(args),
+            Summation::Pairwise => pairwise_sum(args),
+        })
+    }
+
+    fn derivatives(&self, args: &[f32]) -> Option<Vec<f32>> {
+        Some(vec![1.0; args.len()])
+    }
+}
+
+fn compensated_sum(args: &[f32]) -> f32 {
+    let mut sum = 0f32;
+    let mut compensation = 0f32;
+    for x in args {
+        let t = sum + x;
+        // Recover what the addition rounded off from the smaller operand.
+        compensation += if sum.abs() >= x.abs() {
+            (sum - t) + x
+        } else {
+            (x - t) + sum
+        };
+        sum = t;
+    }
+    if sum.is_finite() {
+        sum + compensation
+    } else {
+        sum
+    }
+}
+
+fn pairwise_sum(args: &[f32]) -> f32 {
+    if args.len() <= 8 {
+        return args.iter().sum();
+    }
+    let (left, right) = args.split_at(args.len() / 2);
+    pairwise_sum(left) + pairwise_sum(right)
+}
+
+fn log_sum_exp(args: &[f32]) -> f32 {
+    if args.iter().any(|x| x.is_nan()) {
+        return f32::NAN;
+    }
+    let max = args.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if max.is_infinite() {
+        return max;
+    }
+    max + args.iter().map(|x| (x - max).exp()).sum::<f32>().ln()
+}
+
+impl Node {
+    pub fn create_sum(args: Vec<NodeCelled>, summation: Summation) -> NodeCelled {
+        Self::create_custom(Arc::new(Sum(summation)), args)
+    }
+
+    pub fn create_exp(x: NodeCelled) -> NodeCelled {
+        Self::create_custom(Arc::new(Exp), vec![x])
+    }
+
+    pub fn create_log_sum_exp(args: Vec<NodeCelled>) -> NodeCelled {
+        Self::create_custom(Arc::new(LogSumExp), args)
+    }
+}
+
+impl Tensor {
+    /// Sum of a vector's elements, `0` if it has none.
+    pub fn sum(&self) -> Result<NodeCelled, ShapeError> {
+        Ok(self
+            .vector()?
+            .iter()
+            .cloned()
+            .reduce(Node::create_add)
+            .unwrap_or_else(|| Node::create_const(0f32)))
+    }
+
+    /// Sum of a vector's elements as a single `Sum` node, more accurate than
+    /// `sum` for long vectors unless `summation` is `Naive`.
+    pub fn sum_with(&self, summation: Summation) -> Result<NodeCelled, ShapeError> {
+        Ok(Node::create_sum(self.vector()?.to_vec(), summation))
+    }
+
+    /// Mean of a vector's elements, NaN if it has none.
+    pub fn mean(&self) -> Result<NodeCelled, ShapeError> {
+        let n = self.vector()?.len() as f32;
+        Ok(Node::create_mul(self.sum()?, Node::create_const(n.recip())))
+    }
+
+    /// Population variance of a vector's elements, as the mean of squared
+    /// deviations from the mean rather than from sums of squares, which
+    /// cancel catastrophically when the mean is large.
+    pub fn variance(&self) -> Result<NodeCelled, ShapeError> {
+        let mean = self.mean()?;
+        let minus_mean = Node::create_mul(mean, Node::create_const(-1f32));
+        let squares = self.map(|x| {
+            let deviation = Node::create_add(x, minus_mean.clone());
+            Node::create_pow(deviation, Node::create_const(2f32))
+        });
+        squares.mean()
+    }
+
+    /// `ln(sum of e^x)` over a vector, see `LogSumExp`.
+    pub fn log_sum_exp(&self) -> Result<NodeCelled, ShapeError> {
+        Ok(Node::create_log_sum_exp(self.vector()?.to_vec()))
+    }
+
+    /// `e^x / sum of e^x` for every element of a vector, computed as
+    /// `e^(x - log_sum_exp)` so that nothing overflows.
+    pub fn softmax(&self) -> Result<Tensor, ShapeError> {
+        let minus_total = Node::create_mul(self.log_sum_exp()?, Node::create_const(-1f32));
+        Ok(self.map(|x| {
+            let exponent = Node::create_add(x, minus_total.clone());
+            Node::create_exp(exponent)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(values: &[f32]) -> Tensor {
+        Tensor::inputs(vec![values.len()], values).unwrap()
+    }
+
+    fn value(node: &NodeCelled) -> f32 {
+        node.borrow().compute()
+    }
+
+    #[test]
+    fn compensates_rounding_errors() {
+        // 1 followed by a million 1e-8s: each is lost when added to 1 alone.
+        let mut args = vec![1f32];
+        args.resize(1_000_001, 1e-8);
+        let naive = Sum(Summation::Naive).compute(&args).unwrap();
+        let compensated = Sum(Summation::Compensated).compute(&args).unwrap();
+        assert_eq!(naive, 1f32);
+        assert!((compensated - 1.01).abs() < 1e-4, "{compensated}");
+
+        // Cancellation: the 1 survives only with compensation.
+        let args = [1e8f32, 1.0, -1e8];
+        assert_eq!(Sum(Summation::Naive).compute(&args).unwrap(), 0f32);
+        assert_eq!(Sum(Summation::Compensated).compute(&args).unwrap(), 1f32);
+        assert_eq!(Sum::default().compute(&[]).unwrap(), 0f32);
+        let inf = [f32::INFINITY, 1.0];
+        assert_eq!(Sum::default().compute(&inf).unwrap(), f32::INFINITY);
+    }
+
+    #[test]
+    fn sums_pairwise() {
+        let args = vec![0.1f32; 1 << 20];
+        let exact = 0.1f64 as f32 as f64 * (1 << 20) as f64;
+        let error = |summation| (Sum(summation).compute(&args).unwrap() as f64 - exact).abs();
+        assert!(error(Summation::Pairwise) < 1e-3 * error(Summation::Naive));
+        assert!(error(Summation::Pairwise) < 0.01);
+        assert_eq!(Sum(Summation::Pairwise).compute(&[1.0, 2.0]).unwrap(), 3f32);
+
+        let x = vector(&[1.0, 2.0, 3.5]);
+        assert_eq!(value(&x.sum_with(Summation::Pairwise).unwrap()), 6.5f32);
+        assert_eq!(value(&x.sum().unwrap()), 6.5f32);
+    }
+
+    #[test]
+    fn log_sum_exp_doesnt_overflow() {
+        let x = vector(&[1000.0, 1000.0]);
+        let total = value(&x.log_sum_exp().unwrap());
+        assert!((total - (1000.0 + 2f32.ln())).abs() < 1e-3);
+        let x = vector(&[-1000.0, -1000.0]);
+        let total = value(&x.log_sum_exp().unwrap());
+        assert!((total - (2f32.ln() - 1000.0)).abs() < 1e-3);
+
+        assert_eq!(LogSumExp.compute(&[]).unwrap(), f32::NEG_INFINITY);
+        assert!(LogSumExp.compute(&[1.0, f32::NAN]).unwrap().is_nan());
+        let derivatives = LogSumExp.derivatives(&[0.0, 0.0]).unwrap();
+        assert_eq!(derivatives, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn softmax_sums_to_one() {
+        let x = vector(&[1000.0, 1001.0, 999.0]);
+        let values: Vec<_> = x.softmax().unwrap().nodes().iter().map(value).collect();
+        let expected = [1f32.exp(), 2f32.exp(), 1.0];
+        let total: f32 = expected.iter().sum();
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected / total).abs() < 1e-4, "{values:?}");
+        }
+        assert!((values.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+
+        // e^10 through `Exp`, where `Pow` of `e` is 2 ulps off.
+        assert_eq!(
+            value(&Node::create_exp(Node::create_input(10f32))),
+            10f32.exp()
+        );
+    }
+
+    #[test]
+    fn variance_of_shifted_values() {
+        let x = vector(&[1e6 + 1.0, 1e6 + 2.0, 1e6 + 3.0, 1e6 + 4.0]);
+        assert_eq!(value(&x.mean().unwrap()), 1e6 + 2.5);
+        assert_eq!(value(&x.variance().unwrap()), 1.25f32);
+        assert!(value(&vector(&[]).mean().unwrap()).is_nan());
+    }
+}
@@ -0,0 +1,291 @@
+//! Named formulas loaded from files, reloaded when the files change so that
+//! running services pick up new business formulas without a restart.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::compiled::CompiledGraph;
+use crate::computational_graph::{Node, NodeCelled};
+use crate::parser::{FormulasError, Parser};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    /// A formula doesn't parse, or formulas refer to each other in a loop.
+    Formulas(FormulasError),
+    /// A line of a config file that isn't `name = formula`.
+    BadLine {
+        line: usize,
+    },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "formula registry: {e}"),
+            Self::Formulas(e) => e.fmt(f),
+            Self::BadLine { line } => write!(f, "line {line} isn't `name = formula`"),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<io::Error> for RegistryError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<FormulasError> for RegistryError {
+    fn from(e: FormulasError) -> Self {
+        Self::Formulas(e)
+    }
+}
+
+/// Compiled formulas by name.
+pub type Formulas = HashMap<String, CompiledGraph>;
+
+/// Formulas read from `path`: either a directory holding one formula per
+/// `*.formula` file, named by the file's stem, or a config file of
+/// `name = formula` lines, where blank lines and lines starting with `#`
+/// are skipped. Formulas may use other formulas by name, see
+/// `Parser::parse_formulas`, and are compiled over the `variables` passed to
+/// `open`, in order.
+///
+/// Files aren't watched: `reload` checks their modification times, and
+/// services call it as often as they want updates to show.
+#[derive(Debug)]
+pub struct FormulaRegistry {
+    path: PathBuf,
+    parser: Parser,
+    variables: Vec<NodeCelled>,
+    /// Files the formulas were read from, as they were then.
+    stamps: HashMap<PathBuf, Stamp>,
+    current: Arc<RwLock<Arc<Formulas>>>,
+}
+
+impl FormulaRegistry {
+    pub fn open(
+        path: impl AsRef<Path>,
+        parser: Parser,
+        variables: &[&str],
+    ) -> Result<Self, RegistryError> {
+        let nodes: Vec<_> = variables.iter().map(|_| Node::create_input(0.0)).collect();
+        let parser = variables
+            .iter()
+            .zip(&nodes)
+            .fold(parser, |parser, (name, node)| {
+                parser.with_variable(name, node.clone())
+            });
+        let mut res = Self {
+            path: path.as_ref().to_path_buf(),
+            parser,
+            variables: nodes,
+            stamps: HashMap::new(),
+            current: Arc::default(),
+        };
+        res.load()?;
+        Ok(res)
+    }
+
+    /// Snapshot of the formulas, unaffected by later reloads.
+    pub fn formulas(&self) -> Arc<Formulas> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Read access to the formulas for other threads, seeing each reload.
+    pub fn handle(&self) -> RegistryHandle {
+        RegistryHandle {
+            current: self.current.clone(),
+        }
+    }
+
+    /// Reads the formulas again if a file was added, changed or removed since
+    /// the last load, and says whether it did. Every formula is compiled
+    /// before the new set replaces the old one at once, so readers never see
+    /// a mix, and a bad edit leaves the previous formulas in place.
+    pub fn reload(&mut self) -> Result<bool, RegistryError> {
+        if self.scan()? == self.stamps {
+            return Ok(false);
+        }
+        self.load()?;
+        Ok(true)
+    }
+
+    fn load(&mut self) -> Result<(), RegistryError> {
+        let stamps = self.scan()?;
+        let sources = self.sources(&stamps)?;
+        let sources: Vec<_> = sources
+            .iter()
+            .map(|(name, text)| (name.as_str(), text.as_str()))
+            .collect();
+        let formulas: Formulas = self
+            .parser
+            .parse_formulas(&sources)?
+            .into_iter()
+            .map(|(name, output)| {
+                let graph = CompiledGraph::compile(&output, &self.variables);
+                (name, graph)
+            })
+            .collect();
+        *self.current.write().unwrap() = Arc::new(formulas);
+        self.stamps = stamps;
+        Ok(())
+    }
+
+    /// The files formulas are read from, as they are now.
+    fn scan(&self) -> io::Result<HashMap<PathBuf, Stamp>> {
+        let mut res = HashMap::new();
+        if !self.path.is_dir() {
+            res.insert(self.path.clone(), stamp(&self.path)?);
+            return Ok(res);
+        }
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "formula")
+            {
+                res.insert(path.clone(), stamp(&path)?);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Names and texts of the formulas in `files`. Later lines of a config
+    /// file replace earlier ones of the same name.
+    fn sources(
+        &self,
+        files: &HashMap<PathBuf, Stamp>,
+    ) -> Result<Vec<(String, String)>, RegistryError> {
+        if !self.path.is_dir() {
+            let mut res = Vec::new();
+            for (i, line) in fs::read_to_string(&self.path)?.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (name, text) = line
+                    .split_once('=')
+                    .filter(|(name, _)| !name.trim().is_empty())
+                    .ok_or(RegistryError::BadLine { line: i + 1 })?;
+                res.push((name.trim().to_string(), text.to_string()));
+            }
+            return Ok(res);
+        }
+
+        let mut res = Vec::new();
+        for path in files.keys() {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            res.push((name, fs::read_to_string(path)?));
+        }
+        Ok(res)
+    }
+}
+
+/// Modification time and length of a file, telling whether it changed even
+/// where times are coarse.
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &Path) -> io::Result<Stamp> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+/// Shared view of a `FormulaRegistry`'s formulas, cheap to clone and send to
+/// other threads.
+#[derive(Debug, Clone)]
+pub struct RegistryHandle {
+    current: Arc<RwLock<Arc<Formulas>>>,
+}
+
+impl RegistryHandle {
+    /// The formulas as of the last reload.
+    pub fn formulas(&self) -> Arc<Formulas> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<CompiledGraph> {
+        self.current.read().unwrap().get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cg-registry-{}-{name}", std::process::id()))
+    }
+
+    fn eval(formulas: &Formulas, name: &str, inputs: &[f32]) -> f32 {
+        formulas[name].eval(inputs).unwrap()
+    }
+
+    #[test]
+    fn reloads_config_files() {
+        let path = path("config");
+        fs::write(
+            &path,
+            "# prices\nnet = price * quantity\n\ngross = net * 1.5\n",
+        )
+        .unwrap();
+        let mut registry =
+            FormulaRegistry::open(&path, Parser::new(), &["price", "quantity"]).unwrap();
+        let handle = registry.handle();
+        let before = registry.formulas();
+        assert_eq!(eval(&before, "net", &[2.0, 3.0]), 6f32);
+        assert_eq!(eval(&before, "gross", &[2.0, 3.0]), 9f32);
+        assert!(!registry.reload().unwrap());
+
+        fs::write(&path, "net = price * quantity - 1\ngross = net * 1.25\n").unwrap();
+        assert!(registry.reload().unwrap());
+        assert_eq!(
+            handle.get("gross").unwrap().eval(&[2.0, 3.0]).unwrap(),
+            6.25f32
+        );
+        // Snapshots don't change.
+        assert_eq!(eval(&before, "net", &[2.0, 3.0]), 6f32);
+
+        // Bad edits leave the formulas in place.
+        fs::write(&path, "net = gross\ngross = net\n").unwrap();
+        let error = registry.reload().unwrap_err();
+        assert!(matches!(
+            error,
+            RegistryError::Formulas(FormulasError::Cycle(_))
+        ));
+        fs::write(&path, "net = price\nnot a formula\n").unwrap();
+        let error = registry.reload().unwrap_err();
+        assert_eq!(error.to_string(), "line 2 isn't `name = formula`");
+        assert_eq!(eval(&handle.formulas(), "net", &[2.0, 3.0]), 5f32);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_directories_of_formulas() {
+        let dir = path("dir");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("area.formula"), "width * height").unwrap();
+        fs::write(dir.join("volume.formula"), "area * 2").unwrap();
+        fs::write(dir.join("notes.txt"), "not a formula").unwrap();
+        let mut registry =
+            FormulaRegistry::open(&dir, Parser::new(), &["width", "height"]).unwrap();
+        let formulas = registry.formulas();
+        assert_eq!(formulas.len(), 2);
+        assert_eq!(eval(&formulas, "volume", &[3.0, 4.0]), 24f32);
+
+        fs::remove_file(dir.join("volume.formula")).unwrap();
+        assert!(registry.reload().unwrap());
+        assert!(registry.handle().get("volume").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let missing = FormulaRegistry::open(path("missing"), Parser::new(), &[]);
+        assert!(matches!(missing, Err(RegistryError::Io(_))));
+    }
+}
@@ -0,0 +1,207 @@
+//! Markdown calculation sheets, listing what a graph computes and from what.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::computational_graph::{EvalError, InputKind, Node, NodeCelled};
+
+/// Document for the graphs computing `outputs`: their inputs, named
+/// intermediate nodes and outputs, each with its formula in terms of named
+/// nodes, current value and doc string.
+#[derive(Debug, Clone)]
+pub struct Report {
+    title: String,
+    doc: Option<String>,
+    outputs: Vec<NodeCelled>,
+}
+
+impl Report {
+    pub fn new(title: impl Into<String>, outputs: Vec<NodeCelled>) -> Self {
+        Self {
+            title: title.into(),
+            doc: None,
+            outputs,
+        }
+    }
+
+    /// Text under the title, on what the graphs compute as a whole.
+    pub fn with_doc(mut self, doc: impl Into<String>) -> Self {
+        self.doc = Some(doc.into());
+        self
+    }
+
+    pub fn render(&self) -> Result<String, EvalError> {
+        let outputs: Vec<_> = self.outputs.iter().map(|output| output.borrow()).collect();
+        let outputs: Vec<&Node> = outputs.iter().map(|output| &**output).collect();
+        render(&self.title, self.doc.as_deref(), &outputs)
+    }
+}
+
+impl Node {
+    /// `Report` on the graph computing this node, titled with its name and
+    /// introduced by its doc string.
+    pub fn report(&self) -> Result<String, EvalError> {
+        let title = self.name();
+        let doc = self.doc();
+        render(
+            title.as_deref().unwrap_or("Calculation"),
+            doc.as_deref(),
+            &[self],
+        )
+    }
+}
+
+fn render(title: &str, doc: Option<&str>, outputs: &[&Node]) -> Result<String, EvalError> {
+    let output_ids: HashSet<_> = outputs.iter().map(|output| output.id()).collect();
+    let mut seen = HashSet::new();
+    let mut below = Vec::new();
+    for output in outputs {
+        for child in output.children() {
+            for node in Node::topo_order(&child) {
+                let id = node.borrow().id();
+                if !output_ids.contains(&id) && seen.insert(id) {
+                    below.push(node);
+                }
+            }
+        }
+    }
+
+    let mut inputs = Vec::new();
+    let mut named = Vec::new();
+    for node in &below {
+        let node_ref = node.borrow();
+        match &*node_ref {
+            // Unnamed constants show in the formulas already.
+            Node::Input {
+                kind: InputKind::Const,
+                ..
+            } if node_ref.name().is_none() => {}
+            Node::Input { .. } => inputs.push(node_ref),
+            _ if node_ref.name().is_some() => named.push(node_ref),
+            _ => {}
+        }
+    }
+
+    let mut out = format!("# {title}\n");
+    if let Some(doc) = doc {
+        write!(out, "\n{doc}\n").unwrap();
+    }
+
+    if !inputs.is_empty() {
+        out.push_str("\n## Inputs\n\n| Name | Value | Description |\n|---|---|---|\n");
+        for input in &inputs {
+            writeln!(
+                out,
+                "| {} | {} | {} |",
+                cell(&input.to_string()),
+                input.try_compute()?,
+                cell(input.doc().as_deref().unwrap_or(""))
+            )
+            .unwrap();
+        }
+    }
+
+    if !named.is_empty() {
+        let named: Vec<&Node> = named.iter().map(|node| &**node).collect();
+        write_formulas(&mut out, "Intermediate values", &named)?;
+    }
+    write_formulas(&mut out, "Outputs", outputs)?;
+
+    Ok(out)
+}
+
+fn write_formulas(out: &mut String, heading: &str, nodes: &[&Node]) -> Result<(), EvalError> {
+    write!(
+        out,
+        "\n## {heading}\n\n| Name | Formula | Value | Description |\n|---|---|---|---|\n"
+    )
+    .unwrap();
+    for (i, node) in nodes.iter().enumerate() {
+        let name = match node.name() {
+            Some(name) => name.to_string(),
+            None => format!("output {}", i + 1),
+        };
+        // Inputs have no formula, `Display` shows their name.
+        let formula = match node {
+            Node::Input { .. } => String::new(),
+            _ => node.to_string(),
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            cell(&name),
+            cell(&formula),
+            node.try_compute()?,
+            cell(node.doc().as_deref().unwrap_or(""))
+        )
+        .unwrap();
+    }
+
+    Ok(())
+}
+
+/// `text` as a Markdown table cell, on one line and without column breaks.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_inputs_named_nodes_and_outputs() {
+        let price = Node::create_input(10f32);
+        price.borrow().set_name("price");
+        price.borrow().set_doc("Unit price | in EUR");
+        let quantity = Node::create_input(3f32);
+        quantity.borrow().set_name("quantity");
+        let net = Node::create_mul(price, quantity);
+        net.borrow().set_name("net");
+        let gross = Node::create_mul(net, Node::create_const(1.5f32));
+        gross.borrow().set_name("gross");
+        gross.borrow().set_doc("Net plus\ntax");
+
+        let tables = "
+## Inputs
+
+| Name | Value | Description |
+|---|---|---|
+| price | 10 | Unit price \\| in EUR |
+| quantity | 3 |  |
+
+## Intermediate values
+
+| Name | Formula | Value | Description |
+|---|---|---|---|
+| net | (price * quantity) | 30 |  |
+
+## Outputs
+
+| Name | Formula | Value | Description |
+|---|---|---|---|
+| gross | (net * 1.5) | 45 | Net plus tax |
+";
+        let report = gross.borrow().report().unwrap();
+        assert_eq!(report, format!("# gross\n\nNet plus\ntax\n{tables}"));
+        let sheet = Report::new("Invoice", vec![gross]).with_doc("Totals.");
+        assert_eq!(
+            sheet.render().unwrap(),
+            format!("# Invoice\n\nTotals.\n{tables}")
+        );
+    }
+
+    #[test]
+    fn numbers_unnamed_outputs() {
+        let x = Node::create_input(2f32);
+        x.borrow().set_name("x");
+        let outputs = vec![
+            Node::create_add(x.clone(), Node::create_const(1f32)),
+            Node::create_sin(x),
+        ];
+        let report = Report::new("Two", outputs).render().unwrap();
+        assert!(report.contains("| output 1 | (x + 1) | 3 |  |"), "{report}");
+        assert!(report.contains("| output 2 | sin(x) |"), "{report}");
+        assert!(!report.contains("Intermediate"));
+    }
+}
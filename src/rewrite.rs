@@ -0,0 +1,291 @@
+//! Algebraic rewriting: rules are offered every node bottom-up, and passes
+//! repeat until none of them fires. Like the passes in `optimize`, the input
+//! graph is left untouched and the result is bound to the same `Input` nodes.
+
+use std::mem;
+use std::rc::Rc;
+
+use crate::computational_graph::{
+    BinaryOp, CachePolicy, InputKind, Node, NodeCelled, NodeId, UnaryOp,
+};
+use crate::optimize::transform;
+
+/// Passes after which `rewrite` gives up, e.g. when `Distribute` and `Factor`
+/// undo each other forever.
+pub const MAX_PASSES: usize = 64;
+
+pub trait Rule {
+    /// Replacement for `node`, whose children have been rewritten already.
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled>;
+}
+
+impl<F: Fn(&NodeCelled) -> Option<NodeCelled>> Rule for F {
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled> {
+        self(node)
+    }
+}
+
+/// Rules in priority order: the first one matching a node wins.
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every built-in rule that shrinks the graph. `Distribute` isn't one: it
+    /// undoes `Factor`.
+    pub fn simplify() -> Self {
+        Self::new()
+            .with(Pythagorean)
+            .with(CombineLikeTerms)
+            .with(Factor)
+    }
+
+    pub fn with(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+}
+
+/// Applies `rules` until a pass changes nothing, or `MAX_PASSES` times.
+pub fn rewrite(output: &NodeCelled, rules: &RuleSet) -> NodeCelled {
+    let first = NodeId::upcoming();
+    let mut current = output.clone();
+
+    for _ in 0..MAX_PASSES {
+        let next = transform(&current, &mut |node| {
+            rules.rules.iter().find_map(|rule| rule.apply(node))
+        });
+        if Rc::ptr_eq(&next, &current) {
+            break;
+        }
+        current = next;
+    }
+
+    // Results of earlier passes the last one rebuilt.
+    Node::detach_unused(first, &[output, &current]);
+    current
+}
+
+/// `a * (b + c)` into `a * b + a * c`, and likewise with the sum on the left.
+/// Don't combine it with `Factor`, which undoes it: `rewrite` would only stop
+/// after `MAX_PASSES`.
+#[derive(Debug, Clone, Copy)]
+pub struct Distribute;
+
+impl Rule for Distribute {
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled> {
+        let (a, b) = split(node, &BinaryOp::Mul)?;
+
+        if let Some((b1, b2)) = split(&b, &BinaryOp::Add) {
+            return Some(Node::create_add(
+                Node::create_mul(a.clone(), b1),
+                Node::create_mul(a, b2),
+            ));
+        }
+        let (a1, a2) = split(&a, &BinaryOp::Add)?;
+        Some(Node::create_add(
+            Node::create_mul(a1, b.clone()),
+            Node::create_mul(a2, b),
+        ))
+    }
+}
+
+/// `a * b + a * c` into `a * (b + c)`, the common factor on either side.
+#[derive(Debug, Clone, Copy)]
+pub struct Factor;
+
+impl Rule for Factor {
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled> {
+        let (p, q) = split(node, &BinaryOp::Add)?;
+        let (p1, p2) = split(&p, &BinaryOp::Mul)?;
+        let (q1, q2) = split(&q, &BinaryOp::Mul)?;
+
+        for (common, p_rest) in [(&p1, &p2), (&p2, &p1)] {
+            for (candidate, q_rest) in [(&q1, &q2), (&q2, &q1)] {
+                if same(common, candidate) {
+                    return Some(Node::create_mul(
+                        common.clone(),
+                        Node::create_add(p_rest.clone(), q_rest.clone()),
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// `c1 * x + c2 * x` into `(c1 + c2) * x` for constant `c1`, `c2` (a bare `x`
+/// counts as `1 * x`), and the sum of two constants into one constant.
+#[derive(Debug, Clone, Copy)]
+pub struct CombineLikeTerms;
+
+impl Rule for CombineLikeTerms {
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled> {
+        let (p, q) = split(node, &BinaryOp::Add)?;
+        if let (Some(p), Some(q)) = (as_const(&p), as_const(&q)) {
+            return Some(Node::create_const(p + q));
+        }
+
+        let (p_coef, p_base) = term(&p);
+        let (q_coef, q_base) = term(&q);
+        if !same(&p_base, &q_base) {
+            return None;
+        }
+
+        let coef = p_coef + q_coef;
+        if coef == 1f32 {
+            return Some(p_base);
+        }
+        Some(Node::create_mul(Node::create_const(coef), p_base))
+    }
+}
+
+/// `sin(x)^2 + cos(x)^2` into `1`, in either order. Squares may be written as
+/// `Pow(_, 2)` or as a product of a node with itself.
+#[derive(Debug, Clone, Copy)]
+pub struct Pythagorean;
+
+impl Rule for Pythagorean {
+    fn apply(&self, node: &NodeCelled) -> Option<NodeCelled> {
+        let (p, q) = split(node, &BinaryOp::Add)?;
+        let (p, q) = (square_root(&p)?, square_root(&q)?);
+
+        let (p_op, p_arg) = unary(&p)?;
+        let (q_op, q_arg) = unary(&q)?;
+        let complementary = matches!(
+            (p_op, q_op),
+            (UnaryOp::Sin, UnaryOp::Cos) | (UnaryOp::Cos, UnaryOp::Sin)
+        );
+        if !complementary || !same(&p_arg, &q_arg) {
+            return None;
+        }
+
+        Some(Node::create_const(1f32))
+    }
+}
+
+/// Operands of `node` if it's a binary node of the same kind as `op`.
+fn split(node: &NodeCelled, op: &BinaryOp) -> Option<(NodeCelled, NodeCelled)> {
+    match &*node.borrow() {
+        Node::Binary {
+            op: node_op, a, b, ..
+        } if mem::discriminant(node_op) == mem::discriminant(op) => Some((a.clone(), b.clone())),
+        _ => None,
+    }
+}
+
+fn unary(node: &NodeCelled) -> Option<(UnaryOp, NodeCelled)> {
+    match &*node.borrow() {
+        Node::Unary { op, x, .. } => Some((op.clone(), x.clone())),
+        _ => None,
+    }
+}
+
+fn as_const(node: &NodeCelled) -> Option<f32> {
+    match &*node.borrow() {
+        Node::Input {
+            x,
+            kind: InputKind::Const,
+            ..
+        } => Some(*x.borrow()),
+        _ => None,
+    }
+}
+
+/// `x` for `x^2` and `x * x`.
+fn square_root(node: &NodeCelled) -> Option<NodeCelled> {
+    if let Some((base, exponent)) = split(node, &BinaryOp::Pow(Default::default())) {
+        return (as_const(&exponent) == Some(2f32)).then_some(base);
+    }
+    let (a, b) = split(node, &BinaryOp::Mul)?;
+    same(&a, &b).then_some(a)
+}
+
+/// Splits `node` into a constant coefficient and the rest.
+fn term(node: &NodeCelled) -> (f32, NodeCelled) {
+    if let Some((a, b)) = split(node, &BinaryOp::Mul) {
+        if let Some(coef) = as_const(&a) {
+            return (coef, b);
+        }
+        if let Some(coef) = as_const(&b) {
+            return (coef, a);
+        }
+    }
+
+    (1f32, node.clone())
+}
+
+/// Structural equality. Non-constant inputs are only equal to themselves, and
+/// volatile nodes, e.g. random ones, to nothing: each use is a new draw, so
+/// `r + r` isn't `2 * r`.
+fn same(a: &NodeCelled, b: &NodeCelled) -> bool {
+    let volatile = |node: &NodeCelled| {
+        Node::topo_order(node)
+            .iter()
+            .any(|node| node.borrow().cache_policy() == CachePolicy::NoCache)
+    };
+    !volatile(a) && !volatile(b) && same_structure(a, b)
+}
+
+fn same_structure(a: &NodeCelled, b: &NodeCelled) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+
+    let (a_children, b_children) = (a.borrow().children(), b.borrow().children());
+    a.borrow().same_op(&b.borrow())
+        && a_children.len() == b_children.len()
+        && a_children
+            .iter()
+            .zip(&b_children)
+            .all(|(a, b)| same_structure(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    #[test]
+    fn volatile_nodes_are_distinct() {
+        let rng = Rng::new(1);
+        let r = Node::create_uniform(&rng, Node::create_const(0.0), Node::create_const(1.0));
+        let twice = Node::create_add(r.clone(), r.clone());
+        let rules = RuleSet::simplify();
+        assert!(Rc::ptr_eq(&rewrite(&twice, &rules), &twice));
+
+        let pythagorean = Node::create_add(
+            Node::create_pow(Node::create_sin(r.clone()), Node::create_const(2.0)),
+            Node::create_pow(Node::create_cos(r), Node::create_const(2.0)),
+        );
+        assert!(Rc::ptr_eq(&rewrite(&pythagorean, &rules), &pythagorean));
+    }
+
+    #[test]
+    fn stable_nodes_are_combined() {
+        let x = Node::create_input(3.0);
+        let twice = Node::create_add(x.clone(), x);
+        let combined = rewrite(&twice, &RuleSet::simplify());
+        assert_eq!(combined.borrow().compute(), 6.0);
+        assert!(!Rc::ptr_eq(&combined, &twice));
+    }
+
+    #[test]
+    fn simplify_reaches_a_fixpoint() {
+        let [a, b, c] = [1.0, 2.0, 3.0].map(Node::create_input);
+        let product = Node::create_mul(a.clone(), Node::create_add(b.clone(), c.clone()));
+        let expanded = Node::create_add(Node::create_mul(a.clone(), b), Node::create_mul(a, c));
+
+        let rules = RuleSet::simplify();
+        for output in [product, expanded] {
+            let simplified = rewrite(&output, &rules);
+            assert!(Rc::ptr_eq(&rewrite(&simplified, &rules), &simplified));
+        }
+    }
+}
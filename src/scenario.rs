@@ -0,0 +1,540 @@
+//! Scenario tables: named sets of input values, read from and written to CSV
+//! or JSON, and run over a graph into a table of results.
+//!
+//! In CSV, the header is `scenario` followed by the column names, and each
+//! row a scenario's name followed by its values. In JSON, a table is an array
+//! of objects, each with the scenario's name under `"scenario"` and a number
+//! per column. JSON has no NaN or infinities: they're written as `null`,
+//! which reads as NaN.
+//!
+//! ```text
+//! [{"scenario": "base", "rate": 0.05, "years": 10}]
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::computational_graph::{EvalError, NodeCelled};
+use crate::context::Context;
+
+#[derive(Debug, Clone)]
+pub enum ScenarioError {
+    /// Malformed CSV on line `line`, counting from 1.
+    Csv {
+        line: usize,
+        reason: &'static str,
+    },
+    /// Malformed JSON at byte `at`.
+    Json {
+        at: usize,
+        reason: &'static str,
+    },
+    /// `scenario` doesn't have exactly one value per column.
+    Shape {
+        scenario: String,
+    },
+    /// A column of the table matches none of the inputs it's run with.
+    UnknownInput(String),
+    Eval(EvalError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Csv { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::Json { at, reason } => write!(f, "at byte {at}: {reason}"),
+            Self::Shape { scenario } => {
+                write!(f, "scenario {scenario} doesn't have one value per column")
+            }
+            Self::UnknownInput(column) => write!(f, "no input named {column}"),
+            Self::Eval(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<EvalError> for ScenarioError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+/// Values by scenario and column: inputs for `run`, or the outputs it
+/// returns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScenarioTable {
+    columns: Vec<String>,
+    rows: Vec<(String, Vec<f32>)>,
+}
+
+impl ScenarioTable {
+    pub fn new(columns: &[&str]) -> Self {
+        Self {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds scenario `name` with one value per column.
+    pub fn push(&mut self, name: &str, values: Vec<f32>) {
+        assert_eq!(values.len(), self.columns.len(), "One value per column");
+        self.rows.push((name.to_string(), values));
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Scenario names and values, in order.
+    pub fn rows(&self) -> &[(String, Vec<f32>)] {
+        &self.rows
+    }
+
+    pub fn get(&self, scenario: &str, column: &str) -> Option<f32> {
+        let column = self.columns.iter().position(|c| c == column)?;
+        let (_, values) = self.rows.iter().find(|(name, _)| name == scenario)?;
+        Some(values[column])
+    }
+
+    /// `outputs` in each scenario, with the columns setting the `inputs` of
+    /// the same name. Scenarios are evaluated as `Context`s, so the graph's
+    /// own inputs and caches are left as they are.
+    pub fn run(
+        &self,
+        inputs: &[(&str, NodeCelled)],
+        outputs: &[(&str, NodeCelled)],
+    ) -> Result<ScenarioTable, ScenarioError> {
+        let by_name: HashMap<_, _> = inputs.iter().map(|(name, node)| (*name, node)).collect();
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                by_name
+                    .get(column.as_str())
+                    .copied()
+                    .ok_or_else(|| ScenarioError::UnknownInput(column.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let names: Vec<_> = outputs.iter().map(|(name, _)| *name).collect();
+        let mut res = ScenarioTable::new(&names);
+        for (scenario, values) in &self.rows {
+            let mut ctx = Context::new();
+            for (input, x) in columns.iter().zip(values) {
+                ctx.set(input, *x);
+            }
+            let values = outputs
+                .iter()
+                .map(|(_, output)| output.borrow().compute_in(&ctx))
+                .collect::<Result<_, _>>()?;
+            res.push(scenario, values);
+        }
+        Ok(res)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("scenario");
+        for column in &self.columns {
+            res.push(',');
+            write_csv_field(&mut res, column);
+        }
+        res.push('\n');
+        for (name, values) in &self.rows {
+            write_csv_field(&mut res, name);
+            for x in values {
+                write!(res, ",{x}").unwrap();
+            }
+            res.push('\n');
+        }
+        res
+    }
+
+    /// Reads what `to_csv` writes. Fields may be quoted, with `""` for a
+    /// quote, but may not span lines.
+    pub fn from_csv(text: &str) -> Result<Self, ScenarioError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let Some((_, header)) = lines.next() else {
+            return Err(ScenarioError::Csv {
+                line: 1,
+                reason: "missing header",
+            });
+        };
+        let header = csv_fields(header, 1)?;
+        let mut res = Self {
+            columns: header[1..].to_vec(),
+            rows: Vec::new(),
+        };
+        for (i, line) in lines {
+            let mut fields = csv_fields(line, i + 1)?.into_iter();
+            let name = fields.next().unwrap();
+            let values = fields
+                .map(|field| {
+                    field.trim().parse().map_err(|_| ScenarioError::Csv {
+                        line: i + 1,
+                        reason: "invalid number",
+                    })
+                })
+                .collect::<Result<Vec<f32>, _>>()?;
+            if values.len() != res.columns.len() {
+                return Err(ScenarioError::Shape { scenario: name });
+            }
+            res.rows.push((name, values));
+        }
+        Ok(res)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut res = String::from("[");
+        for (i, (name, values)) in self.rows.iter().enumerate() {
+            res.push_str(if i == 0 { "\n  {" } else { ",\n  {" });
+            res.push_str("\"scenario\": ");
+            write_json_string(&mut res, name);
+            for (column, x) in self.columns.iter().zip(values) {
+                res.push_str(", ");
+                write_json_string(&mut res, column);
+                if x.is_finite() {
+                    write!(res, ": {x}").unwrap();
+                } else {
+                    res.push_str(": null");
+                }
+            }
+            res.push('}');
+        }
+        res.push_str(if self.rows.is_empty() { "]\n" } else { "\n]\n" });
+        res
+    }
+
+    /// Reads what `to_json` writes. Columns are ordered as first met, and
+    /// every scenario needs a value for each.
+    pub fn from_json(text: &str) -> Result<Self, ScenarioError> {
+        let mut json = Json { text, at: 0 };
+        let objects = json.table()?;
+        json.skip_space();
+        if json.at != text.len() {
+            return Err(json.error("trailing characters"));
+        }
+
+        let mut res = Self::default();
+        for object in &objects {
+            for (key, _) in &object.1 {
+                if !res.columns.contains(key) {
+                    res.columns.push(key.clone());
+                }
+            }
+        }
+        for (name, fields) in objects {
+            let values = res
+                .columns
+                .iter()
+                .map(|column| fields.iter().find(|(key, _)| key == column).map(|f| f.1))
+                .collect::<Option<Vec<_>>>();
+            match values {
+                Some(values) if values.len() == fields.len() => res.rows.push((name, values)),
+                _ => return Err(ScenarioError::Shape { scenario: name }),
+            }
+        }
+        Ok(res)
+    }
+}
+
+pub(crate) fn write_csv_field(out: &mut String, field: &str) {
+    if field.contains([',', '"', '\n', '\r']) || field.trim() != field {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+fn csv_fields(line: &str, number: usize) -> Result<Vec<String>, ScenarioError> {
+    let error = |reason| ScenarioError::Csv {
+        line: number,
+        reason,
+    };
+    let mut res = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(error("unterminated quote")),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err(error("text after a quoted field"));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                field.push(c);
+            }
+        }
+        res.push(field);
+        if chars.next().is_none() {
+            return Ok(res);
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Just enough of a JSON reader for tables: an array of flat objects of
+/// strings and numbers.
+struct Json<'a> {
+    text: &'a str,
+    at: usize,
+}
+
+/// A scenario's name and its values by column, in the object's order.
+type Object = (String, Vec<(String, f32)>);
+
+impl Json<'_> {
+    fn error(&self, reason: &'static str) -> ScenarioError {
+        ScenarioError::Json {
+            at: self.at,
+            reason,
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.at..];
+        self.at += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.text[self.at..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.at += c.len_utf8();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char, reason: &'static str) -> Result<(), ScenarioError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(reason))
+        }
+    }
+
+    fn table(&mut self) -> Result<Vec<Object>, ScenarioError> {
+        self.expect('[', "expected `[`")?;
+        let mut res = Vec::new();
+        if self.eat(']') {
+            return Ok(res);
+        }
+        loop {
+            res.push(self.object()?);
+            if self.eat(']') {
+                return Ok(res);
+            }
+            self.expect(',', "expected `,` or `]`")?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Object, ScenarioError> {
+        let start = self.at;
+        self.expect('{', "expected `{`")?;
+        let mut name = None;
+        let mut fields: Vec<(String, f32)> = Vec::new();
+        if !self.eat('}') {
+            loop {
+                let key = self.string()?;
+                self.expect(':', "expected `:`")?;
+                if key == "scenario" {
+                    name = Some(self.string()?);
+                } else {
+                    if fields.iter().any(|(other, _)| *other == key) {
+                        return Err(self.error("duplicate key"));
+                    }
+                    let value = self.number()?;
+                    fields.push((key, value));
+                }
+                if self.eat('}') {
+                    break;
+                }
+                self.expect(',', "expected `,` or `}`")?;
+            }
+        }
+        let name = name.ok_or(ScenarioError::Json {
+            at: start,
+            reason: "missing \"scenario\"",
+        })?;
+        Ok((name, fields))
+    }
+
+    fn string(&mut self) -> Result<String, ScenarioError> {
+        self.expect('"', "expected a string")?;
+        let mut res = String::new();
+        let mut chars = self.text[self.at..].char_indices();
+        loop {
+            let Some((i, c)) = chars.next() else {
+                self.at = self.text.len();
+                return Err(self.error("unterminated string"));
+            };
+            match c {
+                '"' => {
+                    self.at += i + 1;
+                    return Ok(res);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| {
+                                    self.at += i;
+                                    self.error("invalid escape")
+                                })?
+                        }
+                        _ => {
+                            self.at += i;
+                            return Err(self.error("invalid escape"));
+                        }
+                    };
+                    res.push(escaped);
+                }
+                c => res.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, ScenarioError> {
+        self.skip_space();
+        let rest = &self.text[self.at..];
+        if let Some(after) = rest.strip_prefix("null") {
+            self.at = self.text.len() - after.len();
+            return Ok(f32::NAN);
+        }
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(rest.len());
+        let x = rest[..len]
+            .parse()
+            .map_err(|_| self.error("expected a number"))?;
+        self.at += len;
+        Ok(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    fn table() -> ScenarioTable {
+        let mut table = ScenarioTable::new(&["rate", "years"]);
+        table.push("base", vec![0.5, 2.0]);
+        table.push("stress, high", vec![1.0, 3.0]);
+        table
+    }
+
+    #[test]
+    fn runs_scenarios_leaving_the_graph_alone() {
+        let (rate, years) = (Node::create_input(0f32), Node::create_input(1f32));
+        // Growth of 1 at `rate` over `years`.
+        let growth = Node::create_pow(
+            Node::create_add(Node::create_const(1f32), rate.clone()),
+            years.clone(),
+        );
+        assert_eq!(growth.borrow().compute(), 1f32);
+
+        let inputs = [("rate", rate.clone()), ("years", years.clone())];
+        let res = table().run(&inputs, &[("growth", growth.clone())]).unwrap();
+        assert_eq!(res.columns(), ["growth"]);
+        assert_eq!(res.get("base", "growth"), Some(2.25));
+        assert_eq!(res.get("stress, high", "growth"), Some(8.0));
+        assert_eq!(res.get("other", "growth"), None);
+
+        assert_eq!(growth.borrow().compute(), 1f32);
+        assert_eq!(rate.borrow().cached_value(), Some(0f32));
+        let res = table().run(&inputs[..1], &[("growth", growth)]);
+        assert!(matches!(res, Err(ScenarioError::UnknownInput(column)) if column == "years"));
+    }
+
+    #[test]
+    fn round_trips_through_csv() {
+        let csv = table().to_csv();
+        assert_eq!(
+            csv,
+            "scenario,rate,years\nbase,0.5,2\n\"stress, high\",1,3\n"
+        );
+        assert_eq!(ScenarioTable::from_csv(&csv).unwrap(), table());
+
+        let res = ScenarioTable::from_csv("scenario,rate\nbase,0.5,2\n");
+        assert!(matches!(res, Err(ScenarioError::Shape { scenario }) if scenario == "base"));
+        let res = ScenarioTable::from_csv("scenario,rate\n\nbase,high\n");
+        assert_eq!(res.unwrap_err().to_string(), "line 3: invalid number");
+        let res = ScenarioTable::from_csv("scenario,rate\n\"base,1\n");
+        assert_eq!(res.unwrap_err().to_string(), "line 2: unterminated quote");
+        assert!(ScenarioTable::from_csv("").is_err());
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut table = table();
+        table.push("unknown", vec![f32::NAN, 1.0]);
+        let json = table.to_json();
+        assert!(json.contains("{\"scenario\": \"base\", \"rate\": 0.5, \"years\": 2}"));
+        assert!(json.contains("\"rate\": null"));
+        let read = ScenarioTable::from_json(&json).unwrap();
+        assert_eq!(read.rows()[..2], table.rows()[..2]);
+        assert!(read.get("unknown", "rate").unwrap().is_nan());
+        assert_eq!(
+            ScenarioTable::from_json("[]").unwrap(),
+            ScenarioTable::default()
+        );
+
+        let res = ScenarioTable::from_json(r#"[{"scenario": "a", "x": 1}, {"scenario": "b"}]"#);
+        assert!(matches!(res, Err(ScenarioError::Shape { scenario }) if scenario == "b"));
+        let res = ScenarioTable::from_json(r#"[{"x": 1}]"#);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "at byte 1: missing \"scenario\""
+        );
+        let res = ScenarioTable::from_json(r#"[{"scenario": "a", "x": 1, "x": 2}]"#);
+        assert_eq!(res.unwrap_err().to_string(), "at byte 31: duplicate key");
+        let res = ScenarioTable::from_json("[] x");
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "at byte 3: trailing characters"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! Parallel evaluation of compiled graphs on a work-stealing thread pool.
+//!
+//! The tape is cut into tasks by dependency level: instructions of one level
+//! don't read each other, so each level is split into up to one chunk per
+//! worker. A task is queued once every task it reads from has finished.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::compiled::{CompiledGraph, Instr};
+use crate::computational_graph::{CachePolicy, EvalError, NodeId};
+
+/// Levels smaller than this are not split further.
+const MIN_TASK_LEN: usize = 64;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct PoolShared {
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    next_queue: AtomicUsize,
+    idle: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl PoolShared {
+    /// Own queue first (LIFO end), then the other workers' oldest jobs.
+    fn find_job(&self, own: usize) -> Option<Job> {
+        if let Some(job) = self.queues[own].lock().unwrap().pop_back() {
+            return Some(job);
+        }
+        (1..self.queues.len())
+            .map(|offset| (own + offset) % self.queues.len())
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+    }
+
+    fn submit(&self, job: Job) {
+        let queue = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        self.queues[queue].lock().unwrap().push_back(job);
+        let _idle = self.idle.lock().unwrap();
+        self.wake.notify_one();
+    }
+}
+
+pub struct ThreadPool {
+    shared: Arc<PoolShared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "Thread pool needs at least one thread");
+
+        let shared = Arc::new(PoolShared {
+            queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            next_queue: AtomicUsize::new(0),
+            idle: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..threads)
+            .map(|own| {
+                let shared = shared.clone();
+                thread::spawn(move || worker_loop(&shared, own))
+            })
+            .collect();
+
+        Self { shared, workers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        let idle = self.shared.idle.lock().unwrap();
+        self.shared.wake.notify_all();
+        drop(idle);
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_loop(shared: &PoolShared, own: usize) {
+    loop {
+        if let Some(job) = shared.find_job(own) {
+            job();
+            continue;
+        }
+
+        // Submitters notify under `idle`, so re-checking while holding it
+        // can't miss a wake-up.
+        let idle = shared.idle.lock().unwrap();
+        if shared.shutdown.load(Ordering::Acquire) {
+            return;
+        }
+        if let Some(job) = shared.find_job(own) {
+            drop(idle);
+            job();
+            continue;
+        }
+        drop(shared.wake.wait(idle).unwrap());
+    }
+}
+
+/// Why an instruction produced no value.
+enum Failure {
+    Error(EvalError),
+    /// A custom op panicked, with this payload.
+    Panic(Box<dyn Any + Send>),
+}
+
+struct Task {
+    instrs: Vec<usize>,
+    successors: Vec<usize>,
+    predecessors: usize,
+}
+
+/// State of one `compute_scheduled` call, shared by its tasks.
+struct Run {
+    instrs: Arc<[Instr]>,
+    ids: Arc<[NodeId]>,
+    inputs: Vec<f32>,
+    tasks: Vec<Task>,
+    slots: Vec<AtomicU32>,
+    pending: Vec<AtomicUsize>,
+    remaining: AtomicUsize,
+    /// The failing instruction earliest on the tape, which `eval` would
+    /// report or panic at, and how it failed.
+    error: Mutex<Option<(usize, Failure)>>,
+    done: Mutex<Option<mpsc::Sender<()>>>,
+}
+
+impl Run {
+    fn execute(self: &Arc<Self>, task: usize, pool: &Arc<PoolShared>) {
+        // Instructions after a failed one are skipped. Operands of a failed
+        // instruction may be garbage, but then an error earlier on the tape
+        // is reported anyway.
+        let failed_at = self.error.lock().unwrap().as_ref().map(|(at, _)| *at);
+        for &i in &self.tasks[task].instrs {
+            if failed_at.is_some_and(|at| i > at) {
+                break;
+            }
+            // Caught, so that the run still finishes and the caller panics
+            // instead of waiting forever.
+            let value = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.instrs[i].eval(self.ids[i], &self.inputs, |slot| {
+                    f32::from_bits(self.slots[slot].load(Ordering::Relaxed))
+                })
+            }));
+            let failure = match value {
+                Ok(Ok(value)) => {
+                    self.slots[i].store(value.to_bits(), Ordering::Relaxed);
+                    continue;
+                }
+                Ok(Err(e)) => Failure::Error(e),
+                Err(payload) => Failure::Panic(payload),
+            };
+            let mut error = self.error.lock().unwrap();
+            if error.as_ref().is_none_or(|(at, _)| i < *at) {
+                *error = Some((i, failure));
+            }
+            break;
+        }
+
+        for &successor in &self.tasks[task].successors {
+            if self.pending[successor].fetch_sub(1, Ordering::AcqRel) == 1 {
+                self.spawn(successor, pool);
+            }
+        }
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            if let Some(done) = self.done.lock().unwrap().take() {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    fn spawn(self: &Arc<Self>, task: usize, pool: &Arc<PoolShared>) {
+        let run = self.clone();
+        let pool_ref = pool.clone();
+        pool.submit(Box::new(move || run.execute(task, &pool_ref)));
+    }
+}
+
+impl CompiledGraph {
+    /// Same result as `eval`, bit for bit and including the error reported,
+    /// with independent instructions spread over `pool`. Graphs with `NoCache`
+    /// custom ops, such as random ones, depend on the order of evaluation and
+    /// are evaluated on the calling thread instead. A panicking custom op
+    /// panics on the calling thread, as with `eval`.
+    pub fn compute_scheduled(&self, inputs: &[f32], pool: &ThreadPool) -> Result<f32, EvalError> {
+        assert_eq!(
+            inputs.len(),
+            self.input_count(),
+            "Wrong number of input values"
+        );
+        let ordered = self.instrs.iter().any(|instr| {
+            matches!(instr, Instr::Custom(op, _) if op.cache_policy() == CachePolicy::NoCache)
+        });
+        if ordered {
+            return self.eval(inputs);
+        }
+
+        let tasks = partition(&self.instrs, pool.threads());
+        let (done, finished) = mpsc::channel();
+        let run = Arc::new(Run {
+            instrs: self.instrs.clone(),
+            ids: self.ids.clone(),
+            inputs: inputs.to_vec(),
+            pending: tasks
+                .iter()
+                .map(|t| AtomicUsize::new(t.predecessors))
+                .collect(),
+            remaining: AtomicUsize::new(tasks.len()),
+            slots: (0..self.instrs.len()).map(|_| AtomicU32::new(0)).collect(),
+            tasks,
+            error: Mutex::new(None),
+            done: Mutex::new(Some(done)),
+        });
+
+        for (i, task) in run.tasks.iter().enumerate() {
+            if task.predecessors == 0 {
+                run.spawn(i, &pool.shared);
+            }
+        }
+        finished.recv().expect("scheduled evaluation was dropped");
+
+        match run.error.lock().unwrap().take() {
+            Some((_, Failure::Error(e))) => return Err(e),
+            Some((_, Failure::Panic(payload))) => panic::resume_unwind(payload),
+            None => {}
+        }
+        let output = &run.slots[self.instrs.len() - 1];
+        Ok(f32::from_bits(output.load(Ordering::Acquire)))
+    }
+}
+
+fn partition(instrs: &[Instr], threads: usize) -> Vec<Task> {
+    let mut levels: Vec<usize> = Vec::with_capacity(instrs.len());
+    for instr in instrs {
+        let level = instr.operands().iter().map(|&op| levels[op] + 1).max();
+        levels.push(level.unwrap_or(0));
+    }
+
+    let depth = levels.iter().max().map_or(0, |max| max + 1);
+    let mut by_level = vec![Vec::new(); depth];
+    for (i, &level) in levels.iter().enumerate() {
+        by_level[level].push(i);
+    }
+
+    let mut tasks = Vec::new();
+    let mut task_of = vec![0; instrs.len()];
+    for level in by_level {
+        let chunk_len = level.len().div_ceil(threads).max(MIN_TASK_LEN);
+        for chunk in level.chunks(chunk_len) {
+            for &i in chunk {
+                task_of[i] = tasks.len();
+            }
+            tasks.push(Task {
+                instrs: chunk.to_vec(),
+                successors: Vec::new(),
+                predecessors: 0,
+            });
+        }
+    }
+
+    for (i, instr) in instrs.iter().enumerate() {
+        for op in instr.operands() {
+            let (from, to) = (task_of[op], task_of[i]);
+            if !tasks[from].successors.contains(&to) {
+                tasks[from].successors.push(to);
+                tasks[to].predecessors += 1;
+            }
+        }
+    }
+
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::{CustomOp, Node, NodeCelled, PowPolicy};
+
+    #[derive(Debug)]
+    struct Explode;
+
+    impl CustomOp for Explode {
+        fn name(&self) -> &str {
+            "explode"
+        }
+
+        fn compute(&self, _: &[f32]) -> Result<f32, String> {
+            panic!("explode")
+        }
+    }
+
+    /// A wide graph, so that it's split into several tasks.
+    fn wide(x: &NodeCelled, width: usize) -> NodeCelled {
+        (0..width)
+            .map(|i| Node::create_mul(x.clone(), Node::create_const(i as f32)))
+            .reduce(Node::create_add)
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_eval() {
+        let pool = ThreadPool::new(3);
+        let x = Node::create_input(0.0);
+        let compiled = CompiledGraph::compile(&wide(&x, 500), std::slice::from_ref(&x));
+        for input in [0.0, 1.5, -2.0] {
+            let expected = compiled.eval(&[input]).unwrap();
+            let res = compiled.compute_scheduled(&[input], &pool).unwrap();
+            assert_eq!(res.to_bits(), expected.to_bits());
+        }
+    }
+
+    #[test]
+    fn reports_first_error() {
+        let pool = ThreadPool::new(2);
+        let x = Node::create_input(0.0);
+        let zero = Node::create_const(0.0);
+        let strict = |exponent| {
+            let exponent = Node::create_const(exponent);
+            Node::create_strict_pow(zero.clone(), exponent, PowPolicy::Error)
+        };
+        let output = Node::create_add(wide(&x, 200), Node::create_add(strict(-1.0), strict(-2.0)));
+        let compiled = CompiledGraph::compile(&output, std::slice::from_ref(&x));
+        let expected = compiled.eval(&[1.0]).unwrap_err();
+        let res = compiled.compute_scheduled(&[1.0], &pool).unwrap_err();
+        assert_eq!(res.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn panicking_op_panics_caller() {
+        let pool = ThreadPool::new(2);
+        let x = Node::create_input(0.0);
+        let exploding = Node::create_custom(Arc::new(Explode), vec![x.clone()]);
+        let output = Node::create_add(wide(&x, 200), exploding);
+        let compiled = CompiledGraph::compile(&output, std::slice::from_ref(&x));
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            compiled.compute_scheduled(&[1.0], &pool)
+        }));
+        assert!(res.is_err());
+
+        // The workers survive.
+        let compiled = CompiledGraph::compile(&wide(&x, 200), std::slice::from_ref(&x));
+        assert!(compiled.compute_scheduled(&[1.0], &pool).is_ok());
+    }
+}
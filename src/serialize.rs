@@ -0,0 +1,1253 @@
+//! Line-based text format for graphs:
+//!
+//! ```text
+//! computational-graph 1
+//! 0 input 1.5
+//! 1 const 2.0
+//! 2 pow error 0 1
+//! 3 round 2 half-even 2
+//! output 3
+//! ```
+//!
+//! Every node line is `<id> <op> [<params>] [<operand ids>]`. `serialize`
+//! lists operands before their users, but readers accept any order. Subgraphs
+//! are written as
+//! `subgraph <id> <name> <input count> <input ids> <output ids>` after their
+//! nodes, and referenced by `composite <subgraph id> <port> <args>`. Names are
+//! percent-encoded, `-` stands for no name. `feed <node id> <source id>`
+//! connects a `delay` or `accumulate` node to the node it samples on `step`.
+//! `name <node id> <name>` and `doc <node id> <doc>` follow named and
+//! documented nodes, percent-encoded too. Node names must be unique.
+//!
+//! New ops don't change the version: older files stay valid, and older crates
+//! reject the op by name. Changes to how existing ops are written bump it, with
+//! a step in `MIGRATIONS` that rewrites files of the previous version.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::computational_graph::{
+    Accumulation, BinaryOp, Comparison, CustomOp, InputKind, Node, NodeCelled, PowPolicy,
+    RoundMode, Subgraph, TernaryOp, UnaryOp,
+};
+
+const MAGIC: &str = "computational-graph";
+
+/// Tokens of one line, after the header.
+type Record = Vec<String>;
+
+/// `MIGRATIONS[i]` rewrites the records of a version `i + 1` file into
+/// version `i + 2`. Version 1 is the first format, nothing to migrate yet.
+const MIGRATIONS: &[fn(&mut Vec<Record>)] = &[];
+
+/// Version written by `serialize`. Files of any earlier version load too.
+pub const FORMAT_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    /// The text doesn't start with a `computational-graph <version>` line.
+    MissingHeader,
+    /// Written by a newer crate, in a format this one can't read.
+    UnsupportedVersion {
+        version: u32,
+    },
+    /// An op this crate doesn't know, likely added by a newer one.
+    UnknownOp {
+        line: usize,
+        op: String,
+    },
+    /// A custom op not registered with the `Deserializer`.
+    UnknownCustomOp {
+        line: usize,
+        name: String,
+    },
+    Malformed {
+        line: usize,
+        reason: &'static str,
+    },
+    /// Readable, but not a well-formed graph, see `validate`.
+    Invalid(Vec<Violation>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(f, "not a serialized graph"),
+            Self::UnsupportedVersion { version } => write!(
+                f,
+                "format version {version} is newer than the supported {FORMAT_VERSION}"
+            ),
+            Self::UnknownOp { line, op } => write!(f, "line {line}: unknown op \"{op}\""),
+            Self::UnknownCustomOp { line, name } => {
+                write!(f, "line {line}: unregistered custom op \"{name}\"")
+            }
+            Self::Malformed { line, reason } => write!(f, "line {line}: {reason}"),
+            Self::Invalid(violations) => {
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{violation}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Writes the graphs computing `outputs`, in the current `FORMAT_VERSION`.
+/// Bound inputs are written as plain inputs with their current value, cache
+/// policies aren't written. Graphs with two nodes of the same name are
+/// written, but don't `validate`.
+pub fn serialize(outputs: &[NodeCelled]) -> String {
+    let mut out = format!("{MAGIC} {FORMAT_VERSION}\n");
+    for item in items(outputs) {
+        writeln!(out, "{item}").unwrap();
+    }
+    out
+}
+
+/// What `serialize` writes, one item per line.
+pub(crate) fn items(outputs: &[NodeCelled]) -> Vec<Item> {
+    let mut writer = Writer::default();
+    for output in outputs {
+        writer.write_graph(output);
+    }
+    // Feeds may lead to more delays, and so on.
+    let mut fed = 0;
+    while let Some(delay) = writer.delays.get(fed).cloned() {
+        fed += 1;
+        let feed = match &*delay.borrow() {
+            Node::Input { feed, .. } => feed.borrow().clone(),
+            _ => unreachable!(),
+        };
+        if let Some(feed) = feed {
+            writer.write_graph(&feed);
+            writer.items.push(Item::Feed {
+                line: 0,
+                delay: writer.nodes[&Rc::as_ptr(&delay)],
+                source: writer.nodes[&Rc::as_ptr(&feed)],
+            });
+        }
+    }
+    for output in outputs {
+        let id = writer.nodes[&Rc::as_ptr(output)];
+        writer.items.push(Item::Output { line: 0, id });
+    }
+
+    writer.items
+}
+
+#[derive(Default)]
+struct Writer {
+    items: Vec<Item>,
+    nodes: HashMap<*const std::cell::RefCell<Node>, usize>,
+    subgraphs: HashMap<*const Subgraph, usize>,
+    /// Delays and accumulators written so far, whose feeds are written after
+    /// the graphs.
+    delays: Vec<NodeCelled>,
+}
+
+impl Writer {
+    fn write_graph(&mut self, output: &NodeCelled) {
+        for node in Node::topo_order(output) {
+            if self.nodes.contains_key(&Rc::as_ptr(&node)) {
+                continue;
+            }
+            if let Node::Composite { graph, .. } = &*node.borrow() {
+                self.write_subgraph(graph);
+            }
+
+            let id = self.nodes.len();
+            let item = self.node_item(id, &node.borrow());
+            self.items.push(item);
+            self.nodes.insert(Rc::as_ptr(&node), id);
+            if let Some(name) = node.borrow().name() {
+                self.items.push(Item::Name {
+                    line: 0,
+                    id,
+                    name: name.to_string(),
+                });
+            }
+            if let Some(doc) = node.borrow().doc() {
+                self.items.push(Item::Doc {
+                    line: 0,
+                    id,
+                    doc: doc.to_string(),
+                });
+            }
+            if let Node::Input {
+                kind: InputKind::Delay | InputKind::Accumulate(_),
+                ..
+            } = &*node.borrow()
+            {
+                self.delays.push(node.clone());
+            }
+        }
+    }
+
+    fn write_subgraph(&mut self, graph: &Rc<Subgraph>) {
+        if self.subgraphs.contains_key(&Rc::as_ptr(graph)) {
+            return;
+        }
+        // Inputs first: a port may not depend on every one of them.
+        for node in graph.inputs().iter().chain(graph.outputs()) {
+            self.write_graph(node);
+        }
+
+        let id = self.subgraphs.len();
+        let ids = |nodes: &[NodeCelled]| {
+            nodes
+                .iter()
+                .map(|node| self.nodes[&Rc::as_ptr(node)])
+                .collect()
+        };
+        self.items.push(Item::Subgraph {
+            line: 0,
+            id,
+            name: graph.name().map(str::to_string),
+            inputs: ids(graph.inputs()),
+            outputs: ids(graph.outputs()),
+        });
+        self.subgraphs.insert(Rc::as_ptr(graph), id);
+    }
+
+    fn node_item(&self, id: usize, node: &Node) -> Item {
+        let op = match node {
+            Node::Input { x, kind, .. } => Op::Input(*kind, *x.borrow()),
+            Node::Binary { op, .. } => Op::Binary(op.clone()),
+            Node::Unary { op, .. } => Op::Unary(op.clone()),
+            Node::Ternary { op, .. } => Op::Ternary(op.clone()),
+            Node::Custom { op, .. } => Op::Custom(op.name().to_string()),
+            Node::Composite { graph, port, .. } => Op::Composite {
+                graph: self.subgraphs[&Rc::as_ptr(graph)],
+                port: *port,
+            },
+        };
+
+        Item::Node {
+            line: 0,
+            id,
+            op,
+            operands: node
+                .children()
+                .iter()
+                .map(|child| self.nodes[&Rc::as_ptr(child)])
+                .collect(),
+        }
+    }
+}
+
+/// Structural problem found by `validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A node with the wrong number of operands.
+    Arity {
+        line: usize,
+        op: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A reference to a node defined nowhere in the file.
+    Dangling { line: usize, id: usize },
+    /// A composite referring to a subgraph defined nowhere in the file.
+    UnknownSubgraph { line: usize, id: usize },
+    /// A second definition of a node or subgraph id; the first one is kept.
+    DuplicateId { line: usize, id: usize },
+    /// Node `id` is among its own operands, directly or through others.
+    Cycle { line: usize, id: usize },
+    /// A subgraph input that isn't a settable `input` or `time` node.
+    NotSettable { line: usize, id: usize },
+    /// A composite reading a port its subgraph doesn't have.
+    NoSuchPort { line: usize, port: usize },
+    /// A feed for node `id`, which isn't a `delay` or `accumulate`.
+    NotDelay { line: usize, id: usize },
+    /// A second node named `name`.
+    DuplicateName { line: usize, name: String },
+    /// A second name or doc for node `id`; the first one is kept.
+    Relabeled { line: usize, id: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arity {
+                line,
+                op,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: {op} takes {expected} operands, found {found}"
+            ),
+            Self::Dangling { line, id } => write!(f, "line {line}: node {id} is not defined"),
+            Self::UnknownSubgraph { line, id } => {
+                write!(f, "line {line}: subgraph {id} is not defined")
+            }
+            Self::DuplicateId { line, id } => write!(f, "line {line}: {id} is defined twice"),
+            Self::Cycle { line, id } => write!(f, "line {line}: node {id} depends on itself"),
+            Self::NotSettable { line, id } => {
+                write!(f, "line {line}: subgraph input {id} is not settable")
+            }
+            Self::NoSuchPort { line, port } => write!(f, "line {line}: no output port {port}"),
+            Self::NotDelay { line, id } => write!(f, "line {line}: node {id} can't be fed"),
+            Self::DuplicateName { line, name } => {
+                write!(f, "line {line}: another node is named \"{name}\"")
+            }
+            Self::Relabeled { line, id } => {
+                write!(f, "line {line}: node {id} already has a name or doc")
+            }
+        }
+    }
+}
+
+/// Every structural problem of a serialized graph: op arities, references to
+/// undefined nodes, duplicate ids and names, and cycles. `Deserializer::deserialize`
+/// runs this first and only builds violation-free graphs. Fails only if the
+/// text can't be read at all.
+pub fn validate(text: &str) -> Result<Vec<Violation>, LoadError> {
+    Ok(check(&parse(text)?).0)
+}
+
+/// Reads graphs written by `serialize`, resolving custom ops by name.
+#[derive(Debug, Default)]
+pub struct Deserializer {
+    custom: HashMap<String, Arc<dyn CustomOp>>,
+}
+
+impl Deserializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_custom(mut self, op: Arc<dyn CustomOp>) -> Self {
+        self.custom.insert(op.name().to_string(), op);
+        self
+    }
+
+    /// The output nodes, in the order they were serialized. Definitions may
+    /// come in any order.
+    pub fn deserialize(&self, text: &str) -> Result<Vec<NodeCelled>, LoadError> {
+        self.build_items(&parse(text)?)
+    }
+
+    /// The outputs of `items`, in the order of their `Item::Output`s.
+    pub(crate) fn build_items(&self, items: &[Item]) -> Result<Vec<NodeCelled>, LoadError> {
+        let (violations, order) = check(items);
+        if !violations.is_empty() {
+            return Err(LoadError::Invalid(violations));
+        }
+
+        let nodes = items.nodes();
+        let subgraphs = items.subgraphs();
+        let mut built: HashMap<usize, NodeCelled> = HashMap::new();
+        let mut graphs: HashMap<usize, Rc<Subgraph>> = HashMap::new();
+        for key in order {
+            match key {
+                Key::Node(id) => {
+                    let (line, op, operands) = nodes[&id];
+                    let node = self.build(line, op, operands, &built, &graphs)?;
+                    built.insert(id, node);
+                }
+                Key::Subgraph(id) => {
+                    let (_, name, inputs, outputs) = subgraphs[&id];
+                    let lookup = |ids: &[usize]| ids.iter().map(|id| built[id].clone()).collect();
+                    let graph = match name {
+                        Some(name) => Subgraph::named(name, lookup(inputs), lookup(outputs)),
+                        None => Subgraph::new(lookup(inputs), lookup(outputs)),
+                    };
+                    graphs.insert(id, graph);
+                }
+            }
+        }
+
+        for item in items {
+            match item {
+                Item::Feed { delay, source, .. } => {
+                    built[delay].borrow().feed(built[source].clone());
+                }
+                Item::Name { id, name, .. } => built[id].borrow().set_name(name.as_str()),
+                Item::Doc { id, doc, .. } => built[id].borrow().set_doc(doc.as_str()),
+                _ => {}
+            }
+        }
+
+        Ok(items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Output { id, .. } => Some(built[id].clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn build(
+        &self,
+        line: usize,
+        op: &Op,
+        operands: &[usize],
+        built: &HashMap<usize, NodeCelled>,
+        graphs: &HashMap<usize, Rc<Subgraph>>,
+    ) -> Result<NodeCelled, LoadError> {
+        let mut operands = operands.iter().map(|id| built[id].clone());
+        let mut next = || operands.next().unwrap();
+
+        Ok(match op {
+            // Feeds of delays and accumulators come with the `feed` lines.
+            Op::Input(kind, x) => Node::create_input_node(*kind, *x, None),
+            Op::Binary(op) => Node::create_binary_node(op.clone(), next(), next()),
+            Op::Unary(op) => Node::create_unary_node(op.clone(), next()),
+            Op::Ternary(op) => Node::create_ternary_node(op.clone(), next(), next(), next()),
+            Op::Custom(name) => {
+                let op =
+                    self.custom
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| LoadError::UnknownCustomOp {
+                            line,
+                            name: name.clone(),
+                        })?;
+                Node::create_custom(op, operands.collect())
+            }
+            Op::Composite { graph, port } => {
+                Node::create_composite_node(graphs[graph].clone(), *port, operands.collect())
+            }
+        })
+    }
+}
+
+pub(crate) enum Op {
+    Input(InputKind, f32),
+    Binary(BinaryOp),
+    Unary(UnaryOp),
+    Ternary(TernaryOp),
+    Custom(String),
+    Composite { graph: usize, port: usize },
+}
+
+impl Op {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Input(kind, _) => match kind {
+                InputKind::Value => "input",
+                InputKind::Time => "time",
+                InputKind::Const => "const",
+                InputKind::Delay => "delay",
+                InputKind::Accumulate(_) => "accumulate",
+            },
+            Self::Binary(op) => match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Mul => "mul",
+                BinaryOp::Pow(_) => "pow",
+                BinaryOp::And => "and",
+                BinaryOp::Or => "or",
+                BinaryOp::Compare(_) => "compare",
+            },
+            Self::Unary(op) => match op {
+                UnaryOp::Sin => "sin",
+                UnaryOp::Cos => "cos",
+                UnaryOp::Not => "not",
+                UnaryOp::Round { .. } => "round",
+            },
+            Self::Ternary(op) => match op {
+                TernaryOp::MulAdd => "mul-add",
+                TernaryOp::Select => "select",
+            },
+            Self::Custom(_) => "custom",
+            Self::Composite { .. } => "composite",
+        }
+    }
+
+    /// Operand count, where it's fixed by the op alone.
+    fn arity(&self) -> Option<usize> {
+        match self {
+            Self::Input(..) => Some(0),
+            Self::Binary(_) => Some(2),
+            Self::Unary(_) => Some(1),
+            Self::Ternary(_) => Some(3),
+            Self::Custom(_) | Self::Composite { .. } => None,
+        }
+    }
+}
+
+/// The op's part of a node line: its name and parameters.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())?;
+        match self {
+            Self::Input(InputKind::Accumulate(accumulation), x) => {
+                write!(f, " {} {x:?}", accumulation_name(*accumulation))
+            }
+            Self::Input(_, x) => write!(f, " {x:?}"),
+            Self::Binary(BinaryOp::Pow(policy)) => write!(f, " {}", pow_policy_name(*policy)),
+            Self::Binary(BinaryOp::Compare(cmp)) => write!(f, " {}", comparison_name(*cmp)),
+            Self::Unary(UnaryOp::Round { digits, mode }) => {
+                write!(f, " {digits} {}", round_mode_name(*mode))
+            }
+            Self::Custom(name) => write!(f, " {}", encode(name)),
+            Self::Composite { graph, port } => write!(f, " {graph} {port}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A line of the file, without the line break.
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ids, rest) = match self {
+            Self::Node {
+                id, op, operands, ..
+            } => {
+                write!(f, "{id} {op}")?;
+                (operands, &[][..])
+            }
+            Self::Subgraph {
+                id,
+                name,
+                inputs,
+                outputs,
+                ..
+            } => {
+                let name = name.as_deref().map_or("-".to_string(), encode);
+                write!(f, "subgraph {id} {name} {}", inputs.len())?;
+                (inputs, &outputs[..])
+            }
+            Self::Output { id, .. } => return write!(f, "output {id}"),
+            Self::Feed { delay, source, .. } => return write!(f, "feed {delay} {source}"),
+            Self::Name { id, name, .. } => return write!(f, "name {id} {}", encode(name)),
+            Self::Doc { id, doc, .. } => return write!(f, "doc {id} {}", encode(doc)),
+        };
+        for id in ids.iter().chain(rest) {
+            write!(f, " {id}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of the file, read but not checked. Items not read from a file
+/// have `line` 0, or a position in whatever they were read from.
+pub(crate) enum Item {
+    Node {
+        line: usize,
+        id: usize,
+        op: Op,
+        operands: Vec<usize>,
+    },
+    Subgraph {
+        line: usize,
+        id: usize,
+        name: Option<String>,
+        inputs: Vec<usize>,
+        outputs: Vec<usize>,
+    },
+    Output {
+        line: usize,
+        id: usize,
+    },
+    /// Not an operand: delays may be fed from nodes that depend on them.
+    Feed {
+        line: usize,
+        delay: usize,
+        source: usize,
+    },
+    Name {
+        line: usize,
+        id: usize,
+        name: String,
+    },
+    Doc {
+        line: usize,
+        id: usize,
+        doc: String,
+    },
+}
+
+type NodeItem<'a> = (usize, &'a Op, &'a [usize]);
+type SubgraphItem<'a> = (usize, Option<&'a str>, &'a [usize], &'a [usize]);
+
+trait Items {
+    /// First definition of each node id.
+    fn nodes(&self) -> HashMap<usize, NodeItem<'_>>;
+
+    /// First definition of each subgraph id.
+    fn subgraphs(&self) -> HashMap<usize, SubgraphItem<'_>>;
+}
+
+impl Items for [Item] {
+    fn nodes(&self) -> HashMap<usize, NodeItem<'_>> {
+        let mut res = HashMap::new();
+        for item in self {
+            if let Item::Node {
+                line,
+                id,
+                op,
+                operands,
+            } = item
+            {
+                res.entry(*id).or_insert((*line, op, &operands[..]));
+            }
+        }
+        res
+    }
+
+    fn subgraphs(&self) -> HashMap<usize, SubgraphItem<'_>> {
+        let mut res = HashMap::new();
+        for item in self {
+            if let Item::Subgraph {
+                line,
+                id,
+                name,
+                inputs,
+                outputs,
+            } = item
+            {
+                res.entry(*id)
+                    .or_insert((*line, name.as_deref(), &inputs[..], &outputs[..]));
+            }
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Node(usize),
+    Subgraph(usize),
+}
+
+/// Reads the header, migrates older versions and parses every line.
+fn parse(text: &str) -> Result<Vec<Item>, LoadError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let version = match lines.next().map(|(_, line)| line.split_once(' ')) {
+        Some(Some((MAGIC, version))) => version
+            .parse::<u32>()
+            .map_err(|_| LoadError::MissingHeader)?,
+        _ => return Err(LoadError::MissingHeader),
+    };
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion { version });
+    }
+
+    let (numbers, mut records): (Vec<usize>, Vec<Record>) = lines
+        .map(|(number, line)| {
+            let record = line.split_whitespace().map(str::to_string).collect();
+            (number, record)
+        })
+        .unzip();
+    for migrate in &MIGRATIONS[version as usize - 1..] {
+        migrate(&mut records);
+    }
+
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, record)| {
+            // Migrations may add or drop lines, numbers then are approximate.
+            let line = numbers.get(i).copied().unwrap_or(0);
+            parse_record(record, line)
+        })
+        .collect()
+}
+
+fn parse_record(record: &[String], line: usize) -> Result<Item, LoadError> {
+    let mut tokens = Tokens {
+        record,
+        next: 0,
+        line,
+    };
+
+    let item = match tokens.next()? {
+        "output" => Item::Output {
+            line,
+            id: tokens.number()?,
+        },
+        "feed" => Item::Feed {
+            line,
+            delay: tokens.number()?,
+            source: tokens.number()?,
+        },
+        "name" => Item::Name {
+            line,
+            id: tokens.number()?,
+            name: tokens.text()?,
+        },
+        "doc" => Item::Doc {
+            line,
+            id: tokens.number()?,
+            doc: tokens.text()?,
+        },
+        "subgraph" => {
+            let id = tokens.number()?;
+            let name = match tokens.next()? {
+                "-" => None,
+                name => Some(decode(name).ok_or(LoadError::Malformed {
+                    line,
+                    reason: "invalid name encoding",
+                })?),
+            };
+            let input_count = tokens.number()?;
+            let inputs = (0..input_count)
+                .map(|_| tokens.number())
+                .collect::<Result<_, _>>()?;
+            Item::Subgraph {
+                line,
+                id,
+                name,
+                inputs,
+                outputs: tokens.rest()?,
+            }
+        }
+        id => Item::Node {
+            line,
+            id: id.parse().map_err(|_| LoadError::Malformed {
+                line,
+                reason: "expected a node id",
+            })?,
+            op: parse_op(&mut tokens)?,
+            operands: tokens.rest()?,
+        },
+    };
+
+    tokens.finish()?;
+    Ok(item)
+}
+
+fn parse_op(tokens: &mut Tokens) -> Result<Op, LoadError> {
+    let line = tokens.line;
+    Ok(match tokens.next()? {
+        "input" => Op::Input(InputKind::Value, tokens.parse("expected a number")?),
+        "time" => Op::Input(InputKind::Time, tokens.parse("expected a number")?),
+        "const" => Op::Input(InputKind::Const, tokens.parse("expected a number")?),
+        "delay" => Op::Input(InputKind::Delay, tokens.parse("expected a number")?),
+        "accumulate" => Op::Input(
+            InputKind::Accumulate(tokens.keyword(accumulation_name)?),
+            tokens.parse("expected a number")?,
+        ),
+        "add" => Op::Binary(BinaryOp::Add),
+        "mul" => Op::Binary(BinaryOp::Mul),
+        "pow" => Op::Binary(BinaryOp::Pow(tokens.keyword(pow_policy_name)?)),
+        "and" => Op::Binary(BinaryOp::And),
+        "or" => Op::Binary(BinaryOp::Or),
+        "compare" => Op::Binary(BinaryOp::Compare(tokens.keyword(comparison_name)?)),
+        "sin" => Op::Unary(UnaryOp::Sin),
+        "cos" => Op::Unary(UnaryOp::Cos),
+        "not" => Op::Unary(UnaryOp::Not),
+        "round" => Op::Unary(UnaryOp::Round {
+            digits: tokens.parse("expected a digit count")?,
+            mode: tokens.keyword(round_mode_name)?,
+        }),
+        "mul-add" => Op::Ternary(TernaryOp::MulAdd),
+        "select" => Op::Ternary(TernaryOp::Select),
+        "custom" => Op::Custom(decode(tokens.next()?).ok_or(LoadError::Malformed {
+            line,
+            reason: "invalid name encoding",
+        })?),
+        "composite" => Op::Composite {
+            graph: tokens.number()?,
+            port: tokens.number()?,
+        },
+        op => {
+            return Err(LoadError::UnknownOp {
+                line,
+                op: op.to_string(),
+            })
+        }
+    })
+}
+
+/// Violations, and an order in which to build the definitions such that
+/// everything comes after what it references.
+fn check(items: &[Item]) -> (Vec<Violation>, Vec<Key>) {
+    let mut violations = Vec::new();
+    let nodes = items.nodes();
+    let subgraphs = items.subgraphs();
+
+    // First definitions, in file order.
+    let mut roots = Vec::new();
+    let mut defined = HashSet::new();
+    for item in items {
+        let (line, key) = match item {
+            Item::Node { line, id, .. } => (*line, Key::Node(*id)),
+            Item::Subgraph { line, id, .. } => (*line, Key::Subgraph(*id)),
+            Item::Output { .. } | Item::Feed { .. } | Item::Name { .. } | Item::Doc { .. } => {
+                continue
+            }
+        };
+        if defined.insert(key) {
+            roots.push(key);
+        } else {
+            let (Key::Node(id) | Key::Subgraph(id)) = key;
+            violations.push(Violation::DuplicateId { line, id });
+        }
+    }
+
+    let mut named: HashSet<&str> = HashSet::new();
+    let mut labeled: HashSet<(usize, bool)> = HashSet::new();
+    for item in items {
+        let mut node_refs = |line: usize, ids: &[usize]| {
+            for &id in ids {
+                if !nodes.contains_key(&id) {
+                    violations.push(Violation::Dangling { line, id });
+                }
+            }
+        };
+
+        match item {
+            Item::Node {
+                line, op, operands, ..
+            } => {
+                node_refs(*line, operands);
+                let expected = match op {
+                    Op::Composite { graph, port } => match subgraphs.get(graph) {
+                        Some(&(_, _, inputs, outputs)) => {
+                            if *port >= outputs.len() {
+                                violations.push(Violation::NoSuchPort {
+                                    line: *line,
+                                    port: *port,
+                                });
+                            }
+                            Some(inputs.len())
+                        }
+                        None => {
+                            violations.push(Violation::UnknownSubgraph {
+                                line: *line,
+                                id: *graph,
+                            });
+                            None
+                        }
+                    },
+                    op => op.arity(),
+                };
+                if let Some(expected) = expected.filter(|expected| *expected != operands.len()) {
+                    violations.push(Violation::Arity {
+                        line: *line,
+                        op: op.name(),
+                        expected,
+                        found: operands.len(),
+                    });
+                }
+            }
+            Item::Subgraph {
+                line,
+                inputs,
+                outputs,
+                ..
+            } => {
+                node_refs(*line, inputs);
+                node_refs(*line, outputs);
+                for &id in inputs {
+                    let settable = match nodes.get(&id) {
+                        Some(&(_, Op::Input(kind, _), _)) => *kind != InputKind::Const,
+                        Some(_) => false,
+                        None => true,
+                    };
+                    if !settable {
+                        violations.push(Violation::NotSettable { line: *line, id });
+                    }
+                }
+            }
+            Item::Output { line, id } => node_refs(*line, &[*id]),
+            Item::Name { line, id, name } => {
+                node_refs(*line, &[*id]);
+                if !labeled.insert((*id, true)) {
+                    violations.push(Violation::Relabeled {
+                        line: *line,
+                        id: *id,
+                    });
+                } else if !named.insert(name) {
+                    violations.push(Violation::DuplicateName {
+                        line: *line,
+                        name: name.clone(),
+                    });
+                }
+            }
+            Item::Doc { line, id, .. } => {
+                node_refs(*line, &[*id]);
+                if !labeled.insert((*id, false)) {
+                    violations.push(Violation::Relabeled {
+                        line: *line,
+                        id: *id,
+                    });
+                }
+            }
+            Item::Feed {
+                line,
+                delay,
+                source,
+            } => {
+                node_refs(*line, &[*delay, *source]);
+                if let Some((_, op, _)) = nodes.get(delay) {
+                    if !matches!(
+                        op,
+                        Op::Input(InputKind::Delay | InputKind::Accumulate(_), _)
+                    ) {
+                        violations.push(Violation::NotDelay {
+                            line: *line,
+                            id: *delay,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    let dependencies = |key: Key| -> Vec<Key> {
+        match key {
+            Key::Node(id) => match nodes.get(&id) {
+                Some(&(_, op, operands)) => {
+                    let mut res: Vec<_> = operands.iter().map(|id| Key::Node(*id)).collect();
+                    if let Op::Composite { graph, .. } = op {
+                        res.push(Key::Subgraph(*graph));
+                    }
+                    res
+                }
+                None => Vec::new(),
+            },
+            Key::Subgraph(id) => match subgraphs.get(&id) {
+                Some(&(_, _, inputs, outputs)) => inputs
+                    .iter()
+                    .chain(outputs)
+                    .map(|id| Key::Node(*id))
+                    .collect(),
+                None => Vec::new(),
+            },
+        }
+    };
+    let known = |key: &Key| match key {
+        Key::Node(id) => nodes.contains_key(id),
+        Key::Subgraph(id) => subgraphs.contains_key(id),
+    };
+
+    // Depth-first, keys on the current path are `false`, finished ones `true`.
+    // Only composites refer to subgraphs, so every cycle passes a node.
+    let mut order = Vec::new();
+    let mut state: HashMap<Key, bool> = HashMap::new();
+    for root in roots {
+        let mut stack = vec![(root, root, false)];
+        while let Some((key, user, expanded)) = stack.pop() {
+            if expanded {
+                state.insert(key, true);
+                order.push(key);
+                continue;
+            }
+            match state.get(&key) {
+                Some(true) => continue,
+                Some(false) => {
+                    let id = match (key, user) {
+                        (Key::Node(id), _) | (Key::Subgraph(_), Key::Node(id)) => id,
+                        (Key::Subgraph(_), Key::Subgraph(_)) => unreachable!(),
+                    };
+                    violations.push(Violation::Cycle {
+                        line: nodes[&id].0,
+                        id,
+                    });
+                    continue;
+                }
+                None => {}
+            }
+            state.insert(key, false);
+            stack.push((key, user, true));
+            stack.extend(
+                dependencies(key)
+                    .into_iter()
+                    .filter(known)
+                    .map(|dependency| (dependency, key, false)),
+            );
+        }
+    }
+
+    violations.sort_by_key(|violation| match violation {
+        Violation::Arity { line, .. }
+        | Violation::Dangling { line, .. }
+        | Violation::UnknownSubgraph { line, .. }
+        | Violation::DuplicateId { line, .. }
+        | Violation::Cycle { line, .. }
+        | Violation::NotSettable { line, .. }
+        | Violation::NoSuchPort { line, .. }
+        | Violation::NotDelay { line, .. }
+        | Violation::DuplicateName { line, .. }
+        | Violation::Relabeled { line, .. } => *line,
+    });
+    (violations, order)
+}
+
+struct Tokens<'a> {
+    record: &'a [String],
+    next: usize,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, LoadError> {
+        let token = self.record.get(self.next).ok_or(LoadError::Malformed {
+            line: self.line,
+            reason: "missing field",
+        })?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, reason: &'static str) -> Result<T, LoadError> {
+        let line = self.line;
+        self.next()?
+            .parse()
+            .map_err(|_| LoadError::Malformed { line, reason })
+    }
+
+    fn number(&mut self) -> Result<usize, LoadError> {
+        self.parse("expected an id")
+    }
+
+    /// The next token, percent-decoded.
+    fn text(&mut self) -> Result<String, LoadError> {
+        let line = self.line;
+        decode(self.next()?).ok_or(LoadError::Malformed {
+            line,
+            reason: "invalid name encoding",
+        })
+    }
+
+    /// The remaining tokens, as ids.
+    fn rest(&mut self) -> Result<Vec<usize>, LoadError> {
+        let mut res = Vec::new();
+        while self.next < self.record.len() {
+            res.push(self.number()?);
+        }
+        Ok(res)
+    }
+
+    /// The variant of `T` whose `name` is the next token.
+    fn keyword<T: Keyword>(&mut self, name: fn(T) -> &'static str) -> Result<T, LoadError> {
+        let line = self.line;
+        let token = self.next()?;
+        T::ALL
+            .iter()
+            .copied()
+            .find(|variant| name(*variant) == token)
+            .ok_or(LoadError::UnknownOp {
+                line,
+                op: token.to_string(),
+            })
+    }
+
+    fn finish(&self) -> Result<(), LoadError> {
+        if self.next != self.record.len() {
+            return Err(LoadError::Malformed {
+                line: self.line,
+                reason: "too many fields",
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) trait Keyword: Copy + PartialEq + 'static {
+    const ALL: &'static [Self];
+}
+
+impl Keyword for PowPolicy {
+    const ALL: &'static [Self] = &[Self::Native, Self::Error, Self::Nan, Self::ZeroPowZeroIsOne];
+}
+
+impl Keyword for Comparison {
+    const ALL: &'static [Self] = &[Self::Lt, Self::Le, Self::Gt, Self::Ge, Self::Eq, Self::Ne];
+}
+
+impl Keyword for RoundMode {
+    const ALL: &'static [Self] = &[Self::HalfUp, Self::HalfEven, Self::Trunc];
+}
+
+impl Keyword for Accumulation {
+    const ALL: &'static [Self] = &[Self::Sum, Self::Min, Self::Max, Self::Count];
+}
+
+fn pow_policy_name(policy: PowPolicy) -> &'static str {
+    match policy {
+        PowPolicy::Native => "native",
+        PowPolicy::Error => "error",
+        PowPolicy::Nan => "nan",
+        PowPolicy::ZeroPowZeroIsOne => "zero-pow-zero-is-one",
+    }
+}
+
+fn comparison_name(cmp: Comparison) -> &'static str {
+    match cmp {
+        Comparison::Lt => "lt",
+        Comparison::Le => "le",
+        Comparison::Gt => "gt",
+        Comparison::Ge => "ge",
+        Comparison::Eq => "eq",
+        Comparison::Ne => "ne",
+    }
+}
+
+fn round_mode_name(mode: RoundMode) -> &'static str {
+    match mode {
+        RoundMode::HalfUp => "half-up",
+        RoundMode::HalfEven => "half-even",
+        RoundMode::Trunc => "trunc",
+    }
+}
+
+fn accumulation_name(accumulation: Accumulation) -> &'static str {
+    match accumulation {
+        Accumulation::Sum => "sum",
+        Accumulation::Min => "min",
+        Accumulation::Max => "max",
+        Accumulation::Count => "count",
+    }
+}
+
+/// Percent-encodes everything but ASCII alphanumerics and `_`.
+fn encode(name: &str) -> String {
+    let mut res = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' {
+            res.push(byte as char);
+        } else {
+            write!(res, "%{byte:02X}").unwrap();
+        }
+    }
+    res
+}
+
+pub(crate) fn decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &str) -> Result<Vec<NodeCelled>, LoadError> {
+        Deserializer::new().deserialize(text)
+    }
+
+    #[test]
+    fn round_trips_names_and_docs() {
+        let rate = Node::create_input(0.5);
+        rate.borrow().set_name("interest rate");
+        let output = Node::create_mul(rate.clone(), Node::create_const(2.0));
+        output.borrow().set_doc("Twice the rate.\nIn percent.");
+
+        let text = serialize(&[output]);
+        assert!(text.contains("name 0 interest%20rate\n"), "{text}");
+        let loaded = load(&text).unwrap();
+        let output = loaded[0].borrow();
+        assert_eq!(
+            output.doc().as_deref(),
+            Some("Twice the rate.\nIn percent.")
+        );
+        assert_eq!(output.name(), None);
+        let rate = output.children()[0].clone();
+        assert_eq!(rate.borrow().name().as_deref(), Some("interest rate"));
+        assert_eq!(serialize(&loaded), text);
+    }
+
+    #[test]
+    fn validate_accepts_well_formed_graphs() {
+        let text = "computational-graph 1\n\
+                    2 add 0 1\n\
+                    0 input 1.0\n\
+                    1 const 2.0\n\
+                    name 0 x\n\
+                    output 2\n";
+        assert_eq!(validate(text).unwrap(), vec![]);
+        assert_eq!(load(text).unwrap()[0].borrow().compute(), 3.0);
+    }
+
+    #[test]
+    fn validate_reports_every_violation() {
+        let text = "computational-graph 1\n\
+                    0 input 1.0\n\
+                    1 add 0\n\
+                    2 mul 0 7\n\
+                    0 const 3.0\n\
+                    3 add 4 0\n\
+                    4 add 3 0\n\
+                    5 composite 9 0 0\n\
+                    feed 0 1\n\
+                    name 0 x\n\
+                    name 1 x\n\
+                    name 1 y\n\
+                    doc 8 z\n\
+                    output 6\n";
+        let violations = validate(text).unwrap();
+        let expected = [
+            Violation::Arity {
+                line: 3,
+                op: "add",
+                expected: 2,
+                found: 1,
+            },
+            Violation::Dangling { line: 4, id: 7 },
+            Violation::DuplicateId { line: 5, id: 0 },
+            Violation::UnknownSubgraph { line: 8, id: 9 },
+            Violation::NotDelay { line: 9, id: 0 },
+            Violation::DuplicateName {
+                line: 11,
+                name: "x".into(),
+            },
+            Violation::Relabeled { line: 12, id: 1 },
+            Violation::Dangling { line: 13, id: 8 },
+            Violation::Dangling { line: 14, id: 6 },
+        ];
+        for violation in &expected {
+            assert!(
+                violations.contains(violation),
+                "{violation} in {violations:?}"
+            );
+        }
+        let cycles = violations
+            .iter()
+            .filter(|violation| matches!(violation, Violation::Cycle { .. }))
+            .count();
+        assert_eq!(cycles, 1);
+        assert_eq!(violations.len(), expected.len() + 1);
+        assert!(matches!(load(text), Err(LoadError::Invalid(_))));
+    }
+
+    #[test]
+    fn duplicate_names_fail_to_load() {
+        let (a, b) = (Node::create_input(1.0), Node::create_input(2.0));
+        a.borrow().set_name("x");
+        b.borrow().set_name("x");
+        let text = serialize(&[Node::create_add(a, b)]);
+        let violations = validate(&text).unwrap();
+        assert!(matches!(&violations[..], [Violation::DuplicateName { name, .. }] if name == "x"));
+    }
+
+    #[test]
+    fn subgraph_ports_must_be_settable() {
+        let text = "computational-graph 1\n\
+                    0 const 1.0\n\
+                    1 add 0 0\n\
+                    subgraph 0 f 1 0 1\n\
+                    output 1\n";
+        assert_eq!(
+            validate(text).unwrap(),
+            vec![Violation::NotSettable { line: 4, id: 0 }]
+        );
+    }
+}
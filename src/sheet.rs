@@ -0,0 +1,361 @@
+//! Spreadsheet over the graph: named cells holding numbers or formulas.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::computational_graph::{EvalError, Node, NodeCelled};
+use crate::parser::{ParseError, Parser};
+use crate::template::GraphTemplate;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SheetError {
+    /// The formula entered into `cell` doesn't parse. Spans are bytes of the
+    /// whole entry.
+    Parse {
+        cell: String,
+        error: Box<ParseError>,
+    },
+    /// The entry for `cell` is neither a number nor a formula.
+    NotANumber { cell: String },
+    /// The formula would make `cell` depend on itself.
+    Cycle { cell: String },
+}
+
+impl fmt::Display for SheetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { cell, error } => write!(f, "{cell}, {error}"),
+            Self::NotANumber { cell } => write!(f, "{cell}: expected a number or a formula"),
+            Self::Cycle { cell } => write!(f, "{cell} would depend on itself"),
+        }
+    }
+}
+
+impl std::error::Error for SheetError {}
+
+/// Cells named like identifiers (`A1`, `total`) holding a number or, after a
+/// `=`, a formula over other cells:
+///
+/// ```text
+/// =(A1 + A2) * rate
+/// =select(A1 > 0, sqrt(A1), 0)
+/// ```
+///
+/// Formulas are in `Parser`'s default grammar, with any name not followed by
+/// `(` reading a cell. Cells that were never set read as `0`.
+///
+/// Each cell is a graph node. Changing a number only sets an input, so reading
+/// values afterwards recomputes just what depends on it. Changing a formula
+/// rebuilds the cells depending on it; nodes taken from `node` before then
+/// stop following changes.
+#[derive(Debug, Default)]
+pub struct Sheet {
+    cells: HashMap<String, Cell>,
+}
+
+#[derive(Debug)]
+struct Cell {
+    entry: String,
+    /// `None` for numbers and blanks.
+    formula: Option<Formula>,
+    node: NodeCelled,
+}
+
+/// A formula parsed once over a placeholder per cell it reads, instantiated
+/// over the cells' nodes whenever it's built.
+#[derive(Debug)]
+struct Formula {
+    /// Cells read, each once, in the order of the placeholders.
+    references: Vec<String>,
+    template: GraphTemplate,
+}
+
+impl Formula {
+    fn parse(text: &str) -> Result<Self, ParseError> {
+        let parser = Parser::new();
+        let references = parser.references(text);
+        let placeholders: Vec<_> = references.iter().map(|_| Node::create_input(0.0)).collect();
+        let parser = references
+            .iter()
+            .zip(&placeholders)
+            .fold(parser, |parser, (name, placeholder)| {
+                parser.with_variable(name, placeholder.clone())
+            });
+        let output = parser.parse(text)?;
+        Ok(Self {
+            references,
+            template: GraphTemplate::new(output, placeholders),
+        })
+    }
+
+    fn build(&self, cell: impl Fn(&str) -> NodeCelled) -> NodeCelled {
+        let bindings: Vec<_> = self.references.iter().map(|name| cell(name)).collect();
+        self.template.instantiate(&bindings)
+    }
+}
+
+impl Sheet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enters `entry` into `cell`: a number, `=` and a formula, or nothing to
+    /// blank it. On errors the sheet is left as it was.
+    pub fn set(&mut self, cell: &str, entry: &str) -> Result<(), SheetError> {
+        let trimmed = entry.trim();
+        let formula = match trimmed.strip_prefix('=') {
+            Some(text) => {
+                let offset = entry.len() - entry.trim_start().len() + 1;
+                let formula = Formula::parse(text).map_err(|mut error| {
+                    error.span = error.span.start + offset..error.span.end + offset;
+                    SheetError::Parse {
+                        cell: cell.to_string(),
+                        error: Box::new(error),
+                    }
+                })?;
+                Some(formula)
+            }
+            None => None,
+        };
+        let number = match (&formula, trimmed) {
+            (Some(_), _) | (None, "") => 0.0,
+            (None, number) => number.parse().map_err(|_| SheetError::NotANumber {
+                cell: cell.to_string(),
+            })?,
+        };
+
+        if let Some(formula) = &formula {
+            if self.reaches(formula, cell) {
+                return Err(SheetError::Cycle {
+                    cell: cell.to_string(),
+                });
+            }
+        }
+
+        // A number replacing a number is just a new input value.
+        if let (None, Some(existing)) = (&formula, self.cells.get_mut(cell)) {
+            if existing.formula.is_none() {
+                existing.entry = trimmed.to_string();
+                existing.node.borrow().set(number);
+                return Ok(());
+            }
+        }
+
+        let node = match &formula {
+            Some(formula) => self.build(cell, formula),
+            None => {
+                let node = Node::create_input(number);
+                node.borrow().set_name(cell);
+                node
+            }
+        };
+        let old = self.cells.insert(
+            cell.to_string(),
+            Cell {
+                entry: trimmed.to_string(),
+                formula,
+                node,
+            },
+        );
+        if let Some(old) = old {
+            self.release(old.node);
+            self.rebuild_dependents(cell);
+        }
+        Ok(())
+    }
+
+    /// The entry last set, `""` for blank cells.
+    pub fn entry(&self, cell: &str) -> &str {
+        self.cells.get(cell).map_or("", |cell| &cell.entry)
+    }
+
+    pub fn value(&self, cell: &str) -> Result<f32, EvalError> {
+        match self.cells.get(cell) {
+            Some(cell) => cell.node.borrow().try_compute(),
+            None => Ok(0.0),
+        }
+    }
+
+    /// The node computing `cell`, e.g. for a `Report`. Cells that were never
+    /// set have none.
+    pub fn node(&self, cell: &str) -> Option<NodeCelled> {
+        self.cells.get(cell).map(|cell| cell.node.clone())
+    }
+
+    /// Every cell that was set or is read by a formula.
+    pub fn cells(&self) -> impl Iterator<Item = &str> {
+        self.cells.keys().map(String::as_str)
+    }
+
+    /// Whether evaluating `formula` reads `target`, directly or through other
+    /// formulas.
+    fn reaches(&self, formula: &Formula, target: &str) -> bool {
+        let mut stack: Vec<&str> = formula.references.iter().map(String::as_str).collect();
+        let mut seen = HashSet::new();
+        while let Some(cell) = stack.pop() {
+            if cell == target {
+                return true;
+            }
+            if !seen.insert(cell) {
+                continue;
+            }
+            if let Some(Cell {
+                formula: Some(formula),
+                ..
+            }) = self.cells.get(cell)
+            {
+                stack.extend(formula.references.iter().map(String::as_str));
+            }
+        }
+        false
+    }
+
+    /// Node for `formula`, adding blank cells for references to unset ones.
+    fn build(&mut self, cell: &str, formula: &Formula) -> NodeCelled {
+        for reference in &formula.references {
+            if !self.cells.contains_key(reference) {
+                self.set(reference, "").unwrap();
+            }
+        }
+
+        let node = formula.build(|name| self.cells[name].node.clone());
+        // A bare reference is the other cell's node, which keeps its name.
+        let bare = formula
+            .references
+            .iter()
+            .any(|name| Rc::ptr_eq(&self.cells[name].node, &node));
+        if !bare {
+            node.borrow().set_name(cell);
+        }
+        node
+    }
+
+    /// Rebuilds the formulas reading `cell`, directly or not, over its new
+    /// node. Operands come before their users.
+    fn rebuild_dependents(&mut self, cell: &str) {
+        let mut affected = HashSet::new();
+        let mut stack = vec![cell.to_string()];
+        while let Some(changed) = stack.pop() {
+            for (name, other) in &self.cells {
+                let reads = other
+                    .formula
+                    .as_ref()
+                    .is_some_and(|formula| formula.references.contains(&changed));
+                if reads && affected.insert(name.clone()) {
+                    stack.push(name.clone());
+                }
+            }
+        }
+
+        while !affected.is_empty() {
+            let ready: Vec<String> = affected
+                .iter()
+                .filter(|name| {
+                    let formula = self.cells[*name].formula.as_ref().unwrap();
+                    formula
+                        .references
+                        .iter()
+                        .all(|reference| !affected.contains(reference))
+                })
+                .cloned()
+                .collect();
+            for name in ready {
+                let formula = self.cells.get_mut(&name).unwrap().formula.take().unwrap();
+                let node = self.build(&name, &formula);
+                let cell = self.cells.get_mut(&name).unwrap();
+                cell.formula = Some(formula);
+                let old = std::mem::replace(&mut cell.node, node);
+                self.release(old);
+                affected.remove(&name);
+            }
+        }
+    }
+
+    /// Unregisters the nodes of a replaced formula from their operands, so
+    /// changes no longer reach them. Other cells' nodes are left alone.
+    fn release(&self, old: NodeCelled) {
+        let cells: HashSet<_> = self
+            .cells
+            .values()
+            .map(|cell| Rc::as_ptr(&cell.node))
+            .collect();
+        let mut stack = vec![old];
+        while let Some(node) = stack.pop() {
+            if cells.contains(&Rc::as_ptr(&node)) {
+                continue;
+            }
+            Node::detach(&node);
+            stack.extend(node.borrow().children());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseErrorKind;
+
+    #[test]
+    fn formulas_follow_their_cells() {
+        let mut sheet = Sheet::new();
+        sheet.set("A1", "4").unwrap();
+        sheet
+            .set("B1", "=select(A1 > 0, sqrt(A1), 0) * rate")
+            .unwrap();
+        assert_eq!(sheet.value("B1").unwrap(), 0.0);
+
+        sheet.set("rate", "3").unwrap();
+        assert_eq!(sheet.value("B1").unwrap(), 6.0);
+        sheet.set("A1", "=2 ^ 4").unwrap();
+        assert_eq!(sheet.value("B1").unwrap(), 12.0);
+        assert_eq!(
+            sheet.node("B1").unwrap().borrow().name().as_deref(),
+            Some("B1")
+        );
+    }
+
+    #[test]
+    fn bare_references_share_the_node() {
+        let mut sheet = Sheet::new();
+        sheet.set("A1", "2").unwrap();
+        sheet.set("B1", "=A1").unwrap();
+        assert!(Rc::ptr_eq(
+            &sheet.node("A1").unwrap(),
+            &sheet.node("B1").unwrap()
+        ));
+        assert_eq!(
+            sheet.node("B1").unwrap().borrow().name().as_deref(),
+            Some("A1")
+        );
+    }
+
+    #[test]
+    fn parse_errors_point_into_the_entry() {
+        let mut sheet = Sheet::new();
+        let Err(SheetError::Parse { cell, error }) = sheet.set("A1", " = 1 + sine(2)") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(cell, "A1");
+        assert_eq!(error.span, 7..11);
+        assert_eq!(error.kind, ParseErrorKind::UnknownFunction("sine".into()));
+        assert_eq!(sheet.entry("A1"), "");
+
+        assert_eq!(
+            sheet.set("A1", "abc"),
+            Err(SheetError::NotANumber { cell: "A1".into() })
+        );
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut sheet = Sheet::new();
+        sheet.set("A1", "=B1 + 1").unwrap();
+        sheet.set("B1", "=C1 * 2").unwrap();
+        assert_eq!(
+            sheet.set("C1", "=A1"),
+            Err(SheetError::Cycle { cell: "C1".into() })
+        );
+        assert_eq!(sheet.entry("C1"), "");
+    }
+}
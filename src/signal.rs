@@ -0,0 +1,135 @@
+//! Convolution and correlation of vectors, for smoothing and FIR filters.
+
+use crate::computational_graph::{Node, NodeCelled};
+use crate::tensor::{ShapeError, Tensor};
+
+/// Which outputs of a convolution of `n` values with `k` weights to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Padding {
+    /// Only where the kernel lies within the signal, `n - k + 1` values.
+    #[default]
+    Valid,
+    /// The middle `n` values of `Full`, as in NumPy.
+    Same(Border),
+    /// Every overlap of kernel and signal, `n + k - 1` values.
+    Full(Border),
+}
+
+/// What the signal reads as beyond its ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Border {
+    #[default]
+    Zero,
+    /// The nearest end's value.
+    Edge,
+}
+
+impl Tensor {
+    /// `y[m] = sum of x[m - j] * kernel[j]` over a vector `x`, with fresh
+    /// nodes for every output. Weights are nodes too, so they may be inputs.
+    pub fn convolve(&self, kernel: &Tensor, padding: Padding) -> Result<Tensor, ShapeError> {
+        let weights: Vec<_> = kernel.vector()?.iter().rev().cloned().collect();
+        correlate(self.vector()?, &weights, padding)
+    }
+
+    /// `y[m] = sum of x[m + j] * kernel[j]`, a convolution with the kernel
+    /// reversed.
+    pub fn correlate(&self, kernel: &Tensor, padding: Padding) -> Result<Tensor, ShapeError> {
+        correlate(self.vector()?, kernel.vector()?, padding)
+    }
+}
+
+fn correlate(
+    signal: &[NodeCelled],
+    kernel: &[NodeCelled],
+    padding: Padding,
+) -> Result<Tensor, ShapeError> {
+    let (n, k) = (signal.len() as isize, kernel.len() as isize);
+    assert!(k > 0, "A kernel needs a weight");
+
+    // Output `i` starts reading the signal at `i + first`.
+    let (first, len, border) = match padding {
+        Padding::Valid => (0, (n - k + 1).max(0), Border::Zero),
+        Padding::Same(border) => (-k / 2, n, border),
+        Padding::Full(border) => (1 - k, n + k - 1, border),
+    };
+
+    let nodes = (0..len)
+        .map(|i| {
+            let terms = kernel.iter().enumerate().filter_map(|(j, weight)| {
+                let at = i + first + j as isize;
+                let x = match border {
+                    _ if (0..n).contains(&at) => &signal[at as usize],
+                    Border::Edge if n > 0 => &signal[at.clamp(0, n - 1) as usize],
+                    _ => return None,
+                };
+                Some(Node::create_mul(x.clone(), weight.clone()))
+            });
+            terms
+                .reduce(Node::create_add)
+                .unwrap_or_else(|| Node::create_const(0f32))
+        })
+        .collect();
+    Tensor::new(vec![len as usize], nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(values: &[f32]) -> Tensor {
+        Tensor::inputs(vec![values.len()], values).unwrap()
+    }
+
+    #[test]
+    fn convolves_as_numpy() {
+        let (x, kernel) = (vector(&[1.0, 2.0, 3.0, 4.0]), vector(&[1.0, 0.0, -1.0]));
+        let convolve = |padding| x.convolve(&kernel, padding).unwrap().values().unwrap();
+        assert_eq!(convolve(Padding::Valid), [2.0, 2.0]);
+        assert_eq!(convolve(Padding::Same(Border::Zero)), [2.0, 2.0, 2.0, -3.0]);
+        assert_eq!(
+            convolve(Padding::Full(Border::Zero)),
+            [1.0, 2.0, 2.0, 2.0, -3.0, -4.0]
+        );
+        assert_eq!(
+            convolve(Padding::Full(Border::Edge)),
+            [0.0, 1.0, 2.0, 2.0, 1.0, 0.0]
+        );
+
+        let correlation = x.correlate(&kernel, Padding::Valid).unwrap();
+        assert_eq!(correlation.values().unwrap(), [-2.0, -2.0]);
+        let smoothed = x.correlate(&vector(&[0.5, 0.5]), Padding::Same(Border::Edge));
+        assert_eq!(smoothed.unwrap().values().unwrap(), [1.0, 1.5, 2.5, 3.5]);
+    }
+
+    #[test]
+    fn handles_short_signals() {
+        let kernel = vector(&[1.0, 1.0, 1.0]);
+        let short = vector(&[5.0]);
+        assert_eq!(
+            short.convolve(&kernel, Padding::Valid).unwrap().shape(),
+            [0]
+        );
+        let same = short
+            .convolve(&kernel, Padding::Same(Border::Edge))
+            .unwrap();
+        assert_eq!(same.values().unwrap(), [15.0]);
+        let empty = vector(&[]).convolve(&kernel, Padding::Full(Border::Edge));
+        assert_eq!(empty.unwrap().values().unwrap(), [0.0, 0.0]);
+
+        let matrix = Tensor::inputs(vec![1, 1], &[1.0]).unwrap();
+        assert!(short.convolve(&matrix, Padding::Valid).is_err());
+    }
+
+    #[test]
+    fn weights_can_be_inputs() {
+        let weight = Node::create_input(1f32);
+        let kernel = Tensor::new(vec![1], vec![weight.clone()]).unwrap();
+        let y = vector(&[1.0, 2.0])
+            .convolve(&kernel, Padding::Valid)
+            .unwrap();
+        assert_eq!(y.values().unwrap(), [1.0, 2.0]);
+        weight.borrow().set(3f32);
+        assert_eq!(y.values().unwrap(), [3.0, 6.0]);
+    }
+}
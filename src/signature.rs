@@ -0,0 +1,195 @@
+//! HMAC-SHA-256 signatures of serialized graphs, so that a server can refuse
+//! graphs changed since a party holding the key signed them.
+//!
+//! A signature covers the canonical form, what `serialize` writes for the
+//! graphs: `sign` and `verify` take graphs in memory, however they were
+//! loaded. `serialize_signed` appends it to that text as the last line,
+//! `signature <hex>`, and `deserialize_signed` checks it against the text as
+//! written before parsing any of it, so a signed file must be re-signed after
+//! being reformatted or migrated.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::computational_graph::NodeCelled;
+use crate::hash::hmac_sha256;
+use crate::serialize::{serialize, Deserializer, LoadError};
+
+const PREFIX: &str = "signature ";
+
+#[derive(Debug, Clone)]
+pub enum SignatureError {
+    /// The text doesn't end with a `signature` line.
+    Missing,
+    /// A signature that isn't 64 hex digits.
+    Malformed,
+    /// The graphs aren't the ones signed, or were signed with another key.
+    Mismatch,
+    Load(LoadError),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "the graph isn't signed"),
+            Self::Malformed => write!(f, "malformed signature"),
+            Self::Mismatch => write!(f, "the signature doesn't match the graph"),
+            Self::Load(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+impl From<LoadError> for SignatureError {
+    fn from(e: LoadError) -> Self {
+        Self::Load(e)
+    }
+}
+
+/// Compared in constant time by `verify` only, so it has no `PartialEq`.
+#[derive(Debug, Clone, Copy)]
+pub struct Signature([u8; 32]);
+
+impl Signature {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.len() != 64 || !text.is_ascii() {
+            return Err(SignatureError::Malformed);
+        }
+        let mut res = [0; 32];
+        for (i, byte) in res.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)
+                .map_err(|_| SignatureError::Malformed)?;
+        }
+        Ok(Self(res))
+    }
+}
+
+/// Signature of the graphs computing `outputs` under `key`.
+pub fn sign(outputs: &[NodeCelled], key: &[u8]) -> Signature {
+    Signature(hmac_sha256(key, serialize(outputs).as_bytes()))
+}
+
+/// Whether `signature` is that of the graphs computing `outputs` under `key`.
+pub fn verify(outputs: &[NodeCelled], key: &[u8], signature: &Signature) -> bool {
+    same(&sign(outputs, key), signature)
+}
+
+/// Compares in constant time, so that timing doesn't reveal how much of a
+/// forged signature is right.
+fn same(expected: &Signature, signature: &Signature) -> bool {
+    expected
+        .0
+        .iter()
+        .zip(signature.0)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// `serialize`, followed by the signature line.
+pub fn serialize_signed(outputs: &[NodeCelled], key: &[u8]) -> String {
+    format!("{}{PREFIX}{}\n", serialize(outputs), sign(outputs, key))
+}
+
+impl Deserializer {
+    /// Reads what `serialize_signed` writes, failing unless the signature
+    /// matches the text before it under `key`. Nothing is parsed until it
+    /// does, so unsigned text never reaches the parser.
+    pub fn deserialize_signed(
+        &self,
+        text: &str,
+        key: &[u8],
+    ) -> Result<Vec<NodeCelled>, SignatureError> {
+        let trimmed = text.trim_end();
+        let start = trimmed.rfind('\n').map_or(0, |i| i + 1);
+        let (graph, last) = trimmed.split_at(start);
+        let signature = last
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or(SignatureError::Missing)?
+            .trim()
+            .parse()?;
+
+        let expected = Signature(hmac_sha256(key, graph.as_bytes()));
+        if !same(&expected, &signature) {
+            return Err(SignatureError::Mismatch);
+        }
+        Ok(self.deserialize(graph)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    const KEY: &[u8] = b"secret";
+
+    fn signed() -> String {
+        let x = Node::create_input(1.5f32);
+        x.borrow().set_name("x");
+        let y = Node::create_mul(x, Node::create_const(2.0));
+        serialize_signed(&[y], KEY)
+    }
+
+    #[test]
+    fn round_trips_signed_graphs() {
+        let text = signed();
+        let outputs = Deserializer::new().deserialize_signed(&text, KEY).unwrap();
+        assert_eq!(outputs[0].borrow().compute(), 3.0);
+        let signature = text.trim_end().rsplit_once(' ').unwrap().1;
+        assert!(verify(&outputs, KEY, &signature.parse().unwrap()));
+        assert!(!verify(&outputs, b"other", &signature.parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_tampered_text() {
+        let text = signed();
+        let tampered = text.replace("input 1.5", "input 2.5");
+        let res = Deserializer::new().deserialize_signed(&tampered, KEY);
+        assert!(matches!(res, Err(SignatureError::Mismatch)));
+        let res = Deserializer::new().deserialize_signed(&text, b"other");
+        assert!(matches!(res, Err(SignatureError::Mismatch)));
+
+        // Checked before parsing: text that doesn't parse is only a mismatch.
+        let garbage = text.replace("mul", "frobnicate");
+        let res = Deserializer::new().deserialize_signed(&garbage, KEY);
+        assert!(matches!(res, Err(SignatureError::Mismatch)));
+        // Even reformatting breaks the signature.
+        let spaced = text.replace("mul 0 1", "mul  0 1");
+        let res = Deserializer::new().deserialize_signed(&spaced, KEY);
+        assert!(matches!(res, Err(SignatureError::Mismatch)));
+    }
+
+    #[test]
+    fn rejects_missing_and_malformed_signatures() {
+        let text = signed();
+        let (graph, _) = text.trim_end().rsplit_once('\n').unwrap();
+        let res = Deserializer::new().deserialize_signed(graph, KEY);
+        assert!(matches!(res, Err(SignatureError::Missing)));
+        let res =
+            Deserializer::new().deserialize_signed(&format!("{graph}\nsignature 12ab\n"), KEY);
+        assert!(matches!(res, Err(SignatureError::Malformed)));
+        assert!(matches!(
+            "zz".repeat(32).parse::<Signature>(),
+            Err(SignatureError::Malformed)
+        ));
+    }
+}
@@ -0,0 +1,747 @@
+//! Solving a graph for one input: the value making the output hit a target,
+//! such as the rate at which a net present value is zero.
+
+use std::fmt;
+
+use crate::autodiff::{gradient, GradError};
+use crate::computational_graph::{EvalError, NodeCelled};
+use crate::constraint::ConstraintError;
+
+#[derive(Debug, Clone)]
+pub enum SolveError {
+    Eval(EvalError),
+    /// An input's constraint rejected the value found for it.
+    Constraint(ConstraintError),
+    /// No input value got within tolerance. `best` came closest, missing
+    /// the target by `residual`.
+    NoConvergence {
+        best: f32,
+        residual: f32,
+    },
+    /// The output doesn't cross the target between `lo` and `hi`.
+    NotBracketed {
+        lo: f32,
+        hi: f32,
+    },
+    /// `Solver::goal_seek` got stuck. The inputs at `best` came closest,
+    /// missing the target by `residual`.
+    GoalNotReached {
+        best: Vec<f32>,
+        residual: f32,
+    },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Eval(e) => e.fmt(f),
+            Self::Constraint(e) => e.fmt(f),
+            Self::NoConvergence { best, residual } => {
+                write!(
+                    f,
+                    "no solution found, closest was {best}, off by {residual}"
+                )
+            }
+            Self::NotBracketed { lo, hi } => {
+                write!(f, "the target isn't crossed between {lo} and {hi}")
+            }
+            Self::GoalNotReached { best, residual } => {
+                write!(
+                    f,
+                    "target not reached, closest was at {best:?}, off by {residual}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+impl From<EvalError> for SolveError {
+    fn from(e: EvalError) -> Self {
+        Self::Eval(e)
+    }
+}
+
+impl From<ConstraintError> for SolveError {
+    fn from(e: ConstraintError) -> Self {
+        Self::Constraint(e)
+    }
+}
+
+/// Root finding settings.
+#[derive(Debug, Clone)]
+pub struct Solver {
+    max_iterations: usize,
+    tolerance: f32,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self {
+            max_iterations: 100,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+impl Solver {
+    /// Newton steps and Brent iterations each, not counting the search for a
+    /// bracket.
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Largest accepted miss, relative to the target once that exceeds 1.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Value of `input` at which `output` computes to `target`, which `input`
+    /// is left at. Newton's method from the current value comes first, using
+    /// `autodiff`. If it stalls, `bracket` looks around the start and `brent`
+    /// finishes. Like these, it passes over values the input's constraint
+    /// rejects as if the output were NaN there.
+    ///
+    /// On failure, `input` is restored.
+    pub fn solve(
+        &self,
+        output: &NodeCelled,
+        input: &NodeCelled,
+        target: f32,
+    ) -> Result<f32, SolveError> {
+        let start = input.borrow().try_compute()?;
+        let mut problem = self.problem(output, input, target);
+        let res = self.search(&mut problem, start);
+        problem.finish(res, start)
+    }
+
+    fn search(&self, problem: &mut Problem, start: f32) -> Result<Option<f32>, SolveError> {
+        if let Some(x) = self.newton(problem, start)? {
+            return Ok(Some(x));
+        }
+        match problem.bracket(start)? {
+            Some((lo, hi)) => self.refine(problem, lo, hi),
+            None => Ok(None),
+        }
+    }
+
+    /// Interval across which `output` crosses `target`, found by stepping out
+    /// from `start` in both directions with doubling steps, or `None` if
+    /// there's none short of overflowing. `input` is left as it was.
+    pub fn bracket(
+        &self,
+        output: &NodeCelled,
+        input: &NodeCelled,
+        target: f32,
+        start: f32,
+    ) -> Result<Option<(f32, f32)>, SolveError> {
+        let saved = input.borrow().try_compute()?;
+        let res = self.problem(output, input, target).bracket(start);
+        input.borrow().restore(saved);
+        res
+    }
+
+    /// Value of `input` in `[lo, hi]` at which `output` computes to `target`,
+    /// by Brent's method: inverse quadratic interpolation with bisection as a
+    /// fallback, so it converges fast on smooth graphs and surely on any
+    /// continuous one. `output` must cross `target` between `lo` and `hi`. A
+    /// crossing that is a jump rather than a root fails once the bracket
+    /// can't be split any further.
+    ///
+    /// `input` is left at the root, or restored on failure.
+    pub fn brent(
+        &self,
+        output: &NodeCelled,
+        input: &NodeCelled,
+        target: f32,
+        lo: f32,
+        hi: f32,
+    ) -> Result<f32, SolveError> {
+        let start = input.borrow().try_compute()?;
+        let mut problem = self.problem(output, input, target);
+        let res = self.bracketed(&mut problem, lo, hi);
+        problem.finish(res, start)
+    }
+
+    fn bracketed(
+        &self,
+        problem: &mut Problem,
+        lo: f32,
+        hi: f32,
+    ) -> Result<Option<f32>, SolveError> {
+        let (f_lo, f_hi) = (problem.eval(lo)?, problem.eval(hi)?);
+        if problem.solved(f_lo) || problem.solved(f_hi) {
+            return Ok(Some(if f_lo.abs() <= f_hi.abs() { lo } else { hi }));
+        }
+        if !crosses(f_lo, f_hi) {
+            return Err(SolveError::NotBracketed { lo, hi });
+        }
+        self.refine(problem, lo, hi)
+    }
+
+    /// Every root of `output - target` in `[lo, hi]` that shows up as a
+    /// crossing between `samples + 1` evenly spaced points, or as a point
+    /// within tolerance, in increasing order. Roots closer than the spacing
+    /// may cancel out, and ones that only touch the target may be missed.
+    /// `input` is left as it was.
+    pub fn roots(
+        &self,
+        output: &NodeCelled,
+        input: &NodeCelled,
+        target: f32,
+        (lo, hi): (f32, f32),
+        samples: usize,
+    ) -> Result<Vec<Root>, SolveError> {
+        assert!(samples > 0, "Scanning needs a sample");
+        let saved = input.borrow().try_compute()?;
+        let res = self.scan(&mut self.problem(output, input, target), lo, hi, samples);
+        input.borrow().restore(saved);
+        res
+    }
+
+    /// Values of `inputs` within their `bounds` at which `output` computes
+    /// to `target`, like a spreadsheet's goal seek over several cells. From
+    /// the inputs' current values, each step is the smallest change hitting
+    /// the target were `output` linear, halved until it gets closer. Inputs
+    /// at a bound the step would push past are held there. Derivatives come
+    /// from `autodiff`, or from finite differences through custom ops without
+    /// any. Bounds are narrowed to the inputs' constraints' `min` and `max`,
+    /// and every value tried is moved to the nearest one the constraint
+    /// accepts, so on a grid the target may only be reached to its spacing.
+    ///
+    /// With several inputs, many values usually hit the target; this finds
+    /// one near the start. `inputs` are left there, or restored on failure.
+    pub fn goal_seek(
+        &self,
+        output: &NodeCelled,
+        target: f32,
+        inputs: &[NodeCelled],
+        bounds: &[(f32, f32)],
+    ) -> Result<Vec<f32>, SolveError> {
+        assert_eq!(inputs.len(), bounds.len(), "One pair of bounds per input");
+        let bounds: Vec<_> = inputs
+            .iter()
+            .zip(bounds)
+            .map(|(input, &(lo, hi))| {
+                assert!(lo <= hi, "Lower bounds can't exceed upper ones");
+                match input.borrow().constraint() {
+                    Some(constraint) => (
+                        constraint.min().map_or(lo, |min| lo.max(min)),
+                        constraint.max().map_or(hi, |max| hi.min(max)),
+                    ),
+                    None => (lo, hi),
+                }
+            })
+            .collect();
+        let start = inputs
+            .iter()
+            .map(|input| input.borrow().try_compute())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut seek = Seek {
+            output,
+            inputs,
+            target,
+            tolerance: self.tolerance * 1f32.max(target.abs()),
+            bounds,
+            best: (start.clone(), f32::INFINITY),
+        };
+        let clamped = start
+            .iter()
+            .zip(&seek.bounds)
+            .map(|(x, (lo, hi))| x.clamp(*lo, *hi))
+            .collect();
+        let clamped = seek.snap(clamped);
+        match self.seek(&mut seek, clamped) {
+            Ok(Some(x)) => {
+                // Probes aren't changes: the inputs move from the start to
+                // `x` in one.
+                seek.restore(&start);
+                for (input, x) in inputs.iter().zip(&x) {
+                    input.borrow().try_set(*x)?;
+                }
+                Ok(x)
+            }
+            Ok(None) => {
+                seek.restore(&start);
+                let (best, residual) = seek.best;
+                Err(SolveError::GoalNotReached { best, residual })
+            }
+            Err(e) => {
+                seek.restore(&start);
+                Err(e)
+            }
+        }
+    }
+
+    fn seek(&self, seek: &mut Seek, mut x: Vec<f32>) -> Result<Option<Vec<f32>>, SolveError> {
+        let mut fx = seek.eval(&x)?;
+        for _ in 0..self.max_iterations {
+            if fx.abs() <= seek.tolerance {
+                return Ok(Some(x));
+            }
+            let slope = seek.slope(&x)?;
+
+            let mut free = vec![true; x.len()];
+            let step = loop {
+                let norm: f32 = (0..x.len())
+                    .filter(|i| free[*i])
+                    .map(|i| slope[i] * slope[i])
+                    .sum();
+                if !(norm > 0.0 && norm.is_finite()) {
+                    return Ok(None);
+                }
+                let step: Vec<_> = (0..x.len())
+                    .map(|i| if free[i] { -fx * slope[i] / norm } else { 0.0 })
+                    .collect();
+                let mut blocked = false;
+                for (i, (lo, hi)) in seek.bounds.iter().enumerate() {
+                    if (step[i] < 0.0 && x[i] <= *lo) || (step[i] > 0.0 && x[i] >= *hi) {
+                        free[i] = false;
+                        blocked = true;
+                    }
+                }
+                if !blocked {
+                    break step;
+                }
+            };
+
+            let mut scale = 1.0;
+            loop {
+                let candidate = x
+                    .iter()
+                    .zip(&step)
+                    .zip(&seek.bounds)
+                    .map(|((x, dx), (lo, hi))| (x + scale * dx).clamp(*lo, *hi))
+                    .collect();
+                let candidate = seek.snap(candidate);
+                let f_candidate = seek.eval(&candidate)?;
+                if f_candidate.abs() < fx.abs() {
+                    (x, fx) = (candidate, f_candidate);
+                    break;
+                }
+                scale /= 2.0;
+                if scale < 1e-6 {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn scan(
+        &self,
+        problem: &mut Problem,
+        lo: f32,
+        hi: f32,
+        samples: usize,
+    ) -> Result<Vec<Root>, SolveError> {
+        let points: Vec<_> = (0..=samples)
+            .map(|i| lo + (hi - lo) * (i as f32 / samples as f32))
+            .collect();
+        let values = points
+            .iter()
+            .map(|x| problem.eval(*x))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut res = Vec::new();
+        for i in 0..=samples {
+            if problem.solved(values[i]) {
+                res.push(Root {
+                    x: points[i],
+                    residual: values[i].abs(),
+                });
+                continue;
+            }
+            let Some(next) = values.get(i + 1) else {
+                continue;
+            };
+            if problem.solved(*next) || !crosses(values[i], *next) {
+                continue;
+            }
+            if let Some(x) = self.refine(problem, points[i], points[i + 1])? {
+                let residual = problem.eval(x)?.abs();
+                res.push(Root { x, residual });
+            }
+        }
+        Ok(res)
+    }
+
+    fn problem<'a>(
+        &self,
+        output: &'a NodeCelled,
+        input: &'a NodeCelled,
+        target: f32,
+    ) -> Problem<'a> {
+        Problem {
+            output,
+            input,
+            target,
+            tolerance: self.tolerance * 1f32.max(target.abs()),
+            best: (f32::NAN, f32::INFINITY),
+        }
+    }
+
+    fn newton(&self, problem: &mut Problem, mut x: f32) -> Result<Option<f32>, SolveError> {
+        for _ in 0..self.max_iterations {
+            let fx = problem.eval(x)?;
+            if problem.solved(fx) {
+                return Ok(Some(x));
+            }
+            let Ok(slope) = gradient(problem.output, std::slice::from_ref(problem.input)) else {
+                return Ok(None);
+            };
+            let next = x - fx / slope[0];
+            if !next.is_finite() || next == x {
+                return Ok(None);
+            }
+            x = next;
+        }
+        Ok(None)
+    }
+
+    /// Brent's method on `[lo, hi]`, across which the output crosses the
+    /// target, as in Numerical Recipes' `zbrent`.
+    fn refine(&self, problem: &mut Problem, lo: f32, hi: f32) -> Result<Option<f32>, SolveError> {
+        let (mut a, mut b) = (lo as f64, hi as f64);
+        let (mut fa, mut fb) = (problem.eval(lo)? as f64, problem.eval(hi)? as f64);
+        let (mut c, mut fc) = (b, fb);
+        let (mut d, mut e) = (b - a, b - a);
+
+        for _ in 0..self.max_iterations {
+            if problem.solved(fb as f32) {
+                return Ok(Some(b as f32));
+            }
+            if (fb > 0.0) == (fc > 0.0) {
+                (c, fc) = (a, fa);
+                (d, e) = (b - a, b - a);
+            }
+            if fc.abs() < fb.abs() {
+                (a, fa) = (b, fb);
+                (b, fb) = (c, fc);
+                (c, fc) = (a, fa);
+            }
+
+            // Half the bracket, and the smallest step `f32` can take at `b`.
+            let half = (c - b) / 2.0;
+            let resolution = f32::EPSILON as f64 * b.abs().max(f32::MIN_POSITIVE as f64);
+            if half.abs() <= resolution {
+                return Ok(None);
+            }
+
+            if e.abs() >= resolution && fa.abs() > fb.abs() {
+                let s = fb / fa;
+                let (mut p, mut q) = if a == c {
+                    (2.0 * half * s, 1.0 - s)
+                } else {
+                    let (q, r) = (fa / fc, fb / fc);
+                    (
+                        s * (2.0 * half * q * (q - r) - (b - a) * (r - 1.0)),
+                        (q - 1.0) * (r - 1.0) * (s - 1.0),
+                    )
+                };
+                if p > 0.0 {
+                    q = -q;
+                }
+                p = p.abs();
+                let limit = (3.0 * half * q - (resolution * q).abs()).min((e * q).abs());
+                if 2.0 * p < limit {
+                    (e, d) = (d, p / q);
+                } else {
+                    (d, e) = (half, half);
+                }
+            } else {
+                (d, e) = (half, half);
+            }
+
+            (a, fa) = (b, fb);
+            b += if d.abs() > resolution {
+                d
+            } else {
+                resolution.copysign(half)
+            };
+            // Stay on values the input can hold.
+            b = b as f32 as f64;
+            fb = problem.eval(b as f32)? as f64;
+        }
+        Ok(None)
+    }
+}
+
+/// Root found by `Solver::roots`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Root {
+    pub x: f32,
+    /// Distance of the output from the target at `x`.
+    pub residual: f32,
+}
+
+/// `Solver::solve` with the default settings.
+pub fn solve_for(output: &NodeCelled, input: &NodeCelled, target: f32) -> Result<f32, SolveError> {
+    Solver::default().solve(output, input, target)
+}
+
+struct Problem<'a> {
+    output: &'a NodeCelled,
+    input: &'a NodeCelled,
+    target: f32,
+    tolerance: f32,
+    /// Input value with the smallest miss so far, and the miss.
+    best: (f32, f32),
+}
+
+impl Problem<'_> {
+    /// Output minus target at input `x`, NaN if the input's constraint
+    /// rejects `x`, so that searches pass over it as over a gap.
+    fn eval(&mut self, x: f32) -> Result<f32, SolveError> {
+        if self.input.borrow().probe(x).is_err() {
+            return Ok(f32::NAN);
+        }
+        let res = self.output.borrow().try_compute()? - self.target;
+        if res.abs() < self.best.1 {
+            self.best = (x, res.abs());
+        }
+        Ok(res)
+    }
+
+    fn solved(&self, fx: f32) -> bool {
+        fx.abs() <= self.tolerance
+    }
+
+    /// Leaves the input at the solution `res`, or at `start` if there's none
+    /// or the search failed.
+    fn finish(&self, res: Result<Option<f32>, SolveError>, start: f32) -> Result<f32, SolveError> {
+        match res {
+            Ok(Some(x)) => {
+                // Probes aren't changes: the input moves from the start to
+                // `x` in one.
+                self.input.borrow().restore(start);
+                self.input.borrow().try_set(x)?;
+                Ok(x)
+            }
+            Ok(None) => {
+                self.input.borrow().restore(start);
+                let (best, residual) = self.best;
+                Err(SolveError::NoConvergence { best, residual })
+            }
+            Err(e) => {
+                self.input.borrow().restore(start);
+                Err(e)
+            }
+        }
+    }
+
+    fn bracket(&mut self, start: f32) -> Result<Option<(f32, f32)>, SolveError> {
+        let f_start = self.eval(start)?;
+        if f_start.is_nan() {
+            return Ok(None);
+        }
+        let mut step = 0.1f32.max(start.abs() * 0.1);
+        let mut previous = [(start, f_start); 2];
+        while step.is_finite() {
+            for (side, direction) in [-1f32, 1.0].into_iter().enumerate() {
+                let x = start + direction * step;
+                let fx = self.eval(x)?;
+                let (last, f_last) = previous[side];
+                if crosses(f_last, fx) {
+                    return Ok(Some(if x < last { (x, last) } else { (last, x) }));
+                }
+                if !fx.is_nan() {
+                    previous[side] = (x, fx);
+                }
+            }
+            step *= 2.0;
+        }
+        Ok(None)
+    }
+}
+
+struct Seek<'a> {
+    output: &'a NodeCelled,
+    inputs: &'a [NodeCelled],
+    target: f32,
+    tolerance: f32,
+    bounds: Vec<(f32, f32)>,
+    /// Input values with the smallest miss so far, and the miss.
+    best: (Vec<f32>, f32),
+}
+
+impl Seek<'_> {
+    /// `x` moved to the nearest value input `i`'s constraint accepts.
+    fn nearest(&self, i: usize, x: f32) -> f32 {
+        match self.inputs[i].borrow().constraint() {
+            Some(constraint) => constraint.nearest(x),
+            None => x,
+        }
+    }
+
+    fn snap(&self, x: Vec<f32>) -> Vec<f32> {
+        x.into_iter()
+            .enumerate()
+            .map(|(i, x)| self.nearest(i, x))
+            .collect()
+    }
+
+    fn probe(&self, x: &[f32]) -> Result<(), SolveError> {
+        for (input, x) in self.inputs.iter().zip(x) {
+            input.borrow().probe(*x)?;
+        }
+        Ok(())
+    }
+
+    fn restore(&self, x: &[f32]) {
+        for (input, x) in self.inputs.iter().zip(x) {
+            input.borrow().restore(*x);
+        }
+    }
+
+    /// Output minus target at input values `x`.
+    fn eval(&mut self, x: &[f32]) -> Result<f32, SolveError> {
+        self.probe(x)?;
+        let res = self.output.borrow().try_compute()? - self.target;
+        if res.abs() < self.best.1 {
+            self.best = (x.to_vec(), res.abs());
+        }
+        Ok(res)
+    }
+
+    /// Derivatives of the output at `x`, the current input values.
+    fn slope(&mut self, x: &[f32]) -> Result<Vec<f32>, SolveError> {
+        match gradient(self.output, self.inputs) {
+            Ok(slope) => return Ok(slope),
+            Err(GradError::Eval(e)) => return Err(e.into()),
+            Err(GradError::Constraint(e)) => return Err(e.into()),
+            Err(GradError::NotDifferentiable { .. }) => {}
+        }
+
+        let mut res = Vec::with_capacity(x.len());
+        let mut moved = x.to_vec();
+        for (i, (lo, hi)) in self.bounds.clone().into_iter().enumerate() {
+            // At least a step apart on a grid, which snapping would undo.
+            let constraint = self.inputs[i].borrow().constraint();
+            let step = constraint.and_then(|constraint| constraint.step());
+            let h = (1e-3 * 1f32.max(x[i].abs())).max(step.unwrap_or(0.0));
+            let a = self.nearest(i, (x[i] - h).max(lo));
+            let b = self.nearest(i, (x[i] + h).min(hi));
+            if a == b {
+                res.push(0.0);
+                continue;
+            }
+            moved[i] = a;
+            let fa = self.eval(&moved)?;
+            moved[i] = b;
+            let fb = self.eval(&moved)?;
+            moved[i] = x[i];
+            res.push((fb - fa) / (b - a));
+        }
+        self.probe(x)?;
+        Ok(res)
+    }
+}
+
+/// Whether a continuous function going from `a` to `b` passes through zero.
+fn crosses(a: f32, b: f32) -> bool {
+    !a.is_nan() && !b.is_nan() && (a < 0.0) != (b < 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit;
+    use crate::computational_graph::{Node, PowPolicy};
+    use crate::constraint::Constraint;
+
+    /// `1 / x`, failing at zero.
+    fn reciprocal(x: &NodeCelled) -> NodeCelled {
+        Node::create_strict_pow(x.clone(), Node::create_input(-1f32), PowPolicy::Error)
+    }
+
+    #[test]
+    fn brent_restores_input_on_error() {
+        let x = Node::create_input(2.5f32);
+        let output = reciprocal(&x);
+        let res = Solver::default().brent(&output, &x, 1f32, 0f32, 10f32);
+        assert!(matches!(res, Err(SolveError::Eval(_))));
+        assert_eq!(x.borrow().compute(), 2.5f32);
+    }
+
+    #[test]
+    fn brent_restores_input_when_not_bracketed() {
+        let x = Node::create_input(2.5f32);
+        let output = reciprocal(&x);
+        let res = Solver::default().brent(&output, &x, -1f32, 1f32, 10f32);
+        assert!(matches!(res, Err(SolveError::NotBracketed { .. })));
+        assert_eq!(x.borrow().compute(), 2.5f32);
+    }
+
+    #[test]
+    fn solve_restores_input_on_error() {
+        // Newton's first step from 2 lands on 0.
+        let x = Node::create_input(2f32);
+        let output = reciprocal(&x);
+        let res = Solver::default().solve(&output, &x, 1f32);
+        assert!(matches!(res, Err(SolveError::Eval(_))));
+        assert_eq!(x.borrow().compute(), 2f32);
+    }
+
+    #[test]
+    fn goal_seek_keeps_to_constraints() {
+        let x = Node::create_input(0f32);
+        x.borrow()
+            .set_constraint(Constraint::new().with_min(0f32).with_step(0.5));
+        let output = Node::create_mul(x.clone(), Node::create_input(2f32));
+        let (solver, inputs) = (Solver::default(), [x.clone()]);
+
+        let res = solver.goal_seek(&output, 3f32, &inputs, &[(-10f32, 10f32)]);
+        assert_eq!(res.unwrap(), [1.5f32]);
+        assert_eq!(x.borrow().compute(), 1.5f32);
+
+        // Off the grid, the closest grid point is as near as it gets.
+        let res = solver.goal_seek(&output, 3.4f32, &inputs, &[(-10f32, 10f32)]);
+        let Err(SolveError::GoalNotReached { best, .. }) = res else {
+            panic!("{res:?}");
+        };
+        assert_eq!(best, [1.5f32]);
+        assert_eq!(x.borrow().compute(), 1.5f32);
+    }
+
+    #[test]
+    fn goal_seek_records_only_its_result() {
+        let x = Node::create_input(1f32);
+        let output = Node::create_mul(x.clone(), x.clone());
+        let inputs = [x.clone()];
+        let log = audit::AuditLog::new();
+        audit::record_to(&log);
+
+        let res = Solver::default().goal_seek(&output, -1f32, &inputs, &[(-5f32, 5f32)]);
+        assert!(matches!(res, Err(SolveError::GoalNotReached { .. })));
+        assert!(log.is_empty());
+
+        let res = Solver::default().goal_seek(&output, 4f32, &inputs, &[(0f32, 5f32)]);
+        audit::stop();
+        let root = res.unwrap()[0];
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].old, entries[0].new), (1f32, root));
+    }
+
+    #[test]
+    fn roots_skip_rejected_values() {
+        let x = Node::create_input(1f32);
+        x.borrow().set_constraint(Constraint::new().with_min(0f32));
+        let output = Node::create_mul(x.clone(), x.clone());
+        let solver = Solver::default();
+
+        let roots = solver.roots(&output, &x, 4f32, (-5f32, 5f32), 10).unwrap();
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0].x - 2f32).abs() < 1e-5);
+        assert_eq!(x.borrow().compute(), 1f32);
+
+        let bracket = solver.bracket(&output, &x, 4f32, 0.5f32).unwrap().unwrap();
+        assert!(bracket.0 >= 0f32 && bracket.0 <= 2f32 && bracket.1 >= 2f32);
+        assert_eq!(x.borrow().compute(), 1f32);
+    }
+}
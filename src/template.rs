@@ -0,0 +1,106 @@
+//! Formulas built once and instantiated per entity, each instance reading its
+//! own input nodes.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::computational_graph::{Node, NodeCelled};
+use crate::optimize::transform;
+
+/// A graph over placeholder `Input`s. Instances get fresh nodes for everything
+/// depending on a placeholder; the rest, including inputs that aren't
+/// placeholders (time, global parameters), is shared by all of them.
+#[derive(Debug, Clone)]
+pub struct GraphTemplate {
+    output: NodeCelled,
+    placeholders: Vec<NodeCelled>,
+}
+
+impl GraphTemplate {
+    pub fn new(output: NodeCelled, placeholders: Vec<NodeCelled>) -> Self {
+        for placeholder in &placeholders {
+            assert!(
+                matches!(&*placeholder.borrow(), Node::Input { .. }),
+                "Placeholders must be \"Input\" nodes"
+            );
+        }
+
+        Self {
+            output,
+            placeholders,
+        }
+    }
+
+    /// Creates `placeholders` fresh inputs and hands them to `build`.
+    pub fn build(placeholders: usize, build: impl FnOnce(&[NodeCelled]) -> NodeCelled) -> Self {
+        let placeholders: Vec<_> = (0..placeholders)
+            .map(|_| Node::create_input(0f32))
+            .collect();
+        Self::new(build(&placeholders), placeholders)
+    }
+
+    pub fn placeholder_count(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    /// Output node of a new instance, with placeholder `i` replaced by
+    /// `bindings[i]`, which may be any node.
+    pub fn instantiate(&self, bindings: &[NodeCelled]) -> NodeCelled {
+        assert_eq!(
+            bindings.len(),
+            self.placeholders.len(),
+            "Wrong number of bindings"
+        );
+
+        let bound: HashMap<_, _> = self
+            .placeholders
+            .iter()
+            .zip(bindings)
+            .map(|(placeholder, binding)| (Rc::as_ptr(placeholder), binding.clone()))
+            .collect();
+        transform(&self.output, &mut |node| {
+            bound.get(&Rc::as_ptr(node)).cloned()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_read_their_own_inputs() {
+        let rate = Node::create_input(0.5f32);
+        let template = GraphTemplate::build(2, |p| {
+            Node::create_mul(Node::create_add(p[0].clone(), p[1].clone()), rate.clone())
+        });
+        assert_eq!(template.placeholder_count(), 2);
+
+        let a = [Node::create_input(1f32), Node::create_input(3f32)];
+        let b = [Node::create_input(10f32), Node::create_const(20f32)];
+        let (first, second) = (template.instantiate(&a), template.instantiate(&b));
+        assert_eq!(first.borrow().compute(), 2f32);
+        assert_eq!(second.borrow().compute(), 15f32);
+
+        a[0].borrow().set(5f32);
+        assert_eq!(first.borrow().compute(), 4f32);
+        assert_eq!(second.borrow().compute(), 15f32);
+        rate.borrow().set(1f32);
+        assert_eq!(first.borrow().compute(), 8f32);
+        assert_eq!(second.borrow().compute(), 30f32);
+    }
+
+    #[test]
+    fn shares_what_no_placeholder_reaches() {
+        let time = Node::create_input(2f32);
+        let shared = Node::create_sin(time.clone());
+        let template = GraphTemplate::build(1, |p| Node::create_add(p[0].clone(), shared.clone()));
+        let first = template.instantiate(&[Node::create_input(1f32)]);
+        let second = template.instantiate(&[Node::create_input(2f32)]);
+        let children = |node: &NodeCelled| node.borrow().children();
+        assert!(Rc::ptr_eq(&children(&first)[1], &shared));
+        assert!(Rc::ptr_eq(&children(&second)[1], &shared));
+        assert!(!Rc::ptr_eq(&first, &second));
+        assert_eq!(second.borrow().compute(), 2f32 + 2f32.sin());
+    }
+}
@@ -0,0 +1,287 @@
+//! Tensors as row-major arrays of scalar nodes, combined elementwise with
+//! NumPy-style broadcasting. Shapes are checked when ops are built, so a
+//! mismatch never reaches evaluation.
+
+use std::fmt;
+
+use crate::computational_graph::{Comparison, EvalError, Node, NodeCelled};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShapeError {
+    /// `nodes` elements can't fill `shape`.
+    Size { shape: Vec<usize>, nodes: usize },
+    /// Some dimension differs and neither is `1`.
+    Mismatch { left: Vec<usize>, right: Vec<usize> },
+    /// An op takes tensors of rank `expected` only.
+    Rank { expected: usize, shape: Vec<usize> },
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Size { shape, nodes } => write!(f, "{nodes} nodes don't fill shape {shape:?}"),
+            Self::Mismatch { left, right } => {
+                write!(f, "shapes {left:?} and {right:?} don't broadcast")
+            }
+            Self::Rank { expected, shape } => {
+                write!(f, "shape {shape:?} isn't of rank {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+/// Shape both `a` and `b` broadcast to. Shapes are aligned at their last
+/// dimension, and missing or size `1` dimensions stretch to the other one.
+pub fn broadcast_shapes(a: &[usize], b: &[usize]) -> Result<Vec<usize>, ShapeError> {
+    let rank = a.len().max(b.len());
+    // Dimension `i` from the end, `1` past the start.
+    let dim = |shape: &[usize], i: usize| shape.len().checked_sub(i + 1).map_or(1, |i| shape[i]);
+
+    let mut res: Vec<_> = (0..rank)
+        .map(|i| match (dim(a, i), dim(b, i)) {
+            (x, y) if x == y || y == 1 => Ok(x),
+            (1, y) => Ok(y),
+            _ => Err(ShapeError::Mismatch {
+                left: a.to_vec(),
+                right: b.to_vec(),
+            }),
+        })
+        .collect::<Result<_, _>>()?;
+    res.reverse();
+    Ok(res)
+}
+
+#[derive(Debug, Clone)]
+pub struct Tensor {
+    shape: Vec<usize>,
+    nodes: Vec<NodeCelled>,
+}
+
+impl Tensor {
+    pub fn new(shape: Vec<usize>, nodes: Vec<NodeCelled>) -> Result<Self, ShapeError> {
+        if shape.iter().product::<usize>() != nodes.len() {
+            return Err(ShapeError::Size {
+                shape,
+                nodes: nodes.len(),
+            });
+        }
+        Ok(Self { shape, nodes })
+    }
+
+    /// Rank 0 tensor of `node`, which broadcasts to any shape.
+    pub fn scalar(node: NodeCelled) -> Self {
+        Self {
+            shape: Vec::new(),
+            nodes: vec![node],
+        }
+    }
+
+    /// Fresh inputs at `values`, in row-major order.
+    pub fn inputs(shape: Vec<usize>, values: &[f32]) -> Result<Self, ShapeError> {
+        let nodes = values.iter().map(|x| Node::create_input(*x)).collect();
+        Self::new(shape, nodes)
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// Elements in row-major order.
+    pub fn nodes(&self) -> &[NodeCelled] {
+        &self.nodes
+    }
+
+    /// The elements of a rank 1 tensor.
+    pub(crate) fn vector(&self) -> Result<&[NodeCelled], ShapeError> {
+        match self.shape[..] {
+            [_] => Ok(&self.nodes),
+            _ => Err(ShapeError::Rank {
+                expected: 1,
+                shape: self.shape.clone(),
+            }),
+        }
+    }
+
+    pub fn get(&self, index: &[usize]) -> Option<&NodeCelled> {
+        if index.len() != self.shape.len() || index.iter().zip(&self.shape).any(|(i, n)| i >= n) {
+            return None;
+        }
+        let offset = index
+            .iter()
+            .zip(&self.shape)
+            .fold(0, |offset, (i, n)| offset * n + i);
+        self.nodes.get(offset)
+    }
+
+    /// This tensor repeated along stretched dimensions, sharing its nodes.
+    pub fn broadcast_to(&self, shape: &[usize]) -> Result<Self, ShapeError> {
+        if broadcast_shapes(&self.shape, shape)? != shape {
+            return Err(ShapeError::Mismatch {
+                left: self.shape.clone(),
+                right: shape.to_vec(),
+            });
+        }
+
+        // Row-major strides of this tensor, aligned to `shape`'s last
+        // dimension, `0` where it stretches.
+        let lead = shape.len() - self.shape.len();
+        let mut strides = vec![0; shape.len()];
+        let mut stride = 1;
+        for (i, n) in self.shape.iter().enumerate().rev() {
+            if *n != 1 {
+                strides[lead + i] = stride;
+            }
+            stride *= n;
+        }
+
+        let len = shape.iter().product();
+        let nodes = (0..len)
+            .map(|mut flat| {
+                let mut offset = 0;
+                for (n, stride) in shape.iter().zip(&strides).rev() {
+                    offset += flat % n * stride;
+                    flat /= n;
+                }
+                self.nodes[offset].clone()
+            })
+            .collect();
+        Ok(Self {
+            shape: shape.to_vec(),
+            nodes,
+        })
+    }
+
+    /// The elements as a vector.
+    pub fn flatten(&self) -> Self {
+        Self {
+            shape: vec![self.nodes.len()],
+            nodes: self.nodes.clone(),
+        }
+    }
+
+    /// `f` of every element.
+    pub fn map(&self, mut f: impl FnMut(NodeCelled) -> NodeCelled) -> Self {
+        Self {
+            shape: self.shape.clone(),
+            nodes: self.nodes.iter().cloned().map(&mut f).collect(),
+        }
+    }
+
+    /// `f` of every pair of elements once both are broadcast to a common
+    /// shape.
+    pub fn zip_with(
+        &self,
+        other: &Self,
+        mut f: impl FnMut(NodeCelled, NodeCelled) -> NodeCelled,
+    ) -> Result<Self, ShapeError> {
+        let shape = broadcast_shapes(&self.shape, &other.shape)?;
+        let (a, b) = (self.broadcast_to(&shape)?, other.broadcast_to(&shape)?);
+        let nodes = a
+            .nodes
+            .into_iter()
+            .zip(b.nodes)
+            .map(|(a, b)| f(a, b))
+            .collect();
+        Ok(Self { shape, nodes })
+    }
+
+    pub fn add(&self, other: &Self) -> Result<Self, ShapeError> {
+        self.zip_with(other, Node::create_add)
+    }
+
+    pub fn mul(&self, other: &Self) -> Result<Self, ShapeError> {
+        self.zip_with(other, Node::create_mul)
+    }
+
+    pub fn pow(&self, other: &Self) -> Result<Self, ShapeError> {
+        self.zip_with(other, Node::create_pow)
+    }
+
+    pub fn and(&self, other: &Self) -> Result<Self, ShapeError> {
+        self.zip_with(other, Node::create_and)
+    }
+
+    pub fn or(&self, other: &Self) -> Result<Self, ShapeError> {
+        self.zip_with(other, Node::create_or)
+    }
+
+    pub fn compare(&self, other: &Self, cmp: Comparison) -> Result<Self, ShapeError> {
+        self.zip_with(other, |a, b| Node::create_compare(a, b, cmp))
+    }
+
+    /// Element values, in row-major order.
+    pub fn values(&self) -> Result<Vec<f32>, EvalError> {
+        self.nodes
+            .iter()
+            .map(|node| node.borrow().try_compute())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn broadcasts_shapes() {
+        assert_eq!(broadcast_shapes(&[2, 3], &[3]), Ok(vec![2, 3]));
+        assert_eq!(broadcast_shapes(&[4, 1, 3], &[2, 1]), Ok(vec![4, 2, 3]));
+        assert_eq!(broadcast_shapes(&[], &[5]), Ok(vec![5]));
+        assert_eq!(
+            broadcast_shapes(&[2, 3], &[2]),
+            Err(ShapeError::Mismatch {
+                left: vec![2, 3],
+                right: vec![2],
+            })
+        );
+    }
+
+    #[test]
+    fn indexes_row_major() {
+        let t = Tensor::inputs(vec![2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(t.get(&[1, 0]).unwrap().borrow().compute(), 4f32);
+        assert_eq!(t.get(&[0, 2]).unwrap().borrow().compute(), 3f32);
+        assert!(t.get(&[2, 0]).is_none());
+        assert!(t.get(&[0]).is_none());
+        assert_eq!(t.flatten().shape(), [6]);
+
+        let res = Tensor::inputs(vec![2, 2], &[1.0]);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "1 nodes don't fill shape [2, 2]"
+        );
+        let rank = t.vector().unwrap_err();
+        assert_eq!(rank.to_string(), "shape [2, 3] isn't of rank 1");
+    }
+
+    #[test]
+    fn combines_elementwise_with_broadcasting() {
+        let m = Tensor::inputs(vec![2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let row = Tensor::inputs(vec![3], &[10.0, 20.0, 30.0]).unwrap();
+        let column = Tensor::inputs(vec![2, 1], &[1.0, -1.0]).unwrap();
+        let sum = m.add(&row).unwrap();
+        assert_eq!(sum.shape(), [2, 3]);
+        assert_eq!(sum.values().unwrap(), [11.0, 22.0, 33.0, 14.0, 25.0, 36.0]);
+        let product = m.mul(&column).unwrap();
+        assert_eq!(product.values().unwrap(), [1.0, 2.0, 3.0, -4.0, -5.0, -6.0]);
+        let two = Tensor::scalar(Node::create_const(2f32));
+        assert_eq!(
+            row.pow(&two).unwrap().values().unwrap(),
+            [100.0, 400.0, 900.0]
+        );
+        let greater = m.compare(&two, Comparison::Gt).unwrap();
+        assert_eq!(greater.values().unwrap(), [0.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+
+        // Broadcasting shares the stretched tensor's nodes.
+        let stretched = column.broadcast_to(&[2, 3]).unwrap();
+        assert!(Rc::ptr_eq(
+            stretched.get(&[1, 2]).unwrap(),
+            column.get(&[1, 0]).unwrap()
+        ));
+        assert!(column.broadcast_to(&[3]).is_err());
+        assert!(m.add(&column.flatten()).is_err());
+    }
+}
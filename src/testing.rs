@@ -0,0 +1,176 @@
+//! Helpers for testing code that builds graphs: approximate comparisons,
+//! ready-made graphs and random ones.
+
+use crate::computational_graph::{Comparison, Node, NodeCelled, RoundMode};
+use crate::random::Rng;
+
+/// Whether `a` and `b` differ by at most `eps`, relative to the larger of them
+/// once that exceeds 1. NaNs equal each other, infinities only themselves.
+pub fn approx_eq(a: f32, b: f32, eps: f32) -> bool {
+    if a.is_nan() || b.is_nan() {
+        return a.is_nan() && b.is_nan();
+    }
+    if a.is_infinite() || b.is_infinite() {
+        return a == b;
+    }
+    (a - b).abs() <= eps * 1f32.max(a.abs()).max(b.abs())
+}
+
+/// Panics unless `node` computes to `expected`, within `eps` as in
+/// `approx_eq`, naming the formula on failure.
+#[track_caller]
+pub fn assert_node_approx_eq(node: &NodeCelled, expected: f32, eps: f32) {
+    let node = node.borrow();
+    let value = node
+        .try_compute()
+        .unwrap_or_else(|e| panic!("{node} failed to compute: {e}"));
+    assert!(
+        approx_eq(value, expected, eps),
+        "{node} = {value}, expected {expected} (eps {eps})"
+    );
+}
+
+/// Panics unless `a` and `b` have the same topology, ops and constants, as
+/// compared by `Node::fingerprint`.
+#[track_caller]
+pub fn assert_graph_eq(a: &NodeCelled, b: &NodeCelled) {
+    assert!(
+        Node::fingerprint(a) == Node::fingerprint(b),
+        "graphs differ:\n  {}\n  {}",
+        a.borrow(),
+        b.borrow()
+    );
+}
+
+/// A graph along with its inputs, in the order setters expect.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub inputs: Vec<NodeCelled>,
+    pub output: NodeCelled,
+}
+
+impl Fixture {
+    /// `x1 + x2 * sin(x2 + x3^x4)` at `1, 2, 3, 3`, which is about `-0.32727`.
+    pub fn example() -> Self {
+        let inputs: Vec<_> = [1f32, 2f32, 3f32, 3f32]
+            .into_iter()
+            .map(Node::create_input)
+            .collect();
+        let [x1, x2, x3, x4] = [0, 1, 2, 3].map(|i| inputs[i].clone());
+        let output = Node::create_add(
+            x1,
+            Node::create_mul(
+                x2.clone(),
+                Node::create_sin(Node::create_add(x2, Node::create_pow(x3, x4))),
+            ),
+        );
+        Self { inputs, output }
+    }
+
+    /// `x0 + x1 + ... + x(n-1)` at `0, 1, ...`, a graph `n` nodes deep.
+    pub fn chain(n: usize) -> Self {
+        assert!(n > 0, "A chain needs an input");
+        let inputs: Vec<_> = (0..n).map(|i| Node::create_input(i as f32)).collect();
+        let output = inputs[1..]
+            .iter()
+            .fold(inputs[0].clone(), |sum, x| Node::create_add(sum, x.clone()));
+        Self { inputs, output }
+    }
+
+    /// Last of `ops` nodes over `inputs` inputs valued in `[-1, 1)`, each op
+    /// reading random earlier nodes, so some may not reach the output. Ops
+    /// stay finite on finite operands (no `Pow`), and the same seed builds the
+    /// same graph.
+    pub fn random(rng: &Rng, inputs: usize, ops: usize) -> Self {
+        assert!(inputs > 0, "A random graph needs an input");
+        let inputs: Vec<_> = (0..inputs)
+            .map(|_| Node::create_input(rng.next_f32() * 2.0 - 1.0))
+            .collect();
+        let mut nodes = inputs.clone();
+        for _ in 0..ops {
+            let pick = || nodes[(rng.next_u64() % nodes.len() as u64) as usize].clone();
+            let node = match rng.next_u64() % 7 {
+                0 => Node::create_add(pick(), pick()),
+                1 => Node::create_mul(pick(), pick()),
+                2 => Node::create_sin(pick()),
+                3 => Node::create_cos(pick()),
+                4 => Node::create_mul_add(pick(), pick(), pick()),
+                5 => Node::create_select(
+                    Node::create_compare(pick(), pick(), Comparison::Lt),
+                    pick(),
+                    pick(),
+                ),
+                _ => Node::create_round(pick(), 3, RoundMode::HalfUp),
+            };
+            nodes.push(node);
+        }
+
+        Self {
+            output: nodes.pop().unwrap(),
+            inputs,
+        }
+    }
+
+    /// Sets the inputs, in order, to `values`.
+    pub fn set(&self, values: &[f32]) {
+        assert_eq!(values.len(), self.inputs.len(), "One value per input");
+        for (input, x) in self.inputs.iter().zip(values) {
+            input.borrow().set(*x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_approximately() {
+        assert!(approx_eq(0.1, 0.1001, 1e-3));
+        assert!(!approx_eq(0.1, 0.102, 1e-3));
+        // Relative beyond 1.
+        assert!(approx_eq(1000.0, 1000.9, 1e-3));
+        assert!(!approx_eq(1000.0, 1002.0, 1e-3));
+        assert!(approx_eq(f32::NAN, f32::NAN, 0.0));
+        assert!(!approx_eq(f32::NAN, 0.0, 1.0));
+        assert!(approx_eq(f32::INFINITY, f32::INFINITY, 0.0));
+        assert!(!approx_eq(f32::INFINITY, f32::MAX, 1.0));
+    }
+
+    #[test]
+    fn fixtures_compute() {
+        let example = Fixture::example();
+        assert_node_approx_eq(&example.output, -0.32727, 1e-4);
+        example.set(&[0.0, 0.0, 1.0, 1.0]);
+        assert_node_approx_eq(&example.output, 0.0, 0.0);
+
+        let chain = Fixture::chain(5);
+        assert_node_approx_eq(&chain.output, 10.0, 0.0);
+        assert_eq!(Node::topo_order(&chain.output).len(), 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 2 (eps 0.001)")]
+    fn names_formulas_computing_wrong_values() {
+        assert_node_approx_eq(&Fixture::chain(2).output, 2.0, 1e-3);
+    }
+
+    #[test]
+    fn seeds_determine_random_fixtures() {
+        let build = |seed| Fixture::random(&Rng::new(seed), 3, 20);
+        let (a, b, c) = (build(1), build(1), build(2));
+        assert_graph_eq(&a.output, &b.output);
+        assert_ne!(Node::fingerprint(&a.output), Node::fingerprint(&c.output));
+        assert_eq!(a.inputs.len(), 3);
+        for input in &a.inputs {
+            assert!((-1.0..1.0).contains(&input.borrow().compute()));
+        }
+        assert!(a.output.borrow().compute().is_finite());
+    }
+
+    #[test]
+    #[should_panic(expected = "graphs differ")]
+    fn tells_graphs_apart() {
+        assert_graph_eq(&Fixture::chain(2).output, &Fixture::chain(3).output);
+    }
+}
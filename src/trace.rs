@@ -0,0 +1,358 @@
+//! Spans and events describing evaluation and graph edits: a computation is
+//! a span, each op run, cache hit, invalidation and edit within it an event.
+//!
+//! They are emitted to the `tracing` crate at `TRACE` level, named as in
+//! their `Display` and with their fields as fields, and also handed to this
+//! thread's `Subscriber`, if any, e.g. a `Recorder` to inspect one
+//! evaluation. When neither wants them, nothing is built.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tracing::Level;
+
+use crate::computational_graph::{CachePolicy, NodeId};
+
+thread_local! {
+    static SUBSCRIBER: RefCell<Option<Rc<dyn Subscriber>>> = const { RefCell::new(None) };
+}
+
+/// Receives this thread's spans and events.
+pub trait Subscriber {
+    fn enter(&self, span: &Span);
+
+    /// `span` ended, `elapsed` after `enter`.
+    fn exit(&self, span: &Span, elapsed: Duration);
+
+    fn event(&self, event: &Event);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Span {
+    /// `try_compute` (and so `compute`) of a node.
+    Compute { node: NodeId },
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compute { node } => write!(f, "compute node={node}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A node's op ran.
+    Computed {
+        node: NodeId,
+        op: String,
+        value: f32,
+    },
+    /// A node's cached value was current.
+    CacheHit {
+        node: NodeId,
+    },
+    /// `node` and what depends on it were marked stale.
+    Invalidated {
+        node: NodeId,
+        generation: u64,
+    },
+    /// The inputs below `node` were restored to their defaults.
+    Reset {
+        node: NodeId,
+        generation: u64,
+    },
+    Created {
+        node: NodeId,
+        op: String,
+        operands: Vec<NodeId>,
+    },
+    Set {
+        node: NodeId,
+        old: f32,
+        new: f32,
+    },
+    Fed {
+        node: NodeId,
+        source: NodeId,
+    },
+    CachePolicy {
+        node: NodeId,
+        policy: CachePolicy,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Computed { node, op, value } => {
+                write!(f, "computed node={node} op={op} value={value}")
+            }
+            Self::CacheHit { node } => write!(f, "cache-hit node={node}"),
+            Self::Invalidated { node, generation } => {
+                write!(f, "invalidated node={node} generation={generation}")
+            }
+            Self::Reset { node, generation } => {
+                write!(f, "reset node={node} generation={generation}")
+            }
+            Self::Created { node, op, operands } => {
+                write!(f, "created node={node} op={op} operands=[")?;
+                for (i, operand) in operands.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{operand}")?;
+                }
+                f.write_str("]")
+            }
+            Self::Set { node, old, new } => write!(f, "set node={node} old={old} new={new}"),
+            Self::Fed { node, source } => write!(f, "fed node={node} source={source}"),
+            Self::CachePolicy { node, policy } => {
+                write!(f, "cache-policy node={node} policy={policy:?}")
+            }
+        }
+    }
+}
+
+/// Sends this thread's spans and events to `subscriber` from now on,
+/// returning the one it replaces.
+pub fn set_subscriber(subscriber: Rc<dyn Subscriber>) -> Option<Rc<dyn Subscriber>> {
+    SUBSCRIBER.with(|current| current.borrow_mut().replace(subscriber))
+}
+
+pub fn clear_subscriber() -> Option<Rc<dyn Subscriber>> {
+    SUBSCRIBER.with(|current| current.borrow_mut().take())
+}
+
+/// Keeps every span and event, e.g. to inspect one evaluation.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    records: RefCell<Vec<Record>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Enter(Span),
+    Exit(Span, Duration),
+    Event(Event),
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns what was recorded so far, starting over.
+    pub fn take(&self) -> Vec<Record> {
+        self.records.take()
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enter(&self, span: &Span) {
+        self.records.borrow_mut().push(Record::Enter(span.clone()));
+    }
+
+    fn exit(&self, span: &Span, elapsed: Duration) {
+        self.records
+            .borrow_mut()
+            .push(Record::Exit(span.clone(), elapsed));
+    }
+
+    fn event(&self, event: &Event) {
+        self.records.borrow_mut().push(Record::Event(event.clone()));
+    }
+}
+
+fn subscriber() -> Option<Rc<dyn Subscriber>> {
+    // Cloned so subscribers may compute nodes or replace themselves.
+    SUBSCRIBER.with(|current| current.borrow().clone())
+}
+
+fn traced() -> bool {
+    tracing::enabled!(Level::TRACE)
+}
+
+/// Sends the event `make` builds to `tracing` and the subscriber, if they
+/// want it.
+pub(crate) fn event(make: impl FnOnce() -> Event) {
+    let subscriber = subscriber();
+    if subscriber.is_none() && !traced() {
+        return;
+    }
+
+    let event = make();
+    emit(&event);
+    if let Some(subscriber) = subscriber {
+        subscriber.event(&event);
+    }
+}
+
+fn emit(event: &Event) {
+    match event {
+        Event::Computed { node, op, value } => {
+            tracing::event!(Level::TRACE, %node, op = op.as_str(), value, "computed")
+        }
+        Event::CacheHit { node } => tracing::event!(Level::TRACE, %node, "cache-hit"),
+        Event::Invalidated { node, generation } => {
+            tracing::event!(Level::TRACE, %node, generation, "invalidated")
+        }
+        Event::Reset { node, generation } => {
+            tracing::event!(Level::TRACE, %node, generation, "reset")
+        }
+        Event::Created { node, op, operands } => {
+            let operands: Vec<_> = operands.iter().map(NodeId::to_string).collect();
+            let operands = operands.join(",");
+            tracing::event!(Level::TRACE, %node, op = op.as_str(), operands, "created")
+        }
+        Event::Set { node, old, new } => tracing::event!(Level::TRACE, %node, old, new, "set"),
+        Event::Fed { node, source } => tracing::event!(Level::TRACE, %node, %source, "fed"),
+        Event::CachePolicy { node, policy } => {
+            tracing::event!(Level::TRACE, %node, ?policy, "cache-policy")
+        }
+    }
+}
+
+/// Enters `span`, in `tracing` and with the subscriber, until the guard is
+/// dropped.
+pub(crate) fn enter(span: impl FnOnce() -> Span) -> Entered {
+    let subscriber = subscriber();
+    if subscriber.is_none() && !traced() {
+        return Entered {
+            _traced: None,
+            subscribed: None,
+        };
+    }
+
+    let span = span();
+    let traced = match &span {
+        Span::Compute { node } => tracing::trace_span!("compute", %node).entered(),
+    };
+    let subscribed = subscriber.map(|subscriber| {
+        subscriber.enter(&span);
+        (subscriber, span, Instant::now())
+    });
+    Entered {
+        _traced: Some(traced),
+        subscribed,
+    }
+}
+
+pub(crate) struct Entered {
+    /// Exited after `subscribed`, on drop.
+    _traced: Option<tracing::span::EnteredSpan>,
+    subscribed: Option<(Rc<dyn Subscriber>, Span, Instant)>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if let Some((subscriber, span, start)) = &self.subscribed {
+            subscriber.exit(span, start.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record as Values};
+    use tracing::Metadata;
+
+    use crate::computational_graph::Node;
+
+    /// Spans entered and event messages, by name, as `tracing` sees them.
+    #[derive(Default)]
+    struct Collector {
+        seen: Arc<Mutex<Vec<String>>>,
+        spans: Mutex<Vec<&'static str>>,
+    }
+
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{value:?}");
+            }
+        }
+    }
+
+    impl tracing::Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata().name());
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Values<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.seen.lock().unwrap().push(message.0);
+        }
+
+        fn enter(&self, span: &Id) {
+            let name = self.spans.lock().unwrap()[span.into_u64() as usize - 1];
+            self.seen.lock().unwrap().push(format!("enter {name}"));
+        }
+
+        fn exit(&self, span: &Id) {
+            let name = self.spans.lock().unwrap()[span.into_u64() as usize - 1];
+            self.seen.lock().unwrap().push(format!("exit {name}"));
+        }
+    }
+
+    #[test]
+    fn emits_to_tracing() {
+        let collector = Collector::default();
+        let seen = collector.seen.clone();
+        tracing::subscriber::with_default(collector, || {
+            let x = Node::create_input(2.0);
+            let y = Node::create_mul(x.clone(), x);
+            assert_eq!(y.borrow().compute(), 4.0);
+            y.borrow().compute();
+        });
+
+        let seen = seen.lock().unwrap();
+        let computed = seen.iter().position(|s| s == "computed").unwrap();
+        assert_eq!(seen[computed - 1], "enter compute");
+        assert_eq!(seen[computed + 1], "exit compute");
+        assert!(seen.iter().any(|s| s == "created"));
+        assert!(seen.iter().any(|s| s == "cache-hit"));
+    }
+
+    #[test]
+    fn records_with_a_subscriber() {
+        let x = Node::create_input(2.0);
+        let y = Node::create_add(x.clone(), x);
+        let recorder = Rc::new(Recorder::new());
+        set_subscriber(recorder.clone());
+        y.borrow().compute();
+        clear_subscriber();
+
+        let node = y.borrow().id();
+        let records = recorder.take();
+        assert_eq!(records[0], Record::Enter(Span::Compute { node }));
+        assert!(records.iter().any(|record| matches!(
+            record,
+            Record::Event(Event::Computed { node: n, value, .. }) if *n == node && *value == 4.0
+        )));
+        assert!(matches!(
+            records.last(),
+            Some(Record::Exit(Span::Compute { .. }, _))
+        ));
+    }
+}
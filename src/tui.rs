@@ -0,0 +1,401 @@
+//! Terminal browser for debugging graphs: lists the nodes below an output
+//! with their cached values, edits inputs and shows what recomputes.
+//!
+//! Drawn with ratatui on a crossterm terminal. `Browser::run` takes over the
+//! terminal until quit; `handle_key` and `render` are the same steps, for
+//! driving the browser from another application's event loop.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use crossterm::event::{self, KeyCode, KeyEvent};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::computational_graph::{InputKind, Node, NodeCelled, NodeId};
+
+const HELP: &str = "↑/↓ j/k: move, PgUp/PgDn, g: go to node, enter/e: set input, \
+                    c: compute, r: reset inputs, q: quit";
+
+/// Rows `PageUp` and `PageDown` move by.
+const PAGE: usize = 10;
+
+/// Whether `Browser::handle_key` asks to go on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// What a line typed at the prompt is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    /// A new value for the selected input.
+    Set,
+    /// The id of the node to select.
+    GoTo,
+}
+
+/// Browser over the nodes below an output, output first.
+pub struct Browser {
+    output: NodeCelled,
+    nodes: Vec<NodeCelled>,
+    list: ListState,
+    /// Values after the last key, to mark what the next one changes.
+    shown: HashMap<NodeId, Option<f32>>,
+    changed: HashSet<NodeId>,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+}
+
+impl Browser {
+    pub fn new(output: NodeCelled) -> Self {
+        let mut nodes = Node::topo_order(&output);
+        nodes.reverse();
+        let shown = nodes
+            .iter()
+            .map(|node| {
+                let node = node.borrow();
+                (node.id(), node.cached_value())
+            })
+            .collect();
+        Self {
+            output,
+            nodes,
+            list: ListState::default().with_selected(Some(0)),
+            shown,
+            changed: HashSet::new(),
+            prompt: None,
+            message: HELP.to_string(),
+        }
+    }
+
+    pub fn selected(&self) -> &NodeCelled {
+        &self.nodes[self.list.selected().unwrap_or(0)]
+    }
+
+    /// Nodes whose value the last key changed, or made stale.
+    pub fn changed(&self) -> &HashSet<NodeId> {
+        &self.changed
+    }
+
+    /// Applies one key press, see `HELP`. While a prompt is open, keys edit
+    /// it: enter applies it and escape closes it.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Flow {
+        if self.prompt.is_some() {
+            self.edit_prompt(key.code);
+        } else {
+            self.message.clear();
+            match key.code {
+                KeyCode::Down | KeyCode::Char('j') => self.select_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.select_by(-1),
+                KeyCode::PageDown => self.select_by(PAGE as isize),
+                KeyCode::PageUp => self.select_by(-(PAGE as isize)),
+                KeyCode::Home => self.list.select_first(),
+                KeyCode::End => self.list.select(Some(self.nodes.len() - 1)),
+                KeyCode::Char('g') => self.prompt = Some((Prompt::GoTo, String::new())),
+                KeyCode::Enter | KeyCode::Char('e') => self.open_set(),
+                KeyCode::Char('c') => {
+                    self.message = match self.output.borrow().try_compute() {
+                        Ok(value) => format!("Output = {value}"),
+                        Err(e) => e.to_string(),
+                    }
+                }
+                KeyCode::Char('r') => self.output.borrow().reset_inputs(),
+                KeyCode::Char('q') | KeyCode::Esc => return Flow::Quit,
+                KeyCode::Char('h' | '?') => self.message = HELP.to_string(),
+                _ => {}
+            }
+        }
+        self.mark_changes();
+        Flow::Continue
+    }
+
+    fn select_by(&mut self, rows: isize) {
+        let selected = self.list.selected().unwrap_or(0);
+        let selected = selected.saturating_add_signed(rows);
+        self.list.select(Some(selected.min(self.nodes.len() - 1)));
+    }
+
+    fn edit_prompt(&mut self, key: KeyCode) {
+        let Some((prompt, text)) = &mut self.prompt else {
+            return;
+        };
+        match key {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let (prompt, text) = (*prompt, std::mem::take(text));
+                self.prompt = None;
+                match prompt {
+                    Prompt::Set => match text.trim().parse::<f32>() {
+                        Ok(value) => self.set(value),
+                        Err(_) => self.message = "Expected a number".to_string(),
+                    },
+                    Prompt::GoTo => match self.find(&text) {
+                        Some(i) => self.list.select(Some(i)),
+                        None => self.message = "No such node".to_string(),
+                    },
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open_set(&mut self) {
+        let node = self.selected().borrow();
+        if !matches!(
+            &*node,
+            Node::Input {
+                kind: InputKind::Value,
+                ..
+            }
+        ) {
+            drop(node);
+            self.message = "Only value inputs can be set".to_string();
+            return;
+        }
+        let value = node.compute().to_string();
+        drop(node);
+        self.prompt = Some((Prompt::Set, value));
+    }
+
+    /// Index of the node with id `id`, written as `3` or `#3`.
+    fn find(&self, id: &str) -> Option<usize> {
+        let id = id.trim().trim_start_matches('#');
+        self.nodes
+            .iter()
+            .position(|node| node.borrow().id().to_string() == format!("#{id}"))
+    }
+
+    fn set(&mut self, value: f32) {
+        let res = self.selected().borrow().try_set(value);
+        if let Err(e) = res {
+            self.message = e.to_string();
+        }
+    }
+
+    fn mark_changes(&mut self) {
+        self.changed.clear();
+        for node in &self.nodes {
+            let node = node.borrow();
+            let value = node.cached_value();
+            if self
+                .shown
+                .insert(node.id(), value)
+                .is_some_and(|shown| !same(shown, value))
+            {
+                self.changed.insert(node.id());
+            }
+        }
+    }
+
+    /// Draws every node with its value, `?` where it is stale, and `*` where
+    /// the last key changed it, followed by the selected node's details and
+    /// the prompt or the last key's message.
+    pub fn render(&mut self, frame: &mut Frame) {
+        let node = self.selected().clone();
+        let node = node.borrow();
+        let mut details = vec![Line::from(format!("{}: {node:.3}", node.id()))];
+        if let Some(doc) = node.doc() {
+            details.push(Line::from(format!("  {doc}")));
+        }
+        let operands: Vec<_> = node
+            .children()
+            .iter()
+            .map(|child| child.borrow().id().to_string())
+            .collect();
+        if !operands.is_empty() {
+            details.push(Line::from(format!("  operands: {}", operands.join(", "))));
+        }
+        details.push(Line::from(format!("  cache: {:?}", node.cache_policy())));
+        drop(node);
+
+        let [nodes_area, details_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(details.len() as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let items: Vec<ListItem> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let node = node.borrow();
+                let value = node.cached_value();
+                let changed = self.changed.contains(&node.id());
+                let label = match node.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("{node:.2}"),
+                };
+                let item = ListItem::new(format!(
+                    "{:>5} {:<40} {}{}",
+                    node.id().to_string(),
+                    label,
+                    value.map_or("?".to_string(), |value| value.to_string()),
+                    if changed { " *" } else { "" }
+                ));
+                match (changed, value) {
+                    (true, _) => item.yellow(),
+                    (false, None) => item.dim(),
+                    (false, Some(_)) => item,
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title("Nodes"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, nodes_area, &mut self.list);
+
+        frame.render_widget(
+            Paragraph::new(details).block(Block::bordered().title("Selected")),
+            details_area,
+        );
+
+        let status = match &self.prompt {
+            Some((Prompt::Set, text)) => format!("Set {} = {text}", self.selected().borrow().id()),
+            Some((Prompt::GoTo, text)) => format!("Go to node: {text}"),
+            None => self.message.clone(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    /// Takes over the terminal, drawing and handling keys until `q`, then
+    /// restores it.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut terminal = ratatui::try_init()?;
+        let res = self.run_on(&mut terminal);
+        ratatui::try_restore()?;
+        res
+    }
+
+    fn run_on(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.render(frame))?;
+            if let Some(key) = event::read()?.as_key_press_event() {
+                if self.handle_key(key) == Flow::Quit {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn same(a: Option<f32>, b: Option<f32>) -> bool {
+    a.map(f32::to_bits) == b.map(f32::to_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::rc::Rc;
+
+    fn press(browser: &mut Browser, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            browser.handle_key(KeyEvent::from(code));
+        }
+    }
+
+    fn screen(browser: &mut Browser) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| browser.render(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let mut res = String::new();
+        for y in 0..buffer.area.height {
+            for x in 0..buffer.area.width {
+                res.push_str(buffer[(x, y)].symbol());
+            }
+            res.push('\n');
+        }
+        res
+    }
+
+    /// `x * 2 + y`, with named inputs.
+    fn graph() -> (NodeCelled, NodeCelled) {
+        let x = Node::create_input(1.0);
+        x.borrow().set_name("x");
+        let y = Node::create_input(3.0);
+        y.borrow().set_name("y");
+        let output = Node::create_add(Node::create_mul(x.clone(), Node::create_const(2.0)), y);
+        (output, x)
+    }
+
+    #[test]
+    fn navigates_with_keys() {
+        let (output, x) = graph();
+        let mut browser = Browser::new(output.clone());
+        assert!(Rc::ptr_eq(browser.selected(), &output));
+
+        browser.handle_key(KeyEvent::from(KeyCode::End));
+        browser.handle_key(KeyEvent::from(KeyCode::Up));
+        press(&mut browser, "jjj");
+        assert!(Rc::ptr_eq(
+            browser.selected(),
+            browser.nodes.last().unwrap()
+        ));
+        browser.handle_key(KeyEvent::from(KeyCode::PageUp));
+        assert!(Rc::ptr_eq(browser.selected(), &output));
+
+        let id = x.borrow().id().to_string();
+        press(&mut browser, &format!("g{}\n", id.trim_start_matches('#')));
+        assert!(Rc::ptr_eq(browser.selected(), &x));
+        press(&mut browser, "g999\n");
+        assert_eq!(browser.message, "No such node");
+        assert_eq!(
+            browser.handle_key(KeyEvent::from(KeyCode::Char('q'))),
+            Flow::Quit
+        );
+    }
+
+    #[test]
+    fn edits_inputs_and_marks_recomputation() {
+        let (output, x) = graph();
+        output.borrow().compute();
+        let mut browser = Browser::new(output.clone());
+        let id = x.borrow().id().to_string();
+        press(&mut browser, &format!("g{id}\n"));
+
+        // The prompt starts from the current value.
+        press(&mut browser, "e");
+        assert!(screen(&mut browser).contains(&format!("Set {id} = 1")));
+        browser.handle_key(KeyEvent::from(KeyCode::Backspace));
+        press(&mut browser, "4\n");
+        assert_eq!(x.borrow().compute(), 4.0);
+        assert!(browser.changed().contains(&output.borrow().id()));
+        assert!(screen(&mut browser).contains("?"));
+
+        press(&mut browser, "c");
+        assert_eq!(browser.message, "Output = 11");
+        assert!(browser.changed().contains(&output.borrow().id()));
+        assert!(screen(&mut browser).contains("11 *"));
+
+        press(&mut browser, "k");
+        assert!(browser.changed().is_empty());
+        press(&mut browser, "e\x1b");
+        assert!(browser.prompt.is_none());
+    }
+
+    #[test]
+    fn only_value_inputs_are_set() {
+        let (output, _) = graph();
+        let mut browser = Browser::new(output);
+        press(&mut browser, "e");
+        assert!(browser.prompt.is_none());
+        assert_eq!(browser.message, "Only value inputs can be set");
+    }
+}
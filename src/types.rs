@@ -0,0 +1,373 @@
+//! Static types of node values: numbers or booleans, and the shapes of
+//! tensors of them. Ops evaluate whatever they are given, so mixing the two
+//! isn't an evaluation error; checking types before evaluating catches
+//! formulas that only do so by mistake.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::computational_graph::{BinaryOp, Comparison, Node, NodeId, TernaryOp, UnaryOp};
+use crate::tensor::Tensor;
+
+/// What a single node's value means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Scalar,
+    /// `1` or `0`, from logical ops and comparisons.
+    Bool,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Scalar => "scalar",
+            Self::Bool => "bool",
+        })
+    }
+}
+
+/// Type of a tensor's value, displayed as `scalar`, `vector[3]`,
+/// `bool matrix[2×3]` or `tensor[2×3×4]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Type {
+    pub kind: Kind,
+    pub shape: Vec<usize>,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.shape.is_empty() {
+            return write!(f, "{}", self.kind);
+        }
+        if self.kind == Kind::Bool {
+            f.write_str("bool ")?;
+        }
+        let dims: Vec<_> = self.shape.iter().map(usize::to_string).collect();
+        let name = match self.shape.len() {
+            1 => "vector",
+            2 => "matrix",
+            _ => "tensor",
+        };
+        write!(f, "{name}[{}]", dims.join("×"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    /// Operand `operand`, counted from 0, of `node`, printed as `formula`,
+    /// has the wrong kind.
+    Operand {
+        node: NodeId,
+        formula: String,
+        operand: usize,
+        expected: Kind,
+        found: Kind,
+    },
+    /// Element `index` of a tensor differs in kind from the first one.
+    Element {
+        node: NodeId,
+        index: usize,
+        expected: Kind,
+        found: Kind,
+    },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Operand {
+                node,
+                formula,
+                operand,
+                expected,
+                found,
+            } => write!(
+                f,
+                "node {node} `{formula}`: operand {operand} is {found}, expected {expected}"
+            ),
+            Self::Element {
+                node,
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "tensor element {index} (node {node}) is {found}, expected {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Result of `Node::check_types`.
+#[derive(Debug, Clone)]
+pub struct Types {
+    kinds: HashMap<NodeId, Kind>,
+}
+
+impl Types {
+    /// Kind of `node`, if it was checked. Nodes inside composites aren't
+    /// listed.
+    pub fn kind(&self, node: &Node) -> Option<Kind> {
+        self.kinds.get(&node.id()).copied()
+    }
+}
+
+impl Node {
+    /// Kinds of this node and everything it depends on. Fails on the first
+    /// node, in topological order, that reads a bool as a number or the other
+    /// way around: arithmetic, rounding and ordering comparisons take
+    /// scalars, logical ops and `Select` conditions take bools, `Eq` and `Ne`
+    /// compare operands of the same kind, and `Select` branches agree. Inputs
+    /// and custom ops yield scalars, and custom ops accept either kind.
+    pub fn check_types(&self) -> Result<Types, TypeError> {
+        let mut kinds = HashMap::new();
+        infer(self, &HashMap::new(), &mut kinds)?;
+        Ok(Types { kinds })
+    }
+}
+
+impl Tensor {
+    /// Type of this tensor, checking every element's graph.
+    pub fn check_type(&self) -> Result<Type, TypeError> {
+        let mut kinds = HashMap::new();
+        let mut kind = None;
+        for (index, node) in self.nodes().iter().enumerate() {
+            let node = node.borrow();
+            let found = infer(&node, &HashMap::new(), &mut kinds)?;
+            match kind {
+                None => kind = Some(found),
+                Some(expected) if expected != found => {
+                    return Err(TypeError::Element {
+                        node: node.id(),
+                        index,
+                        expected,
+                        found,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(Type {
+            kind: kind.unwrap_or(Kind::Scalar),
+            shape: self.shape().to_vec(),
+        })
+    }
+}
+
+/// Kinds of `output` and its dependencies into `kinds`, `seeds` giving those
+/// of subgraph inputs. Returns the one of `output`.
+fn infer(
+    output: &Node,
+    seeds: &HashMap<NodeId, Kind>,
+    kinds: &mut HashMap<NodeId, Kind>,
+) -> Result<Kind, TypeError> {
+    for child in output.children() {
+        for node in Node::topo_order(&child) {
+            let node = node.borrow();
+            if !kinds.contains_key(&node.id()) {
+                let kind = kind(&node, seeds, kinds)?;
+                kinds.insert(node.id(), kind);
+            }
+        }
+    }
+    match kinds.get(&output.id()) {
+        Some(kind) => Ok(*kind),
+        None => {
+            let kind = kind(output, seeds, kinds)?;
+            kinds.insert(output.id(), kind);
+            Ok(kind)
+        }
+    }
+}
+
+/// Kind of `node` from its operands' ones in `kinds`.
+fn kind(
+    node: &Node,
+    seeds: &HashMap<NodeId, Kind>,
+    kinds: &HashMap<NodeId, Kind>,
+) -> Result<Kind, TypeError> {
+    let args: Vec<Kind> = node
+        .children()
+        .iter()
+        .map(|child| kinds[&child.borrow().id()])
+        .collect();
+    let expect = |operands: &[usize], expected: Kind| {
+        for operand in operands {
+            if args[*operand] != expected {
+                return Err(TypeError::Operand {
+                    node: node.id(),
+                    formula: format!("{node:.3}"),
+                    operand: *operand,
+                    expected,
+                    found: args[*operand],
+                });
+            }
+        }
+        Ok(())
+    };
+
+    Ok(match node {
+        Node::Input { .. } => *seeds.get(&node.id()).unwrap_or(&Kind::Scalar),
+        Node::Binary { op, .. } => match op {
+            BinaryOp::Add | BinaryOp::Mul | BinaryOp::Pow(_) => {
+                expect(&[0, 1], Kind::Scalar)?;
+                Kind::Scalar
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                expect(&[0, 1], Kind::Bool)?;
+                Kind::Bool
+            }
+            BinaryOp::Compare(Comparison::Eq | Comparison::Ne) => {
+                expect(&[1], args[0])?;
+                Kind::Bool
+            }
+            BinaryOp::Compare(_) => {
+                expect(&[0, 1], Kind::Scalar)?;
+                Kind::Bool
+            }
+        },
+        Node::Unary { op, .. } => match op {
+            UnaryOp::Sin | UnaryOp::Cos | UnaryOp::Round { .. } => {
+                expect(&[0], Kind::Scalar)?;
+                Kind::Scalar
+            }
+            UnaryOp::Not => {
+                expect(&[0], Kind::Bool)?;
+                Kind::Bool
+            }
+        },
+        Node::Ternary { op, .. } => match op {
+            TernaryOp::MulAdd => {
+                expect(&[0, 1, 2], Kind::Scalar)?;
+                Kind::Scalar
+            }
+            TernaryOp::Select => {
+                expect(&[0], Kind::Bool)?;
+                expect(&[2], args[1])?;
+                args[1]
+            }
+        },
+        Node::Custom { .. } => Kind::Scalar,
+        Node::Composite { graph, port, .. } => {
+            let seeds = graph
+                .inputs()
+                .iter()
+                .zip(&args)
+                .map(|(input, arg)| (input.borrow().id(), *arg))
+                .collect();
+            infer(
+                &graph.outputs()[*port].borrow(),
+                &seeds,
+                &mut HashMap::new(),
+            )?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::NodeCelled;
+    use crate::function::Function;
+
+    fn lt(a: &NodeCelled, b: f32) -> NodeCelled {
+        Node::create_compare(a.clone(), Node::create_const(b), Comparison::Lt)
+    }
+
+    #[test]
+    fn infers_kinds() {
+        let x = Node::create_input(1f32);
+        let (small, positive) = (lt(&x, 10.0), Node::create_not(lt(&x, 0.0)));
+        let both = Node::create_and(small.clone(), positive);
+        let same = Node::create_compare(both.clone(), small, Comparison::Eq);
+        let out = Node::create_select(same.clone(), Node::create_sin(x.clone()), x.clone());
+
+        let types = out.borrow().check_types().unwrap();
+        assert_eq!(types.kind(&out.borrow()), Some(Kind::Scalar));
+        assert_eq!(types.kind(&both.borrow()), Some(Kind::Bool));
+        assert_eq!(types.kind(&same.borrow()), Some(Kind::Bool));
+        assert_eq!(types.kind(&x.borrow()), Some(Kind::Scalar));
+
+        let either = Node::create_select(same, both.clone(), Node::create_not(both));
+        let types = either.borrow().check_types().unwrap();
+        assert_eq!(types.kind(&either.borrow()), Some(Kind::Bool));
+    }
+
+    #[test]
+    fn rejects_mixed_kinds() {
+        let x = Node::create_input(1f32);
+        let sum = Node::create_add(x.clone(), lt(&x, 0.0));
+        let error = sum.borrow().check_types().unwrap_err();
+        assert!(matches!(
+            error,
+            TypeError::Operand {
+                operand: 1,
+                expected: Kind::Scalar,
+                found: Kind::Bool,
+                ..
+            }
+        ));
+        assert!(error
+            .to_string()
+            .ends_with("operand 1 is bool, expected scalar"));
+
+        let branches = Node::create_select(lt(&x, 0.0), x.clone(), lt(&x, 1.0));
+        let error = branches.borrow().check_types().unwrap_err();
+        assert!(matches!(error, TypeError::Operand { operand: 2, .. }));
+        let condition = Node::create_select(x.clone(), x.clone(), x.clone());
+        let error = condition.borrow().check_types().unwrap_err();
+        assert!(matches!(
+            error,
+            TypeError::Operand {
+                operand: 0,
+                expected: Kind::Bool,
+                ..
+            }
+        ));
+        let equal = Node::create_compare(x.clone(), lt(&x, 0.0), Comparison::Eq);
+        assert!(equal.borrow().check_types().is_err());
+    }
+
+    #[test]
+    fn checks_calls_with_their_arguments() {
+        let negate = Function::define("negate", 1, |p| Node::create_not(p[0].clone()));
+        let x = Node::create_input(1f32);
+        let call = negate.call(vec![lt(&x, 0.0)]);
+        let types = call.borrow().check_types().unwrap();
+        assert_eq!(types.kind(&call.borrow()), Some(Kind::Bool));
+        assert!(negate.call(vec![x]).borrow().check_types().is_err());
+    }
+
+    #[test]
+    fn types_tensors() {
+        let x = Tensor::inputs(vec![2, 3], &[0.0; 6]).unwrap();
+        let ty = x.check_type().unwrap();
+        assert_eq!(ty.to_string(), "matrix[2×3]");
+        let zero = Tensor::scalar(Node::create_const(0f32));
+        let ty = x
+            .compare(&zero, Comparison::Gt)
+            .unwrap()
+            .check_type()
+            .unwrap();
+        assert_eq!(ty.to_string(), "bool matrix[2×3]");
+        assert_eq!(zero.check_type().unwrap().to_string(), "scalar");
+        let ty = Tensor::inputs(vec![1, 1, 2], &[0.0; 2])
+            .unwrap()
+            .check_type();
+        assert_eq!(ty.unwrap().to_string(), "tensor[1×1×2]");
+
+        let first = x.nodes()[0].clone();
+        let mixed = Tensor::new(vec![2], vec![first.clone(), lt(&first, 0.0)]).unwrap();
+        let error = mixed.check_type().unwrap_err();
+        assert!(matches!(
+            error,
+            TypeError::Element {
+                index: 1,
+                expected: Kind::Scalar,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+//! Comparing versions of a graph: both are run over the same scenarios, and
+//! the outputs they disagree on are reported, to review a formula change
+//! before deploying it.
+
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+use crate::computational_graph::{InputKind, Node, NodeCelled};
+use crate::scenario::{write_csv_field, ScenarioError, ScenarioTable};
+use crate::serialize::{Deserializer, LoadError};
+
+#[derive(Debug, Clone)]
+pub enum VersionError {
+    Load(LoadError),
+    /// The output at this position in the file has no name to match it by.
+    UnnamedOutput(usize),
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(e) => e.fmt(f),
+            Self::UnnamedOutput(i) => write!(f, "output {i} has no name"),
+        }
+    }
+}
+
+impl std::error::Error for VersionError {}
+
+impl From<LoadError> for VersionError {
+    fn from(e: LoadError) -> Self {
+        Self::Load(e)
+    }
+}
+
+/// One version of a graph, its inputs and outputs named so that versions
+/// can be matched up.
+#[derive(Debug, Clone)]
+pub struct GraphVersion {
+    inputs: Vec<(String, NodeCelled)>,
+    outputs: Vec<(String, NodeCelled)>,
+}
+
+impl GraphVersion {
+    pub fn new(inputs: &[(&str, NodeCelled)], outputs: &[(&str, NodeCelled)]) -> Self {
+        let owned = |nodes: &[(&str, NodeCelled)]| {
+            nodes
+                .iter()
+                .map(|(name, node)| (name.to_string(), node.clone()))
+                .collect()
+        };
+        Self {
+            inputs: owned(inputs),
+            outputs: owned(outputs),
+        }
+    }
+
+    /// A serialized version, its inputs and outputs matched up by the names
+    /// serialized with them. Unnamed value inputs are left out, keeping their
+    /// value in every scenario; every output needs a name.
+    pub fn load(deserializer: &Deserializer, text: &str) -> Result<Self, VersionError> {
+        let nodes = deserializer.deserialize(text)?;
+        let mut seen = HashSet::new();
+        let inputs = nodes
+            .iter()
+            .flat_map(Node::inputs)
+            .filter(|input| seen.insert(Rc::as_ptr(input)))
+            .filter_map(|input| {
+                let name = match &*input.borrow() {
+                    node @ Node::Input {
+                        kind: InputKind::Value,
+                        ..
+                    } => node.name()?,
+                    _ => return None,
+                };
+                Some((name.to_string(), input))
+            })
+            .collect();
+        let outputs = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, output)| {
+                let name = output.borrow().name();
+                match name {
+                    Some(name) => Ok((name.to_string(), output)),
+                    None => Err(VersionError::UnnamedOutput(i)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { inputs, outputs })
+    }
+
+    /// The outputs in each of `scenarios`, see `ScenarioTable::run`. Columns
+    /// for inputs this version doesn't have are left out, and inputs without
+    /// a column keep their value.
+    pub fn run(&self, scenarios: &ScenarioTable) -> Result<ScenarioTable, ScenarioError> {
+        let known: Vec<usize> = scenarios
+            .columns()
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| self.inputs.iter().any(|(name, _)| name == *column))
+            .map(|(i, _)| i)
+            .collect();
+        let columns: Vec<&str> = known
+            .iter()
+            .map(|i| scenarios.columns()[*i].as_str())
+            .collect();
+        let mut table = ScenarioTable::new(&columns);
+        for (name, values) in scenarios.rows() {
+            table.push(name, known.iter().map(|i| values[*i]).collect());
+        }
+
+        table.run(&borrowed(&self.inputs), &borrowed(&self.outputs))
+    }
+}
+
+fn borrowed(nodes: &[(String, NodeCelled)]) -> Vec<(&str, NodeCelled)> {
+    nodes
+        .iter()
+        .map(|(name, node)| (name.as_str(), node.clone()))
+        .collect()
+}
+
+/// An output in one scenario, under both versions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDelta {
+    pub scenario: String,
+    pub output: String,
+    /// NaN if the old version has no such output.
+    pub old: f32,
+    /// NaN if the new version has no such output.
+    pub new: f32,
+}
+
+impl OutputDelta {
+    pub fn delta(&self) -> f32 {
+        self.new - self.old
+    }
+
+    /// `delta` relative to the old value, infinite if that was zero.
+    pub fn relative(&self) -> f32 {
+        self.delta() / self.old.abs()
+    }
+
+    /// Whether the values differ by more than `tolerance`, or only one is
+    /// NaN.
+    pub fn exceeds(&self, tolerance: f32) -> bool {
+        if self.old.is_nan() || self.new.is_nan() {
+            return self.old.is_nan() != self.new.is_nan();
+        }
+        // Equal infinities would have a NaN delta.
+        self.old != self.new && self.delta().abs() > tolerance
+    }
+}
+
+/// Every output of either version in every scenario, outputs in the old
+/// version's order, followed by those only the new one has.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VersionComparison {
+    deltas: Vec<OutputDelta>,
+}
+
+impl VersionComparison {
+    pub fn new(
+        old: &GraphVersion,
+        new: &GraphVersion,
+        scenarios: &ScenarioTable,
+    ) -> Result<Self, ScenarioError> {
+        let old = old.run(scenarios)?;
+        let new = new.run(scenarios)?;
+        let mut outputs: Vec<&String> = old.columns().iter().collect();
+        outputs.extend(new.columns().iter().filter(|c| !old.columns().contains(c)));
+
+        let mut deltas = Vec::new();
+        for (scenario, _) in scenarios.rows() {
+            for output in &outputs {
+                deltas.push(OutputDelta {
+                    scenario: scenario.clone(),
+                    output: output.to_string(),
+                    old: old.get(scenario, output).unwrap_or(f32::NAN),
+                    new: new.get(scenario, output).unwrap_or(f32::NAN),
+                });
+            }
+        }
+        Ok(Self { deltas })
+    }
+
+    pub fn deltas(&self) -> &[OutputDelta] {
+        &self.deltas
+    }
+
+    /// The deltas that `exceed` `tolerance`, what a review looks at.
+    pub fn changed(&self, tolerance: f32) -> Vec<&OutputDelta> {
+        self.deltas
+            .iter()
+            .filter(|delta| delta.exceeds(tolerance))
+            .collect()
+    }
+
+    /// One row per scenario and output: `scenario,output,old,new,delta`.
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("scenario,output,old,new,delta\n");
+        for delta in &self.deltas {
+            write_csv_field(&mut res, &delta.scenario);
+            res.push(',');
+            write_csv_field(&mut res, &delta.output);
+            writeln!(res, ",{},{},{}", delta.old, delta.new, delta.delta()).unwrap();
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::serialize;
+
+    /// `price * (1 + rate)`, or `price * rate` for `taxed == false`, with a
+    /// named output and an unnamed constant.
+    fn version(taxed: bool) -> String {
+        let rate = Node::create_input(0.2);
+        rate.borrow().set_name("rate");
+        let price = Node::create_input(10.0);
+        price.borrow().set_name("price");
+        let factor = match taxed {
+            true => Node::create_add(Node::create_const(1.0), rate),
+            false => rate,
+        };
+        let total = Node::create_mul(price, factor);
+        total.borrow().set_name("total");
+        serialize(&[total])
+    }
+
+    #[test]
+    fn matches_loaded_versions_by_name() {
+        let deserializer = Deserializer::new();
+        let old = GraphVersion::load(&deserializer, &version(true)).unwrap();
+        let new = GraphVersion::load(&deserializer, &version(false)).unwrap();
+        let mut scenarios = ScenarioTable::new(&["price", "rate"]);
+        scenarios.push("base", vec![100.0, 0.5]);
+
+        let comparison = VersionComparison::new(&old, &new, &scenarios).unwrap();
+        let delta = &comparison.deltas()[0];
+        assert_eq!(
+            (delta.output.as_str(), delta.old, delta.new),
+            ("total", 150.0, 50.0)
+        );
+        assert_eq!(comparison.changed(0.0).len(), 1);
+    }
+
+    #[test]
+    fn loading_needs_named_outputs() {
+        let x = Node::create_input(1.0);
+        let text = serialize(&[Node::create_add(x.clone(), x)]);
+        let res = GraphVersion::load(&Deserializer::new(), &text);
+        assert!(matches!(res, Err(VersionError::UnnamedOutput(0))));
+    }
+}
@@ -0,0 +1,225 @@
+//! Watchpoints: conditions on a node's value, checked each time it's
+//! recomputed or, for inputs, set, calling back when they're met. For
+//! monitoring, e.g. alerting when a risk figure crosses a limit.
+
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::computational_graph::{Node, NodeId};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchCondition {
+    /// The value rises above the threshold, from at or below it.
+    Above(f32),
+    /// The value falls below the threshold, from at or above it.
+    Below(f32),
+    /// The value changes by more than this fraction of the previous one,
+    /// e.g. `0.1` for 10%. Any change from zero counts.
+    ChangedBy(f32),
+}
+
+impl WatchCondition {
+    /// Whether going from `old`, if the node had a value before, to `new`
+    /// meets this condition. Crossings count from no value too.
+    pub fn is_met(&self, old: Option<f32>, new: f32) -> bool {
+        match *self {
+            Self::Above(x) => new > x && old.is_none_or(|old| old <= x),
+            Self::Below(x) => new < x && old.is_none_or(|old| old >= x),
+            Self::ChangedBy(fraction) => {
+                old.is_some_and(|old| (new - old).abs() > fraction * old.abs())
+            }
+        }
+    }
+}
+
+impl fmt::Display for WatchCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Above(x) => write!(f, "above {x}"),
+            Self::Below(x) => write!(f, "below {x}"),
+            Self::ChangedBy(fraction) => write!(f, "changed by more than {}%", fraction * 100.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+impl WatchId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// What a callback is told.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchEvent {
+    pub watch: WatchId,
+    pub node: NodeId,
+    pub condition: WatchCondition,
+    /// The value the watch saw last, `None` if it hasn't seen one.
+    pub old: Option<f32>,
+    pub new: f32,
+}
+
+type Callback = Rc<dyn Fn(&WatchEvent)>;
+
+#[derive(Clone)]
+pub(crate) struct Watch {
+    id: WatchId,
+    condition: WatchCondition,
+    callback: Callback,
+    last: Rc<Cell<Option<f32>>>,
+}
+
+/// Checks `watches` of the node `node` against its new value `new`,
+/// returning the callbacks of those met, with what to tell them.
+pub(crate) fn check(node: NodeId, watches: &[Watch], new: f32) -> Vec<(Callback, WatchEvent)> {
+    watches
+        .iter()
+        .filter_map(|watch| {
+            let old = watch.last.replace(Some(new));
+            watch.condition.is_met(old, new).then(|| {
+                let event = WatchEvent {
+                    watch: watch.id,
+                    node,
+                    condition: watch.condition,
+                    old,
+                    new,
+                };
+                (watch.callback.clone(), event)
+            })
+        })
+        .collect()
+}
+
+impl Node {
+    /// Calls `callback` whenever this node's new value meets `condition`,
+    /// compared with the value it had when watched, then with each new one.
+    /// Callbacks run during the computation, so they may read other nodes
+    /// but mustn't change the graph being computed.
+    pub fn watch(
+        &self,
+        condition: WatchCondition,
+        callback: impl Fn(&WatchEvent) + 'static,
+    ) -> WatchId {
+        let id = WatchId::next();
+        self.watches().borrow_mut().push(Watch {
+            id,
+            condition,
+            callback: Rc::new(callback),
+            last: Rc::new(Cell::new(self.cached_value())),
+        });
+        id
+    }
+
+    /// Removes the watch `id`, returning whether this node had it.
+    pub fn unwatch(&self, id: WatchId) -> bool {
+        let mut watches = self.watches().borrow_mut();
+        let len = watches.len();
+        watches.retain(|watch| watch.id != id);
+        watches.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use crate::computational_graph::CachePolicy;
+
+    fn record(events: &Rc<RefCell<Vec<WatchEvent>>>) -> impl Fn(&WatchEvent) + 'static {
+        let events = events.clone();
+        move |event| events.borrow_mut().push(event.clone())
+    }
+
+    #[test]
+    fn fires_on_crossings_not_recomputes() {
+        let x = Node::create_input(1.0f32);
+        let y = Node::create_mul(x.clone(), Node::create_const(2.0));
+        y.borrow().compute();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let id = y
+            .borrow()
+            .watch(WatchCondition::Above(10.0), record(&events));
+
+        x.borrow().set(6.0);
+        y.borrow().compute();
+        assert_eq!(
+            *events.borrow(),
+            [WatchEvent {
+                watch: id,
+                node: y.borrow().id(),
+                condition: WatchCondition::Above(10.0),
+                old: Some(2.0),
+                new: 12.0,
+            }]
+        );
+
+        // Staying above isn't crossing again, however often it's computed.
+        y.borrow().set_cache_policy(CachePolicy::Recompute);
+        y.borrow().compute();
+        x.borrow().set(7.0);
+        y.borrow().compute();
+        assert_eq!(events.borrow().len(), 1);
+
+        x.borrow().set(1.0);
+        y.borrow().compute();
+        x.borrow().set(8.0);
+        y.borrow().compute();
+        assert_eq!(events.borrow().len(), 2);
+        assert_eq!(events.borrow()[1].old, Some(2.0));
+    }
+
+    #[test]
+    fn watches_inputs_when_set() {
+        let x = Node::create_input(100.0f32);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        x.borrow()
+            .watch(WatchCondition::Below(50.0), record(&events));
+        x.borrow()
+            .watch(WatchCondition::ChangedBy(0.1), record(&events));
+
+        x.borrow().set(95.0);
+        assert!(events.borrow().is_empty());
+        x.borrow().set(40.0);
+        let conditions: Vec<_> = events.borrow().iter().map(|e| e.condition).collect();
+        assert_eq!(
+            conditions,
+            [WatchCondition::Below(50.0), WatchCondition::ChangedBy(0.1)]
+        );
+        // Changes count from the last value, not the first.
+        x.borrow().set(42.0);
+        assert_eq!(events.borrow().len(), 2);
+    }
+
+    #[test]
+    fn unwatches() {
+        let x = Node::create_input(0.0f32);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let id = x
+            .borrow()
+            .watch(WatchCondition::Above(1.0), record(&events));
+        assert!(x.borrow().unwatch(id));
+        assert!(!x.borrow().unwatch(id));
+        x.borrow().set(2.0);
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn conditions_count_from_no_value() {
+        assert!(WatchCondition::Above(0.0).is_met(None, 1.0));
+        assert!(!WatchCondition::Above(0.0).is_met(Some(0.5), 1.0));
+        assert!(WatchCondition::Below(0.0).is_met(Some(0.0), -1.0));
+        assert!(!WatchCondition::ChangedBy(0.5).is_met(None, 1.0));
+        assert!(WatchCondition::ChangedBy(0.5).is_met(Some(0.0), 1e-9));
+        assert_eq!(
+            WatchCondition::ChangedBy(0.25).to_string(),
+            "changed by more than 25%"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! WGSL code generation for `CompiledGraph`.
+//!
+//! The generated compute shader reads row-major input rows from binding 0 and
+//! writes one output per row to binding 1, one invocation per row. Dispatching
+//! it is left to the embedding application's GPU setup.
+
+use std::fmt::{self, Write};
+
+use crate::compiled::{CompiledGraph, Instr};
+use crate::computational_graph::{BinaryOp, NodeId, PowPolicy, RoundMode, TernaryOp, UnaryOp};
+
+pub const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Clone)]
+pub enum WgslError {
+    /// The node's semantics can't be expressed in a shader.
+    Unsupported { node: NodeId, reason: &'static str },
+}
+
+impl fmt::Display for WgslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported { node, reason } => write!(f, "node {node}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for WgslError {}
+
+// Non-finite const-expressions are shader-creation errors, so NaN and infinity
+// are bitcast at runtime, from `let` values. `pow` in WGSL is undefined for
+// `x <= 0`, so `powf` semantics are spelled out.
+const PRELUDE: &str = "\
+@group(0) @binding(0) var<storage, read> inputs: array<f32>;
+@group(0) @binding(1) var<storage, read_write> outputs: array<f32>;
+
+fn nan() -> f32 {
+    let bits = 0x7fc00000u;
+    return bitcast<f32>(bits);
+}
+
+fn inf() -> f32 {
+    let bits = 0x7f800000u;
+    return bitcast<f32>(bits);
+}
+
+fn powf(a: f32, b: f32) -> f32 {
+    if (a == 0.0) {
+        return select(select(0.0, 1.0, b == 0.0), inf(), b < 0.0);
+    }
+    if (a < 0.0) {
+        if (b != round(b)) {
+            return nan();
+        }
+        let r = pow(-a, b);
+        return select(r, -r, abs(b % 2.0) == 1.0);
+    }
+    return pow(a, b);
+}
+";
+
+impl CompiledGraph {
+    pub fn to_wgsl(&self) -> Result<String, WgslError> {
+        let mut res = String::from(PRELUDE);
+        let stride = self.input_count();
+
+        writeln!(res).unwrap();
+        writeln!(res, "@compute @workgroup_size({WORKGROUP_SIZE})").unwrap();
+        writeln!(
+            res,
+            "fn main(@builtin(global_invocation_id) id: vec3<u32>) {{"
+        )
+        .unwrap();
+        writeln!(res, "    let row = id.x;").unwrap();
+        writeln!(res, "    if (row >= arrayLength(&outputs)) {{").unwrap();
+        writeln!(res, "        return;").unwrap();
+        writeln!(res, "    }}").unwrap();
+        writeln!(res, "    let base = row * {stride}u;").unwrap();
+
+        for (i, (instr, id)) in self.instrs.iter().zip(self.ids.iter()).enumerate() {
+            let expr = match instr {
+                Instr::Input(input) => format!("inputs[base + {input}u]"),
+                Instr::Const(x) => literal(*x),
+                Instr::Unary(UnaryOp::Sin, x) => format!("sin(s{x})"),
+                Instr::Unary(UnaryOp::Cos, x) => format!("cos(s{x})"),
+                Instr::Unary(UnaryOp::Not, x) => format!("select(0.0, 1.0, s{x} == 0.0)"),
+                // Rounds the binary value, so halves may come out differently
+                // than on the host.
+                Instr::Unary(UnaryOp::Round { digits, mode }, x) => {
+                    let scale = literal(10f32.powi(*digits));
+                    match mode {
+                        RoundMode::HalfUp => {
+                            format!("sign(s{x}) * floor(abs(s{x}) * {scale} + 0.5) / {scale}")
+                        }
+                        RoundMode::HalfEven => format!("round(s{x} * {scale}) / {scale}"),
+                        RoundMode::Trunc => format!("trunc(s{x} * {scale}) / {scale}"),
+                    }
+                }
+                Instr::Binary(BinaryOp::Add, a, b) => format!("s{a} + s{b}"),
+                Instr::Binary(BinaryOp::Mul, a, b) => format!("s{a} * s{b}"),
+                Instr::Binary(BinaryOp::Pow(policy), a, b) => match policy {
+                    PowPolicy::Native => format!("powf(s{a}, s{b})"),
+                    PowPolicy::Nan => {
+                        format!("select(powf(s{a}, s{b}), nan(), s{a} == 0.0 && s{b} <= 0.0)")
+                    }
+                    PowPolicy::Error | PowPolicy::ZeroPowZeroIsOne => {
+                        return Err(WgslError::Unsupported {
+                            node: *id,
+                            reason: "shaders can't report pow domain errors",
+                        })
+                    }
+                },
+                Instr::Binary(BinaryOp::And, a, b) => {
+                    format!("select(0.0, 1.0, s{a} != 0.0 && s{b} != 0.0)")
+                }
+                Instr::Binary(BinaryOp::Or, a, b) => {
+                    format!("select(0.0, 1.0, s{a} != 0.0 || s{b} != 0.0)")
+                }
+                Instr::Binary(BinaryOp::Compare(cmp), a, b) => {
+                    format!("select(0.0, 1.0, s{a} {cmp} s{b})")
+                }
+                Instr::Ternary(TernaryOp::MulAdd, a, b, c) => format!("fma(s{a}, s{b}, s{c})"),
+                Instr::Ternary(TernaryOp::Select, a, b, c) => {
+                    format!("select(s{c}, s{b}, s{a} != 0.0)")
+                }
+                Instr::Custom(..) => {
+                    return Err(WgslError::Unsupported {
+                        node: *id,
+                        reason: "custom ops run on the host",
+                    })
+                }
+            };
+            writeln!(res, "    let s{i}: f32 = {expr};").unwrap();
+        }
+
+        writeln!(res, "    outputs[row] = s{};", self.instrs.len() - 1).unwrap();
+        writeln!(res, "}}").unwrap();
+
+        Ok(res)
+    }
+}
+
+fn literal(x: f32) -> String {
+    if x.is_nan() {
+        "nan()".to_string()
+    } else if x.is_infinite() {
+        if x > 0f32 { "inf()" } else { "-inf()" }.to_string()
+    } else {
+        format!("{x:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computational_graph::Node;
+
+    #[test]
+    fn non_finite_values_are_computed_at_runtime() {
+        let x = Node::create_input(1.0);
+        let output = Node::create_add(
+            Node::create_mul(x.clone(), Node::create_const(f32::INFINITY)),
+            Node::create_pow(x.clone(), Node::create_const(f32::NAN)),
+        );
+        let shader = CompiledGraph::compile(&output, &[x]).to_wgsl().unwrap();
+
+        assert!(!shader.contains("const "), "{shader}");
+        assert!(shader.contains("let s1: f32 = inf();"), "{shader}");
+        assert!(shader.contains("let s3: f32 = nan();"), "{shader}");
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(literal(f32::NAN), "nan()");
+        assert_eq!(literal(f32::NEG_INFINITY), "-inf()");
+        assert_eq!(literal(1.0), "1.0");
+    }
+}